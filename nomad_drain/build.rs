@@ -0,0 +1,25 @@
+//! Embed build information, exposed by `nomad_drain::build_info`
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let git_sha = Command::new("git")
+        .arg("rev-parse")
+        .arg("--short=12")
+        .arg("HEAD")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_default();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    println!("cargo:rustc-env=NOMAD_DRAIN_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=NOMAD_DRAIN_BUILD_TIMESTAMP={}", timestamp);
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/index");
+}
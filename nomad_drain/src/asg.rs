@@ -0,0 +1,105 @@
+//! Helpers for AWS Auto Scaling Group lifecycle hooks
+use std::fmt;
+
+use log::info;
+use rusoto_autoscaling::{Autoscaling, RecordLifecycleActionHeartbeatType};
+use serde::{Deserialize, Serialize};
+
+use crate::events::AsgEventDetails;
+
+/// Result to complete a lifecycle action with
+///
+/// For terminating instances, both results let the instance terminate. `Abandon` also skips any
+/// remaining lifecycle hooks.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LifecycleActionResult {
+    Continue,
+    Abandon,
+}
+
+impl LifecycleActionResult {
+    /// Value of the result expected by the Auto Scaling API
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LifecycleActionResult::Continue => "CONTINUE",
+            LifecycleActionResult::Abandon => "ABANDON",
+        }
+    }
+}
+
+impl fmt::Display for LifecycleActionResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Extend the timeout of the lifecycle action for the event
+///
+/// Once the heartbeat timeout of the lifecycle hook elapses, Auto Scaling takes the default result
+/// of the hook regardless of whether the drain is complete. Record heartbeats periodically while
+/// waiting on long drains to reset the timeout.
+pub fn record_lifecycle_action_heartbeat<A>(
+    autoscaling_client: &A,
+    event: &AsgEventDetails,
+) -> Result<(), crate::Error>
+where
+    A: Autoscaling + ?Sized,
+{
+    info!(
+        "Recording lifecycle action heartbeat for AWS Instance ID {}",
+        event.instance_id
+    );
+    autoscaling_client
+        .record_lifecycle_action_heartbeat(heartbeat_request(event))
+        .sync()?;
+    Ok(())
+}
+
+fn heartbeat_request(event: &AsgEventDetails) -> RecordLifecycleActionHeartbeatType {
+    RecordLifecycleActionHeartbeatType {
+        auto_scaling_group_name: event.auto_scaling_group_name.clone(),
+        instance_id: Some(event.instance_id.clone()),
+        lifecycle_action_token: Some(event.lifecycle_action_token.clone()),
+        lifecycle_hook_name: event.lifecycle_hook_name.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::events::AsgLifecycleTransition;
+
+    #[test]
+    fn lifecycle_action_results_match_the_api() -> Result<(), crate::Error> {
+        let result: LifecycleActionResult = serde_json::from_str(r#""ABANDON""#)?;
+        assert_eq!(LifecycleActionResult::Abandon, result);
+        assert_eq!("CONTINUE", LifecycleActionResult::Continue.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn heartbeat_request_identifies_lifecycle_action() {
+        let event = AsgEventDetails {
+            lifecycle_action_token: "87654321-4321-4321-4321-210987654321".to_string(),
+            auto_scaling_group_name: "nomad-clients".to_string(),
+            instance_id: "i-1234567890abcdef0".to_string(),
+            lifecycle_transition: AsgLifecycleTransition::InstanceTerminating,
+            lifecycle_hook_name: "drain".to_string(),
+            notification_metadata: None,
+            origin: None,
+            destination: None,
+        };
+
+        let request = heartbeat_request(&event);
+        assert_eq!("nomad-clients", request.auto_scaling_group_name);
+        assert_eq!(Some("i-1234567890abcdef0"), request.instance_id.as_deref());
+        assert_eq!(
+            Some("87654321-4321-4321-4321-210987654321"),
+            request.lifecycle_action_token.as_deref()
+        );
+        assert_eq!("drain", request.lifecycle_hook_name);
+    }
+}
@@ -1,5 +1,6 @@
 use std::borrow::{Borrow, Cow};
 use std::collections::HashMap;
+use std::time::Duration;
 
 use futures::future::Future;
 use log::{debug, info};
@@ -8,12 +9,21 @@ use rusoto_core::param::{Params, ServiceParams};
 use rusoto_core::signature::{SignedRequest, SignedRequestPayload};
 use rusoto_core::Region;
 use rusoto_core::{DefaultCredentialsProvider, ProvideAwsCredentials};
+use rusoto_sts::{Sts, StsClient};
 use serde::{Deserialize, Serialize};
 
+pub mod imds;
+
 // Reference:
 // https://github.com/hashicorp/vault/blob/d12547c7faa9c216d1411827bc16606535cb3e61/builtin/credential/aws/path_login.go#L1640
 const IAM_SERVER_ID_HEADER: &str = "X-Vault-AWS-IAM-Server-ID";
 
+/// Set to `regional` to use regional STS endpoints, like the AWS SDKs
+const STS_REGIONAL_ENDPOINTS_ENV: &str = "AWS_STS_REGIONAL_ENDPOINTS";
+
+/// Time to wait for the instance metadata service when inferring the region, off EC2
+const INFER_REGION_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// Returns AWS credentials according to the behaviour documented
 /// [here](https://rusoto.github.io/rusoto/rusoto_credential/struct.ChainProvider.html).
 pub fn credentials() -> Result<AwsCredentials, crate::Error> {
@@ -21,6 +31,85 @@ pub fn credentials() -> Result<AwsCredentials, crate::Error> {
     Ok(provider.credentials().wait()?)
 }
 
+/// Returns the ARN of the IAM principal of the default credentials
+///
+/// Calls STS `GetCallerIdentity`, which only succeeds if AWS accepts requests signed with the
+/// credentials. This is the same call Vault makes to verify AWS IAM logins.
+pub fn caller_identity(region: Region) -> Result<String, crate::Error> {
+    let client = StsClient::new(region);
+    let response = client.get_caller_identity(Default::default()).sync()?;
+    Ok(response.arn.unwrap_or_default())
+}
+
+/// Infer the region from `AWS_REGION` or `AWS_DEFAULT_REGION`, or from the instance metadata on EC2
+///
+/// Regions are returned by name, since regions newer than the SDK cannot be parsed into a
+/// `Region`.
+pub fn infer_region() -> Option<String> {
+    let region = std::env::var("AWS_REGION")
+        .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+        .ok()
+        .filter(|region| !region.is_empty());
+    if region.is_some() {
+        return region;
+    }
+    let client = reqwest::Client::builder()
+        .timeout(INFER_REGION_TIMEOUT)
+        .build()
+        .ok()?;
+    match imds::get_instance_identity_document(Some(client)) {
+        Ok(document) => Some(document.region),
+        Err(e) => {
+            debug!("Unable to infer the region from instance metadata: {}", e);
+            None
+        }
+    }
+}
+
+/// Partition of a region, e.g. `aws-cn` for the China regions
+pub fn partition(region_name: &str) -> &'static str {
+    if region_name.starts_with("cn-") {
+        "aws-cn"
+    } else if region_name.starts_with("us-gov-") {
+        "aws-us-gov"
+    } else if region_name.starts_with("us-isob-") {
+        "aws-iso-b"
+    } else if region_name.starts_with("us-iso-") {
+        "aws-iso"
+    } else {
+        "aws"
+    }
+}
+
+/// Region of the STS endpoint of a region, in the partition of the region
+pub fn sts_region(region_name: &str) -> Region {
+    let dns_suffix = match partition(region_name) {
+        "aws-cn" => "amazonaws.com.cn",
+        "aws-iso" => "c2s.ic.gov",
+        "aws-iso-b" => "sc2s.sgov.gov",
+        _ => "amazonaws.com",
+    };
+    Region::Custom {
+        name: region_name.to_string(),
+        endpoint: format!("sts.{}.{}", region_name, dns_suffix),
+    }
+}
+
+/// Region of the STS endpoint to sign requests for when none is provided
+///
+/// The global endpoint is used in the `aws` partition, which Vault verifies logins against unless
+/// configured with another `sts_endpoint`, unless `regional` endpoints are requested. The other
+/// partitions have no global endpoint.
+fn default_sts_region(region_name: Option<&str>, regional: bool) -> Region {
+    match region_name {
+        Some(region_name) if regional || partition(region_name) != "aws" => sts_region(region_name),
+        _ => Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: "sts.amazonaws.com".to_string(),
+        },
+    }
+}
+
 /// Payload for use when authenticating with Vault AWS Authentication using the IAM method
 ///
 /// See [Vault's Documentation](https://www.vaultproject.io/docs/auth/aws.html#iam-auth-method)
@@ -40,7 +129,10 @@ pub struct VaultAwsAuthIamPayload {
 impl VaultAwsAuthIamPayload {
     /// Create a payload for use with Vault AWS Authentication using the IAM method
     ///
-    /// If you do not provide a `region`, we will use a the "global" AWS STS endpoint.
+    /// If you do not provide a `region`, it is inferred with `infer_region`. Outside of the `aws`
+    /// partition, the STS endpoint of the region is used. Otherwise, the "global" AWS STS endpoint
+    /// is used unless `AWS_STS_REGIONAL_ENDPOINTS` is set to `regional`, in which case Vault has
+    /// to be configured with the matching `sts_endpoint` and `sts_region`.
     ///
     /// If the Vault AWS Authentication method has the
     /// [`iam_server_id_header_value`](https://www.vaultproject.io/api/auth/aws/index.html#iam_server_id_header_value)
@@ -60,11 +152,12 @@ impl VaultAwsAuthIamPayload {
             .as_ref()
             .map(|r| Cow::Borrowed(r.borrow()))
             .unwrap_or_else(|| {
-                debug!("No region provided: using \"global\" us-east-1 endpoint.");
-                Cow::Owned(Region::Custom {
-                    name: "us-east-1".to_string(),
-                    endpoint: "sts.amazonaws.com".to_string(),
-                })
+                let regional = std::env::var(STS_REGIONAL_ENDPOINTS_ENV)
+                    .map(|value| value.eq_ignore_ascii_case("regional"))
+                    .unwrap_or(false);
+                let region = default_sts_region(infer_region().as_deref(), regional);
+                debug!("No region provided: using STS endpoint {:?}", region);
+                Cow::Owned(region)
             });
 
         // Code below is referenced from the code for
@@ -130,6 +223,209 @@ impl VaultAwsAuthIamPayload {
     }
 }
 
+/// Upload an object to S3 with a request signed by the provided credentials
+///
+/// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
+/// CA certificate.
+pub fn put_s3_object(
+    credentials: &AwsCredentials,
+    region: &Region,
+    bucket: &str,
+    key: &str,
+    body: Vec<u8>,
+    content_type: &str,
+    client: Option<reqwest::Client>,
+) -> Result<(), crate::Error> {
+    info!("Uploading s3://{}/{}", bucket, key);
+    let client = match client {
+        Some(client) => client,
+        None => reqwest::Client::new(),
+    };
+    let request = build_put_s3_object_request(
+        &client,
+        credentials,
+        region,
+        bucket,
+        key,
+        body,
+        content_type,
+    )?;
+    debug!("Executing request: {:#?}", request);
+    let mut response = client.execute(request)?;
+    debug!("Response received: {:#?}", response);
+    if !response.status().is_success() {
+        Err(crate::Error::S3UploadFailed {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            status: response.status().as_u16(),
+            body: response.text()?,
+        })?;
+    }
+    Ok(())
+}
+
+/// Download an object from S3 with a request signed by the provided credentials
+///
+/// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
+/// CA certificate.
+pub fn get_s3_object(
+    credentials: &AwsCredentials,
+    region: &Region,
+    bucket: &str,
+    key: &str,
+    client: Option<reqwest::Client>,
+) -> Result<Vec<u8>, crate::Error> {
+    info!("Downloading s3://{}/{}", bucket, key);
+    let client = match client {
+        Some(client) => client,
+        None => reqwest::Client::new(),
+    };
+    let request = build_get_s3_object_request(&client, credentials, region, bucket, key)?;
+    debug!("Executing request: {:#?}", request);
+    let mut response = client.execute(request)?;
+    debug!("Response received: {:#?}", response);
+    if !response.status().is_success() {
+        Err(crate::Error::S3DownloadFailed {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            status: response.status().as_u16(),
+            body: response.text()?,
+        })?;
+    }
+    let mut body = vec![];
+    response.copy_to(&mut body)?;
+    Ok(body)
+}
+
+fn build_put_s3_object_request(
+    client: &reqwest::Client,
+    credentials: &AwsCredentials,
+    region: &Region,
+    bucket: &str,
+    key: &str,
+    body: Vec<u8>,
+    content_type: &str,
+) -> Result<reqwest::Request, crate::Error> {
+    let mut request = SignedRequest::new("PUT", "s3", region, &format!("/{}/{}", bucket, key));
+    request.set_payload(Some(body.clone()));
+    request.set_content_type(content_type.to_string());
+    request.sign_with_plus(credentials, true);
+
+    into_reqwest_request(client, reqwest::Method::PUT, &request, body)
+}
+
+/// Convert a signed request into a `reqwest::Request` with the provided body
+fn build_get_s3_object_request(
+    client: &reqwest::Client,
+    credentials: &AwsCredentials,
+    region: &Region,
+    bucket: &str,
+    key: &str,
+) -> Result<reqwest::Request, crate::Error> {
+    let mut request = SignedRequest::new("GET", "s3", region, &format!("/{}/{}", bucket, key));
+    request.sign_with_plus(credentials, true);
+
+    into_reqwest_request(client, reqwest::Method::GET, &request, vec![])
+}
+
+/// Publish a message to an SNS topic with a request signed by the provided credentials
+///
+/// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
+/// CA certificate.
+pub fn publish_sns_message(
+    credentials: &AwsCredentials,
+    region: &Region,
+    topic_arn: &str,
+    subject: Option<&str>,
+    message: &str,
+    client: Option<reqwest::Client>,
+) -> Result<(), crate::Error> {
+    info!("Publishing message to SNS topic {}", topic_arn);
+    let client = match client {
+        Some(client) => client,
+        None => reqwest::Client::new(),
+    };
+    let request = build_publish_sns_message_request(
+        &client,
+        credentials,
+        region,
+        topic_arn,
+        subject,
+        message,
+    )?;
+    debug!("Executing request: {:#?}", request);
+    let mut response = client.execute(request)?;
+    debug!("Response received: {:#?}", response);
+    if !response.status().is_success() {
+        Err(crate::Error::SnsPublishFailed {
+            topic_arn: topic_arn.to_string(),
+            status: response.status().as_u16(),
+            body: response.text()?,
+        })?;
+    }
+    Ok(())
+}
+
+/// Region of the resource identified by an ARN, if the ARN has a valid one
+///
+/// SNS topics, for example, have to be published to in their region.
+pub fn arn_region(arn: &str) -> Option<Region> {
+    arn.split(':').nth(3).and_then(|region| region.parse().ok())
+}
+
+fn build_publish_sns_message_request(
+    client: &reqwest::Client,
+    credentials: &AwsCredentials,
+    region: &Region,
+    topic_arn: &str,
+    subject: Option<&str>,
+    message: &str,
+) -> Result<reqwest::Request, crate::Error> {
+    let mut params = Params::new();
+    params.put("Action", "Publish");
+    params.put("Version", "2010-03-31");
+    params.put("TopicArn", topic_arn);
+    params.put("Message", message);
+    if let Some(subject) = subject {
+        params.put("Subject", subject);
+    }
+    let body = serde_urlencoded::to_string(&params)
+        .expect("parameters to be URL encoded")
+        .into_bytes();
+
+    let mut request = SignedRequest::new("POST", "sns", region, "/");
+    request.set_content_type("application/x-www-form-urlencoded".to_string());
+    request.set_payload(Some(body.clone()));
+    request.sign_with_plus(credentials, true);
+
+    into_reqwest_request(client, reqwest::Method::POST, &request, body)
+}
+
+pub(crate) fn into_reqwest_request(
+    client: &reqwest::Client,
+    method: reqwest::Method,
+    request: &SignedRequest,
+    body: Vec<u8>,
+) -> Result<reqwest::Request, crate::Error> {
+    let url = format!(
+        "{}://{}{}",
+        request.scheme(),
+        request.hostname(),
+        request.canonical_path()
+    );
+    let mut builder = client.request(method, &url).body(body);
+    for (name, values) in request.headers.iter() {
+        // Set by reqwest to the same values
+        if name == "host" || name == "content-length" {
+            continue;
+        }
+        for value in values {
+            builder = builder.header(name.as_str(), value.as_slice());
+        }
+    }
+    Ok(builder.build()?)
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -172,6 +468,123 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    #[test]
+    fn put_s3_object_request_is_signed() -> Result<(), crate::Error> {
+        let client = reqwest::Client::new();
+        let request = build_put_s3_object_request(
+            &client,
+            &credentials()?,
+            &Region::ApSoutheast1,
+            "audit",
+            "drains/i-1234567890abcdef0.json",
+            b"{}".to_vec(),
+            "application/json",
+        )?;
+
+        assert_eq!(&reqwest::Method::PUT, request.method());
+        assert_eq!(
+            "https://s3-ap-southeast-1.amazonaws.com/audit/drains/i-1234567890abcdef0.json",
+            request.url().to_string()
+        );
+        assert!(request.headers().contains_key("authorization"));
+        assert!(request.headers().contains_key("x-amz-content-sha256"));
+        assert_eq!(
+            Some("application/json"),
+            request
+                .headers()
+                .get("content-type")
+                .and_then(|value| value.to_str().ok())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn get_s3_object_request_is_signed() -> Result<(), crate::Error> {
+        let client = reqwest::Client::new();
+        let request = build_get_s3_object_request(
+            &client,
+            &credentials()?,
+            &Region::ApSoutheast1,
+            "checkpoints",
+            "drains/i-1234567890abcdef0.json.gz",
+        )?;
+
+        assert_eq!(&reqwest::Method::GET, request.method());
+        assert_eq!(
+            "https://s3-ap-southeast-1.amazonaws.com/checkpoints/drains/i-1234567890abcdef0.json.gz",
+            request.url().to_string()
+        );
+        assert!(request.headers().contains_key("authorization"));
+        Ok(())
+    }
+
+    #[test]
+    fn default_sts_regions_respect_partitions() {
+        let endpoint = |region: Region| match region {
+            Region::Custom { name, endpoint } => (name, endpoint),
+            other => panic!("Unexpected region {:?}", other),
+        };
+        let global = ("us-east-1".to_string(), "sts.amazonaws.com".to_string());
+
+        assert_eq!(global, endpoint(default_sts_region(None, true)));
+        assert_eq!(
+            global,
+            endpoint(default_sts_region(Some("eu-west-1"), false))
+        );
+        assert_eq!(
+            (
+                "eu-west-1".to_string(),
+                "sts.eu-west-1.amazonaws.com".to_string()
+            ),
+            endpoint(default_sts_region(Some("eu-west-1"), true))
+        );
+        assert_eq!(
+            (
+                "cn-northwest-1".to_string(),
+                "sts.cn-northwest-1.amazonaws.com.cn".to_string()
+            ),
+            endpoint(default_sts_region(Some("cn-northwest-1"), false))
+        );
+        assert_eq!(
+            (
+                "us-gov-east-1".to_string(),
+                "sts.us-gov-east-1.amazonaws.com".to_string()
+            ),
+            endpoint(default_sts_region(Some("us-gov-east-1"), false))
+        );
+        assert_eq!("aws-iso-b", partition("us-isob-east-1"));
+    }
+
+    #[test]
+    fn publish_sns_message_request_is_signed() -> Result<(), crate::Error> {
+        let topic_arn = "arn:aws:sns:ap-southeast-1:123456789012:drains";
+        let request = build_publish_sns_message_request(
+            &reqwest::Client::new(),
+            &credentials()?,
+            &Region::ApSoutheast1,
+            topic_arn,
+            Some("Drained"),
+            "{}",
+        )?;
+
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!(
+            "https://sns.ap-southeast-1.amazonaws.com/",
+            request.url().to_string()
+        );
+        assert!(request.headers().contains_key("authorization"));
+        assert_eq!(
+            Some("application/x-www-form-urlencoded"),
+            request
+                .headers()
+                .get("content-type")
+                .and_then(|value| value.to_str().ok())
+        );
+        assert_eq!(Some(Region::ApSoutheast1), arn_region(topic_arn));
+        assert_eq!(None, arn_region("drains"));
+        Ok(())
+    }
+
     #[test]
     fn vault_aws_iam_payload_has_default_global_region() -> Result<(), crate::Error> {
         let payload = vault_aws_iam_payload(Some("vault.example.com"), None)?;
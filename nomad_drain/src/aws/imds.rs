@@ -0,0 +1,149 @@
+//! EC2 instance metadata
+//!
+//! Requests use [IMDSv2](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/configuring-instance-metadata-service.html)
+//! session tokens, falling back to IMDSv1 when no token can be retrieved.
+use std::str::FromStr;
+
+use log::{debug, info};
+use rusoto_core::Region;
+use serde::{Deserialize, Serialize};
+
+/// Base URL of the EC2 instance metadata service
+const INSTANCE_METADATA_URL: &str = "http://169.254.169.254/latest";
+
+/// Lifetime of the session tokens requested
+const TOKEN_TTL_SECONDS: &str = "60";
+
+/// Identity document of an EC2 instance
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceIdentityDocument {
+    pub account_id: String,
+    pub architecture: String,
+    pub availability_zone: String,
+    pub image_id: String,
+    pub instance_id: String,
+    pub instance_type: String,
+    #[serde(default)]
+    pub private_ip: Option<String>,
+    pub region: String,
+}
+
+/// Request an IMDSv2 session token
+///
+/// You can optionally provide a `reqwest::Client` if you have specific needs like a proxy
+pub fn get_token(client: Option<reqwest::Client>) -> Result<crate::Secret, crate::Error> {
+    let client = client.unwrap_or_else(reqwest::Client::new);
+    let token = client
+        .put(&format!("{}/api/token", INSTANCE_METADATA_URL))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", TOKEN_TTL_SECONDS)
+        .send()?
+        .error_for_status()?
+        .text()?;
+    Ok(crate::Secret(token))
+}
+
+/// Returns the identity document of the EC2 instance this is running on
+///
+/// See `get_token` for the `client` parameter.
+pub fn get_instance_identity_document(
+    client: Option<reqwest::Client>,
+) -> Result<InstanceIdentityDocument, crate::Error> {
+    info!("Retrieving EC2 instance identity document");
+    let document = get(client, "dynamic/instance-identity/document")?;
+    Ok(crate::from_json_str(&document)?)
+}
+
+/// Returns the PKCS #7 signature of the identity document of the EC2 instance this is running on
+///
+/// Newlines are stripped, as expected by the Vault AWS EC2 authentication method.
+///
+/// See `get_token` for the `client` parameter.
+pub fn get_instance_identity_pkcs7(
+    client: Option<reqwest::Client>,
+) -> Result<String, crate::Error> {
+    info!("Retrieving EC2 instance identity document signature");
+    let pkcs7 = get(client, "dynamic/instance-identity/pkcs7")?;
+    Ok(strip_newlines(&pkcs7))
+}
+
+/// Returns the region of the EC2 instance this is running on
+///
+/// See `get_token` for the `client` parameter.
+pub fn get_region(client: Option<reqwest::Client>) -> Result<Region, crate::Error> {
+    let document = get_instance_identity_document(client)?;
+    Ok(Region::from_str(&document.region)?)
+}
+
+/// Retrieve the metadata at `path`, with a session token if one can be retrieved
+fn get(client: Option<reqwest::Client>, path: &str) -> Result<String, crate::Error> {
+    let client = client.unwrap_or_else(reqwest::Client::new);
+    let token = match get_token(Some(client.clone())) {
+        Ok(token) => Some(token),
+        Err(e) => {
+            debug!(
+                "Unable to retrieve IMDSv2 session token, falling back to IMDSv1: {}",
+                e
+            );
+            None
+        }
+    };
+    let request = build_get_request(&client, path, token.as_ref())?;
+    Ok(client.execute(request)?.error_for_status()?.text()?)
+}
+
+fn build_get_request(
+    client: &reqwest::Client,
+    path: &str,
+    token: Option<&crate::Secret>,
+) -> Result<reqwest::Request, crate::Error> {
+    let request = client.get(&format!("{}/{}", INSTANCE_METADATA_URL, path));
+    let request = match token {
+        Some(token) => request.header("X-aws-ec2-metadata-token", token.expose_secret()),
+        None => request,
+    };
+    Ok(request.build()?)
+}
+
+fn strip_newlines(s: &str) -> String {
+    s.chars().filter(|c| *c != '\n' && *c != '\r').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pkcs7_newlines_are_stripped() {
+        assert_eq!(
+            "MIAGCSqGSIb3DQEHAqCAMIACAQEx",
+            strip_newlines("MIAGCSqG\nSIb3DQEH\r\nAqCAMIACAQEx\n")
+        );
+    }
+
+    #[test]
+    fn session_token_is_sent_when_available() -> Result<(), crate::Error> {
+        let client = reqwest::Client::new();
+        let token = crate::Secret("token".to_string());
+        let request = build_get_request(&client, "dynamic/instance-identity/pkcs7", Some(&token))?;
+        assert_eq!(
+            "http://169.254.169.254/latest/dynamic/instance-identity/pkcs7",
+            request.url().to_string()
+        );
+        assert_eq!("token", request.headers()["X-aws-ec2-metadata-token"]);
+
+        let request = build_get_request(&client, "dynamic/instance-identity/pkcs7", None)?;
+        assert!(!request.headers().contains_key("X-aws-ec2-metadata-token"));
+        Ok(())
+    }
+
+    #[test]
+    fn instance_identity_document_is_deserialized() -> Result<(), crate::Error> {
+        let document: InstanceIdentityDocument = serde_json::from_str(include_str!(
+            "../../fixtures/instance_identity_document.json"
+        ))?;
+        assert_eq!("i-1234567890abcdef0", document.instance_id);
+        assert_eq!(Region::UsWest2, Region::from_str(&document.region)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! Treatment of batch allocations during drains
+//!
+//! Nomad does not migrate allocations of batch jobs off draining nodes. It leaves them running
+//! until they complete or the drain deadline stops them, which surprises many operators during
+//! scale-in. A `BatchPolicy` picks what happens to them instead, with rules matching the type and
+//! ID of their jobs:
+//!
+//! ```rust
+//! use nomad_drain::batch::{BatchAction, BatchPolicy};
+//!
+//! let policy: BatchPolicy = serde_json::from_str(
+//!     r#"[
+//!         { "job": "reports-*", "action": "wait" },
+//!         { "job_type": "*", "job": "cache", "action": "migrate" }
+//!     ]"#,
+//! )
+//! .unwrap();
+//! assert!(!policy.is_empty());
+//! ```
+use serde::{Deserialize, Serialize};
+
+use crate::nomad::{Allocation, Deadline};
+
+/// What to do with allocations that a drain would otherwise leave to its deadline
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchAction {
+    /// Wait for the allocations to complete, however long they take. The drain is submitted
+    /// without a deadline.
+    Wait,
+    /// Stop the allocations as soon as the drain is submitted, so that they are rescheduled
+    /// elsewhere
+    Migrate,
+    /// Leave the allocations running until the drain deadline stops them, like Nomad does
+    #[default]
+    Deadline,
+}
+
+/// A rule of a `BatchPolicy`
+///
+/// Patterns match the whole value, with `*` matching any number of characters.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct BatchRule {
+    /// Pattern of the type of jobs the rule applies to
+    #[serde(default = "BatchRule::default_job_type")]
+    pub job_type: String,
+    /// Pattern of the IDs of jobs the rule applies to
+    #[serde(default = "BatchRule::default_job")]
+    pub job: String,
+    /// What to do with allocations of matching jobs
+    pub action: BatchAction,
+}
+
+impl BatchRule {
+    fn default_job_type() -> String {
+        "batch".to_string()
+    }
+
+    fn default_job() -> String {
+        "*".to_string()
+    }
+
+    /// Whether the rule applies to the allocation
+    pub fn matches(&self, allocation: &Allocation) -> bool {
+        let job_type = allocation
+            .job
+            .as_ref()
+            .map(|job| job.job_type.as_str())
+            .unwrap_or_default();
+        matches_pattern(&self.job_type, job_type) && matches_pattern(&self.job, &allocation.job_id)
+    }
+}
+
+/// Rules deciding what happens to allocations when their node is drained
+///
+/// The first rule matching an allocation applies. Allocations that no rule matches are left to
+/// the drain deadline.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct BatchPolicy(pub Vec<BatchRule>);
+
+impl BatchPolicy {
+    /// Whether the policy has no rules, and leaves every allocation to the drain deadline
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// What to do with the allocation
+    pub fn action(&self, allocation: &Allocation) -> BatchAction {
+        self.0
+            .iter()
+            .find(|rule| rule.matches(allocation))
+            .map(|rule| rule.action)
+            .unwrap_or_default()
+    }
+
+    /// Deadline to drain a node running `allocations` with
+    ///
+    /// Drains of nodes running allocations that are waited for have no deadline. Every other
+    /// allocation on the node is then also left to migrate for as long as it takes.
+    pub fn deadline(&self, deadline: Deadline, allocations: &[Allocation]) -> Deadline {
+        let wait = allocations
+            .iter()
+            .filter(|allocation| !allocation.is_terminal())
+            .any(|allocation| self.action(allocation) == BatchAction::Wait);
+        if wait {
+            Deadline::NoDeadline
+        } else {
+            deadline
+        }
+    }
+
+    /// Allocations that should be stopped as soon as the drain is submitted
+    pub fn to_migrate<'a>(
+        &'a self,
+        allocations: &'a [Allocation],
+    ) -> impl Iterator<Item = &'a Allocation> + 'a {
+        allocations.iter().filter(move |allocation| {
+            !allocation.is_terminal() && self.action(allocation) == BatchAction::Migrate
+        })
+    }
+}
+
+/// Whether `value` matches the whole `pattern`, where `*` matches any number of characters
+fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match value.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        // No wildcard
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::AllocationFixture;
+
+    #[test]
+    fn patterns_match_whole_values() {
+        assert!(matches_pattern("batch", "batch"));
+        assert!(!matches_pattern("batch", "sysbatch"));
+        assert!(matches_pattern("*batch", "sysbatch"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("reports-*", "reports-daily"));
+        assert!(!matches_pattern("reports-*", "daily-reports"));
+        assert!(matches_pattern("a*b*c", "a-b-c"));
+        assert!(!matches_pattern("a*b*c", "a-c-b"));
+        assert!(!matches_pattern("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn first_matching_rule_applies() -> Result<(), crate::Error> {
+        let policy: BatchPolicy = serde_json::from_str(
+            r#"[
+                { "job": "reports-*", "action": "wait" },
+                { "action": "migrate" }
+            ]"#,
+        )?;
+        let reports = AllocationFixture::running()
+            .with_job_id("reports-daily")
+            .with_job_type("batch")
+            .allocation();
+        let backfill = AllocationFixture::running()
+            .with_job_id("backfill")
+            .with_job_type("batch")
+            .allocation();
+        let service = AllocationFixture::running()
+            .with_job_id("reports-api")
+            .with_job_type("service")
+            .allocation();
+
+        assert_eq!(BatchAction::Wait, policy.action(&reports));
+        assert_eq!(BatchAction::Migrate, policy.action(&backfill));
+        assert_eq!(BatchAction::Deadline, policy.action(&service));
+
+        let allocations = vec![backfill, service];
+        assert_eq!(
+            vec!["backfill"],
+            policy
+                .to_migrate(&allocations)
+                .map(|allocation| allocation.job_id.as_str())
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn drains_have_no_deadline_while_allocations_are_waited_for() -> Result<(), crate::Error> {
+        let policy: BatchPolicy = serde_json::from_str(r#"[{ "action": "wait" }]"#)?;
+        let batch = AllocationFixture::running().with_job_type("batch");
+        let deadline = Deadline::Duration(std::time::Duration::from_secs(60));
+
+        assert_eq!(
+            Deadline::NoDeadline,
+            policy.deadline(deadline, &[batch.allocation()])
+        );
+        assert_eq!(
+            deadline,
+            policy.deadline(
+                deadline,
+                &[batch
+                    .with_client_status(crate::nomad::AllocationClientStatus::Complete)
+                    .allocation()]
+            )
+        );
+        assert_eq!(
+            deadline,
+            BatchPolicy::default().deadline(
+                deadline,
+                &[AllocationFixture::running()
+                    .with_job_type("batch")
+                    .allocation()]
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+//! Compact checkpoints of state passed between invocations
+//!
+//! AWS Step Functions limits the state passed between states to 256 KB, which large allocation
+//! lists can exceed. Checkpoints are passed as JSON when they fit, gzipped when they do not, and
+//! offloaded to S3 with a pointer left in the state when even that is too large.
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::info;
+use rusoto_core::credential::AwsCredentials;
+use rusoto_core::Region;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Maximum size of the state passed between AWS Step Functions states
+pub const STEP_FUNCTIONS_STATE_LIMIT: usize = 256 * 1024;
+
+/// A checkpoint of some state
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "encoding", rename_all = "snake_case")]
+pub enum Checkpoint {
+    /// The state as JSON
+    Json { data: serde_json::Value },
+    /// The state as gzipped JSON, base64 encoded
+    Gzip { data: String },
+    /// The state as gzipped JSON, stored in S3
+    S3 { bucket: String, key: String },
+}
+
+/// S3 location to offload checkpoints that are too large to pass inline to
+#[derive(Clone, Debug)]
+pub struct S3Offload {
+    pub credentials: AwsCredentials,
+    pub region: Region,
+    pub bucket: String,
+    pub key: String,
+}
+
+impl Checkpoint {
+    /// Checkpoint the state, compressing or offloading it so that the serialized checkpoint does
+    /// not exceed `limit` bytes
+    ///
+    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
+    /// CA certificate.
+    pub fn pack<T>(
+        state: &T,
+        limit: usize,
+        offload: Option<&S3Offload>,
+        client: Option<reqwest::Client>,
+    ) -> Result<Self, crate::Error>
+    where
+        T: Serialize,
+    {
+        let data = serde_json::to_value(state)?;
+        let json = serde_json::to_vec(&data)?;
+        let checkpoint = Checkpoint::Json { data };
+        if serde_json::to_vec(&checkpoint)?.len() <= limit {
+            return Ok(checkpoint);
+        }
+
+        let compressed = compress(&json)?;
+        let checkpoint = Checkpoint::Gzip {
+            data: base64::encode(&compressed),
+        };
+        let compressed_size = serde_json::to_vec(&checkpoint)?.len();
+        if compressed_size <= limit {
+            return Ok(checkpoint);
+        }
+
+        match offload {
+            Some(offload) => {
+                info!(
+                    "Offloading checkpoint of {} bytes to s3://{}/{}",
+                    compressed.len(),
+                    offload.bucket,
+                    offload.key
+                );
+                crate::aws::put_s3_object(
+                    &offload.credentials,
+                    &offload.region,
+                    &offload.bucket,
+                    &offload.key,
+                    compressed,
+                    "application/gzip",
+                    client,
+                )?;
+                Ok(Checkpoint::S3 {
+                    bucket: offload.bucket.clone(),
+                    key: offload.key.clone(),
+                })
+            }
+            None => Err(crate::Error::CheckpointTooLarge {
+                size: compressed_size,
+                limit,
+            }),
+        }
+    }
+
+    /// Restore the state from the checkpoint
+    ///
+    /// Credentials and the region of the bucket are only required for checkpoints offloaded to
+    /// S3. See `pack` for the `client` parameter.
+    pub fn unpack<T>(
+        &self,
+        s3: Option<(&AwsCredentials, &Region)>,
+        client: Option<reqwest::Client>,
+    ) -> Result<T, crate::Error>
+    where
+        T: DeserializeOwned,
+    {
+        let json = match self {
+            Checkpoint::Json { data } => return Ok(T::deserialize(data)?),
+            Checkpoint::Gzip { data } => decompress(&base64::decode(data)?)?,
+            Checkpoint::S3 { bucket, key } => match s3 {
+                Some((credentials, region)) => decompress(&crate::aws::get_s3_object(
+                    credentials,
+                    region,
+                    bucket,
+                    key,
+                    client,
+                )?)?,
+                None => Err(crate::Error::CheckpointInS3 {
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                })?,
+            },
+        };
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>, crate::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, crate::Error> {
+    let mut decoder = GzDecoder::new(data);
+    let mut decompressed = vec![];
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocation_ids(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| format!("{:08x}-8786-fdf6-4497-98445c891fb7", i))
+            .collect()
+    }
+
+    #[test]
+    fn small_checkpoints_are_passed_as_json() -> Result<(), crate::Error> {
+        let state = allocation_ids(2);
+        let checkpoint = Checkpoint::pack(&state, STEP_FUNCTIONS_STATE_LIMIT, None, None)?;
+
+        assert!(matches!(checkpoint, Checkpoint::Json { .. }));
+        assert_eq!(state, checkpoint.unpack::<Vec<String>>(None, None)?);
+        Ok(())
+    }
+
+    #[test]
+    fn large_checkpoints_are_compressed() -> Result<(), crate::Error> {
+        let state = allocation_ids(1000);
+        let limit = serde_json::to_vec(&state)?.len() / 2;
+        let checkpoint = Checkpoint::pack(&state, limit, None, None)?;
+
+        assert!(matches!(checkpoint, Checkpoint::Gzip { .. }));
+        assert!(serde_json::to_vec(&checkpoint)?.len() <= limit);
+
+        let json = serde_json::to_string(&checkpoint)?;
+        let deserialized: Checkpoint = serde_json::from_str(&json)?;
+        assert_eq!(state, deserialized.unpack::<Vec<String>>(None, None)?);
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoints_too_large_without_offload_are_rejected() {
+        match Checkpoint::pack(&allocation_ids(1000), 64, None, None) {
+            Err(crate::Error::CheckpointTooLarge { limit: 64, .. }) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn offloaded_checkpoints_require_credentials() {
+        let checkpoint = Checkpoint::S3 {
+            bucket: "checkpoints".to_string(),
+            key: "drains/i-1234567890abcdef0.json.gz".to_string(),
+        };
+        match checkpoint.unpack::<Vec<String>>(None, None) {
+            Err(crate::Error::CheckpointInS3 { .. }) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
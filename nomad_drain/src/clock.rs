@@ -0,0 +1,96 @@
+//! Abstraction over time so that waits can be simulated
+//!
+//! Clients use the `SystemClock` by default. Tests can use a `SimulatedClock` instead, where
+//! sleeping advances time instantly, to exercise long retries and waits deterministically.
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Source of time and sleeps
+pub trait Clock: Debug + Send + Sync {
+    /// The current instant
+    fn now(&self) -> Instant;
+
+    /// Block for the provided duration
+    fn sleep(&self, duration: Duration);
+
+    /// Time elapsed since an earlier instant
+    fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().duration_since(earlier)
+    }
+}
+
+/// The system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// A clock that only advances when slept on or advanced explicitly
+#[derive(Debug)]
+pub struct SimulatedClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl Default for SimulatedClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+}
+
+impl SimulatedClock {
+    /// Create a clock starting at the current instant
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Advance the clock without sleeping
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().expect("clock to not be poisoned") += duration;
+    }
+
+    /// Total time simulated so far
+    pub fn simulated(&self) -> Duration {
+        *self.elapsed.lock().expect("clock to not be poisoned")
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> Instant {
+        self.start + self.simulated()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated_clock_advances_on_sleep() {
+        let clock = SimulatedClock::new();
+        let start = clock.now();
+
+        clock.sleep(Duration::from_secs(600));
+        clock.advance(Duration::from_secs(5));
+
+        assert_eq!(Duration::from_secs(605), clock.elapsed(start));
+        assert_eq!(Duration::from_secs(605), clock.simulated());
+    }
+}
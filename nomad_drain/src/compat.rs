@@ -0,0 +1,91 @@
+//! Versions of Nomad and Vault supported by this crate
+//!
+//! Check servers against the matrix before rolling out, so that an upgrade of either does not go
+//! unnoticed until the first drain fails.
+use std::fmt;
+
+use crate::nomad::NomadVersion;
+
+/// Nomad versions, from the introduction of the node drain API in 0.8
+pub const NOMAD: SupportedVersions = SupportedVersions {
+    minimum: NomadVersion::new(0, 8, 0),
+    latest_tested: NomadVersion::new(1, 6, 0),
+};
+
+/// Vault versions, from the introduction of the `sys/internal/ui/mounts` endpoint used to look up
+/// the Nomad secrets engine
+pub const VAULT: SupportedVersions = SupportedVersions {
+    minimum: NomadVersion::new(1, 1, 0),
+    latest_tested: NomadVersion::new(1, 15, 0),
+};
+
+/// Range of versions of a server supported by this crate
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SupportedVersions {
+    /// Oldest supported version
+    pub minimum: NomadVersion,
+    /// Latest minor version tested against. Patch releases of it are tested as well.
+    pub latest_tested: NomadVersion,
+}
+
+/// Whether a version of a server is supported
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compatibility {
+    /// Within the tested range
+    Supported,
+    /// Newer than the latest tested minor version. Expected to work, but not tested.
+    Untested,
+    /// Older than the minimum version
+    Unsupported,
+}
+
+impl SupportedVersions {
+    /// Check a version against the range
+    pub fn check(&self, version: &NomadVersion) -> Compatibility {
+        if *version < self.minimum {
+            Compatibility::Unsupported
+        } else if (version.major, version.minor)
+            > (self.latest_tested.major, self.latest_tested.minor)
+        {
+            Compatibility::Untested
+        } else {
+            Compatibility::Supported
+        }
+    }
+}
+
+impl fmt::Display for SupportedVersions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} to {}.{}.x",
+            self.minimum, self.latest_tested.major, self.latest_tested.minor
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_checked_against_the_matrix() {
+        assert_eq!(
+            Compatibility::Unsupported,
+            NOMAD.check(&NomadVersion::new(0, 7, 1))
+        );
+        assert_eq!(
+            Compatibility::Supported,
+            NOMAD.check(&NomadVersion::new(0, 8, 0))
+        );
+        assert_eq!(
+            Compatibility::Supported,
+            NOMAD.check(&NomadVersion::new(1, 6, 5))
+        );
+        assert_eq!(
+            Compatibility::Untested,
+            NOMAD.check(&NomadVersion::new(1, 7, 0))
+        );
+        assert_eq!("0.8.0 to 1.6.x", NOMAD.to_string());
+    }
+}
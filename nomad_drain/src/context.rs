@@ -0,0 +1,141 @@
+//! Context carried by clients into every request they make
+//!
+//! A `RequestContext` holds the concerns that apply to a whole operation rather than a single
+//! call: when it has to be done by, how its requests are correlated in logs, and whether it is
+//! allowed to change anything. Clients are given a context once with `with_context`, and every
+//! call they make honours it.
+use std::time::{Duration, Instant};
+
+use log::info;
+use reqwest::header::HeaderValue;
+
+use crate::clock::Clock;
+
+/// Header the trace ID is sent in, for correlation in proxy and server logs
+pub const TRACE_ID_HEADER: &str = "X-Correlation-ID";
+
+/// Context of the requests made by a client
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RequestContext {
+    /// Requests fail with `Error::DeadlineExceeded` once the deadline has passed, and blocking
+    /// queries are shortened to end before it
+    pub deadline: Option<Instant>,
+    /// ID sent with every request to correlate them with the operation that made them
+    pub trace_id: Option<String>,
+    /// Refuse to make changes to the Nomad cluster with `Error::ReadOnly`
+    pub dry_run: bool,
+}
+
+impl RequestContext {
+    /// An empty context without a deadline, trace ID or dry run
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Finish by the provided deadline
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Correlate requests with the provided trace ID
+    pub fn with_trace_id<S: AsRef<str>>(mut self, trace_id: S) -> Self {
+        self.trace_id = Some(trace_id.as_ref().to_string());
+        self
+    }
+
+    /// Set whether changes are refused
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Time left before the deadline according to `clock`, if there is a deadline
+    pub fn remaining(&self, clock: &dyn Clock) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+            let now = clock.now();
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /// Fail if the deadline has passed according to `clock`
+    pub fn check_deadline(&self, clock: &dyn Clock) -> Result<(), crate::Error> {
+        match self.remaining(clock) {
+            Some(remaining) if remaining == Duration::from_secs(0) => {
+                Err(crate::Error::DeadlineExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail with `Error::ReadOnly` if changes are refused
+    pub fn check_mutable(&self, operation: &str) -> Result<(), crate::Error> {
+        if self.dry_run {
+            info!("Dry run: refusing to {}", operation);
+            Err(crate::Error::ReadOnly {
+                operation: operation.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Add the trace ID, if any, to a request
+    pub fn apply(&self, mut request: reqwest::Request) -> Result<reqwest::Request, crate::Error> {
+        if let Some(ref trace_id) = self.trace_id {
+            request
+                .headers_mut()
+                .insert(TRACE_ID_HEADER, HeaderValue::from_str(trace_id)?);
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::SimulatedClock;
+
+    #[test]
+    fn deadlines_are_checked_against_the_clock() {
+        let clock = SimulatedClock::new();
+        let context = RequestContext::new().with_deadline(clock.now() + Duration::from_secs(10));
+        assert_eq!(Some(Duration::from_secs(10)), context.remaining(&clock));
+        assert!(context.check_deadline(&clock).is_ok());
+
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(Some(Duration::from_secs(0)), context.remaining(&clock));
+        match context.check_deadline(&clock) {
+            Err(crate::Error::DeadlineExceeded) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert!(RequestContext::new().check_deadline(&clock).is_ok());
+    }
+
+    #[test]
+    fn trace_ids_are_sent_with_requests() -> Result<(), crate::Error> {
+        let client = reqwest::Client::new();
+        let request = client.get("http://127.0.0.1:4646/v1/agent/self").build()?;
+        let request = RequestContext::new()
+            .with_trace_id("trace")
+            .apply(request)?;
+        assert_eq!("trace", request.headers()[TRACE_ID_HEADER]);
+        Ok(())
+    }
+
+    #[test]
+    fn dry_runs_refuse_changes() {
+        assert!(RequestContext::new().check_mutable("drain").is_ok());
+        match RequestContext::new()
+            .with_dry_run(true)
+            .check_mutable("drain Node ID id")
+        {
+            Err(crate::Error::ReadOnly { operation }) => assert_eq!("drain Node ID id", operation),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
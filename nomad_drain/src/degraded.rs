@@ -0,0 +1,99 @@
+//! Tracking optional operations that failed
+//!
+//! Some operations, like writing audit summaries or recording lifecycle action heartbeats, are
+//! nice to have but should never fail a drain. Record their failures in `DegradedOperations` and
+//! carry on, so that they are still reported alongside the outcome of the drain.
+use std::fmt::Display;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// An optional operation that failed
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DegradedOperation {
+    /// Name of the operation, e.g. `audit summary`
+    pub operation: String,
+    /// Why the operation failed
+    pub error: String,
+}
+
+/// Optional operations that failed, in the order they failed in
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct DegradedOperations(Vec<DegradedOperation>);
+
+impl DegradedOperations {
+    /// No operation has failed yet
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Record and log the failure of `operation`
+    pub fn record<E: Display>(&mut self, operation: &str, error: E) {
+        warn!("Optional operation {} failed: {}", operation, error);
+        self.0.push(DegradedOperation {
+            operation: operation.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    /// Record the failure of `operation`, if it failed
+    pub fn check<T, E: Display>(&mut self, operation: &str, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.record(operation, e);
+                None
+            }
+        }
+    }
+
+    /// Add the failures recorded in `other`
+    pub fn extend(&mut self, other: DegradedOperations) {
+        self.0.extend(other.0);
+    }
+
+    /// Whether no operation has failed
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The failed operations
+    pub fn operations(&self) -> &[DegradedOperation] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_are_recorded_in_order() -> Result<(), crate::Error> {
+        let mut degraded = DegradedOperations::new();
+        assert!(degraded.is_empty());
+        assert_eq!(Some(1), degraded.check("heartbeat", Ok::<_, String>(1)));
+        assert!(degraded.is_empty());
+
+        assert_eq!(None, degraded.check("heartbeat", Err::<(), _>("throttled")));
+        let mut other = DegradedOperations::new();
+        other.record("audit summary", "access denied");
+        degraded.extend(other);
+
+        assert_eq!(
+            vec!["heartbeat", "audit summary"],
+            degraded
+                .operations()
+                .iter()
+                .map(|degraded| degraded.operation.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            r#"[{"operation":"heartbeat","error":"throttled"},{"operation":"audit summary","error":"access denied"}]"#,
+            serde_json::to_string(&degraded)?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+//! The drain workflow behind a single type
+//!
+//! `NomadDrain` bundles the configured Nomad, Vault and AWS clients with the settings of drains,
+//! so that binaries only have to configure it and call the operations they expose.
+//!
+//! ```rust,no_run
+//! use nomad_drain::nomad::Client;
+//! use nomad_drain::NomadDrain;
+//!
+//! # fn main() -> Result<(), nomad_drain::Error> {
+//! let nomad_client = Client::builder("https://nomad.service.consul:4646")
+//!     .with_token("token")
+//!     .build()?;
+//! let drain = NomadDrain::new(nomad_client);
+//! let result = drain.drain_instance("i-1234567890abcdef0", true)?;
+//! println!("Migrated {} allocations", result.allocations);
+//! # Ok(())
+//! # }
+//! ```
+use std::time::Duration;
+
+use rusoto_autoscaling::{Autoscaling, AutoscalingClient};
+use rusoto_core::Region;
+use serde::{Deserialize, Serialize};
+
+use crate::batch::BatchPolicy;
+use crate::nomad::{
+    self, Allocation, DrainSpec, Node, NodeDrainResponse, NodeEligibility, NodeStatus,
+};
+use crate::plan::DrainPlan;
+use crate::vault;
+
+/// Nomad, Vault and AWS clients configured to drain nodes
+///
+/// The Vault client, if any, is only held so that its token, and the leases obtained with it,
+/// live as long as the Nomad client that uses them.
+pub struct NomadDrain<A = AutoscalingClient> {
+    nomad_client: nomad::Client,
+    vault_client: Option<vault::Client>,
+    autoscaling_client: A,
+    drain_spec: DrainSpec,
+    batch_policy: BatchPolicy,
+    include_non_ready: bool,
+    skip_eligibility: bool,
+    max_duration: Option<Duration>,
+}
+
+/// Outcome of a drain started with `NomadDrain`
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct DrainResult {
+    /// Nomad Node ID
+    pub node_id: String,
+    /// AWS Instance ID of the node, if known
+    pub instance_id: Option<String>,
+    /// IDs of the evaluations created by the drain
+    pub eval_ids: Vec<String>,
+    /// Number of allocations running on the node when the drain started, which are migrated
+    pub allocations: usize,
+    /// Time the drain started
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Time the drain was seen to complete. Drains that are not monitored have no completion time.
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl DrainResult {
+    /// Time taken for the drain to complete, if it was seen to complete
+    pub fn duration(&self) -> Option<Duration> {
+        self.completed_at
+            .and_then(|completed_at| (completed_at - self.started_at).to_std().ok())
+    }
+}
+
+/// Drain status of a node
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct DrainStatus {
+    /// Nomad Node ID
+    pub node_id: String,
+    /// AWS Instance ID of the node, if known
+    pub instance_id: Option<String>,
+    /// Status of the node
+    pub status: NodeStatus,
+    /// Scheduling eligibility of the node
+    pub eligibility: NodeEligibility,
+    /// Whether the node is draining
+    pub draining: bool,
+    /// Time at which remaining allocations are stopped, if the drain has a deadline
+    pub force_deadline: Option<chrono::DateTime<chrono::Utc>>,
+    /// Number of allocations on the node that have not stopped yet
+    pub allocations: usize,
+}
+
+impl DrainStatus {
+    fn new(node: &Node, allocations: usize) -> Self {
+        Self {
+            node_id: node.id.clone(),
+            instance_id: node.instance_id().map(ToString::to_string),
+            status: node.status,
+            eligibility: node.scheduling_eligibility,
+            draining: node.drain,
+            force_deadline: node
+                .drain_strategy
+                .as_ref()
+                .and_then(nomad::DrainStrategy::deadline),
+            allocations,
+        }
+    }
+}
+
+impl NomadDrain {
+    /// Drain nodes with the Nomad client, using the default AWS region for Auto Scaling
+    pub fn new(nomad_client: nomad::Client) -> Self {
+        Self {
+            nomad_client,
+            vault_client: None,
+            autoscaling_client: AutoscalingClient::new(Region::default()),
+            drain_spec: Default::default(),
+            batch_policy: Default::default(),
+            include_non_ready: false,
+            skip_eligibility: false,
+            max_duration: None,
+        }
+    }
+}
+
+impl<A> NomadDrain<A>
+where
+    A: Autoscaling,
+{
+    /// Hold on to the Vault client the Nomad token was obtained with
+    pub fn with_vault_client(mut self, vault_client: vault::Client) -> Self {
+        self.vault_client = Some(vault_client);
+        self
+    }
+
+    /// Plan drains with another Auto Scaling client
+    pub fn with_autoscaling_client<B>(self, autoscaling_client: B) -> NomadDrain<B>
+    where
+        B: Autoscaling,
+    {
+        NomadDrain {
+            nomad_client: self.nomad_client,
+            vault_client: self.vault_client,
+            autoscaling_client,
+            drain_spec: self.drain_spec,
+            batch_policy: self.batch_policy,
+            include_non_ready: self.include_non_ready,
+            skip_eligibility: self.skip_eligibility,
+            max_duration: self.max_duration,
+        }
+    }
+
+    /// Drain nodes with the drain specification
+    pub fn with_drain_spec(mut self, drain_spec: DrainSpec) -> Self {
+        self.drain_spec = drain_spec;
+        self
+    }
+
+    /// Treat allocations, of batch jobs by default, with the policy instead of leaving them to the
+    /// drain deadline
+    pub fn with_batch_policy(mut self, batch_policy: BatchPolicy) -> Self {
+        self.batch_policy = batch_policy;
+        self
+    }
+
+    /// Set whether instances are also matched to nodes that are down or initializing
+    pub fn with_include_non_ready(mut self, include_non_ready: bool) -> Self {
+        self.include_non_ready = include_non_ready;
+        self
+    }
+
+    /// Set whether to skip making drained nodes ineligible, for tokens that are only allowed to
+    /// drain nodes
+    pub fn with_skip_eligibility(mut self, skip_eligibility: bool) -> Self {
+        self.skip_eligibility = skip_eligibility;
+        self
+    }
+
+    /// Give up monitoring drains with `Error::DrainTimeout` after monitoring them for this long
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    /// The Nomad client
+    pub fn nomad_client(&self) -> &nomad::Client {
+        &self.nomad_client
+    }
+
+    /// The Vault client, if any
+    pub fn vault_client(&self) -> Option<&vault::Client> {
+        self.vault_client.as_ref()
+    }
+
+    /// Drain the node of an AWS instance, waiting for the drain to complete if `monitor` is set
+    pub fn drain_instance(
+        &self,
+        instance_id: &str,
+        monitor: bool,
+    ) -> Result<DrainResult, crate::Error> {
+        let node = self
+            .nomad_client
+            .find_node_by_instance_id(instance_id, self.include_non_ready)?
+            .node
+            .data;
+        let mut result = self.drain_node(&node.id, monitor)?;
+        result.instance_id = Some(instance_id.to_string());
+        Ok(result)
+    }
+
+    /// Drain a node, waiting for the drain to complete if `monitor` is set
+    pub fn drain_node(&self, node_id: &str, monitor: bool) -> Result<DrainResult, crate::Error> {
+        let allocations = self.allocations(node_id)?;
+        let started_at = chrono::Utc::now();
+        let drain_spec = Some(DrainSpec {
+            deadline: self
+                .batch_policy
+                .deadline(self.drain_spec.deadline, &allocations),
+            ..self.drain_spec.clone()
+        });
+        let response = if self.skip_eligibility {
+            self.nomad_client
+                .set_node_drain(node_id, false, drain_spec)?
+        } else {
+            self.nomad_client
+                .set_node_drain_ineligible(node_id, drain_spec)?
+        };
+        for allocation in self.batch_policy.to_migrate(&allocations) {
+            self.nomad_client.stop_allocation(&allocation.id)?;
+        }
+
+        let completed_at = if monitor {
+            self.nomad_client
+                .monitor_node_drain(node_id, None, self.max_duration)?;
+            Some(chrono::Utc::now())
+        } else {
+            None
+        };
+        Ok(DrainResult {
+            node_id: node_id.to_string(),
+            instance_id: None,
+            eval_ids: response.eval_ids().to_vec(),
+            allocations: allocations.len(),
+            started_at,
+            completed_at,
+        })
+    }
+
+    /// Cancel the drain of a node and make it eligible for new allocations again
+    pub fn undrain(&self, node_id: &str) -> Result<NodeDrainResponse, crate::Error> {
+        self.nomad_client.cancel_node_drain(node_id)
+    }
+
+    /// Plan the drain of every node in an AWS Auto Scaling Group with the drain specification
+    pub fn plan(&self, auto_scaling_group: &str) -> Result<DrainPlan, crate::Error> {
+        DrainPlan::for_auto_scaling_group(
+            &self.nomad_client,
+            &self.autoscaling_client,
+            auto_scaling_group,
+            self.drain_spec.clone(),
+        )
+    }
+
+    /// Drain status of a node
+    pub fn status(&self, node_id: &str) -> Result<DrainStatus, crate::Error> {
+        let node = self.nomad_client.node_details(node_id, None, None)?.data;
+        let allocations = self.allocations(node_id)?;
+        Ok(DrainStatus::new(&node, allocations.len()))
+    }
+
+    /// Allocations on the node that have not stopped
+    fn allocations(&self, node_id: &str) -> Result<Vec<Allocation>, crate::Error> {
+        Ok(self
+            .nomad_client
+            .allocations(node_id, None, None)?
+            .data
+            .into_iter()
+            .filter(|allocation| !allocation.is_terminal())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::context::RequestContext;
+    use crate::fixtures::NodeFixture;
+
+    #[test]
+    fn drain_status_is_read_from_the_node() {
+        let node = NodeFixture::ready()
+            .with_id("a")
+            .with_instance_id("i-123")
+            .draining()
+            .node();
+        let status = DrainStatus::new(&node, 2);
+
+        assert_eq!("a", status.node_id);
+        assert_eq!(Some("i-123"), status.instance_id.as_deref());
+        assert!(status.draining);
+        assert_eq!(NodeEligibility::Ineligible, status.eligibility);
+        assert_eq!(2, status.allocations);
+    }
+
+    #[test]
+    fn drains_are_not_cancelled_on_dry_runs() -> Result<(), crate::Error> {
+        let nomad_client = nomad::Client::builder("http://127.0.0.1:4646")
+            .build()?
+            .with_context(RequestContext::new().with_dry_run(true));
+        match NomadDrain::new(nomad_client).undrain("id") {
+            Err(crate::Error::ReadOnly { operation }) => {
+                assert_eq!("cancel the drain of Node ID id", operation)
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn drain_result_duration_is_measured_when_completed() {
+        let started_at = chrono::Utc::now();
+        let mut result = DrainResult {
+            node_id: "a".to_string(),
+            instance_id: None,
+            eval_ids: vec![],
+            allocations: 0,
+            started_at,
+            completed_at: None,
+        };
+        assert_eq!(None, result.duration());
+
+        result.completed_at = Some(started_at + chrono::Duration::seconds(90));
+        assert_eq!(Some(Duration::from_secs(90)), result.duration());
+    }
+}
@@ -1,66 +1,375 @@
-use failure_derive::Fail;
+use thiserror::Error;
 
 /// Error type for this library
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum Error {
     /// Errors related to retrieving AWS credentials
-    #[fail(display = "Error retrieving AWS credentials: {}", _0)]
-    CredentialsError(#[cause] rusoto_core::CredentialsError),
+    #[error("Error retrieving AWS credentials")]
+    CredentialsError(#[from] rusoto_core::CredentialsError),
     /// Errors related to API HTTP calls
-    #[fail(display = "Error making HTTP Request: {}", _0)]
-    ReqwestError(#[cause] reqwest::Error),
+    #[error("Error making HTTP Request")]
+    ReqwestError(#[from] reqwest::Error),
     /// Errors parsing headers
-    #[fail(display = "Error parsing HTTP header: {}", _0)]
-    HeadersErrors(#[cause] reqwest::header::ToStrError),
+    #[error("Error parsing HTTP header")]
+    HeadersErrors(#[from] reqwest::header::ToStrError),
+    /// Errors building header values
+    #[error("Invalid HTTP header value")]
+    InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
     /// Errors related to URL parsing
-    #[fail(display = "Error Parsing URL: {}", _0)]
-    UrlParseError(#[cause] url::ParseError),
+    #[error("Error Parsing URL")]
+    UrlParseError(#[from] url::ParseError),
+    /// Requests were redirected too many times
+    #[error("Too many redirects, last redirected to {location}")]
+    TooManyRedirects { location: String },
     /// Response from Vault was unexpected
-    #[fail(display = "Unexpected response from Vault: {}", _0)]
+    #[error("Unexpected response from Vault: {0}")]
     InvalidVaultResponse(String),
+    /// MFA credentials are not in the form `method_name[:passcode]`
+    #[error("MFA credentials must be in the form `method_name[:passcode]`")]
+    InvalidMfaCredentials,
+    /// No Vault mount matches the path
+    #[error("No Vault mount found for path `{path}`")]
+    VaultMountNotFound { path: String },
+    /// Vault mount is not of the expected type
+    #[error("Vault mount `{path}` is of type {actual}, expected {expected}")]
+    UnexpectedVaultMountType {
+        path: String,
+        actual: String,
+        expected: String,
+    },
     /// Nomad Node not found
-    #[fail(display = "No Nomad Node found for AWS instance ID: {}", instance_id)]
+    #[error("No Nomad Node found for AWS instance ID: {instance_id}")]
     NomadNodeNotFound { instance_id: String },
+    /// Nomad Node did not register or become ready in time
+    #[error("Nomad Node for AWS instance ID {instance_id} did not become ready in time")]
+    NomadNodeNotReady { instance_id: String },
+    /// A Nomad token is in use, but the Nomad agent does not enforce ACLs
+    #[error(
+        "A Nomad token is in use, but ACLs are disabled on the Nomad agent. Stop using a Nomad \
+         token instead"
+    )]
+    NomadAclsDisabled,
+    /// Nomad Node was garbage collected or purged
+    #[error("Nomad Node ID {node_id} no longer exists")]
+    NomadNodeGone { node_id: String },
+    /// Nomad Node drain was monitored for longer than allowed
+    #[error("Drain of Nomad Node ID {node_id} did not complete after {elapsed:?}")]
+    DrainTimeout {
+        node_id: String,
+        elapsed: std::time::Duration,
+    },
     /// Errors parsing Numbers
-    #[fail(display = "Error parsing integer: {}", _0)]
-    ParseIntError(#[cause] std::num::ParseIntError),
+    #[error("Error parsing integer")]
+    ParseIntError(#[from] std::num::ParseIntError),
+    /// Errors reading files
+    #[error("Error reading file")]
+    IoError(#[from] std::io::Error),
+    /// Errors parsing certificates or keys
+    #[error("Error parsing certificate or key")]
+    OpenSslError(#[from] openssl::error::ErrorStack),
+    /// Only one of a client certificate and its key were provided
+    #[error("Client certificates must be provided along with their private keys")]
+    IncompleteClientCertificate,
     /// Errors deserializing JSON
-    #[fail(display = "Error deserializing JSON: {}", _0)]
-    JsonError(#[cause] serde_json::Error),
+    #[error("Error deserializing JSON")]
+    JsonError(#[from] serde_json::Error),
+    /// Errors deserializing JSON, with the path to the field that failed to deserialize
+    #[error("Error deserializing JSON at `{path}`")]
+    JsonPathError {
+        path: String,
+        #[source]
+        error: serde_json::Error,
+    },
+    /// Response from Nomad could not be deserialized
+    #[error("Unexpected response from Nomad ({diagnostics}) at `{path}`")]
+    UnexpectedNomadResponse {
+        path: String,
+        #[source]
+        error: serde_json::Error,
+        diagnostics: crate::nomad::ResponseDiagnostics,
+    },
+    /// Nomad responded with an HTTP error status
+    #[error("Nomad responded with {diagnostics}: {message}")]
+    NomadApi {
+        status: u16,
+        /// Body of the response, which Nomad fills with a plain text error message
+        message: String,
+        diagnostics: crate::nomad::ResponseDiagnostics,
+    },
+    /// Vault responded with an HTTP error status
+    #[error("Vault responded with HTTP {status}: {}", .errors.join("; "))]
+    VaultApi { status: u16, errors: Vec<String> },
+    /// None of the candidate Nomad tokens were accepted
+    #[error("None of the {candidates} candidate Nomad tokens were accepted")]
+    NoValidNomadToken { candidates: usize },
+    /// A Vault client was built without a token or a method to login with
+    #[error("No Vault token or authentication method was provided")]
+    MissingVaultCredentials,
+    /// More nodes were listed than the configured maximum
+    #[error(
+        "More than {limit} Nomad nodes were listed. Filter nodes by instance ID (Nomad 1.1 \
+         or later), shard node listings or raise the limit"
+    )]
+    TooManyNodes { limit: usize },
+    /// The Nomad server is too old to drain nodes with
+    #[error("Nomad {version} is not supported. Nomad 0.8 or later is required to drain nodes")]
+    UnsupportedNomadVersion { version: String },
+    /// The Nomad token was still rejected after waiting for it to replicate
+    #[error("Nomad token was rejected after waiting for it to be replicated")]
+    NomadTokenRejected,
+    /// The deadline of the client passed before the operation completed
+    #[error("Deadline exceeded before the operation completed")]
+    DeadlineExceeded,
+    /// A mutating call was made through a read-only client
+    #[error("Refusing to {operation} with a read-only client")]
+    ReadOnly { operation: String },
+    /// Errors verifying AWS credentials with STS
+    #[error("Error getting AWS caller identity")]
+    CallerIdentityError(#[from] rusoto_sts::GetCallerIdentityError),
+    /// Errors describing AWS Auto Scaling Groups
+    #[error("Error describing Auto Scaling Groups")]
+    AutoscalingError(#[from] rusoto_autoscaling::DescribeAutoScalingGroupsError),
+    /// Errors recording lifecycle action heartbeats
+    #[error("Error recording lifecycle action heartbeat")]
+    LifecycleHeartbeatError(#[from] rusoto_autoscaling::RecordLifecycleActionHeartbeatError),
+    /// S3 rejected an upload
+    #[error("Error uploading s3://{bucket}/{key}: HTTP {status}: {body}")]
+    S3UploadFailed {
+        bucket: String,
+        key: String,
+        status: u16,
+        body: String,
+    },
+    /// S3 rejected a download
+    #[error("Error downloading s3://{bucket}/{key}: HTTP {status}: {body}")]
+    S3DownloadFailed {
+        bucket: String,
+        key: String,
+        status: u16,
+        body: String,
+    },
+    /// Errors decoding base64
+    #[error("Error decoding base64")]
+    Base64DecodeError(#[from] base64::DecodeError),
+    /// Checkpoint is too large to pass inline, and cannot be offloaded to S3
+    #[error(
+        "Checkpoint of {size} bytes exceeds the limit of {limit} bytes and no S3 bucket is \
+         configured to offload it to"
+    )]
+    CheckpointTooLarge { size: usize, limit: usize },
+    /// Checkpoint is stored in S3, but no credentials were provided to retrieve it with
+    #[error("Checkpoint is stored at s3://{bucket}/{key} but no AWS credentials were provided")]
+    CheckpointInS3 { bucket: String, key: String },
+    /// Systems Manager rejected a request
+    #[error("Error calling Systems Manager {target}: HTTP {status}: {body}")]
+    SsmRequestFailed {
+        target: String,
+        status: u16,
+        body: String,
+    },
+    /// DynamoDB rejected a request
+    #[error("Error calling DynamoDB {target}: HTTP {status}: {body}")]
+    DynamoDbRequestFailed {
+        target: String,
+        status: u16,
+        body: String,
+    },
+    /// SNS rejected a message
+    #[error("Error publishing to SNS topic {topic_arn}: HTTP {status}: {body}")]
+    SnsPublishFailed {
+        topic_arn: String,
+        status: u16,
+        body: String,
+    },
+    /// Systems Manager command did not succeed on the instance
+    #[error(
+        "Systems Manager command {command_id} on AWS instance ID {instance_id} ended with \
+         status {status}: {details}"
+    )]
+    SsmCommandFailed {
+        instance_id: String,
+        command_id: String,
+        status: String,
+        details: String,
+    },
+    /// Systems Manager command did not complete in time
+    #[error(
+        "Systems Manager command {command_id} on AWS instance ID {instance_id} did not \
+         complete in time"
+    )]
+    SsmCommandTimedOut {
+        instance_id: String,
+        command_id: String,
+    },
+    /// The drain plan violates the concurrency or capacity guards
+    #[error("Refusing to apply the drain plan: {reason}")]
+    DrainPlanRefused { reason: String },
+    /// Auto Scaling Group not found
+    #[error("Auto Scaling Group {name} not found")]
+    AutoScalingGroupNotFound { name: String },
+    /// Errors parsing AWS regions
+    #[error("Error parsing AWS region")]
+    ParseRegionError(#[from] rusoto_core::region::ParseRegionError),
 }
 
-impl From<rusoto_core::CredentialsError> for Error {
-    fn from(error: rusoto_core::CredentialsError) -> Self {
-        Error::CredentialsError(error)
-    }
+/// Broad categories of errors, for binaries to report with stable process exit codes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// Credentials or tokens were missing or rejected
+    Authentication,
+    /// A node, mount, Auto Scaling Group or other resource does not exist
+    NotFound,
+    /// An operation did not complete in time
+    Timeout,
+    /// Nomad or Vault could not be reached at all
+    Unreachable,
+    /// Configuration or input is invalid
+    Configuration,
+    /// Any other error
+    Other,
 }
 
-impl From<reqwest::Error> for Error {
-    fn from(error: reqwest::Error) -> Self {
-        Error::ReqwestError(error)
+impl ErrorKind {
+    /// Process exit code for errors of this kind
+    ///
+    /// `2` is left for invalid command line usage.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Authentication => 3,
+            ErrorKind::NotFound => 4,
+            ErrorKind::Timeout => 5,
+            ErrorKind::Unreachable => 6,
+            ErrorKind::Configuration => 7,
+        }
     }
 }
 
-impl From<reqwest::header::ToStrError> for Error {
-    fn from(error: reqwest::header::ToStrError) -> Self {
-        Error::HeadersErrors(error)
+impl Error {
+    /// Category of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            _ if self.is_timeout() => ErrorKind::Timeout,
+            _ if self.is_unreachable() => ErrorKind::Unreachable,
+            Error::CredentialsError(_)
+            | Error::InvalidMfaCredentials
+            | Error::NoValidNomadToken { .. }
+            | Error::MissingVaultCredentials
+            | Error::NomadTokenRejected
+            | Error::CallerIdentityError(_) => ErrorKind::Authentication,
+            Error::NomadNodeNotFound { .. }
+            | Error::NomadNodeGone { .. }
+            | Error::VaultMountNotFound { .. }
+            | Error::AutoScalingGroupNotFound { .. } => ErrorKind::NotFound,
+            Error::NomadNodeNotReady { .. }
+            | Error::DrainTimeout { .. }
+            | Error::DeadlineExceeded
+            | Error::SsmCommandTimedOut { .. } => ErrorKind::Timeout,
+            Error::UrlParseError(_)
+            | Error::IncompleteClientCertificate
+            | Error::OpenSslError(_)
+            | Error::UnexpectedVaultMountType { .. }
+            | Error::UnsupportedNomadVersion { .. }
+            | Error::NomadAclsDisabled
+            | Error::DrainPlanRefused { .. }
+            | Error::ParseRegionError(_) => ErrorKind::Configuration,
+            _ => match self.status() {
+                Some(401) | Some(403) => ErrorKind::Authentication,
+                Some(404) => ErrorKind::NotFound,
+                _ => ErrorKind::Other,
+            },
+        }
+    }
+
+    /// HTTP status code of the response, if the error is an API responding with an error status
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::NomadApi { status, .. } | Error::VaultApi { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether the error is a request to the server timing out
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::ReqwestError(error) => crate::retry::is_timeout(error),
+            _ => false,
+        }
+    }
+
+    /// Whether the error is a failure to connect to the server or get any response from it
+    pub fn is_unreachable(&self) -> bool {
+        match self {
+            Error::ReqwestError(error) => crate::retry::is_transient(error),
+            _ => false,
+        }
     }
 }
 
-impl From<url::ParseError> for Error {
-    fn from(error: url::ParseError) -> Self {
-        Error::UrlParseError(error)
+/// The error followed by each of its causes, on one line
+///
+/// Errors wrapping another error only describe what failed, and leave the details to their
+/// causes. Use this where only a single line is shown, instead of the error alone.
+pub fn display_chain(error: &dyn std::error::Error) -> String {
+    let mut chain = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        chain.push_str(": ");
+        chain.push_str(&cause.to_string());
+        source = cause.source();
     }
+    chain
 }
 
-impl From<std::num::ParseIntError> for Error {
-    fn from(error: std::num::ParseIntError) -> Self {
-        Error::ParseIntError(error)
+impl From<serde_path_to_error::Error<serde_json::Error>> for Error {
+    fn from(error: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        Error::JsonPathError {
+            path: error.path().to_string(),
+            error: error.into_inner(),
+        }
     }
 }
 
-impl From<serde_json::Error> for Error {
-    fn from(error: serde_json::Error) -> Self {
-        Error::JsonError(error)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_categorised_by_kind() {
+        let error = Error::VaultApi {
+            status: 403,
+            errors: vec!["permission denied".to_string()],
+        };
+        assert_eq!(ErrorKind::Authentication, error.kind());
+        assert_eq!(3, error.kind().exit_code());
+
+        let error = Error::NomadNodeNotFound {
+            instance_id: "i-1234567890abcdef0".to_string(),
+        };
+        assert_eq!(4, error.kind().exit_code());
+        assert_eq!(5, Error::DeadlineExceeded.kind().exit_code());
+        assert_eq!(
+            ErrorKind::Other,
+            Error::InvalidVaultResponse("unexpected".to_string()).kind()
+        );
+    }
+
+    #[test]
+    fn request_timeouts_are_not_unreachable() {
+        let error = Error::ReqwestError(crate::fixtures::timed_out());
+        assert!(error.is_timeout());
+        assert_eq!(ErrorKind::Timeout, error.kind());
+    }
+
+    #[test]
+    fn errors_chain_their_sources() {
+        let error = Error::from("not a number".parse::<u64>().unwrap_err());
+        let source = std::error::Error::source(&error).expect("source to be chained");
+        assert_eq!("invalid digit found in string", source.to_string());
+        assert_eq!(
+            "Error parsing integer: invalid digit found in string",
+            display_chain(&error)
+        );
+        assert!(std::error::Error::source(&Error::DeadlineExceeded).is_none());
     }
 }
@@ -0,0 +1,397 @@
+//! AWS Auto Scaling Group lifecycle event models
+//!
+//! These are the `detail` of lifecycle hook events delivered through CloudWatch Events or
+//! EventBridge, or the body of lifecycle hook notifications delivered to SQS queues, shared so
+//! that every consumer parses them the same way.
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// Keys of the lifecycle hook event details, as delivered by AWS
+const DETAIL_KEYS: &[&str] = &[
+    "LifecycleActionToken",
+    "AutoScalingGroupName",
+    "EC2InstanceId",
+    "LifecycleTransition",
+    "LifecycleHookName",
+    "NotificationMetadata",
+    "Origin",
+    "Destination",
+];
+
+/// Other keys some event routes carry detail values in, and the key AWS uses for them
+const DETAIL_KEY_ALIASES: &[(&str, &str)] = &[("InstanceId", "EC2InstanceId")];
+
+/// Version of the schema of CloudWatch Events and EventBridge events these details are parsed from
+///
+/// Events of other versions are still parsed as leniently as possible.
+pub const EVENT_VERSION: &str = "0";
+
+/// `Event` of the notification Auto Scaling sends when a lifecycle hook is created
+const TEST_NOTIFICATION_EVENT: &str = "autoscaling:TEST_NOTIFICATION";
+
+/// Details of an Auto Scaling Group lifecycle hook event
+///
+/// Keys are matched regardless of casing, since some routers between the Auto Scaling Group and
+/// the consumer transpose them to `lowerCamelCase` or `snake_case`. Details are always serialized
+/// with the keys AWS uses.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(remote = "Self", rename_all = "PascalCase")]
+pub struct AsgEventDetails {
+    pub lifecycle_action_token: String,
+    pub auto_scaling_group_name: String,
+    #[serde(rename = "EC2InstanceId")]
+    pub instance_id: String,
+    pub lifecycle_transition: AsgLifecycleTransition,
+    pub lifecycle_hook_name: String,
+    /// Metadata of the lifecycle hook, for users to pass options through. Metadata given as JSON
+    /// values rather than strings by some event routes are serialized back to strings.
+    #[serde(default, deserialize_with = "deserialize_metadata")]
+    pub notification_metadata: Option<String>,
+    /// Where the instance is moving from. Only present for groups with a warm pool.
+    #[serde(default)]
+    pub origin: Option<AsgInstanceLocation>,
+    /// Where the instance is moving to. Only present for groups with a warm pool.
+    #[serde(default)]
+    pub destination: Option<AsgInstanceLocation>,
+}
+
+impl AsgEventDetails {
+    /// Whether the instance is moving into or out of a warm pool
+    pub fn is_warm_pool_transition(&self) -> bool {
+        self.origin == Some(AsgInstanceLocation::WarmPool)
+            || self.destination == Some(AsgInstanceLocation::WarmPool)
+    }
+
+    /// Parse the notification metadata as JSON, or return the default if there is none
+    pub fn metadata<T>(&self) -> Result<T, serde_json::Error>
+    where
+        T: DeserializeOwned + Default,
+    {
+        match self.notification_metadata {
+            Some(ref metadata) if !metadata.trim().is_empty() => serde_json::from_str(metadata),
+            _ => Ok(Default::default()),
+        }
+    }
+}
+
+fn deserialize_metadata<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => None,
+        Some(Value::String(metadata)) => Some(metadata),
+        Some(metadata) => Some(metadata.to_string()),
+    })
+}
+
+impl Serialize for AsgEventDetails {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        AsgEventDetails::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AsgEventDetails {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let details = serde_json::Map::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| (canonical_key(key), value))
+            .collect();
+        AsgEventDetails::deserialize(serde_json::Value::Object(details)).map_err(D::Error::custom)
+    }
+}
+
+/// Add the instance ID to event details that have none, from the ARN of an instance in the
+/// `resources` of the event
+///
+/// Some event routes only identify the instance among the resources of the event.
+pub fn detail_with_instance_id(detail: Value, resources: &[String]) -> Value {
+    let mut detail = match detail {
+        Value::Object(detail) => detail,
+        other => return other,
+    };
+    let has_instance_id = detail
+        .keys()
+        .any(|key| canonical_key(key.clone()) == "EC2InstanceId");
+    if !has_instance_id {
+        if let Some(instance_id) = resources.iter().find_map(|arn| instance_id_from_arn(arn)) {
+            detail.insert("EC2InstanceId".to_string(), Value::from(instance_id));
+        }
+    }
+    Value::Object(detail)
+}
+
+/// Instance ID in the ARN of an EC2 instance, e.g.
+/// `arn:aws:ec2:us-east-1:123456789012:instance/i-1234567890abcdef0`
+pub fn instance_id_from_arn(arn: &str) -> Option<&str> {
+    let mut parts = arn.splitn(6, ':');
+    match (parts.next(), parts.nth(1), parts.nth(2)) {
+        (Some("arn"), Some("ec2"), Some(resource)) => resource
+            .strip_prefix("instance/")
+            .filter(|instance_id| !instance_id.is_empty()),
+        _ => None,
+    }
+}
+
+/// The key AWS uses for a detail key in any casing. Unknown keys are returned unchanged.
+fn canonical_key(key: String) -> String {
+    let folded = fold_case(&key);
+    DETAIL_KEYS
+        .iter()
+        .find(|canonical| fold_case(canonical) == folded)
+        .copied()
+        .or_else(|| {
+            DETAIL_KEY_ALIASES
+                .iter()
+                .find(|(alias, _)| fold_case(alias) == folded)
+                .map(|(_, canonical)| *canonical)
+        })
+        .map(ToString::to_string)
+        .unwrap_or(key)
+}
+
+fn fold_case(key: &str) -> String {
+    key.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// A lifecycle hook notification, as delivered to SQS queues
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LifecycleNotification {
+    /// Sent when the lifecycle hook is created, to check that notifications are delivered
+    Test,
+    /// A lifecycle action to handle
+    Action(AsgEventDetails),
+}
+
+impl LifecycleNotification {
+    /// Parse the notification from the body of a message
+    pub fn from_message(message: &str) -> Result<Self, serde_json::Error> {
+        let message: Value = serde_json::from_str(message)?;
+        if message.get("Event").and_then(Value::as_str) == Some(TEST_NOTIFICATION_EVENT) {
+            return Ok(LifecycleNotification::Test);
+        }
+        Ok(LifecycleNotification::Action(serde_json::from_value(
+            message,
+        )?))
+    }
+}
+
+/// Lifecycle transition of an Auto Scaling Group instance
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum AsgLifecycleTransition {
+    #[serde(rename = "autoscaling:EC2_INSTANCE_LAUNCHING")]
+    InstanceLaunching,
+    #[serde(rename = "autoscaling:EC2_INSTANCE_TERMINATING")]
+    InstanceTerminating,
+}
+
+/// Location of an instance in a lifecycle transition
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum AsgInstanceLocation {
+    #[serde(rename = "EC2")]
+    Ec2,
+    AutoScalingGroup,
+    WarmPool,
+    /// A location introduced after this was written
+    #[serde(other)]
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terminating_event_is_deserialized_properly() {
+        let json = r#"
+{
+  "LifecycleActionToken": "87654321-4321-4321-4321-210987654321",
+  "AutoScalingGroupName": "nomad-clients",
+  "LifecycleHookName": "nomad-drain",
+  "EC2InstanceId": "i-1234567890abcdef0",
+  "LifecycleTransition": "autoscaling:EC2_INSTANCE_TERMINATING",
+  "NotificationMetadata": "{\"dry_run\": true}"
+}
+"#;
+        let event: AsgEventDetails = serde_json::from_str(json).unwrap();
+
+        assert_eq!("i-1234567890abcdef0", event.instance_id);
+        assert_eq!(
+            AsgLifecycleTransition::InstanceTerminating,
+            event.lifecycle_transition
+        );
+        assert_eq!(
+            Some(r#"{"dry_run": true}"#),
+            event.notification_metadata.as_deref()
+        );
+        assert!(!event.is_warm_pool_transition());
+    }
+
+    #[test]
+    fn warm_pool_event_is_deserialized_properly() {
+        let json = r#"
+{
+  "LifecycleActionToken": "87654321-4321-4321-4321-210987654321",
+  "AutoScalingGroupName": "nomad-clients",
+  "LifecycleHookName": "nomad-drain",
+  "EC2InstanceId": "i-1234567890abcdef0",
+  "LifecycleTransition": "autoscaling:EC2_INSTANCE_LAUNCHING",
+  "Origin": "EC2",
+  "Destination": "WarmPool"
+}
+"#;
+        let event: AsgEventDetails = serde_json::from_str(json).unwrap();
+
+        assert_eq!(Some(AsgInstanceLocation::Ec2), event.origin);
+        assert_eq!(Some(AsgInstanceLocation::WarmPool), event.destination);
+        assert!(event.is_warm_pool_transition());
+    }
+
+    #[test]
+    fn transposed_key_casing_is_deserialized_properly() {
+        let expected: AsgEventDetails =
+            serde_json::from_str(include_str!("../fixtures/asg_event.json")).unwrap();
+        for json in &[
+            include_str!("../fixtures/asg_event_camel_case.json"),
+            include_str!("../fixtures/asg_event_snake_case.json"),
+        ] {
+            let event: AsgEventDetails = serde_json::from_str(json).unwrap();
+            assert_eq!(expected, event);
+        }
+
+        let serialized = serde_json::to_value(&expected).unwrap();
+        assert_eq!("i-1234567890abcdef0", serialized["EC2InstanceId"]);
+    }
+
+    #[test]
+    fn details_are_parsed_leniently() -> Result<(), crate::Error> {
+        let event: AsgEventDetails = serde_json::from_str(
+            r#"
+{
+  "LifecycleActionToken": "87654321-4321-4321-4321-210987654321",
+  "AutoScalingGroupName": "nomad-clients",
+  "LifecycleHookName": "nomad-drain",
+  "EC2InstanceId": "i-1234567890abcdef0",
+  "LifecycleTransition": "autoscaling:EC2_INSTANCE_TERMINATING",
+  "NotificationMetadata": { "dry_run": true },
+  "Origin": "AutoScalingGroup",
+  "Destination": "Hibernated"
+}
+"#,
+        )?;
+        assert_eq!(
+            Some(r#"{"dry_run":true}"#),
+            event.notification_metadata.as_deref()
+        );
+        assert_eq!(Some(AsgInstanceLocation::Unknown), event.destination);
+
+        let metadata: serde_json::Map<String, Value> = event.metadata()?;
+        assert_eq!(Some(&Value::Bool(true)), metadata.get("dry_run"));
+
+        let event = AsgEventDetails {
+            notification_metadata: Some(" ".to_string()),
+            ..event
+        };
+        let metadata: serde_json::Map<String, Value> = event.metadata()?;
+        assert!(metadata.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn instance_ids_are_read_from_every_detail_variant() {
+        let expected: AsgEventDetails =
+            serde_json::from_str(include_str!("../fixtures/asg_event.json")).unwrap();
+
+        let event: AsgEventDetails =
+            serde_json::from_str(include_str!("../fixtures/asg_event_instance_id.json")).unwrap();
+        assert_eq!(expected, event);
+
+        let event: Value =
+            serde_json::from_str(include_str!("../fixtures/asg_event_resources.json")).unwrap();
+        let resources: Vec<String> = serde_json::from_value(event["resources"].clone()).unwrap();
+        let detail = detail_with_instance_id(event["detail"].clone(), &resources);
+        let event: AsgEventDetails = serde_json::from_value(detail).unwrap();
+        assert_eq!(expected, event);
+
+        // Instance IDs in the detail take precedence over resources
+        let detail = detail_with_instance_id(
+            serde_json::to_value(&expected).unwrap(),
+            &["arn:aws:ec2:us-east-1:123456789012:instance/i-0000000000000000".to_string()],
+        );
+        assert_eq!("i-1234567890abcdef0", detail["EC2InstanceId"]);
+    }
+
+    #[test]
+    fn instance_ids_are_parsed_from_arns() {
+        assert_eq!(
+            Some("i-1234567890abcdef0"),
+            instance_id_from_arn("arn:aws:ec2:us-east-1:123456789012:instance/i-1234567890abcdef0")
+        );
+        assert_eq!(
+            None,
+            instance_id_from_arn("arn:aws:autoscaling:us-east-1:123456789012:autoScalingGroup:59fcbb81:autoScalingGroupName/nomad-clients")
+        );
+        assert_eq!(
+            None,
+            instance_id_from_arn("arn:aws:ec2:us-east-1:123456789012:volume/vol-1234567890abcdef0")
+        );
+        assert_eq!(None, instance_id_from_arn("i-1234567890abcdef0"));
+    }
+
+    #[test]
+    fn sqs_notifications_are_parsed() -> Result<(), crate::Error> {
+        let action = LifecycleNotification::from_message(
+            r#"
+{
+  "Origin": "AutoScalingGroup",
+  "LifecycleHookName": "nomad-drain",
+  "Destination": "EC2",
+  "AccountId": "123456789012",
+  "RequestId": "d2c1e1a5-6b1a-4c5f-8d3e-0e4e3b0e9a1b",
+  "LifecycleTransition": "autoscaling:EC2_INSTANCE_TERMINATING",
+  "AutoScalingGroupName": "nomad-clients",
+  "Service": "AWS Auto Scaling",
+  "Time": "2019-01-16T10:23:42.000Z",
+  "EC2InstanceId": "i-1234567890abcdef0",
+  "LifecycleActionToken": "87654321-4321-4321-4321-210987654321"
+}
+"#,
+        )?;
+        match action {
+            LifecycleNotification::Action(event) => {
+                assert_eq!("i-1234567890abcdef0", event.instance_id);
+                assert_eq!(Some(AsgInstanceLocation::Ec2), event.destination);
+            }
+            other => panic!("Unexpected notification {:?}", other),
+        }
+
+        let test = LifecycleNotification::from_message(
+            r#"
+{
+  "AccountId": "123456789012",
+  "RequestId": "d2c1e1a5-6b1a-4c5f-8d3e-0e4e3b0e9a1b",
+  "AutoScalingGroupARN": "arn:aws:autoscaling:us-east-1:123456789012:autoScalingGroup",
+  "AutoScalingGroupName": "nomad-clients",
+  "Service": "AWS Auto Scaling",
+  "Event": "autoscaling:TEST_NOTIFICATION",
+  "Time": "2019-01-16T10:23:42.000Z"
+}
+"#,
+        )?;
+        assert_eq!(LifecycleNotification::Test, test);
+        Ok(())
+    }
+}
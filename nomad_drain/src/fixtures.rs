@@ -0,0 +1,383 @@
+//! Builders of Nomad API responses for tests
+//!
+//! Available with the `test-util` feature. The builders start from responses recorded from a real
+//! Nomad agent and only change the fields that matter to a test, so that the JSON they produce is
+//! always accepted by the clients. Use the typed structs to test code working on them directly, or
+//! the JSON to script a mock Nomad server or a `StubTransport`.
+//!
+//! ```rust
+//! use nomad_drain::fixtures::NodeFixture;
+//!
+//! let node = NodeFixture::ready().with_instance_id("i-123").draining().node();
+//! assert_eq!(Some("i-123"), node.instance_id());
+//! assert!(node.drain);
+//! ```
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use crate::headers::NOMAD_INDEX_HEADER;
+use crate::nomad::{
+    Allocation, AllocationClientStatus, BlockingResponse, Node, NodeEligibility, NodeStatus,
+    NodesInList,
+};
+use crate::Transport;
+
+const NODE_FIXTURE: &str = include_str!("../fixtures/nomad_node.json");
+const DRAINING_NODE_FIXTURE: &str = include_str!("../fixtures/nomad_node_draining.json");
+const ALLOCATIONS_FIXTURE: &str = include_str!("../fixtures/node_allocations.json");
+
+/// Builder of a Nomad node, as returned by `/v1/node/:id`
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeFixture(Value);
+
+impl NodeFixture {
+    /// A ready node that is eligible for scheduling and not draining
+    pub fn ready() -> Self {
+        NodeFixture(serde_json::from_str(NODE_FIXTURE).expect("valid node fixture"))
+    }
+
+    /// A node that missed its heartbeats
+    pub fn down() -> Self {
+        Self::ready().with_status(NodeStatus::Down)
+    }
+
+    /// Set the ID of the node
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.0["ID"] = json!(id);
+        self
+    }
+
+    /// Set the ID of the EC2 instance of the node, which is also used as its name
+    pub fn with_instance_id(mut self, instance_id: &str) -> Self {
+        self.0["Name"] = json!(instance_id);
+        self.0["Attributes"]["unique.platform.aws.instance-id"] = json!(instance_id);
+        self
+    }
+
+    /// Set the status of the node
+    pub fn with_status(mut self, status: NodeStatus) -> Self {
+        self.0["Status"] = json!(status);
+        self
+    }
+
+    /// Set the scheduling eligibility of the node
+    pub fn with_eligibility(mut self, eligibility: NodeEligibility) -> Self {
+        self.0["SchedulingEligibility"] = json!(eligibility);
+        self
+    }
+
+    /// Set the modify index of the node
+    pub fn with_modify_index(mut self, modify_index: u64) -> Self {
+        self.0["ModifyIndex"] = json!(modify_index);
+        self
+    }
+
+    /// Set a node attribute
+    pub fn with_attribute(mut self, name: &str, value: &str) -> Self {
+        self.0["Attributes"][name] = json!(value);
+        self
+    }
+
+    /// Drain the node, making it ineligible for scheduling
+    pub fn draining(mut self) -> Self {
+        let draining: Value =
+            serde_json::from_str(DRAINING_NODE_FIXTURE).expect("valid draining node fixture");
+        self.0["Drain"] = json!(true);
+        self.0["DrainStrategy"] = draining["DrainStrategy"].clone();
+        self.with_eligibility(NodeEligibility::Ineligible)
+    }
+
+    /// The node as JSON
+    pub fn value(&self) -> &Value {
+        &self.0
+    }
+
+    /// The node serialized as JSON
+    pub fn json(&self) -> String {
+        self.0.to_string()
+    }
+
+    /// The node
+    pub fn node(&self) -> Node {
+        serde_json::from_value(self.0.clone()).expect("valid node")
+    }
+
+    /// The node as returned by a blocking query at `index`
+    pub fn blocking(&self, index: u64) -> BlockingResponse<Node> {
+        BlockingResponse {
+            index,
+            data: self.node(),
+            diagnostics: Default::default(),
+        }
+    }
+
+    /// The node as listed by `/v1/nodes`
+    pub fn list_value(&self) -> Value {
+        let node = &self.0;
+        json!({
+            "Address": node["HTTPAddr"]
+                .as_str()
+                .and_then(|address| address.split(':').next()),
+            "CreateIndex": node["CreateIndex"],
+            "Datacenter": node["Datacenter"],
+            "Drain": node["Drain"],
+            "ID": node["ID"],
+            "ModifyIndex": node["ModifyIndex"],
+            "Name": node["Name"],
+            "NodeClass": node["NodeClass"],
+            "SchedulingEligibility": node["SchedulingEligibility"],
+            "Status": node["Status"],
+            "StatusDescription": node["StatusDescription"],
+            "Version": node["Attributes"]["nomad.version"],
+        })
+    }
+
+    /// The node as listed by `/v1/nodes`
+    pub fn in_list(&self) -> NodesInList {
+        serde_json::from_value(self.list_value()).expect("valid listed node")
+    }
+}
+
+/// Serialize a list of nodes as returned by `/v1/nodes`
+pub fn nodes_json(nodes: &[NodeFixture]) -> String {
+    Value::Array(nodes.iter().map(NodeFixture::list_value).collect()).to_string()
+}
+
+/// Builder of an allocation, as returned by `/v1/node/:id/allocations`
+#[derive(Clone, Debug, PartialEq)]
+pub struct AllocationFixture(Value);
+
+impl AllocationFixture {
+    /// A running allocation of a service job
+    pub fn running() -> Self {
+        let allocations: Vec<Value> =
+            serde_json::from_str(ALLOCATIONS_FIXTURE).expect("valid allocations fixture");
+        AllocationFixture(allocations[0].clone())
+    }
+
+    /// An allocation of a service job that has completed
+    pub fn complete() -> Self {
+        Self::running()
+            .with_desired_status("stop")
+            .with_client_status(AllocationClientStatus::Complete)
+    }
+
+    /// Set the ID of the allocation
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.0["ID"] = json!(id);
+        self
+    }
+
+    /// Place the allocation on the node
+    pub fn on_node(mut self, node_id: &str) -> Self {
+        self.0["NodeID"] = json!(node_id);
+        self
+    }
+
+    /// Set the ID of the job of the allocation
+    pub fn with_job_id(mut self, job_id: &str) -> Self {
+        self.0["JobID"] = json!(job_id);
+        self.0["Job"]["ID"] = json!(job_id);
+        self.0["Job"]["Name"] = json!(job_id);
+        self
+    }
+
+    /// Set the type of the job of the allocation, e.g. `service` or `system`
+    pub fn with_job_type(mut self, job_type: &str) -> Self {
+        self.0["Job"]["Type"] = json!(job_type);
+        self
+    }
+
+    /// Set the status of the allocation on the client
+    pub fn with_client_status(mut self, client_status: AllocationClientStatus) -> Self {
+        self.0["ClientStatus"] = json!(client_status);
+        self
+    }
+
+    /// Set the desired status of the allocation, e.g. `run` or `stop`
+    pub fn with_desired_status(mut self, desired_status: &str) -> Self {
+        self.0["DesiredStatus"] = json!(desired_status);
+        self
+    }
+
+    /// The allocation as JSON
+    pub fn value(&self) -> &Value {
+        &self.0
+    }
+
+    /// The allocation serialized as JSON
+    pub fn json(&self) -> String {
+        self.0.to_string()
+    }
+
+    /// The allocation
+    pub fn allocation(&self) -> Allocation {
+        serde_json::from_value(self.0.clone()).expect("valid allocation")
+    }
+}
+
+/// Serialize a list of allocations as returned by `/v1/node/:id/allocations`
+pub fn allocations_json(allocations: &[AllocationFixture]) -> String {
+    Value::Array(allocations.iter().map(|a| a.0.clone()).collect()).to_string()
+}
+
+/// Error of `reqwest` for a request that timed out
+pub fn timed_out() -> reqwest::Error {
+    struct TimingOut;
+
+    impl io::Write for TimingOut {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::TimedOut.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Timeouts of `reqwest` 0.9 are I/O errors, which it returns when copying a body fails too
+    let mut response: reqwest::Response = http::Response::new("body").into();
+    response
+        .copy_to(&mut TimingOut)
+        .expect_err("copying to fail")
+}
+
+/// Status and body of the responses scripted for each method and path
+type ScriptedResponses = HashMap<(String, String), VecDeque<(u16, String)>>;
+
+/// Transport answering the requests of a Nomad `Client` with scripted responses
+///
+/// Responses are matched by the method and path of requests. Responses scripted for the same
+/// request are returned in order, repeating the last one. Requests without a scripted response
+/// are answered with a `404`.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use nomad_drain::fixtures::{NodeFixture, StubTransport};
+/// use nomad_drain::nomad::Client;
+///
+/// let transport = Arc::new(StubTransport::new().with_response(
+///     "GET",
+///     "/v1/node/id",
+///     200,
+///     NodeFixture::ready().with_id("id").json(),
+/// ));
+/// let client = Client::builder("http://127.0.0.1:4646")
+///     .build()?
+///     .with_transport(transport.clone());
+/// assert_eq!("id", client.node_details("id", None, None)?.data.id);
+/// assert_eq!(vec!["GET /v1/node/id"], transport.requests());
+/// # Ok::<(), nomad_drain::Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct StubTransport {
+    responses: Mutex<ScriptedResponses>,
+    requests: Mutex<Vec<String>>,
+}
+
+impl StubTransport {
+    /// A transport without any scripted responses
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Answer a request to `path` with the `status` and `body`, after any earlier responses
+    pub fn with_response<S: Into<String>>(
+        self,
+        method: &str,
+        path: &str,
+        status: u16,
+        body: S,
+    ) -> Self {
+        self.responses
+            .lock()
+            .expect("Not to be poisoned")
+            .entry((method.to_string(), path.to_string()))
+            .or_default()
+            .push_back((status, body.into()));
+        self
+    }
+
+    /// Requests received so far, as `METHOD /path`
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().expect("Not to be poisoned").clone()
+    }
+}
+
+impl Transport for StubTransport {
+    fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response, reqwest::Error> {
+        let key = (
+            request.method().to_string(),
+            request.url().path().to_string(),
+        );
+        // The index grows with every request, like the index of a changing cluster
+        let index = {
+            let mut requests = self.requests.lock().expect("Not to be poisoned");
+            requests.push(format!("{} {}", key.0, key.1));
+            requests.len()
+        };
+        let (status, body) = {
+            let mut responses = self.responses.lock().expect("Not to be poisoned");
+            match responses.get_mut(&key) {
+                Some(queue) if queue.len() > 1 => queue.pop_front().expect("a response"),
+                Some(queue) => queue.front().cloned().expect("a response"),
+                None => (404, "Not found".to_string()),
+            }
+        };
+        Ok(http::Response::builder()
+            .status(status)
+            .header(NOMAD_INDEX_HEADER, index.to_string())
+            .body(body)
+            .expect("valid response")
+            .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_fixtures_are_built() {
+        let node = NodeFixture::ready()
+            .with_id("a")
+            .with_instance_id("i-123")
+            .draining()
+            .node();
+        assert_eq!("a", node.id);
+        assert_eq!(Some("i-123"), node.instance_id());
+        assert!(node.drain);
+        assert!(node.drain_strategy.is_some());
+        assert_eq!(NodeEligibility::Ineligible, node.scheduling_eligibility);
+
+        let listed = NodeFixture::down().with_id("b").in_list();
+        assert_eq!("b", listed.id);
+        assert_eq!(NodeStatus::Down, listed.status);
+        assert_eq!("10.161.242.244", listed.address);
+
+        let nodes: Vec<NodesInList> =
+            serde_json::from_str(&nodes_json(&[NodeFixture::ready(), NodeFixture::down()]))
+                .unwrap();
+        assert_eq!(2, nodes.len());
+    }
+
+    #[test]
+    fn allocation_fixtures_are_built() {
+        let allocation = AllocationFixture::complete()
+            .with_id("a")
+            .on_node("node")
+            .with_job_type("system")
+            .allocation();
+        assert_eq!("a", allocation.id);
+        assert_eq!("node", allocation.node_id);
+        assert!(allocation.is_terminal());
+        assert!(allocation.is_system());
+
+        let allocations: Vec<Allocation> =
+            serde_json::from_str(&allocations_json(&[AllocationFixture::running()])).unwrap();
+        assert!(!allocations[0].is_terminal());
+    }
+}
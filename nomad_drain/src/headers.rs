@@ -0,0 +1,95 @@
+//! Names of the headers used by the Nomad and Vault APIs
+//!
+//! Use these, and the helpers of `RequestBuilderExt`, when extending the clients with endpoints
+//! that this crate does not cover, so that requests are made the same way.
+use std::time::Duration;
+
+/// Header the Nomad ACL token is sent in
+pub const NOMAD_AUTH_HEADER: &str = "X-Nomad-Token";
+/// Header Nomad returns the index of the response in, for blocking queries
+pub const NOMAD_INDEX_HEADER: &str = "X-Nomad-Index";
+/// Header Nomad returns the token of the next page of a paginated listing in
+pub const NOMAD_NEXT_TOKEN_HEADER: &str = "X-Nomad-NextToken";
+/// Header the Nomad namespace of a request is sent in
+pub const NOMAD_NAMESPACE_HEADER: &str = "X-Nomad-Namespace";
+
+/// Header the Vault token is sent in
+pub const VAULT_TOKEN_HEADER: &str = "X-Vault-Token";
+/// Header the Vault namespace of a request is sent in
+pub const VAULT_NAMESPACE_HEADER: &str = "X-Vault-Namespace";
+/// Header requesting Vault to wrap the response, with the TTL of the wrapping token
+pub const VAULT_WRAP_TTL_HEADER: &str = "X-Vault-Wrap-TTL";
+/// Header Login MFA credentials are sent to Vault in
+pub const VAULT_MFA_HEADER: &str = "X-Vault-MFA";
+
+/// Helpers to add Nomad and Vault headers to requests
+///
+/// Implemented for both the synchronous and asynchronous `reqwest` request builders.
+pub trait RequestBuilderExt: Sized {
+    /// Add a header to the request
+    fn with_header(self, name: &'static str, value: &str) -> Self;
+
+    /// Authenticate the request to Nomad with the ACL token
+    fn nomad_token(self, token: &str) -> Self {
+        self.with_header(NOMAD_AUTH_HEADER, token)
+    }
+
+    /// Make the request to Nomad in the namespace
+    fn nomad_namespace(self, namespace: &str) -> Self {
+        self.with_header(NOMAD_NAMESPACE_HEADER, namespace)
+    }
+
+    /// Authenticate the request to Vault with the token
+    fn vault_token(self, token: &str) -> Self {
+        self.with_header(VAULT_TOKEN_HEADER, token)
+    }
+
+    /// Make the request to Vault in the namespace
+    fn vault_namespace(self, namespace: &str) -> Self {
+        self.with_header(VAULT_NAMESPACE_HEADER, namespace)
+    }
+
+    /// Ask Vault to wrap the response in a token living for `ttl`
+    fn vault_wrap_ttl(self, ttl: Duration) -> Self {
+        self.with_header(VAULT_WRAP_TTL_HEADER, &format!("{}s", ttl.as_secs()))
+    }
+}
+
+impl RequestBuilderExt for reqwest::RequestBuilder {
+    fn with_header(self, name: &'static str, value: &str) -> Self {
+        self.header(name, value)
+    }
+}
+
+impl RequestBuilderExt for reqwest::r#async::RequestBuilder {
+    fn with_header(self, name: &'static str, value: &str) -> Self {
+        self.header(name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_added_to_requests() -> Result<(), crate::Error> {
+        let request = reqwest::Client::new()
+            .get("http://127.0.0.1:8200/v1/secret/data/foo")
+            .vault_token("token")
+            .vault_namespace("ops")
+            .vault_wrap_ttl(Duration::from_secs(60))
+            .build()?;
+        assert_eq!("token", request.headers()[VAULT_TOKEN_HEADER]);
+        assert_eq!("ops", request.headers()[VAULT_NAMESPACE_HEADER]);
+        assert_eq!("60s", request.headers()[VAULT_WRAP_TTL_HEADER]);
+
+        let request = reqwest::Client::new()
+            .get("http://127.0.0.1:4646/v1/nodes")
+            .nomad_token("token")
+            .nomad_namespace("ops")
+            .build()?;
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
+        assert_eq!("ops", request.headers()[NOMAD_NAMESPACE_HEADER]);
+        Ok(())
+    }
+}
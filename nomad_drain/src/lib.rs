@@ -2,31 +2,96 @@
 
 mod error;
 
+pub mod asg;
 pub mod aws;
+pub mod batch;
+pub mod checkpoint;
+pub mod clock;
+pub mod compat;
+pub mod context;
+pub mod degraded;
+pub mod drain;
+pub mod events;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fixtures;
+pub mod headers;
+pub mod metrics;
 pub mod nomad;
+pub mod pause;
+pub mod plan;
+pub mod retry;
+pub mod ssm;
+pub mod tls;
 pub mod vault;
 
-pub use crate::error::Error;
+pub use crate::drain::NomadDrain;
+pub use crate::error::{display_chain, Error, ErrorKind};
 
 use std::fmt;
-use std::ops::Deref;
+use std::time::Duration;
 
 use futures::future::Future;
 use rusoto_core::credential::AwsCredentials;
 use rusoto_core::{DefaultCredentialsProvider, ProvideAwsCredentials, Region};
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 
 /// A wrapper around a String with custom implementation of Display and Debug to not leak
 /// secrets during logging.
-#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
-pub struct Secret(pub String);
-
-impl Deref for Secret {
-    type Target = String;
+///
+/// Secrets are compared in constant time, so that comparisons do not reveal how much of a secret
+/// was guessed correctly. The secret is only accessible with `expose_secret`, so that every use
+/// stands out, and its buffer is wiped when it is dropped.
+///
+/// Secrets are only serializable with the `serialize-secrets` feature. Wrap them in
+/// `SerializableSecret` to send them in request payloads.
+#[derive(Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Secret(String);
 
-    fn deref(&self) -> &Self::Target {
+impl Secret {
+    /// The secret itself. Take care not to log it.
+    pub fn expose_secret(&self) -> &str {
         &self.0
     }
+
+    /// The first `len` characters followed by `***`, to tell secrets apart in diagnostics
+    ///
+    /// Only use this with identifiers like token accessors, which are sensitive but cannot be used
+    /// as credentials. Never with the tokens themselves.
+    pub fn redacted_prefix(&self, len: usize) -> String {
+        let prefix: String = self.0.chars().take(len).collect();
+        format!("{}***", prefix)
+    }
+
+    /// The secret, serializable in the clear
+    pub fn serializable(&self) -> SerializableSecret<'_> {
+        SerializableSecret(self)
+    }
+}
+
+#[cfg(feature = "serialize-secrets")]
+impl Serialize for Secret {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq::constant_time_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+}
+
+impl Eq for Secret {}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
 }
 
 impl fmt::Debug for Secret {
@@ -41,26 +106,74 @@ impl fmt::Display for Secret {
     }
 }
 
-impl AsRef<str> for Secret {
-    fn as_ref(&self) -> &str {
-        &self.0
-    }
-}
-
 impl From<String> for Secret {
     fn from(s: String) -> Self {
         Secret(s)
     }
 }
 
+/// A secret that is serialized in the clear, for payloads that have to send it
+#[derive(Clone, Copy, Debug)]
+pub struct SerializableSecret<'a>(pub &'a Secret);
+
+impl<'a> Serialize for SerializableSecret<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.0.expose_secret())
+    }
+}
+
+/// Details of the build of this library
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BuildInfo {
+    /// Version of the crate
+    pub version: String,
+    /// Abbreviated git commit SHA the crate was built from, if built from a git checkout
+    pub git_sha: Option<String>,
+    /// Time of the build
+    pub built_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.version)?;
+        if let Some(ref git_sha) = self.git_sha {
+            write!(f, " ({})", git_sha)?;
+        }
+        write!(f, " built at {}", self.built_at.to_rfc3339())
+    }
+}
+
+/// Returns the details of the build of this library
+pub fn build_info() -> BuildInfo {
+    use chrono::TimeZone;
+
+    let git_sha = env!("NOMAD_DRAIN_GIT_SHA");
+    let timestamp = env!("NOMAD_DRAIN_BUILD_TIMESTAMP")
+        .parse()
+        .unwrap_or_default();
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: if git_sha.is_empty() {
+            None
+        } else {
+            Some(git_sha.to_string())
+        },
+        built_at: chrono::Utc.timestamp(timestamp, 0),
+    }
+}
+
 /// Use AWS credentials to obtain a token from Vault
 ///
 /// If the Vault AWS Authentication method has the
 /// [`iam_server_id_header_value`](https://www.vaultproject.io/api/auth/aws/index.html#iam_server_id_header_value)
 /// configured, you *must* provide the configured value in the `header_value` parameter.
 ///
-/// If `region` is `None`, we will infer the Region using the behaviour documented
-/// [here](https://rusoto.github.io/rusoto/rusoto_core/region/enum.Region.html#default).
+/// If `region` is `None`, the region of the STS endpoint is inferred as documented on
+/// `aws::VaultAwsAuthIamPayload::new`.
 pub fn login_to_vault(
     vault_address: &str,
     vault_auth_path: &str,
@@ -71,13 +184,103 @@ pub fn login_to_vault(
 ) -> Result<vault::Client, Error> {
     let aws_payload = aws::VaultAwsAuthIamPayload::new(aws_credentials, header_value, region);
 
-    vault::Client::login_aws_iam(
-        &vault_address,
-        vault_auth_path,
-        vault_auth_role,
-        &aws_payload,
-        None,
-    )
+    vault::Client::builder(vault_address)
+        .with_auth(vault::AwsIam {
+            path: vault_auth_path.to_string(),
+            role: vault_auth_role.to_string(),
+            payload: aws_payload,
+        })
+        .build()
+}
+
+/// Use AWS credentials to obtain a token from Vault, retrying while Vault cannot find the IAM
+/// principal yet
+///
+/// Newly created IAM roles can fail to login for a while due to IAM eventual consistency. Such
+/// failures are retried until `retry_window` has elapsed.
+///
+/// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
+/// CA certificate or require client authentication. See `login_to_vault` for the other
+/// parameters.
+#[allow(clippy::too_many_arguments)]
+pub fn login_to_vault_with_retry(
+    vault_address: &str,
+    vault_auth_path: &str,
+    vault_auth_role: &str,
+    aws_credentials: &AwsCredentials,
+    header_value: Option<&str>,
+    region: Option<Region>,
+    retry_window: Duration,
+    client: Option<reqwest::Client>,
+) -> Result<vault::Client, Error> {
+    let aws_payload = aws::VaultAwsAuthIamPayload::new(aws_credentials, header_value, region);
+
+    let builder = vault::Client::builder(vault_address)
+        .with_auth(vault::AwsIam {
+            path: vault_auth_path.to_string(),
+            role: vault_auth_role.to_string(),
+            payload: aws_payload,
+        })
+        .with_login_retry_window(retry_window);
+    match client {
+        Some(client) => builder.with_http_client(client),
+        None => builder,
+    }
+    .build()
+}
+
+/// Deserialize JSON, keeping track of the path to the field that failed to deserialize
+pub(crate) fn from_json_str<T>(
+    json: &str,
+) -> Result<T, serde_path_to_error::Error<serde_json::Error>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let deserializer = &mut serde_json::Deserializer::from_str(json);
+    serde_path_to_error::deserialize(deserializer)
+}
+
+/// Sends the HTTP requests of a client
+///
+/// Implemented by `reqwest::Client`, which sends requests over the network. Clients can be given
+/// another transport to answer requests without a server, like `fixtures::StubTransport`.
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Send the request and return the response
+    fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response, reqwest::Error>;
+}
+
+impl Transport for reqwest::Client {
+    fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response, reqwest::Error> {
+        reqwest::Client::execute(self, request)
+    }
+}
+
+/// Execute a request with an asynchronous HTTP client, resolving to the response status, headers
+/// and body
+pub(crate) fn execute_async(
+    client: &reqwest::r#async::Client,
+    request: reqwest::r#async::Request,
+) -> impl Future<Item = (reqwest::StatusCode, reqwest::header::HeaderMap, String), Error = Error> {
+    use futures::Stream;
+
+    log::debug!("Executing request: {:#?}", request);
+    client
+        .execute(request)
+        .and_then(|response| {
+            log::debug!("Response received: {:#?}", response);
+            let status = response.status();
+            let headers = response.headers().clone();
+            response
+                .into_body()
+                .concat2()
+                .map(move |body| (status, headers, body))
+        })
+        .from_err()
+        .map(|(status, headers, body)| {
+            let body = String::from_utf8_lossy(&body).into_owned();
+            log::debug!("Response body: {}", body);
+            (status, headers, body)
+        })
 }
 
 /// Use the priority documented
@@ -110,6 +313,56 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn secrets_are_compared_and_redacted() {
+        let accessor = Secret("8609694a-cdbc-db9b-d345-e782dbb562ed".to_string());
+
+        assert_eq!(accessor, accessor.clone());
+        assert_ne!(accessor, Secret("8609694a".to_string()));
+        assert_eq!("8609694a***", accessor.redacted_prefix(8));
+        assert_eq!("***", format!("{}", accessor));
+        assert_eq!("***", format!("{:?}", accessor));
+        assert_eq!(
+            "8609694a-cdbc-db9b-d345-e782dbb562ed",
+            accessor.expose_secret()
+        );
+    }
+
+    #[test]
+    fn secrets_are_only_serialized_explicitly() -> Result<(), crate::Error> {
+        let token = Secret("token".to_string());
+        assert_eq!(r#""token""#, serde_json::to_string(&token.serializable())?);
+        Ok(())
+    }
+
+    #[test]
+    fn build_info_has_crate_version() {
+        let info = build_info();
+        assert_eq!(env!("CARGO_PKG_VERSION"), info.version);
+        assert!(info.to_string().starts_with(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn json_errors_include_path() {
+        #[derive(Deserialize, Debug)]
+        struct Inner {
+            #[allow(dead_code)]
+            value: u64,
+        }
+        #[derive(Deserialize, Debug)]
+        struct Outer {
+            #[allow(dead_code)]
+            inner: Vec<Inner>,
+        }
+
+        match from_json_str::<Outer>(r#"{"inner": [{"value": 1}, {"value": "two"}]}"#)
+            .map_err(Error::from)
+        {
+            Err(Error::JsonPathError { path, .. }) => assert_eq!("inner[1].value", path),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
     /// Requires Mock server for this test
     #[test]
     fn login_to_vault_is_successful() -> Result<(), crate::Error> {
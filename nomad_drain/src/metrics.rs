@@ -0,0 +1,171 @@
+//! Metrics of drains, for dashboards and alarms
+//!
+//! Metrics are emitted as log lines in the CloudWatch
+//! [Embedded Metric Format](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html),
+//! which CloudWatch Logs extracts metrics from without any API call. Metrics are dimensioned by
+//! the identity of the Nomad cluster.
+use std::fmt::Debug;
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::nomad::ClusterIdentity;
+
+/// Time a drain took to complete
+pub const DRAIN_DURATION_SECONDS: &str = "DrainDurationSeconds";
+/// Number of allocations migrated off drained nodes
+pub const ALLOCATIONS_MIGRATED: &str = "AllocationsMigrated";
+/// Number of drains that failed
+pub const DRAIN_FAILURES: &str = "DrainFailures";
+
+/// Unit of a metric
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Unit {
+    Seconds,
+    Count,
+}
+
+/// A single value of a metric
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub value: f64,
+    pub unit: Unit,
+}
+
+impl Metric {
+    /// A count of things
+    pub fn count(name: &'static str, value: usize) -> Self {
+        Self {
+            name,
+            value: value as f64,
+            unit: Unit::Count,
+        }
+    }
+
+    /// A duration in seconds
+    pub fn seconds(name: &'static str, value: u64) -> Self {
+        Self {
+            name,
+            value: value as f64,
+            unit: Unit::Seconds,
+        }
+    }
+}
+
+/// A destination for metrics
+pub trait MetricsSink: Debug + Send + Sync {
+    /// Emit the metrics of the cluster
+    fn emit(&self, cluster: &ClusterIdentity, metrics: &[Metric]) -> Result<(), crate::Error>;
+}
+
+/// Metrics printed to standard output in the Embedded Metric Format
+///
+/// AWS Lambda forwards standard output to CloudWatch Logs, which turns the lines into metrics in
+/// the namespace.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EmbeddedMetrics {
+    pub namespace: String,
+}
+
+impl EmbeddedMetrics {
+    /// Emit metrics in the namespace
+    pub fn new(namespace: &str) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+        }
+    }
+
+    /// The log line of the metrics at `timestamp`
+    pub fn format(
+        &self,
+        cluster: &ClusterIdentity,
+        metrics: &[Metric],
+        timestamp: chrono::DateTime<chrono::Utc>,
+    ) -> Value {
+        let dimensions: Vec<(&str, &str)> = vec![
+            ("Cluster", cluster.name.as_ref()),
+            ("Region", cluster.region.as_ref()),
+            ("Datacenter", cluster.datacenter.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name, value.as_str())))
+        .collect();
+
+        let definitions: Vec<Value> = metrics
+            .iter()
+            .map(|metric| json!({ "Name": metric.name, "Unit": metric.unit }))
+            .collect();
+        let mut line = Map::new();
+        line.insert(
+            "_aws".to_string(),
+            json!({
+                "Timestamp": timestamp.timestamp_millis(),
+                "CloudWatchMetrics": [{
+                    "Namespace": self.namespace,
+                    "Dimensions": [dimensions.iter().map(|(name, _)| name).collect::<Vec<_>>()],
+                    "Metrics": definitions,
+                }],
+            }),
+        );
+        for (name, value) in dimensions {
+            line.insert(name.to_string(), json!(value));
+        }
+        for metric in metrics {
+            line.insert(metric.name.to_string(), json!(metric.value));
+        }
+        Value::Object(line)
+    }
+}
+
+impl MetricsSink for EmbeddedMetrics {
+    fn emit(&self, cluster: &ClusterIdentity, metrics: &[Metric]) -> Result<(), crate::Error> {
+        println!("{}", self.format(cluster, metrics, chrono::Utc::now()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn metrics_are_formatted_as_embedded_metrics() {
+        let cluster = ClusterIdentity {
+            name: Some("production".to_string()),
+            region: Some("global".to_string()),
+            datacenter: None,
+        };
+        let line = EmbeddedMetrics::new("NomadDrain").format(
+            &cluster,
+            &[
+                Metric::seconds(DRAIN_DURATION_SECONDS, 90),
+                Metric::count(ALLOCATIONS_MIGRATED, 3),
+            ],
+            chrono::Utc.timestamp(1_546_300_800, 0),
+        );
+
+        assert_eq!(
+            json!({
+                "_aws": {
+                    "Timestamp": 1_546_300_800_000_i64,
+                    "CloudWatchMetrics": [{
+                        "Namespace": "NomadDrain",
+                        "Dimensions": [["Cluster", "Region"]],
+                        "Metrics": [
+                            { "Name": "DrainDurationSeconds", "Unit": "Seconds" },
+                            { "Name": "AllocationsMigrated", "Unit": "Count" },
+                        ],
+                    }],
+                },
+                "Cluster": "production",
+                "Region": "global",
+                "DrainDurationSeconds": 90.0,
+                "AllocationsMigrated": 3.0,
+            }),
+            line
+        );
+    }
+}
@@ -1,25 +1,147 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Debug};
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use futures::future::{self, Either, Future, Loop};
 use log::{debug, info, warn};
-use reqwest::{Client as HttpClient, ClientBuilder, RequestBuilder};
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use reqwest::r#async::{Client as AsyncHttpClient, ClientBuilder as AsyncClientBuilder};
+use reqwest::{Client as HttpClient, ClientBuilder as HttpClientBuilder, RequestBuilder};
 use serde::{Deserialize, Serialize};
 
-const NOMAD_AUTH_HEADER: &str = "X-Nomad-Token";
-const NOMAD_INDEX_HEADER: &str = "X-Nomad-Index";
+use crate::clock::{Clock, SystemClock};
+use crate::compat::Compatibility;
+use crate::context::RequestContext;
+use crate::headers::{RequestBuilderExt, NOMAD_INDEX_HEADER, NOMAD_NEXT_TOKEN_HEADER};
+use crate::retry::RetryPolicy;
+use crate::tls::TlsConfig;
+
+/// Timeout of the default HTTP client, long enough for
+/// [blocking queries](https://www.nomadproject.io/api/index.html#blocking-queries)
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(360);
+
+/// First characters of node IDs, which are UUIDs, used to shard node listings
+const NODE_ID_SHARDS: &str = "0123456789abcdef";
+
+/// Node attributes fingerprinted by the Nomad AWS environment fingerprinter
+const AWS_INSTANCE_ID_ATTRIBUTE: &str = "unique.platform.aws.instance-id";
+const AWS_AVAILABILITY_ZONE_ATTRIBUTE: &str = "platform.aws.placement.availability-zone";
+const AWS_INSTANCE_TYPE_ATTRIBUTE: &str = "platform.aws.instance-type";
+const AWS_AMI_ID_ATTRIBUTE: &str = "platform.aws.ami-id";
+
+/// Response headers captured in `ResponseDiagnostics`
+const DIAGNOSTIC_HEADERS: &[&str] = &[
+    NOMAD_INDEX_HEADER,
+    NOMAD_NEXT_TOKEN_HEADER,
+    "X-Nomad-KnownLeader",
+    "X-Nomad-LastContact",
+    "X-Request-Id",
+    "X-Amzn-RequestId",
+    "X-Amzn-Trace-Id",
+    "X-RateLimit-Limit",
+    "X-RateLimit-Remaining",
+    "X-RateLimit-Reset",
+    "Retry-After",
+    "Server",
+    "Via",
+];
+
+/// Hook to authenticate requests to a proxy in front of the Nomad API
+///
+/// The signer is called with every request, including retries, after the Nomad token has been
+/// added, so it can add headers like `Authorization` or sign the complete request.
+pub trait RequestSigner: Debug + Send + Sync {
+    /// Add authentication to the request
+    fn sign(&self, request: &mut reqwest::Request) -> Result<(), crate::Error>;
+}
+
+/// Signer adding a static bearer token, such as an OIDC token, to the `Authorization` header
+#[derive(Clone, Debug)]
+pub struct BearerToken(pub crate::Secret);
+
+impl RequestSigner for BearerToken {
+    fn sign(&self, request: &mut reqwest::Request) -> Result<(), crate::Error> {
+        let value =
+            reqwest::header::HeaderValue::from_str(&format!("Bearer {}", self.0.expose_secret()))?;
+        request
+            .headers_mut()
+            .insert(reqwest::header::AUTHORIZATION, value);
+        Ok(())
+    }
+}
 
 /// Nomad API Client
 #[derive(Clone, Debug)]
 pub struct Client {
     address: String,
     token: Option<crate::Secret>,
+    signer: Option<Arc<dyn RequestSigner>>,
+    region: Option<String>,
+    /// Drain API of the server, probed on the first drain and shared between clones
+    drain_api: Arc<Mutex<Option<DrainApi>>>,
+    drain_meta: Option<HashMap<String, String>>,
+    max_nodes: Option<usize>,
+    shard_node_lists: bool,
+    acl_disabled: bool,
+    namespace: Option<String>,
+    retry_policy: RetryPolicy,
+    clock: Arc<dyn Clock>,
+    wait_timeout: Duration,
+    context: RequestContext,
     client: HttpClient,
+    transport: Option<Arc<dyn crate::Transport>>,
+}
+
+/// Builder of Nomad `Client`s
+///
+/// ```rust,no_run
+/// # use nomad_drain::nomad::Client;
+/// # use nomad_drain::tls::TlsConfig;
+/// let client = Client::builder("https://nomad.service.consul:4646")
+///     .with_token("token")
+///     .with_tls(&TlsConfig::from_nomad_env())
+///     .with_region("global")
+///     .build()?;
+/// # Ok::<(), nomad_drain::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    address: String,
+    token: Option<crate::Secret>,
+    candidate_tokens: Vec<crate::Secret>,
+    http_client: Option<HttpClient>,
+    tls: Option<TlsConfig>,
+    timeout: Duration,
+    user_agent: Option<String>,
+    namespace: Option<String>,
+    region: Option<String>,
+    retry_policy: RetryPolicy,
+    wait_timeout: Duration,
+    context: RequestContext,
+}
+
+/// Asynchronous Nomad API Client
+///
+/// Methods return futures that can be driven by a tokio runtime instead of blocking a thread.
+///
+/// Unlike `Client`, requests are neither retried with a `RetryPolicy` nor scoped to a namespace,
+/// signed with a `RequestSigner` or made in a `RequestContext`. Deadlines, trace IDs and dry runs
+/// are therefore not supported, and drains are submitted even for dry runs. Bound monitoring with
+/// the `max_duration` of `monitor_node_drain` instead.
+#[derive(Clone, Debug)]
+pub struct AsyncClient {
+    address: String,
+    token: Option<crate::Secret>,
+    region: Option<String>,
+    client: AsyncHttpClient,
+    drain_api: Arc<Mutex<Option<DrainApi>>>,
 }
 
 /// Node details in List of nodes
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 #[serde(rename_all = "PascalCase")]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct NodesInList {
     pub address: String,
     pub datacenter: String,
@@ -35,6 +157,14 @@ pub struct NodesInList {
     pub status_description: String,
     #[cfg(all_node_details)]
     pub drivers: HashMap<String, DriverInfo>,
+
+    // Fields known to be ignored, so that strict deserialization only rejects new fields
+    #[cfg(feature = "strict")]
+    #[serde(default, skip_serializing)]
+    create_index: Option<serde_json::Value>,
+    #[cfg(all(feature = "strict", not(all_node_details)))]
+    #[serde(default, skip_serializing)]
+    drivers: Option<serde_json::Value>,
 }
 
 /// Node Data returned from Nomad API
@@ -42,6 +172,7 @@ pub struct NodesInList {
 /// [Reference](https://github.com/hashicorp/nomad-java-sdk/blob/master/sdk/src/main/java/com/hashicorp/nomad/apimodel/Node.java)
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 #[serde(rename_all = "PascalCase")]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Node {
     /// ID of the node
     #[serde(rename = "ID")]
@@ -102,6 +233,26 @@ pub struct Node {
     // /// Events Information
     // #[serde(default)]
     // pub events: Vec<HashMap<String, serde_json::Value>>,
+
+    // Fields known to be ignored, so that strict deserialization only rejects new fields
+    #[cfg(feature = "strict")]
+    #[serde(default, skip_serializing)]
+    events: Option<serde_json::Value>,
+    #[cfg(feature = "strict")]
+    #[serde(default, skip_serializing)]
+    resources: Option<serde_json::Value>,
+    #[cfg(all(feature = "strict", not(all_node_details)))]
+    #[serde(default, skip_serializing)]
+    drivers: Option<serde_json::Value>,
+    #[cfg(all(feature = "strict", not(all_node_details)))]
+    #[serde(default, skip_serializing)]
+    links: Option<serde_json::Value>,
+    #[cfg(all(feature = "strict", not(all_node_details)))]
+    #[serde(default, skip_serializing)]
+    meta: Option<serde_json::Value>,
+    #[cfg(all(feature = "strict", not(all_node_details)))]
+    #[serde(default, skip_serializing)]
+    reserved: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Copy)]
@@ -115,6 +266,91 @@ pub enum NodeStatus {
     Down,
 }
 
+/// Allocation placed on a node
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Allocation {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "NodeID")]
+    pub node_id: String,
+    #[serde(rename = "JobID")]
+    pub job_id: String,
+    /// Job the allocation belongs to
+    #[serde(default)]
+    pub job: Option<AllocationJob>,
+    pub task_group: String,
+    pub desired_status: String,
+    pub client_status: AllocationClientStatus,
+}
+
+impl Allocation {
+    /// Whether the allocation has stopped running on the client
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self.client_status,
+            AllocationClientStatus::Complete
+                | AllocationClientStatus::Failed
+                | AllocationClientStatus::Lost
+        )
+    }
+
+    /// Whether the allocation belongs to a system job. System jobs are not migrated by drains.
+    pub fn is_system(&self) -> bool {
+        self.job
+            .as_ref()
+            .map(|job| job.job_type == "system")
+            .unwrap_or(false)
+    }
+}
+
+/// Summary of an allocation, for reporting what was running on a node
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AllocationSummary {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "JobID")]
+    pub job_id: String,
+    pub task_group: String,
+    pub client_status: AllocationClientStatus,
+    pub desired_status: String,
+}
+
+impl From<&Allocation> for AllocationSummary {
+    fn from(allocation: &Allocation) -> Self {
+        Self {
+            id: allocation.id.clone(),
+            job_id: allocation.job_id.clone(),
+            task_group: allocation.task_group.clone(),
+            client_status: allocation.client_status,
+            desired_status: allocation.desired_status.clone(),
+        }
+    }
+}
+
+/// Subset of the job details embedded in an allocation
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AllocationJob {
+    /// Type of job, e.g. `service`, `batch` or `system`
+    #[serde(rename = "Type")]
+    pub job_type: String,
+}
+
+/// Status of an allocation on the client
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AllocationClientStatus {
+    Pending,
+    Running,
+    Complete,
+    Failed,
+    Lost,
+    Unknown,
+}
+
 /// Node Driver Information
 #[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
 #[serde(rename_all = "PascalCase")]
@@ -200,25 +436,127 @@ pub struct DrainStrategy {
     pub force_deadline: chrono::DateTime<chrono::Utc>,
 }
 
+impl DrainStrategy {
+    /// Time at which allocations that have not migrated are stopped, unless the drain has no
+    /// deadline
+    ///
+    /// Nomad leaves the force deadline at the zero time of Go for drains without a deadline.
+    pub fn deadline(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        if self.force_deadline.timestamp() <= 0 {
+            None
+        } else {
+            Some(self.force_deadline)
+        }
+    }
+
+    /// Time left at `now` until allocations that have not migrated are stopped, unless the drain
+    /// has no deadline
+    pub fn time_until_deadline(&self, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+        self.deadline()
+            .map(|deadline| (deadline - now).to_std().unwrap_or_default())
+    }
+}
+
 /// Specification for draining
-#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
 #[serde(default, rename_all = "PascalCase")]
 pub struct DrainSpec {
-    /// Deadline in seconds
-    pub deadline: u64,
+    /// Deadline by which the drain must complete
+    pub deadline: Deadline,
     /// Whether system jobs are ignored
     pub ignore_system_jobs: bool,
 }
 
-impl Default for DrainSpec {
-    fn default() -> Self {
-        Self {
-            deadline: 3600, // 1 hour
-            ignore_system_jobs: false,
+/// Deadline of a drain, after which allocations remaining on the node are stopped
+///
+/// Deadlines are equal when Nomad treats them the same, so `Deadline::Default` equals a
+/// `Deadline::Duration` of one hour.
+#[derive(Clone, Copy, Debug)]
+pub enum Deadline {
+    /// One hour, the default of the Nomad CLI
+    Default,
+    /// Stop remaining allocations once the duration has elapsed
+    Duration(Duration),
+    /// Stop all allocations immediately
+    Force,
+    /// Wait for allocations to migrate for as long as it takes
+    NoDeadline,
+}
+
+impl Deadline {
+    /// Duration of `Deadline::Default`
+    pub const DEFAULT_DURATION: Duration = Duration::from_secs(3600);
+
+    /// Deadline as the Nomad API represents it: a negative number to force the drain, zero for no
+    /// deadline and the duration in nanoseconds otherwise
+    pub fn as_nanoseconds(self) -> i64 {
+        let duration = match self {
+            Deadline::Default => Self::DEFAULT_DURATION,
+            Deadline::Duration(duration) => duration,
+            Deadline::Force => return -1,
+            Deadline::NoDeadline => return 0,
+        };
+        std::cmp::min(duration.as_nanos(), i64::MAX as u128) as i64
+    }
+
+    /// Duration of the deadline, if it has one
+    pub fn duration(self) -> Option<Duration> {
+        match self {
+            Deadline::Default => Some(Self::DEFAULT_DURATION),
+            Deadline::Duration(duration) => Some(duration),
+            Deadline::Force | Deadline::NoDeadline => None,
+        }
+    }
+
+    /// Deadline from the Nomad API representation
+    pub fn from_nanoseconds(nanoseconds: i64) -> Self {
+        match nanoseconds {
+            nanoseconds if nanoseconds < 0 => Deadline::Force,
+            0 => Deadline::NoDeadline,
+            nanoseconds => Deadline::Duration(Duration::from_nanos(nanoseconds as u64)),
         }
     }
 }
 
+impl From<Duration> for Deadline {
+    fn from(duration: Duration) -> Self {
+        Deadline::Duration(duration)
+    }
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_nanoseconds() == other.as_nanoseconds()
+    }
+}
+
+impl Eq for Deadline {}
+
+impl Default for Deadline {
+    /// The Nomad CLI default
+    fn default() -> Self {
+        Deadline::Default
+    }
+}
+
+impl Serialize for Deadline {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_i64(self.as_nanoseconds())
+    }
+}
+
+impl<'de> Deserialize<'de> for Deadline {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(Self::from_nanoseconds(i64::deserialize(deserializer)?))
+    }
+}
+
 /// Node eligibility for scheduling
 #[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Copy)]
 #[serde(rename_all = "lowercase")]
@@ -246,410 +584,3815 @@ struct NodeEligibilityRequest<'a> {
     pub eligibility: NodeEligibility,
 }
 
-#[derive(Deserialize, Eq, PartialEq, Clone, Debug)]
+/// Response to updating the eligibility or drain strategy of a node
+///
+/// Indices are accepted as numbers or strings, since some proxies re-encode them as strings.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
 #[serde(rename_all = "PascalCase")]
-struct NodeEligibilityResponse {
-    pub eval_create_index: u128,
-    #[serde(rename = "EvalIDs")]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
+pub struct NodeEligibilityResponse {
+    /// Raft index of the evaluations created by the update
+    #[serde(default, deserialize_with = "deserialize_index")]
+    pub eval_create_index: u64,
+    /// IDs of the evaluations created by the update
+    #[serde(rename = "EvalIDs", default)]
     pub eval_ids: Option<Vec<String>>,
-    pub index: u128,
-    pub node_modify_index: u128,
+    /// Raft index of the update
+    #[serde(default, deserialize_with = "deserialize_index")]
+    pub index: u64,
+    /// Raft index the node was modified at
+    #[serde(default, deserialize_with = "deserialize_index")]
+    pub node_modify_index: u64,
+    /// Last index known to the server, returned by newer Nomad versions
+    #[serde(default, deserialize_with = "deserialize_optional_index")]
+    pub last_index: Option<u64>,
+}
+
+impl NodeEligibilityResponse {
+    /// IDs of the evaluations created by the update, if any
+    pub fn eval_ids(&self) -> &[String] {
+        self.eval_ids.as_deref().unwrap_or_default()
+    }
+}
+
+/// Evaluation of a job, created when the state of the cluster changes
+///
+/// Draining a node creates evaluations to place the allocations migrated off the node.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Evaluation {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "JobID")]
+    pub job_id: String,
+    /// What triggered the evaluation, e.g. `node-drain`
+    pub triggered_by: String,
+    /// One of `blocked`, `pending`, `complete`, `failed` or `canceled`
+    pub status: String,
+    #[serde(default)]
+    pub status_description: String,
+    /// Evaluation created for allocations that could not be placed, if any
+    #[serde(default)]
+    pub blocked_eval: String,
+    /// Metrics of the task groups that failed to be placed, keyed by task group
+    #[serde(rename = "FailedTGAllocs", default)]
+    pub failed_tg_allocs: Option<HashMap<String, serde_json::Value>>,
+    pub create_index: u64,
+    pub modify_index: u64,
+}
+
+impl Evaluation {
+    /// Whether the evaluation has been processed by the scheduler
+    pub fn is_terminal(&self) -> bool {
+        matches!(self.status.as_str(), "complete" | "failed" | "canceled")
+    }
+
+    /// Task groups with allocations that could not be placed
+    pub fn failed_task_groups(&self) -> Vec<&str> {
+        let mut task_groups: Vec<&str> = self
+            .failed_tg_allocs
+            .iter()
+            .flat_map(HashMap::keys)
+            .map(String::as_str)
+            .collect();
+        task_groups.sort();
+        task_groups
+    }
 }
 
 #[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
 struct NodeDrainRequest<'a, 'b> {
     #[serde(rename = "NodeID")]
     pub node_id: &'a str,
-    #[serde(rename = "DrainSpec")]
-    pub drain_spec: &'b DrainSpec,
+    /// No drain strategy cancels the drain of the node
+    pub drain_spec: Option<&'b DrainSpec>,
+    pub mark_eligible: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<&'b HashMap<String, String>>,
 }
 
-// These are the same
-type NodeDrainResponse = NodeEligibilityResponse;
+impl<'a, 'b> NodeDrainRequest<'a, 'b> {
+    /// Payload to drain a node with the semantics of the drain API
+    fn new(
+        api: DrainApi,
+        node_id: &'a str,
+        drain_spec: &'b DrainSpec,
+        meta: Option<&'b HashMap<String, String>>,
+    ) -> Self {
+        let meta = match meta {
+            Some(meta) if !api.supports_meta() => {
+                debug!(
+                    "Nomad does not support drain metadata. Not sending {:?}",
+                    meta
+                );
+                None
+            }
+            meta => meta,
+        };
+        Self {
+            node_id,
+            drain_spec: Some(drain_spec),
+            mark_eligible: false,
+            meta,
+        }
+    }
 
-/// Nomad Responses that support blocking requests
-///
-/// See the [documentation](https://www.nomadproject.io/api/index.html#blocking-queries) for more
-/// details
-#[derive(Eq, PartialEq, Clone, Debug)]
-pub struct BlockingResponse<T> {
-    /// The index indicating the "change ID" for the current response
-    pub index: u64,
-    /// The actual data of the response
-    pub data: T,
+    /// Payload to cancel the drain of a node, making it eligible again
+    fn cancel(node_id: &'a str) -> Self {
+        Self {
+            node_id,
+            drain_spec: None,
+            mark_eligible: true,
+            meta: None,
+        }
+    }
 }
 
-impl Client {
-    /// Create a new Nomad Client
-    ///
-    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
-    /// CA certificate or require client authentication.
-    /// The default client has a timeout set to 6 minutes to allow supporting Nomad's
-    /// [blocking queries](https://www.nomadproject.io/api/index.html#blocking-queries). If you
-    /// use your own client, make sure to set this as well.
-    #[allow(clippy::new_ret_no_self)]
-    pub fn new<S1, S2>(
-        address: S1,
-        token: Option<S2>,
-        client: Option<HttpClient>,
-    ) -> Result<Self, crate::Error>
-    where
-        S1: AsRef<str>,
-        S2: AsRef<str>,
-    {
-        let client = match client {
-            Some(client) => client,
-            None => ClientBuilder::new()
-                .timeout(Some(Duration::from_secs(360)))
-                .build()?,
-        };
+/// Semantics of the node drain API, which differ between Nomad versions
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DrainApi {
+    /// Nomad 0.8 to 1.0: drain strategy with a deadline in nanoseconds and `MarkEligible`
+    V0_8,
+    /// Nomad 1.1 and later, including 1.6: drains additionally record `Meta` on the node
+    V1_1,
+}
 
-        Ok(Self {
-            client,
-            address: address.as_ref().to_string(),
-            token: token.map(|s| From::from(s.as_ref().to_string())),
+impl DrainApi {
+    /// Drain API of a Nomad version
+    pub fn for_version(version: &NomadVersion) -> Result<Self, crate::Error> {
+        if crate::compat::NOMAD.check(version) == Compatibility::Unsupported {
+            Err(crate::Error::UnsupportedNomadVersion {
+                version: version.to_string(),
+            })?;
+        }
+        Ok(if *version < NomadVersion::new(1, 1, 0) {
+            DrainApi::V0_8
+        } else {
+            DrainApi::V1_1
         })
     }
 
-    /// Returns the Nomad Server Address
-    pub fn address(&self) -> &str {
-        &self.address
+    /// Whether drains can record metadata on the node
+    pub fn supports_meta(self) -> bool {
+        self == DrainApi::V1_1
     }
+}
 
-    /// Reurns the Nomad Token, if any
-    pub fn token(&self) -> Option<&str> {
-        self.token.as_ref().map(|s| s.as_str())
+impl Default for DrainApi {
+    /// The oldest supported API, which newer servers accept as well
+    fn default() -> Self {
+        DrainApi::V0_8
     }
+}
 
-    /// Returns the HTTP Client used
-    pub fn http_client(&self) -> &HttpClient {
-        &self.client
-    }
+/// Response to updating the drain strategy of a node. The same as `NodeEligibilityResponse`.
+pub type NodeDrainResponse = NodeEligibilityResponse;
 
-    fn execute_request<T>(&self, request: reqwest::Request) -> Result<T, crate::Error>
-    where
-        T: serde::de::DeserializeOwned + Debug,
-    {
-        debug!("Making request: {:#?}", request);
-        let mut response = self.client.execute(request)?;
-        debug!("Received response: {:#?}", response);
-        let body = response.text()?;
-        debug!("Response body: {}", body);
-        let details = serde_json::from_str(&body)?;
-        debug!("Deserialized Details: {:#?}", details);
-        Ok(details)
-    }
+/// Response to purging a node. The same as `NodeEligibilityResponse`.
+pub type NodePurgeResponse = NodeEligibilityResponse;
 
-    fn execute_indexed_request<T>(
-        &self,
-        request: reqwest::Request,
-    ) -> Result<BlockingResponse<T>, crate::Error>
-    where
-        T: serde::de::DeserializeOwned + Debug,
-    {
-        debug!("Making request: {:#?}", request);
-        let mut response = self.client.execute(request)?;
-        debug!("Received response: {:#?}", response);
-        let body = response.text()?;
-        debug!("Response body: {}", body);
-        let details = serde_json::from_str(&body)?;
-        debug!("Deserialized Details: {:#?}", details);
-        Self::make_indexed_response(&response, details)
-    }
+/// Response to stopping an allocation
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AllocationStopResponse {
+    /// ID of the evaluation created to replace the allocation
+    #[serde(rename = "EvalID")]
+    pub eval_id: String,
+    /// Raft index of the update
+    #[serde(default, deserialize_with = "deserialize_index")]
+    pub index: u64,
+}
 
-    /// Get Information about a specific Node ID
-    ///
-    /// Supply the optional parameters to take advantage of
-    /// [blocking queries](https://www.nomadproject.io/api/index.html#blocking-queries)
-    pub fn node_details(
-        &self,
-        node_id: &str,
-        wait_index: Option<u64>,
-        wait_timeout: Option<Duration>,
-    ) -> Result<BlockingResponse<Node>, crate::Error> {
-        info!("Requesting Nomad Node {} details", node_id);
-        let request = self.build_node_details_request(node_id, wait_index, wait_timeout)?;
-        self.execute_indexed_request(request)
-    }
+#[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AllocationSignalRequest<'a> {
+    signal: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task: Option<&'a str>,
+}
 
-    /// Build requests to get node details
-    fn build_node_details_request(
-        &self,
-        node_id: &str,
-        wait_index: Option<u64>,
-        wait_timeout: Option<Duration>,
-    ) -> Result<reqwest::Request, crate::Error> {
-        let address = format!("{}/v1/node/{}", &self.address, node_id);
-        let request = self.client.get(&address);
-        let request = self.add_nomad_token_header(request);
-        let request = Self::add_blocking_requests(request, wait_index, wait_timeout);
-        Ok(request.build()?)
-    }
+/// A Raft index, either as a number or as a string
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Index {
+    Number(u64),
+    String(String),
+}
 
-    /// Return a list of nodes
-    ///
-    /// Supply the optional parameters to take advantage of
-    /// [blocking queries](https://www.nomadproject.io/api/index.html#blocking-queries)
-    fn nodes(
-        &self,
-        wait_index: Option<u64>,
-        wait_timeout: Option<Duration>,
-    ) -> Result<BlockingResponse<Vec<NodesInList>>, crate::Error> {
-        info!("Requesting list of Nomad nodes");
-        let request = self.build_nodes_request(wait_index, wait_timeout)?;
-        self.execute_indexed_request(request)
+impl Index {
+    fn into_u64<E: serde::de::Error>(self) -> Result<u64, E> {
+        match self {
+            Index::Number(index) => Ok(index),
+            Index::String(index) => index.trim().parse().map_err(E::custom),
+        }
     }
+}
 
-    /// Build request to retrieve list of nodes
-    fn build_nodes_request(
-        &self,
-        wait_index: Option<u64>,
-        wait_timeout: Option<Duration>,
-    ) -> Result<reqwest::Request, crate::Error> {
-        let address = format!("{}/v1/nodes", &self.address);
-        let request = self.client.get(&address);
-        let request = self.add_nomad_token_header(request);
-        let request = Self::add_blocking_requests(request, wait_index, wait_timeout);
-        Ok(request.build()?)
-    }
+fn deserialize_index<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Index::deserialize(deserializer)?.into_u64()
+}
 
-    /// Given an AWS Instance ID, find the Node details
-    ///
-    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
+fn deserialize_optional_index<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<Index>::deserialize(deserializer)?
+        .map(Index::into_u64)
+        .transpose()
+}
+
+/// Details of a Nomad ACL token, without the secret
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AclToken {
+    /// Accessor ID of the token
+    #[serde(rename = "AccessorID")]
+    pub accessor_id: String,
+    /// Name of the token
+    #[serde(default)]
+    pub name: String,
+    /// Type of the token, either `client` or `management`
+    #[serde(rename = "Type")]
+    pub token_type: String,
+    /// Policies attached to the token
+    #[serde(default)]
+    pub policies: Option<Vec<String>>,
+    /// Whether the token is replicated to all regions
+    pub global: bool,
+}
+
+/// Details of the Nomad agent the client is talking to
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct AgentSelf {
+    /// Agent configuration
+    pub config: AgentConfig,
+    /// Gossip member details of the agent
+    pub member: AgentMember,
+}
+
+/// Subset of the Nomad agent configuration
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AgentConfig {
+    /// Region of the agent
+    pub region: String,
+    /// Datacenter of the agent
+    pub datacenter: String,
+    /// Name of the agent node
+    pub node_name: String,
+    /// Version of the agent
+    #[serde(default)]
+    pub version: Option<AgentVersion>,
+    /// ACL configuration of the agent
+    #[serde(default, rename = "ACL")]
+    pub acl: Option<AgentAclConfig>,
+}
+
+/// Subset of the ACL configuration of a Nomad agent
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AgentAclConfig {
+    /// Whether ACLs are enforced
+    pub enabled: bool,
+}
+
+/// Gossip member details of a Nomad agent
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AgentMember {
+    /// Name of the member
+    pub name: String,
+    /// Address of the member
+    pub addr: String,
+    /// Member tags
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+/// Version details of a Nomad agent
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct AgentVersion {
+    /// Version number, e.g. `1.6.1`
+    pub version: String,
+    /// Pre-release label, e.g. `beta.1`
+    #[serde(default)]
+    pub version_prerelease: String,
+    /// Build metadata, e.g. `ent`
+    #[serde(default)]
+    pub version_metadata: String,
+}
+
+impl AgentSelf {
+    /// Version of the agent, from its configuration or its gossip tags
+    pub fn version(&self) -> Option<NomadVersion> {
+        self.config
+            .version
+            .as_ref()
+            .map(|version| version.version.as_str())
+            .or_else(|| self.member.tags.get("build").map(String::as_str))
+            .and_then(NomadVersion::parse)
+    }
+
+    /// Whether ACLs are enabled on the agent, if its configuration says so
+    pub fn acl_enabled(&self) -> Option<bool> {
+        self.config.acl.as_ref().map(|acl| acl.enabled)
+    }
+}
+
+/// Major, minor and patch version of Nomad, or of Vault which is versioned the same way
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug)]
+pub struct NomadVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl NomadVersion {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse versions like `1.6.1`, `v0.8.6`, `1.6.0-beta.1` or `1.5.3+ent`, ignoring any
+    /// pre-release label or build metadata
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim().trim_start_matches('v');
+        let version = version.split(&['-', '+'][..]).next()?;
+        let mut parts = version.split('.').map(str::parse::<u64>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+        Some(Self::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for NomadVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Identifies the Nomad cluster that events are attributed to
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ClusterIdentity {
+    /// Operator provided name of the cluster
+    pub name: Option<String>,
+    /// Nomad region
+    pub region: Option<String>,
+    /// Nomad datacenter
+    pub datacenter: Option<String>,
+}
+
+impl ClusterIdentity {
+    /// Whether any of the identifiers known to the agent are missing
+    pub fn is_incomplete(&self) -> bool {
+        self.region.is_none() || self.datacenter.is_none()
+    }
+
+    /// Fill in missing identifiers from the agent details
+    ///
+    /// The name is only ever provided by the operator. The agent's member name is the hostname of
+    /// the agent, which does not identify the cluster.
+    pub fn with_agent(self, agent: &AgentSelf) -> Self {
+        Self {
+            name: self.name,
+            region: self.region.or_else(|| Some(agent.config.region.clone())),
+            datacenter: self
+                .datacenter
+                .or_else(|| Some(agent.config.datacenter.clone())),
+        }
+    }
+}
+
+impl fmt::Display for ClusterIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unknown = "unknown";
+        write!(
+            f,
+            "{}/{}/{}",
+            self.name.as_ref().map_or(unknown, String::as_str),
+            self.region.as_ref().map_or(unknown, String::as_str),
+            self.datacenter.as_ref().map_or(unknown, String::as_str)
+        )
+    }
+}
+
+/// Query parameters to filter and paginate the list of nodes
+#[derive(Serialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct NodesQuery {
+    /// Only return nodes with IDs starting with the prefix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// [Filter expression](https://www.nomadproject.io/api-docs#filtering) evaluated by Nomad.
+    /// Ignored by Nomad versions before 1.1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    /// Maximum number of nodes in each page. Pages are followed until all nodes are retrieved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<u32>,
+    /// Token of the page to start from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_token: Option<String>,
+}
+
+impl NodesQuery {
+    /// Query for nodes fingerprinted with the AWS instance ID
+    pub fn instance_id(instance_id: &str) -> Self {
+        Self {
+            filter: Some(format!(
+                r#"Attributes["{}"] == "{}""#,
+                AWS_INSTANCE_ID_ATTRIBUTE,
+                instance_id.replace('"', "")
+            )),
+            ..Default::default()
+        }
+    }
+
+    /// Queries for each shard of the nodes matching this query, by the next character of their IDs
+    fn shards(&self) -> Vec<Self> {
+        let prefix = self.prefix.clone().unwrap_or_default();
+        NODE_ID_SHARDS
+            .chars()
+            .map(|shard| Self {
+                prefix: Some(format!("{}{}", prefix, shard)),
+                next_token: None,
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// Result of looking up a Nomad node by its AWS instance ID
+#[derive(Clone, Debug)]
+pub struct NodeMatch {
+    /// The node selected for the instance
+    pub node: BlockingResponse<Node>,
+    /// Present when more than one node claims the same instance ID
+    pub ambiguous: Option<AmbiguousNodeMatch>,
+}
+
+/// More than one Nomad node is registered with the same AWS instance ID
+#[derive(Serialize, Eq, PartialEq, Clone, Debug)]
+pub struct AmbiguousNodeMatch {
+    /// The AWS instance ID
+    pub instance_id: String,
+    /// ID of the node that was selected
+    pub selected_node_id: String,
+    /// IDs of the other nodes that matched
+    pub other_node_ids: Vec<String>,
+}
+
+impl fmt::Display for AmbiguousNodeMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AmbiguousNodeMatch: AWS Instance ID {} matches multiple Nomad Nodes. Selected {}, ignored {}",
+            self.instance_id,
+            self.selected_node_id,
+            self.other_node_ids.join(", ")
+        )
+    }
+}
+
+impl NodeMatch {
+    /// Select the preferred node out of all the nodes matching an instance ID
+    ///
+    /// Ready nodes are preferred, followed by the most recently modified node. Nodes that are not
+    /// ready are only selected if `include_non_ready` is set, but are reported as ambiguous
+    /// matches regardless.
+    fn select(
+        instance_id: &str,
+        mut matches: Vec<BlockingResponse<Node>>,
+        include_non_ready: bool,
+    ) -> Option<Self> {
+        matches.sort_by_key(|node| {
+            (
+                node.data.status == NodeStatus::Ready,
+                node.data.modify_index,
+            )
+        });
+        let node = matches.pop()?;
+        if !include_non_ready && node.data.status != NodeStatus::Ready {
+            return None;
+        }
+        let ambiguous = if matches.is_empty() {
+            None
+        } else {
+            Some(AmbiguousNodeMatch {
+                instance_id: instance_id.to_string(),
+                selected_node_id: node.data.id.clone(),
+                other_node_ids: matches.into_iter().rev().map(|node| node.data.id).collect(),
+            })
+        };
+
+        Some(Self { node, ambiguous })
+    }
+}
+
+impl Node {
+    /// Value of a node attribute, if present
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(String::as_str)
+    }
+
+    /// AWS Instance ID of the node
+    pub fn instance_id(&self) -> Option<&str> {
+        self.attribute(AWS_INSTANCE_ID_ATTRIBUTE)
+    }
+
+    /// AWS Availability Zone of the node
+    pub fn availability_zone(&self) -> Option<&str> {
+        self.attribute(AWS_AVAILABILITY_ZONE_ATTRIBUTE)
+    }
+
+    /// AWS EC2 instance type of the node
+    pub fn instance_type(&self) -> Option<&str> {
+        self.attribute(AWS_INSTANCE_TYPE_ATTRIBUTE)
+    }
+
+    /// AWS AMI ID the node was launched from
+    pub fn ami_id(&self) -> Option<&str> {
+        self.attribute(AWS_AMI_ID_ATTRIBUTE)
+    }
+}
+
+impl NodesInList {
+    /// Whether the node is ready and accepting allocations
+    pub fn is_ready(&self) -> bool {
+        self.status == NodeStatus::Ready
+    }
+
+    /// Whether the node is in the provided datacenter
+    pub fn in_datacenter(&self, datacenter: &str) -> bool {
+        self.datacenter == datacenter
+    }
+}
+
+/// Nomad Responses that support blocking requests
+///
+/// See the [documentation](https://www.nomadproject.io/api/index.html#blocking-queries) for more
+/// details
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct BlockingResponse<T> {
+    /// The index indicating the "change ID" for the current response
+    pub index: u64,
+    /// The actual data of the response
+    pub data: T,
+    /// Diagnostic details of the HTTP response
+    pub diagnostics: ResponseDiagnostics,
+}
+
+impl<T> BlockingResponse<T> {
+    /// Transform the data of the response, keeping the index and diagnostics
+    pub fn map<U, F>(self, f: F) -> BlockingResponse<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        BlockingResponse {
+            index: self.index,
+            data: f(self.data),
+            diagnostics: self.diagnostics,
+        }
+    }
+}
+
+/// Details of an HTTP response kept to diagnose which hop (Nomad, proxies or load balancers)
+/// produced it
+#[derive(Serialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct ResponseDiagnostics {
+    /// HTTP status code
+    pub status: u16,
+    /// Diagnostic headers present in the response, keyed by lowercase header name
+    pub headers: BTreeMap<String, String>,
+}
+
+impl ResponseDiagnostics {
+    /// Capture the diagnostic headers out of the response headers
+    pub fn new(status: reqwest::StatusCode, headers: &reqwest::header::HeaderMap) -> Self {
+        let headers = DIAGNOSTIC_HEADERS
+            .iter()
+            .filter_map(|name| {
+                headers
+                    .get(*name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| (name.to_lowercase(), value.to_string()))
+            })
+            .collect();
+
+        Self {
+            status: status.as_u16(),
+            headers,
+        }
+    }
+}
+
+impl fmt::Display for ResponseDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HTTP {}", self.status)?;
+        for (name, value) in &self.headers {
+            write!(f, ", {}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl ClientBuilder {
+    /// Build a client of the Nomad server at `address`, without a token
+    pub fn new<S: AsRef<str>>(address: S) -> Self {
+        Self {
+            address: address.as_ref().to_string(),
+            token: None,
+            candidate_tokens: vec![],
+            http_client: None,
+            tls: None,
+            timeout: DEFAULT_HTTP_TIMEOUT,
+            user_agent: None,
+            namespace: None,
+            region: None,
+            retry_policy: Default::default(),
+            wait_timeout: Duration::from_secs(300),
+            context: Default::default(),
+        }
+    }
+
+    /// Authenticate requests with the ACL token
+    pub fn with_token<S: AsRef<str>>(mut self, token: S) -> Self {
+        self.token = Some(From::from(token.as_ref().to_string()));
+        self
+    }
+
+    /// Authenticate requests with the first of the candidate tokens that Nomad accepts
+    ///
+    /// This is useful during ACL token rotation, when both the old and new tokens are configured.
+    /// When there is more than one candidate, the tokens are tried in order, and the first token
+    /// that passes a self-lookup is used. Tokens rejected with `403 Forbidden` are skipped. Like
+    /// `Client::wait_for_token`, other errors are logged and the token looked up is used without
+    /// verification, so that an unreachable Nomad is left to be dealt with by the first request.
+    /// A single candidate is used like `with_token`.
+    pub fn with_candidate_tokens<S: AsRef<str>>(mut self, tokens: &[S]) -> Self {
+        self.candidate_tokens = tokens
+            .iter()
+            .map(|token| From::from(token.as_ref().to_string()))
+            .collect();
+        self
+    }
+
+    /// Use the provided `reqwest::Client`, for specific needs the builder does not cover
+    ///
+    /// The HTTP client is used as is, ignoring the timeout, TLS settings and user agent of the
+    /// builder. Make sure that its timeout allows for
+    /// [blocking queries](https://www.nomadproject.io/api/index.html#blocking-queries).
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Talk to Nomad with the TLS settings, for clusters with a private CA or mutual TLS
+    pub fn with_tls(mut self, tls: &TlsConfig) -> Self {
+        self.tls = Some(tls.clone());
+        self
+    }
+
+    /// Timeout of HTTP requests. Defaults to 6 minutes, to allow for blocking queries.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Identify requests with the user agent
+    pub fn with_user_agent<S: AsRef<str>>(mut self, user_agent: S) -> Self {
+        self.user_agent = Some(user_agent.as_ref().to_string());
+        self
+    }
+
+    /// Make requests in the namespace by default
+    pub fn with_namespace<S: AsRef<str>>(mut self, namespace: S) -> Self {
+        self.namespace = Some(namespace.as_ref().to_string());
+        self
+    }
+
+    /// Send every request to the region. See `Client::with_region`.
+    pub fn with_region<S: AsRef<str>>(mut self, region: S) -> Self {
+        self.region = Some(region.as_ref().to_string());
+        self
+    }
+
+    /// Retry requests that fail transiently according to the policy
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Wait time of blocking queries made while monitoring, when none is provided. Defaults to
+    /// 5 minutes.
+    pub fn with_wait_timeout(mut self, wait_timeout: Duration) -> Self {
+        self.wait_timeout = wait_timeout;
+        self
+    }
+
+    /// Make every request in the provided context, including the lookups of candidate tokens.
+    /// See `Client::with_context`.
+    pub fn with_context(mut self, context: RequestContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Build the client
+    ///
+    /// With more than one candidate token, this looks the tokens up until one is accepted, and
+    /// fails with `Error::NoValidNomadToken` if none are.
+    pub fn build(self) -> Result<Client, crate::Error> {
+        let client = match self.http_client {
+            Some(client) => client,
+            None => {
+                let mut builder = HttpClientBuilder::new().timeout(Some(self.timeout));
+                if let Some(ref user_agent) = self.user_agent {
+                    let mut headers = HeaderMap::new();
+                    headers.insert(USER_AGENT, HeaderValue::from_str(user_agent)?);
+                    builder = builder.default_headers(headers);
+                }
+                match self.tls {
+                    Some(ref tls) => tls.apply(builder)?,
+                    None => builder,
+                }
+                .build()?
+            }
+        };
+
+        let mut candidate_tokens = self.candidate_tokens;
+        let token = match candidate_tokens.len() {
+            0 => self.token,
+            1 => candidate_tokens.pop(),
+            _ => None,
+        };
+        let nomad_client = Client {
+            client,
+            address: self.address,
+            token,
+            signer: None,
+            region: self.region,
+            drain_api: Default::default(),
+            drain_meta: None,
+            max_nodes: None,
+            shard_node_lists: false,
+            acl_disabled: false,
+            namespace: self.namespace,
+            retry_policy: self.retry_policy,
+            clock: Arc::new(SystemClock),
+            wait_timeout: self.wait_timeout,
+            context: self.context,
+            transport: None,
+        };
+        if candidate_tokens.is_empty() {
+            Ok(nomad_client)
+        } else {
+            nomad_client.select_token(candidate_tokens)
+        }
+    }
+}
+
+impl Client {
+    /// Start building a client of the Nomad server at `address`
+    pub fn builder<S: AsRef<str>>(address: S) -> ClientBuilder {
+        ClientBuilder::new(address)
+    }
+
+    /// Builder for the default HTTP client, with a timeout long enough for blocking queries
+    fn http_client_builder() -> HttpClientBuilder {
+        HttpClientBuilder::new().timeout(Some(DEFAULT_HTTP_TIMEOUT))
+    }
+
+    /// Build the default HTTP client configured with the TLS settings
+    ///
+    /// Provide this to the constructors instead of building your own `reqwest::Client` to talk to
+    /// Nomad clusters with a private CA or mutual TLS.
+    pub fn build_http_client(tls: &TlsConfig) -> Result<HttpClient, crate::Error> {
+        Ok(tls.apply(Self::http_client_builder())?.build()?)
+    }
+
+    /// Replace the HTTP client with the default client configured with the TLS settings
+    pub fn with_tls(mut self, tls: &TlsConfig) -> Result<Self, crate::Error> {
+        self.client = Self::build_http_client(tls)?;
+        Ok(self)
+    }
+
+    /// Wait time of blocking queries made while monitoring, when none is provided. Defaults to
+    /// 5 minutes.
+    pub fn with_wait_timeout(mut self, wait_timeout: Duration) -> Self {
+        self.wait_timeout = wait_timeout;
+        self
+    }
+
+    /// Finish blocking queries and monitoring by the provided deadline
+    ///
+    /// The wait time of blocking queries is shortened as the deadline approaches, accounting for
+    /// the jitter Nomad adds to them. Monitoring fails with `Error::DeadlineExceeded` once the
+    /// deadline has passed.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.context.deadline = Some(deadline);
+        self
+    }
+
+    /// Make every request in the provided context
+    ///
+    /// Besides the deadline of `with_deadline`, the trace ID of the context is sent with every
+    /// request and dry runs refuse changes to the cluster with `Error::ReadOnly`, like
+    /// `ReadOnlyGuard`.
+    pub fn with_context(mut self, context: RequestContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Use the provided clock for retries and waits instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Send requests with the provided transport instead of the HTTP client
+    ///
+    /// Requests are still built, signed and retried by the client.
+    pub fn with_transport(mut self, transport: Arc<dyn crate::Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Retry requests that fail transiently according to the provided policy
+    ///
+    /// Clients are cheap to clone, so a different policy can be used for a single call with
+    /// `client.clone().with_retry_policy(policy)`.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Authenticate every request with the signer, for Nomad APIs behind an authenticating proxy
+    pub fn with_signer(mut self, signer: Arc<dyn RequestSigner>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Drain nodes with the provided API instead of probing the version of the server
+    ///
+    /// Probing requires the token to have `agent:read`.
+    pub fn with_drain_api(mut self, drain_api: DrainApi) -> Self {
+        self.drain_api = Arc::new(Mutex::new(Some(drain_api)));
+        self
+    }
+
+    /// Record the metadata on nodes when draining them, on servers that support it
+    pub fn with_drain_meta(mut self, meta: HashMap<String, String>) -> Self {
+        self.drain_meta = Some(meta);
+        self
+    }
+
+    /// Fail listings of more than `max_nodes` nodes with `Error::TooManyNodes`
+    ///
+    /// On very large clusters, this fails predictably instead of exhausting the memory available.
+    pub fn with_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    /// List nodes in shards by the first character of their IDs when every node has to be
+    /// searched
+    ///
+    /// Each of the 16 shards holds about a sixteenth of the nodes, so that listings stay within
+    /// the limit set with `with_max_nodes` on larger clusters.
+    pub fn with_sharded_node_lists(mut self, shard_node_lists: bool) -> Self {
+        self.shard_node_lists = shard_node_lists;
+        self
+    }
+
+    /// Send every request to the provided region
+    ///
+    /// In federated clusters, pinning requests to the authoritative region avoids failures while
+    /// a newly created token has yet to replicate to the other regions.
+    pub fn with_region<S: AsRef<str>>(mut self, region: S) -> Self {
+        self.region = Some(region.as_ref().to_string());
+        self
+    }
+
+    /// Make requests without a token, for development clusters with ACLs disabled
+    ///
+    /// Any token or signer is dropped, so that a leftover token does not hide the fact that ACLs
+    /// are not enforced. See `check_acl_mode`.
+    pub fn without_acl(mut self) -> Self {
+        warn!("Nomad ACLs are assumed to be disabled. Requests are made without a token");
+        self.token = None;
+        self.signer = None;
+        self.acl_disabled = true;
+        self
+    }
+
+    /// Use the first of the candidate tokens that passes a self-lookup
+    fn select_token(mut self, tokens: Vec<crate::Secret>) -> Result<Self, crate::Error> {
+        let candidates = tokens.len();
+        for (index, token) in tokens.into_iter().enumerate() {
+            self.token = Some(token);
+            match self.token_self() {
+                Ok(token) => {
+                    info!(
+                        "Using Nomad token candidate #{} with accessor ID {}",
+                        index + 1,
+                        token.accessor_id
+                    );
+                    return Ok(self);
+                }
+                Err(ref e) if Self::is_permission_denied(e) => {
+                    warn!("Nomad token candidate #{} was rejected", index + 1);
+                }
+                Err(e) => {
+                    warn!(
+                        "Unable to look up Nomad token candidate #{}: {}. Using it unverified",
+                        index + 1,
+                        e
+                    );
+                    return Ok(self);
+                }
+            }
+        }
+
+        Err(crate::Error::NoValidNomadToken { candidates })
+    }
+
+    /// Returns the Nomad Server Address
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the Nomad Token, if any
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_ref().map(|s| s.expose_secret())
+    }
+
+    /// Drain API of the server, probing its version the first time
+    ///
+    /// If the version cannot be determined, the oldest supported API is used.
+    pub fn drain_api(&self) -> Result<DrainApi, crate::Error> {
+        let mut drain_api = self.drain_api.lock().expect("Not to be poisoned");
+        if let Some(drain_api) = *drain_api {
+            return Ok(drain_api);
+        }
+
+        let probed = match self.agent_self().map(|agent| agent.version()) {
+            Ok(Some(version)) => {
+                let api = DrainApi::for_version(&version)?;
+                info!("Nomad {} uses drain API {:?}", version, api);
+                api
+            }
+            Ok(None) => {
+                warn!("Unable to determine the Nomad version. Using the oldest drain API");
+                DrainApi::default()
+            }
+            Err(e) => {
+                warn!(
+                    "Unable to probe the Nomad version ({}). Using the oldest drain API",
+                    e
+                );
+                DrainApi::default()
+            }
+        };
+        *drain_api = Some(probed);
+        Ok(probed)
+    }
+
+    /// Returns the signer authenticating requests, if any
+    pub fn signer(&self) -> Option<&dyn RequestSigner> {
+        self.signer.as_deref()
+    }
+
+    /// Returns whether requests are made without a token because ACLs are disabled
+    pub fn acl_disabled(&self) -> bool {
+        self.acl_disabled
+    }
+
+    /// Verify that the use of a token matches whether the Nomad agent enforces ACLs
+    ///
+    /// Fails with `Error::NomadAclsDisabled` if the client authenticates requests to an agent that
+    /// does not enforce ACLs, which is confusing at best. The opposite only warrants a warning,
+    /// since anonymous requests are fine if the anonymous policy allows them. Agents that do not
+    /// report their ACL configuration are assumed to be set up correctly.
+    pub fn check_acl_mode(&self) -> Result<(), crate::Error> {
+        let acl_enabled = match self.agent_self() {
+            Ok(agent) => agent.acl_enabled(),
+            // Only agents enforcing ACLs deny requests
+            Err(ref e) if Self::is_permission_denied(e) => Some(true),
+            Err(e) => {
+                warn!("Unable to check the Nomad ACL configuration: {}", e);
+                None
+            }
+        };
+        let authenticated = self.token.is_some() || self.signer.is_some();
+        match acl_enabled {
+            Some(false) if authenticated => Err(crate::Error::NomadAclsDisabled),
+            Some(true) if self.acl_disabled => {
+                warn!("Nomad ACLs are enabled, but requests are made without a token");
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the region requests are sent to, if pinned
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    /// Returns the policy used to retry requests
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Returns the clock used for retries and waits
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    /// Returns the default wait time of blocking queries made while monitoring
+    pub fn wait_timeout(&self) -> Duration {
+        self.wait_timeout
+    }
+
+    /// Returns the deadline, if any
+    pub fn deadline(&self) -> Option<Instant> {
+        self.context.deadline
+    }
+
+    /// Returns the context requests are made in
+    pub fn context(&self) -> &RequestContext {
+        &self.context
+    }
+
+    /// Time left before the deadline, if any
+    fn remaining(&self) -> Option<Duration> {
+        self.context.remaining(&*self.clock)
+    }
+
+    /// Fail if the deadline has passed
+    fn check_deadline(&self) -> Result<(), crate::Error> {
+        self.context.check_deadline(&*self.clock)
+    }
+
+    /// Wait time of a blocking query, shortened to end before the deadline
+    fn blocking_wait(
+        &self,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Option<Duration> {
+        let remaining = match self.remaining() {
+            Some(remaining) if wait_index.is_some() => remaining,
+            _ => return wait_timeout,
+        };
+        let wait = wait_timeout.unwrap_or(self.wait_timeout);
+        // Nomad adds a random jitter of up to `wait / 16` to blocking queries
+        let wait = std::cmp::min(wait, remaining * 15 / 16);
+        Some(std::cmp::max(wait, Duration::from_secs(1)))
+    }
+
+    /// Returns the HTTP Client used
+    pub fn http_client(&self) -> &HttpClient {
+        &self.client
+    }
+
+    fn execute_request<T, F>(&self, build: F) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Debug,
+        F: Fn() -> Result<reqwest::Request, crate::Error>,
+    {
+        Ok(self.execute_indexed_request(build)?.data)
+    }
+
+    /// Execute the request built by `build`, rebuilding it for every retry
+    fn execute_indexed_request<T, F>(&self, build: F) -> Result<BlockingResponse<T>, crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Debug,
+        F: Fn() -> Result<reqwest::Request, crate::Error>,
+    {
+        self.check_deadline()?;
+        let transport: &dyn crate::Transport = match self.transport {
+            Some(ref transport) => &**transport,
+            None => &self.client,
+        };
+        let mut response = self.retry_policy.execute(transport, &*self.clock, || {
+            self.sign(self.context.apply(build()?)?)
+        })?;
+        debug!("Received response: {:#?}", response);
+        let diagnostics = ResponseDiagnostics::new(response.status(), response.headers());
+        let body = response.text()?;
+        debug!("Response body: {}", body);
+        Self::parse_indexed_response(response.headers(), &body, diagnostics)
+    }
+
+    /// Deserialize the body of a response from Nomad
+    fn parse_indexed_response<T>(
+        headers: &reqwest::header::HeaderMap,
+        body: &str,
+        diagnostics: ResponseDiagnostics,
+    ) -> Result<BlockingResponse<T>, crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Debug,
+    {
+        if !(200..300).contains(&diagnostics.status) {
+            warn!("Nomad responded with an error ({}): {}", diagnostics, body);
+            return Err(crate::Error::NomadApi {
+                status: diagnostics.status,
+                message: body.trim().to_string(),
+                diagnostics,
+            });
+        }
+        let details = crate::from_json_str(body).map_err(|error| {
+            warn!(
+                "Unable to deserialize response from Nomad ({}): {}",
+                diagnostics, error
+            );
+            crate::Error::UnexpectedNomadResponse {
+                path: error.path().to_string(),
+                error: error.into_inner(),
+                diagnostics: diagnostics.clone(),
+            }
+        })?;
+        debug!("Deserialized Details: {:#?}", details);
+        Self::make_indexed_response(headers, details, diagnostics)
+    }
+
+    /// Get an endpoint of the Nomad API that the client does not model
+    ///
+    /// `path` is relative to `/v1/`, e.g. `jobs`, and `query` is serialized as its query string.
+    /// The request is made like every other request of the client, with its token, namespace,
+    /// region, retries and logging.
+    ///
+    /// ```rust,no_run
+    /// # fn main() -> Result<(), nomad_drain::Error> {
+    /// let client = nomad_drain::nomad::Client::builder("http://127.0.0.1:4646").build()?;
+    /// let jobs: serde_json::Value = client.get("jobs", &[("prefix", "web")])?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get<T, Q>(&self, path: &str, query: &Q) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Debug,
+        Q: Serialize + ?Sized,
+    {
+        info!("Requesting Nomad {}", path);
+        self.execute_request(|| self.build_get_request(path, query))
+    }
+
+    fn build_get_request<Q>(&self, path: &str, query: &Q) -> Result<reqwest::Request, crate::Error>
+    where
+        Q: Serialize + ?Sized,
+    {
+        let address = format!("{}/v1/{}", &self.address, path.trim_start_matches('/'));
+        let request = self.client.get(&address).query(query);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Post `body` as JSON to an endpoint of the Nomad API that the client does not model
+    ///
+    /// `path` is relative to `/v1/`, like with `get`. Dry runs refuse to post with
+    /// `Error::ReadOnly`, since the client cannot tell whether the endpoint changes the cluster.
+    pub fn post<T, B>(&self, path: &str, body: &B) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Debug,
+        B: Serialize + ?Sized,
+    {
+        self.context.check_mutable(&format!("post to {}", path))?;
+        info!("Posting to Nomad {}", path);
+        self.execute_request(|| self.build_post_request(path, body))
+    }
+
+    fn build_post_request<B>(&self, path: &str, body: &B) -> Result<reqwest::Request, crate::Error>
+    where
+        B: Serialize + ?Sized,
+    {
+        let address = format!("{}/v1/{}", &self.address, path.trim_start_matches('/'));
+        let request = self.client.post(&address).json(body);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Get Information about a specific Node ID
+    ///
+    /// Supply the optional parameters to take advantage of
+    /// [blocking queries](https://www.nomadproject.io/api/index.html#blocking-queries)
+    ///
+    /// Fails with `Error::NomadNodeGone` if the node does not exist, e.g. after it was garbage
+    /// collected.
+    pub fn node_details(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<BlockingResponse<Node>, crate::Error> {
+        info!("Requesting Nomad Node {} details", node_id);
+        self.execute_indexed_request(|| {
+            self.build_node_details_request(node_id, wait_index, wait_timeout)
+        })
+        .map_err(|error| node_gone(node_id, error))
+    }
+
+    /// Get details of the Nomad agent the client is talking to
+    pub fn agent_self(&self) -> Result<AgentSelf, crate::Error> {
+        info!("Requesting Nomad Agent details");
+        self.execute_request(|| self.build_agent_self_request())
+    }
+
+    /// Get the address of the Raft leader of the region
+    ///
+    /// Fails if the cluster has no leader
+    pub fn leader(&self) -> Result<String, crate::Error> {
+        info!("Requesting Nomad leader");
+        self.execute_request(|| self.build_leader_request())
+    }
+
+    fn build_leader_request(&self) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/status/leader", &self.address);
+        let request = self.client.get(&address);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Look up the details of the token in use
+    pub fn token_self(&self) -> Result<AclToken, crate::Error> {
+        info!("Looking up Nomad token");
+        self.execute_request(|| self.build_token_self_request())
+    }
+
+    fn build_token_self_request(&self) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/acl/token/self", &self.address);
+        let request = self.client.get(&address);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Wait for the token to be usable, retrying with exponential backoff while Nomad rejects it
+    ///
+    /// In federated clusters, a token created in the authoritative region is rejected by the
+    /// other regions until it has been replicated. Errors other than `403 Forbidden`, such as ACLs
+    /// being disabled, are logged and ignored.
+    pub fn wait_for_token(&self, retry_window: Duration) -> Result<(), crate::Error> {
+        let start = self.clock.now();
+        let mut delay = Duration::from_secs(1);
+        loop {
+            match self.token_self() {
+                Ok(_) => break Ok(()),
+                Err(ref e)
+                    if Self::is_permission_denied(e)
+                        && self.clock.elapsed(start) + delay < retry_window =>
+                {
+                    warn!(
+                        "Nomad token not accepted yet, possibly pending replication. Retrying in {:?}",
+                        delay
+                    );
+                    self.clock.sleep(delay);
+                    delay = std::cmp::min(delay * 2, Duration::from_secs(16));
+                }
+                Err(ref e) if Self::is_permission_denied(e) => {
+                    Err(crate::Error::NomadTokenRejected)?
+                }
+                Err(e) => {
+                    warn!("Unable to look up Nomad token: {}", e);
+                    break Ok(());
+                }
+            }
+        }
+    }
+
+    /// Whether the error is Nomad rejecting the request with `403 Forbidden`
+    fn is_permission_denied(error: &crate::Error) -> bool {
+        error.status() == Some(reqwest::StatusCode::FORBIDDEN.as_u16())
+    }
+
+    fn build_agent_self_request(&self) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/agent/self", &self.address);
+        let request = self.client.get(&address);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Build requests to get node details
+    fn build_node_details_request(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/node/{}", &self.address, node_id);
+        let request = self.client.get(&address);
+        let request = self.add_request_defaults(request);
+        let request = self.add_blocking_requests(request, wait_index, wait_timeout);
+        Ok(request.build()?)
+    }
+
+    /// Return a list of nodes
+    ///
+    /// Supply the optional parameters to take advantage of
+    /// [blocking queries](https://www.nomadproject.io/api/index.html#blocking-queries)
+    pub fn nodes(
+        &self,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<BlockingResponse<Vec<NodesInList>>, crate::Error> {
+        info!("Requesting list of Nomad nodes");
+        let nodes: BlockingResponse<Vec<NodesInList>> =
+            self.execute_indexed_request(|| self.build_nodes_request(wait_index, wait_timeout))?;
+        self.check_node_count(nodes.data.len())?;
+        Ok(nodes)
+    }
+
+    /// Return the list of nodes matching the query, following pages if paginated
+    pub fn nodes_matching(&self, query: &NodesQuery) -> Result<Vec<NodesInList>, crate::Error> {
+        info!("Requesting list of Nomad nodes matching {:?}", query);
+        let mut query = query.clone();
+        let mut nodes = vec![];
+        loop {
+            let page: BlockingResponse<Vec<NodesInList>> =
+                self.execute_indexed_request(|| self.build_nodes_query_request(&query))?;
+            nodes.extend(page.data);
+            self.check_node_count(nodes.len())?;
+            match page
+                .diagnostics
+                .headers
+                .get(&NOMAD_NEXT_TOKEN_HEADER.to_lowercase())
+            {
+                Some(token) if !token.is_empty() => query.next_token = Some(token.clone()),
+                _ => break Ok(nodes),
+            }
+        }
+    }
+
+    /// Visit the nodes matching the query in shards by the first character of their IDs
+    ///
+    /// The limit set with `with_max_nodes` applies to each shard.
+    pub fn for_each_node_shard<F>(
+        &self,
+        query: &NodesQuery,
+        mut visit: F,
+    ) -> Result<(), crate::Error>
+    where
+        F: FnMut(Vec<NodesInList>) -> Result<(), crate::Error>,
+    {
+        for query in query.shards() {
+            visit(self.nodes_matching(&query)?)?;
+        }
+        Ok(())
+    }
+
+    fn check_node_count(&self, count: usize) -> Result<(), crate::Error> {
+        match self.max_nodes {
+            Some(limit) if count > limit => Err(crate::Error::TooManyNodes { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    fn build_nodes_query_request(
+        &self,
+        query: &NodesQuery,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/nodes", &self.address);
+        let request = self.client.get(&address);
+        let request = self.add_request_defaults(request).query(query);
+        Ok(request.build()?)
+    }
+
+    /// Return a list of nodes that are ready and accepting allocations
+    pub fn ready_nodes(&self) -> Result<Vec<NodesInList>, crate::Error> {
+        let nodes = self.nodes(None, None)?;
+        Ok(nodes
+            .data
+            .into_iter()
+            .filter(NodesInList::is_ready)
+            .collect())
+    }
+
+    /// Return a list of nodes in the provided datacenter
+    pub fn nodes_in_datacenter(&self, datacenter: &str) -> Result<Vec<NodesInList>, crate::Error> {
+        let nodes = self.nodes(None, None)?;
+        Ok(nodes
+            .data
+            .into_iter()
+            .filter(|node| node.in_datacenter(datacenter))
+            .collect())
+    }
+
+    /// Build request to retrieve list of nodes
+    fn build_nodes_request(
+        &self,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/nodes", &self.address);
+        let request = self.client.get(&address);
+        let request = self.add_request_defaults(request);
+        let request = self.add_blocking_requests(request, wait_index, wait_timeout);
+        Ok(request.build()?)
+    }
+
+    /// Given an AWS Instance ID, find the Node details
+    ///
+    /// If more than one node is registered with the same instance ID (for example, when the
+    /// Nomad agent re-registered with a new node ID), the ready node with the highest modify index
+    /// is chosen and the ambiguity is reported in the returned `NodeMatch`.
+    ///
+    /// Only ready nodes are selected unless `include_non_ready` is `true`, in which case nodes that
+    /// are down or initializing can be selected as well. This allows finding instances whose Nomad
+    /// agent has already stopped. Every node is searched regardless, so that nodes in any status
+    /// sharing the instance ID are reported.
+    ///
+    /// Nodes are first filtered by Nomad on the instance ID attribute so that details only have to
+    /// be retrieved for a few nodes. If that matches nothing, for example with Nomad versions that
+    /// do not support filtering, the details of every node are searched instead.
+    pub fn find_node_by_instance_id(
+        &self,
+        instance_id: &str,
+        include_non_ready: bool,
+    ) -> Result<NodeMatch, crate::Error> {
+        info!("Finding Nomad Node ID for AWS Instance ID {}", instance_id);
+        let matches = match self.nodes_matching(&NodesQuery::instance_id(instance_id)) {
+            Ok(ref nodes) if nodes.is_empty() => {
+                debug!("No nodes matched the instance ID filter. Searching all nodes");
+                self.search_all_nodes(instance_id)?
+            }
+            Ok(nodes) => self.node_details_matching(instance_id, nodes)?,
+            Err(e) => {
+                debug!("Unable to filter nodes by instance ID: {}", e);
+                self.search_all_nodes(instance_id)?
+            }
+        };
+
+        let result =
+            NodeMatch::select(instance_id, matches, include_non_ready).ok_or_else(|| {
+                crate::Error::NomadNodeNotFound {
+                    instance_id: instance_id.to_string(),
+                }
+            })?;
+        if let Some(ambiguous) = &result.ambiguous {
+            warn!("{}", ambiguous);
+        }
+        info!(
+            "AWS Instance ID {} is Nomad Node ID {}",
+            instance_id, result.node.data.id
+        );
+        Ok(result)
+    }
+
+    /// Details of every node with the instance ID, searching all nodes, in shards if configured
+    fn search_all_nodes(
+        &self,
+        instance_id: &str,
+    ) -> Result<Vec<BlockingResponse<Node>>, crate::Error> {
+        if !self.shard_node_lists {
+            let nodes = self.nodes(None, None)?.data;
+            return self.node_details_matching(instance_id, nodes);
+        }
+        let mut matches = vec![];
+        self.for_each_node_shard(&Default::default(), |nodes| {
+            matches.extend(self.node_details_matching(instance_id, nodes)?);
+            Ok(())
+        })?;
+        Ok(matches)
+    }
+
+    /// Details of the nodes in the list with the instance ID
+    ///
+    /// Nodes that are garbage collected after they are listed are skipped.
+    fn node_details_matching(
+        &self,
+        instance_id: &str,
+        nodes: Vec<NodesInList>,
+    ) -> Result<Vec<BlockingResponse<Node>>, crate::Error> {
+        let mut matches = vec![];
+        for node in nodes {
+            let details = match self.node_details(&node.id, None, None) {
+                Ok(details) => details,
+                Err(crate::Error::NomadNodeGone { node_id }) => {
+                    debug!("Nomad Node ID {} is gone. Skipping it", node_id);
+                    continue;
+                }
+                Err(e) => Err(e)?,
+            };
+            if details.data.instance_id() == Some(instance_id) {
+                matches.push(details);
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Wait for Nomad to be reachable, checking every `poll_interval` until `timeout` elapses
+    ///
+    /// Only failures to reach Nomad at all are waited out, and the last of them is returned once
+    /// the timeout elapses. Other errors mean that Nomad is reachable, so they are logged and
+    /// ignored.
+    pub fn wait_for_reachable(
+        &self,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<(), crate::Error> {
+        let start = self.clock.now();
+        loop {
+            match self.leader() {
+                Ok(_) => break Ok(()),
+                Err(ref e)
+                    if e.is_unreachable()
+                        && self.clock.elapsed(start) + poll_interval < timeout =>
+                {
+                    warn!(
+                        "Unable to reach Nomad: {}. Retrying in {:?}",
+                        e, poll_interval
+                    );
+                    self.clock.sleep(poll_interval);
+                }
+                Err(e) if e.is_unreachable() => break Err(e),
+                Err(e) => {
+                    warn!("Nomad is reachable but its leader is unknown: {}", e);
+                    break Ok(());
+                }
+            }
+        }
+    }
+
+    /// Wait for the node of a newly launched AWS instance to register and become ready
+    ///
+    /// The Nomad agent on a new instance takes a while to start and register. Nodes are looked up
+    /// every `poll_interval` until the node for the instance is ready, or `timeout` elapses.
+    pub fn wait_for_node_ready(
+        &self,
+        instance_id: &str,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<Node, crate::Error> {
+        let start = self.clock.now();
+        loop {
+            match self.find_node_by_instance_id(instance_id, true) {
+                Ok(node_match) if node_match.node.data.status == NodeStatus::Ready => {
+                    break Ok(node_match.node.data);
+                }
+                Ok(node_match) => info!(
+                    "Nomad Node ID {} is {:?}. Waiting for it to be ready",
+                    node_match.node.data.id, node_match.node.data.status
+                ),
+                Err(crate::Error::NomadNodeNotFound { .. }) => info!(
+                    "AWS Instance ID {} has not registered with Nomad yet",
+                    instance_id
+                ),
+                Err(e) => Err(e)?,
+            }
+
+            if self.clock.elapsed(start) + poll_interval >= timeout {
+                Err(crate::Error::NomadNodeNotReady {
+                    instance_id: instance_id.to_string(),
+                })?;
+            }
+            self.clock.sleep(poll_interval);
+        }
+    }
+
+    /// Get an evaluation, such as one created by draining a node
+    pub fn evaluation(&self, eval_id: &str) -> Result<Evaluation, crate::Error> {
+        info!("Requesting Nomad Evaluation {}", eval_id);
+        self.execute_request(|| self.build_evaluation_request(eval_id))
+    }
+
+    fn build_evaluation_request(&self, eval_id: &str) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/evaluation/{}", &self.address, eval_id);
+        let request = self.client.get(&address);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Set a node eligibility for receiving new allocations
+    ///
+    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
+    /// CA certificate or require client authentication
+    pub fn set_node_eligibility(
+        &self,
+        node_id: &str,
+        eligibility: NodeEligibility,
+    ) -> Result<NodeEligibilityResponse, crate::Error> {
+        info!(
+            "Setting Nomad Node ID {} eligibility to {}",
+            node_id, eligibility
+        );
+        self.context.check_mutable(&format!(
+            "set Node ID {} eligibility to {}",
+            node_id, eligibility
+        ))?;
+        let request = NodeEligibilityRequest {
+            node_id,
+            eligibility,
+        };
+
+        // Request is successful if the response can be deserialized
+        self.execute_request(|| self.build_node_eligibility_request(node_id, &request))
+    }
+
+    fn build_node_eligibility_request(
+        &self,
+        node_id: &str,
+        payload: &NodeEligibilityRequest,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/node/{}/eligibility", self.address, node_id);
+        let request = self.client.post(&address).json(payload);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Drain a node and make sure that it is ineligible for new allocations
+    ///
+    /// Since Nomad 0.8, draining a node also makes it ineligible, so the drain is submitted first
+    /// instead of waiting for a separate eligibility update. Eligibility is then verified from the
+    /// node details, and only updated if the drain did not take care of it.
+    pub fn set_node_drain_ineligible(
+        &self,
+        node_id: &str,
+        drain_spec: Option<DrainSpec>,
+    ) -> Result<NodeDrainResponse, crate::Error> {
+        let response = self.set_node_drain(node_id, false, drain_spec)?;
+        let node = self.node_details(node_id, None, None)?;
+        if node.data.scheduling_eligibility != NodeEligibility::Ineligible {
+            warn!(
+                "Node ID {} is still {} after the drain was submitted",
+                node_id, node.data.scheduling_eligibility
+            );
+            self.set_node_eligibility(node_id, NodeEligibility::Ineligible)?;
+        }
+        Ok(response)
+    }
+
+    /// Mark the node for draining
+    ///
+    /// You can optionally specify a `DrainSpec`. If you don't provide one, we will use the default.
+    ///
+    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
     /// CA certificate or require client authentication
+    pub fn set_node_drain(
+        &self,
+        node_id: &str,
+        monitor: bool,
+        drain_spec: Option<DrainSpec>,
+    ) -> Result<NodeDrainResponse, crate::Error> {
+        self.context
+            .check_mutable(&format!("drain Node ID {}", node_id))?;
+        let drain_spec = drain_spec.unwrap_or_default();
+        let drain_api = self.drain_api()?;
+        info!("Draining Node ID {} with {:#?}", node_id, drain_spec);
+        let payload =
+            NodeDrainRequest::new(drain_api, node_id, &drain_spec, self.drain_meta.as_ref());
+        // Request is successful if the response can be deserialized
+        let response: NodeDrainResponse =
+            self.execute_request(|| self.build_drain_request(node_id, &payload))?;
+
+        if monitor {
+            self.monitor_node_drain(node_id, None, None)?;
+        }
+        Ok(response)
+    }
+
+    fn build_drain_request(
+        &self,
+        node_id: &str,
+        payload: &NodeDrainRequest,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/node/{}/drain", &self.address, node_id);
+        let request = self.client.post(&address).json(payload);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Cancel the drain of a node and make it eligible for new allocations again
+    ///
+    /// Allocations that have already migrated off the node are not moved back.
+    pub fn cancel_node_drain(&self, node_id: &str) -> Result<NodeDrainResponse, crate::Error> {
+        self.context
+            .check_mutable(&format!("cancel the drain of Node ID {}", node_id))?;
+        info!("Cancelling the drain of Node ID {}", node_id);
+        let payload = NodeDrainRequest::cancel(node_id);
+        // Request is successful if the response can be deserialized
+        self.execute_request(|| self.build_drain_request(node_id, &payload))
+    }
+
+    /// Purge a node from the state of the cluster
+    ///
+    /// The node is removed immediately instead of waiting for it to be garbage collected after it
+    /// goes down. Only purge nodes that have been drained.
+    pub fn purge_node(&self, node_id: &str) -> Result<NodePurgeResponse, crate::Error> {
+        self.context
+            .check_mutable(&format!("purge Node ID {}", node_id))?;
+        info!("Purging Nomad Node ID {}", node_id);
+        // Request is successful if the response can be deserialized
+        self.execute_request(|| self.build_purge_node_request(node_id))
+    }
+
+    fn build_purge_node_request(&self, node_id: &str) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/node/{}/purge", &self.address, node_id);
+        let request = self.client.post(&address);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Stop an allocation, so that it is rescheduled elsewhere
+    ///
+    /// Use this to evict an allocation that is holding up a drain instead of waiting for the drain
+    /// deadline to force every allocation off the node. Requires Nomad 0.9.2 or later.
+    pub fn stop_allocation(&self, alloc_id: &str) -> Result<AllocationStopResponse, crate::Error> {
+        self.context
+            .check_mutable(&format!("stop Allocation ID {}", alloc_id))?;
+        info!("Stopping Nomad Allocation ID {}", alloc_id);
+        self.execute_request(|| self.build_stop_allocation_request(alloc_id))
+    }
+
+    fn build_stop_allocation_request(
+        &self,
+        alloc_id: &str,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/allocation/{}/stop", &self.address, alloc_id);
+        let request = self.client.post(&address);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Send a signal, such as `SIGTERM`, to the tasks of an allocation
+    ///
+    /// The signal is sent to every task of the allocation, unless `task` is provided. Use this to
+    /// nudge a task that is not shutting down on its own.
+    pub fn signal_allocation(
+        &self,
+        alloc_id: &str,
+        signal: &str,
+        task: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        self.context.check_mutable(&format!(
+            "signal Allocation ID {} with {}",
+            alloc_id, signal
+        ))?;
+        info!("Sending {} to Nomad Allocation ID {}", signal, alloc_id);
+        let payload = AllocationSignalRequest { signal, task };
+        // Request is successful if the response can be deserialized
+        let _: serde_json::Value =
+            self.execute_request(|| self.build_signal_allocation_request(alloc_id, &payload))?;
+        Ok(())
+    }
+
+    fn build_signal_allocation_request(
+        &self,
+        alloc_id: &str,
+        payload: &AllocationSignalRequest,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/client/allocation/{}/signal", &self.address, alloc_id);
+        let request = self.client.post(&address).json(payload);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Monitor Node Drain
+    ///
+    /// This function will block until the drain is complete, or an error occurs. With
+    /// `max_duration`, monitoring gives up with `Error::DrainTimeout` once the drain has been
+    /// monitored for that long.
+    pub fn monitor_node_drain(
+        &self,
+        node_id: &str,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+    ) -> Result<(), crate::Error> {
+        self.monitor_node_drain_with_heartbeat(node_id, wait_timeout, max_duration, || Ok(()))
+    }
+
+    /// Monitor Node Drain, calling `heartbeat` every time the node is checked and the drain is not
+    /// complete yet
+    ///
+    /// Use this to keep external timeouts, such as lifecycle hook heartbeats, from expiring while
+    /// the drain is in progress. The node is checked at least once every `wait_timeout`. Errors
+    /// returned by `heartbeat` stop the monitoring.
+    pub fn monitor_node_drain_with_heartbeat<F>(
+        &self,
+        node_id: &str,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+        mut heartbeat: F,
+    ) -> Result<(), crate::Error>
+    where
+        F: FnMut() -> Result<(), crate::Error>,
+    {
+        self.monitor_node_drain_with_progress(node_id, wait_timeout, max_duration, false, |event| {
+            if event.completed {
+                Ok(())
+            } else {
+                heartbeat()
+            }
+        })
+    }
+
+    /// Monitor Node Drain, calling `on_event` with the progress every time the node is checked
+    ///
+    /// With `allocations`, monitoring carries on until the allocations on the node have stopped
+    /// like `monitor_node_drain_with_allocations`, and their number is reported as well. The last
+    /// event has `completed` set. Errors returned by `on_event` stop the monitoring.
+    pub fn monitor_node_drain_with_progress<F>(
+        &self,
+        node_id: &str,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+        allocations: bool,
+        on_event: F,
+    ) -> Result<(), crate::Error>
+    where
+        F: FnMut(&DrainEvent) -> Result<(), crate::Error>,
+    {
+        self.resume_node_drain_monitoring(
+            node_id,
+            &Default::default(),
+            wait_timeout,
+            max_duration,
+            allocations,
+            on_event,
+        )
+    }
+
+    /// Monitor Node Drain like `monitor_node_drain_with_progress`, resuming from the `cursor` of
+    /// the last event of an earlier monitoring
+    ///
+    /// Persist the cursor to carry monitoring on in another process, such as the next invocation
+    /// of a Lambda function. Blocking queries then continue from where monitoring left off
+    /// instead of reading the node again from scratch.
+    pub fn resume_node_drain_monitoring<F>(
+        &self,
+        node_id: &str,
+        cursor: &MonitorCursor,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+        allocations: bool,
+        mut on_event: F,
+    ) -> Result<(), crate::Error>
+    where
+        F: FnMut(&DrainEvent) -> Result<(), crate::Error>,
+    {
+        // The procedure is based on https://github.com/hashicorp/nomad/blob/master/api/nodes.go
+
+        let wait_timeout = wait_timeout.unwrap_or(self.wait_timeout);
+        let started = self.clock.now();
+        // Blocking queries are shortened so that monitoring gives up on time
+        let next_wait_timeout = || -> Result<Duration, crate::Error> {
+            self.check_deadline()?;
+            let elapsed = self.clock.elapsed(started);
+            match max_duration {
+                Some(max_duration) if elapsed >= max_duration => Err(crate::Error::DrainTimeout {
+                    node_id: node_id.to_string(),
+                    elapsed,
+                }),
+                Some(max_duration) => Ok(std::cmp::min(wait_timeout, max_duration - elapsed)),
+                None => Ok(wait_timeout),
+            }
+        };
+        let mut progress = DrainProgress::default();
+        if cursor.phase == MonitorPhase::Node {
+            progress.wait_index = cursor.wait_index;
+            info!("Monitoring drain for Node ID {}", node_id);
+            loop {
+                let wait_timeout = next_wait_timeout()?;
+                info!("Checking if Node ID {} drain is complete", node_id);
+                let node = match self.node_details(node_id, progress.wait_index, Some(wait_timeout))
+                {
+                    Err(crate::Error::NomadNodeGone { .. }) => {
+                        warn!(
+                            "Node ID {} is gone. Considering its drain complete",
+                            node_id
+                        );
+                        return on_event(&DrainEvent::gone());
+                    }
+                    node => node?,
+                };
+                let mut event = progress.observe(node_id, node, chrono::Utc::now());
+                let done = event.completed;
+                event.completed &= !allocations;
+                on_event(&event)?;
+                if done {
+                    break;
+                }
+            }
+            info!("Done monitoring drain for Node ID {}", node_id);
+        }
+        if !allocations {
+            return Ok(());
+        }
+
+        let mut wait_index = match cursor.phase {
+            MonitorPhase::Allocations => cursor.wait_index,
+            MonitorPhase::Node => None,
+        };
+        info!("Monitoring allocations for Node ID {}", node_id);
+        loop {
+            let wait_timeout = next_wait_timeout()?;
+            let allocations = self.allocations(node_id, wait_index, Some(wait_timeout))?;
+            let remaining = remaining_allocations(&allocations.data).count();
+            on_event(&DrainEvent {
+                strategy_changed: false,
+                node_down: false,
+                node_gone: false,
+                allocations_remaining: Some(remaining),
+                force_deadline: None,
+                time_until_force_deadline: None,
+                cursor: MonitorCursor {
+                    phase: MonitorPhase::Allocations,
+                    wait_index: Some(allocations.index),
+                },
+                completed: remaining == 0,
+            })?;
+            if remaining == 0 {
+                break;
+            }
+
+            info!(
+                "Node ID {} has {} allocations remaining",
+                node_id, remaining
+            );
+            wait_index = Some(allocations.index);
+        }
+        info!("All allocations stopped for Node ID {}", node_id);
+        Ok(())
+    }
+
+    /// Monitor Node Drain, including the allocations on the node
+    ///
+    /// In addition to `monitor_node_drain`, this blocks until all allocations on the node that do
+    /// not belong to system jobs have stopped, like `nomad node drain -monitor`.
+    pub fn monitor_node_drain_with_allocations(
+        &self,
+        node_id: &str,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+    ) -> Result<(), crate::Error> {
+        self.monitor_node_drain_with_progress(node_id, wait_timeout, max_duration, true, |_| Ok(()))
+    }
+
+    /// Return the allocations placed on a node
+    ///
+    /// Supply the optional parameters to take advantage of
+    /// [blocking queries](https://www.nomadproject.io/api/index.html#blocking-queries)
+    pub fn allocations(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<BlockingResponse<Vec<Allocation>>, crate::Error> {
+        info!("Requesting allocations for Nomad Node {}", node_id);
+        self.execute_indexed_request(|| {
+            self.build_allocations_request(node_id, wait_index, wait_timeout)
+        })
+    }
+
+    /// Return summaries of the allocations placed on a node
+    ///
+    /// See `Client::allocations`
+    pub fn node_allocations(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<BlockingResponse<Vec<AllocationSummary>>, crate::Error> {
+        let response = self.allocations(node_id, wait_index, wait_timeout)?;
+        Ok(response.map(|allocations| allocations.iter().map(From::from).collect()))
+    }
+
+    fn build_allocations_request(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let address = format!("{}/v1/node/{}/allocations", &self.address, node_id);
+        let request = self.client.get(&address);
+        let request = self.add_request_defaults(request);
+        let request = self.add_blocking_requests(request, wait_index, wait_timeout);
+        Ok(request.build()?)
+    }
+
+    /// Add the token header and region query parameter, if any
+    /// Authenticate the request with the signer, if any
+    fn sign(&self, mut request: reqwest::Request) -> Result<reqwest::Request, crate::Error> {
+        if let Some(ref signer) = self.signer {
+            signer.sign(&mut request)?;
+        }
+        Ok(request)
+    }
+
+    fn add_request_defaults(&self, request_builder: RequestBuilder) -> RequestBuilder {
+        let request_builder = match &self.token {
+            Some(token) => request_builder.nomad_token(token.expose_secret()),
+            None => request_builder,
+        };
+        let request_builder = match &self.namespace {
+            Some(namespace) => request_builder.nomad_namespace(namespace),
+            None => request_builder,
+        };
+        match &self.region {
+            Some(region) => request_builder.query(&[("region", region)]),
+            None => request_builder,
+        }
+    }
+
+    fn add_blocking_requests(
+        &self,
+        request_builder: RequestBuilder,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> RequestBuilder {
+        let wait_timeout = self.blocking_wait(wait_index, wait_timeout);
+        let query = blocking_query(wait_index, wait_timeout);
+        if query.is_empty() {
+            request_builder
+        } else {
+            request_builder.query(&query)
+        }
+    }
+
+    fn make_indexed_response<T>(
+        headers: &reqwest::header::HeaderMap,
+        data: T,
+        diagnostics: ResponseDiagnostics,
+    ) -> Result<BlockingResponse<T>, crate::Error> {
+        let index = match headers.get(NOMAD_INDEX_HEADER) {
+            None => 0,
+            Some(index) => index.to_str()?.parse()?,
+        };
+
+        Ok(BlockingResponse {
+            data,
+            index,
+            diagnostics,
+        })
+    }
+}
+
+/// Allocations that a drain has yet to stop
+fn remaining_allocations(allocations: &[Allocation]) -> impl Iterator<Item = &Allocation> {
+    allocations
+        .iter()
+        .filter(|allocation| !allocation.is_system() && !allocation.is_terminal())
+}
+
+/// Query parameters for [blocking queries](https://www.nomadproject.io/api/index.html#blocking-queries)
+fn blocking_query(
+    wait_index: Option<u64>,
+    wait_timeout: Option<Duration>,
+) -> Vec<(&'static str, String)> {
+    match wait_index {
+        Some(index) => {
+            let mut query = vec![("index", index.to_string())];
+            if let Some(timeout) = wait_timeout {
+                query.push(("wait", format!("{}s", timeout.as_secs())));
+            }
+            query
+        }
+        None => vec![],
+    }
+}
+
+/// Progress of a node drain, reported every time the node or its allocations are checked
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DrainEvent {
+    /// The drain strategy of the node was set, updated or cleared since the last check
+    pub strategy_changed: bool,
+    /// The node is down or missed a heartbeat
+    pub node_down: bool,
+    /// The node was garbage collected or purged, which completes the drain
+    pub node_gone: bool,
+    /// Allocations left on the node that do not belong to system jobs, once allocations are
+    /// being monitored
+    pub allocations_remaining: Option<usize>,
+    /// Time at which allocations that have not migrated are stopped, while the drain has a
+    /// deadline
+    pub force_deadline: Option<chrono::DateTime<chrono::Utc>>,
+    /// Time left until `force_deadline` when the node was checked
+    pub time_until_force_deadline: Option<Duration>,
+    /// Where to resume monitoring from after this event
+    pub cursor: MonitorCursor,
+    /// Monitoring is complete
+    pub completed: bool,
+}
+
+/// What is being monitored during a node drain
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorPhase {
+    /// The drain strategy of the node, until Nomad has marked every allocation for migration
+    #[default]
+    Node,
+    /// The allocations on the node, until they have stopped
+    Allocations,
+}
+
+/// Where monitoring of a node drain left off, to resume it from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MonitorCursor {
+    /// What was being monitored
+    pub phase: MonitorPhase,
+    /// Index of the last response, for blocking queries to wait for changes after
+    pub wait_index: Option<u64>,
+}
+
+impl DrainEvent {
+    /// The node is gone, and there is nothing left to monitor
+    fn gone() -> Self {
+        DrainEvent {
+            strategy_changed: false,
+            node_down: false,
+            node_gone: true,
+            allocations_remaining: None,
+            force_deadline: None,
+            time_until_force_deadline: None,
+            cursor: MonitorCursor {
+                phase: MonitorPhase::Allocations,
+                wait_index: None,
+            },
+            completed: true,
+        }
+    }
+}
+
+/// Replace errors for nodes that do not exist with `Error::NomadNodeGone`
+fn node_gone(node_id: &str, error: crate::Error) -> crate::Error {
+    match error {
+        crate::Error::NomadApi { status: 404, .. } => crate::Error::NomadNodeGone {
+            node_id: node_id.to_string(),
+        },
+        error => error,
+    }
+}
+
+/// Progress of a node drain observed while monitoring it
+#[derive(Debug, Default)]
+struct DrainProgress {
+    wait_index: Option<u64>,
+    strategy: Option<DrainStrategy>,
+    strategy_changed: bool,
+}
+
+impl DrainProgress {
+    /// Record the latest details of the node at `now`, returning the progress made since the last
+    /// check
+    fn observe(
+        &mut self,
+        node_id: &str,
+        node: BlockingResponse<Node>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> DrainEvent {
+        let node_down = node.data.status == NodeStatus::Down;
+        if node.data.drain_strategy.is_none() {
+            if self.strategy_changed {
+                info!(
+                    "Node {} has has marked all allocations for migration",
+                    node_id
+                );
+            } else {
+                info!("No drain strategy set for node {}", node_id);
+            }
+            return DrainEvent {
+                strategy_changed: self.strategy.is_some(),
+                node_down,
+                node_gone: false,
+                allocations_remaining: None,
+                force_deadline: None,
+                time_until_force_deadline: None,
+                cursor: MonitorCursor {
+                    phase: MonitorPhase::Allocations,
+                    wait_index: None,
+                },
+                completed: true,
+            };
+        }
+
+        if node_down {
+            warn!("Node {} down", node_id);
+        }
+
+        let strategy_changed = self.strategy != node.data.drain_strategy;
+        if strategy_changed {
+            info!(
+                "Node {} drain updated: {:#?}",
+                node_id, node.data.drain_strategy
+            );
+        }
+
+        let force_deadline = node
+            .data
+            .drain_strategy
+            .as_ref()
+            .and_then(DrainStrategy::deadline);
+        let time_until_force_deadline = node
+            .data
+            .drain_strategy
+            .as_ref()
+            .and_then(|strategy| strategy.time_until_deadline(now));
+        if let (Some(deadline), Some(remaining)) = (force_deadline, time_until_force_deadline) {
+            info!(
+                "Allocations remaining on Node {} are stopped at {} in {}s",
+                node_id,
+                deadline.to_rfc3339(),
+                remaining.as_secs()
+            );
+        }
+
+        self.strategy = node.data.drain_strategy;
+        self.strategy_changed = true;
+        self.wait_index = Some(node.index);
+        DrainEvent {
+            strategy_changed,
+            node_down,
+            node_gone: false,
+            allocations_remaining: None,
+            force_deadline,
+            time_until_force_deadline,
+            cursor: MonitorCursor {
+                phase: MonitorPhase::Node,
+                wait_index: self.wait_index,
+            },
+            completed: false,
+        }
+    }
+}
+
+impl AsyncClient {
+    /// Create a new asynchronous Nomad Client
+    ///
+    /// You can optionally provide a `reqwest::async::Client` if you have specific needs like
+    /// custom root CA certificate or require client authentication. As with `Client::new`, the
+    /// default client has a timeout set to 6 minutes to allow supporting blocking queries.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<S1, S2>(
+        address: S1,
+        token: Option<S2>,
+        client: Option<AsyncHttpClient>,
+    ) -> Result<Self, crate::Error>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let client = match client {
+            Some(client) => client,
+            None => AsyncClientBuilder::new()
+                .timeout(Duration::from_secs(360))
+                .build()?,
+        };
+
+        Ok(Self {
+            client,
+            address: address.as_ref().to_string(),
+            token: token.map(|s| From::from(s.as_ref().to_string())),
+            region: None,
+            drain_api: Default::default(),
+        })
+    }
+
+    /// Send every request to the provided region
+    ///
+    /// See `Client::with_region`
+    pub fn with_region<S: AsRef<str>>(mut self, region: S) -> Self {
+        self.region = Some(region.as_ref().to_string());
+        self
+    }
+
+    /// Drain nodes with the provided API instead of probing the version of the server
+    ///
+    /// See `Client::with_drain_api`
+    pub fn with_drain_api(mut self, drain_api: DrainApi) -> Self {
+        self.drain_api = Arc::new(Mutex::new(Some(drain_api)));
+        self
+    }
+
+    /// Returns the Nomad Server Address
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the Nomad Token, if any
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_ref().map(|s| s.expose_secret())
+    }
+
+    /// Returns the region requests are sent to, if pinned
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    /// Returns the HTTP Client used
+    pub fn http_client(&self) -> &AsyncHttpClient {
+        &self.client
+    }
+
+    /// Add the token header and region query parameter, if any
+    fn add_request_defaults(
+        &self,
+        request_builder: reqwest::r#async::RequestBuilder,
+    ) -> reqwest::r#async::RequestBuilder {
+        let request_builder = match &self.token {
+            Some(token) => request_builder.nomad_token(token.expose_secret()),
+            None => request_builder,
+        };
+        match &self.region {
+            Some(region) => request_builder.query(&[("region", region)]),
+            None => request_builder,
+        }
+    }
+
+    fn execute_indexed_request<T>(
+        &self,
+        request: Result<reqwest::r#async::Request, crate::Error>,
+    ) -> impl Future<Item = BlockingResponse<T>, Error = crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Debug,
+    {
+        let client = self.client.clone();
+        future::result(request)
+            .and_then(move |request| crate::execute_async(&client, request))
+            .and_then(|(status, headers, body)| {
+                let diagnostics = ResponseDiagnostics::new(status, &headers);
+                Client::parse_indexed_response(&headers, &body, diagnostics)
+            })
+    }
+
+    /// Get information about the agent
+    ///
+    /// See `Client::agent_self`
+    pub fn agent_self(&self) -> impl Future<Item = AgentSelf, Error = crate::Error> {
+        info!("Requesting Nomad Agent details");
+        self.execute_indexed_request(self.build_agent_self_request())
+            .map(|response| response.data)
+    }
+
+    fn build_agent_self_request(&self) -> Result<reqwest::r#async::Request, crate::Error> {
+        let address = format!("{}/v1/agent/self", &self.address);
+        let request = self.add_request_defaults(self.client.get(&address));
+        Ok(request.build()?)
+    }
+
+    /// Drain API of the server, probing its version the first time
+    ///
+    /// See `Client::drain_api`
+    pub fn drain_api(&self) -> impl Future<Item = DrainApi, Error = crate::Error> {
+        if let Some(drain_api) = *self.drain_api.lock().expect("Not to be poisoned") {
+            return Either::A(future::ok(drain_api));
+        }
+
+        let cache = Arc::clone(&self.drain_api);
+        Either::B(self.agent_self().then(move |agent| {
+            let probed = match agent.map(|agent| agent.version()) {
+                Ok(Some(version)) => {
+                    let api = DrainApi::for_version(&version)?;
+                    info!("Nomad {} uses drain API {:?}", version, api);
+                    api
+                }
+                Ok(None) => {
+                    warn!("Unable to determine the Nomad version. Using the oldest drain API");
+                    DrainApi::default()
+                }
+                Err(e) => {
+                    warn!(
+                        "Unable to probe the Nomad version ({}). Using the oldest drain API",
+                        e
+                    );
+                    DrainApi::default()
+                }
+            };
+            *cache.lock().expect("Not to be poisoned") = Some(probed);
+            Ok(probed)
+        }))
+    }
+
+    /// Get Information about a specific Node ID
+    ///
+    /// See `Client::node_details`
+    pub fn node_details(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> impl Future<Item = BlockingResponse<Node>, Error = crate::Error> {
+        info!("Requesting Nomad Node {} details", node_id);
+        let request = self.build_node_details_request(node_id, wait_index, wait_timeout);
+        let node_id = node_id.to_string();
+        self.execute_indexed_request(request)
+            .map_err(move |error| node_gone(&node_id, error))
+    }
+
+    fn build_node_details_request(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<reqwest::r#async::Request, crate::Error> {
+        let address = format!("{}/v1/node/{}", &self.address, node_id);
+        let request = self.add_request_defaults(self.client.get(&address));
+        let query = blocking_query(wait_index, wait_timeout);
+        let request = if query.is_empty() {
+            request
+        } else {
+            request.query(&query)
+        };
+        Ok(request.build()?)
+    }
+
+    /// Set node drain
+    ///
+    /// See `Client::set_node_drain`
+    pub fn set_node_drain(
+        &self,
+        node_id: &str,
+        monitor: bool,
+        drain_spec: Option<DrainSpec>,
+    ) -> impl Future<Item = (), Error = crate::Error> {
+        let drain_spec = drain_spec.unwrap_or_default();
+        info!("Draining Node ID {} with {:#?}", node_id, drain_spec);
+        let client = self.clone();
+        let node_id = node_id.to_string();
+        self.drain_api()
+            .and_then(move |drain_api| {
+                let payload = NodeDrainRequest::new(drain_api, &node_id, &drain_spec, None);
+                let request = client.build_drain_request(&node_id, &payload);
+                // Request is successful if the response can be deserialized
+                client
+                    .execute_indexed_request::<NodeDrainResponse>(request)
+                    .map(move |_| (client, node_id))
+            })
+            .and_then(move |(client, node_id)| {
+                if monitor {
+                    Either::A(client.monitor_node_drain(&node_id, None, None))
+                } else {
+                    Either::B(future::ok(()))
+                }
+            })
+    }
+
+    fn build_drain_request(
+        &self,
+        node_id: &str,
+        payload: &NodeDrainRequest,
+    ) -> Result<reqwest::r#async::Request, crate::Error> {
+        let address = format!("{}/v1/node/{}/drain", &self.address, node_id);
+        let request = self.client.post(&address).json(payload);
+        let request = self.add_request_defaults(request);
+        Ok(request.build()?)
+    }
+
+    /// Monitor Node Drain
+    ///
+    /// The returned future resolves when the drain is complete, or an error occurs. With
+    /// `max_duration`, it fails with `Error::DrainTimeout` once the drain has been monitored for
+    /// that long. See `Client::monitor_node_drain`
+    pub fn monitor_node_drain(
+        &self,
+        node_id: &str,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+    ) -> impl Future<Item = (), Error = crate::Error> {
+        let wait_timeout = wait_timeout.unwrap_or_else(|| Duration::from_secs(300));
+        let client = self.clone();
+        let node_id = node_id.to_string();
+        let started = Instant::now();
+
+        info!("Monitoring drain for Node ID {}", node_id);
+        future::loop_fn(DrainProgress::default(), move |mut progress| {
+            let node_id = node_id.clone();
+            let elapsed = started.elapsed();
+            // Blocking queries are shortened so that monitoring gives up on time
+            let wait_timeout = match max_duration {
+                Some(max_duration) if elapsed >= max_duration => {
+                    return Either::A(future::err(crate::Error::DrainTimeout { node_id, elapsed }));
+                }
+                Some(max_duration) => std::cmp::min(wait_timeout, max_duration - elapsed),
+                None => wait_timeout,
+            };
+            info!("Checking if Node ID {} drain is complete", node_id);
+            Either::B(
+                client
+                    .node_details(&node_id, progress.wait_index, Some(wait_timeout))
+                    .then(move |node| match node {
+                        Err(crate::Error::NomadNodeGone { .. }) => {
+                            warn!(
+                                "Node ID {} is gone. Considering its drain complete",
+                                node_id
+                            );
+                            Ok(Loop::Break(()))
+                        }
+                        Err(e) => Err(e),
+                        Ok(node) => {
+                            if progress
+                                .observe(&node_id, node, chrono::Utc::now())
+                                .completed
+                            {
+                                info!("Done monitoring drain for Node ID {}", node_id);
+                                Ok(Loop::Break(()))
+                            } else {
+                                Ok(Loop::Continue(progress))
+                            }
+                        }
+                    }),
+            )
+        })
+    }
+}
+
+/// Wrapper around a Nomad `Client` that refuses to make any changes to the cluster
+///
+/// Read-only calls are passed through to the wrapped client while mutating calls return
+/// `Error::ReadOnly` without contacting Nomad. Useful for dry runs and audit tools that share code
+/// paths with the real thing.
+///
+/// The wrapped client is given a dry run `RequestContext`, so that it refuses changes as well
+/// when handed to code that takes a `Client`.
+#[derive(Clone, Debug)]
+pub struct ReadOnlyGuard {
+    client: Client,
+    /// Whether the client was a dry run before it was wrapped
+    dry_run: bool,
+}
+
+impl ReadOnlyGuard {
+    /// Wrap a client
+    pub fn new(client: Client) -> Self {
+        let dry_run = client.context().dry_run;
+        let context = client.context().clone().with_dry_run(true);
+        Self {
+            client: client.with_context(context),
+            dry_run,
+        }
+    }
+
+    /// Returns the wrapped client, which refuses changes to the cluster with `Error::ReadOnly`
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Returns the wrapped client, allowing mutating calls again
+    pub fn into_inner(self) -> Client {
+        let context = self.client.context().clone().with_dry_run(self.dry_run);
+        self.client.with_context(context)
+    }
+
+    /// Returns the Nomad Server Address
+    pub fn address(&self) -> &str {
+        self.client.address()
+    }
+
+    /// See `Client::node_details`
+    pub fn node_details(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<BlockingResponse<Node>, crate::Error> {
+        self.client.node_details(node_id, wait_index, wait_timeout)
+    }
+
+    /// See `Client::agent_self`
+    pub fn agent_self(&self) -> Result<AgentSelf, crate::Error> {
+        self.client.agent_self()
+    }
+
+    /// See `Client::leader`
+    pub fn leader(&self) -> Result<String, crate::Error> {
+        self.client.leader()
+    }
+
+    /// See `Client::evaluation`
+    pub fn evaluation(&self, eval_id: &str) -> Result<Evaluation, crate::Error> {
+        self.client.evaluation(eval_id)
+    }
+
+    /// See `Client::nodes`
+    pub fn nodes(
+        &self,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<BlockingResponse<Vec<NodesInList>>, crate::Error> {
+        self.client.nodes(wait_index, wait_timeout)
+    }
+
+    /// See `Client::ready_nodes`
+    pub fn ready_nodes(&self) -> Result<Vec<NodesInList>, crate::Error> {
+        self.client.ready_nodes()
+    }
+
+    /// See `Client::nodes_in_datacenter`
+    pub fn nodes_in_datacenter(&self, datacenter: &str) -> Result<Vec<NodesInList>, crate::Error> {
+        self.client.nodes_in_datacenter(datacenter)
+    }
+
+    /// See `Client::nodes_matching`
+    pub fn nodes_matching(&self, query: &NodesQuery) -> Result<Vec<NodesInList>, crate::Error> {
+        self.client.nodes_matching(query)
+    }
+
+    /// See `Client::find_node_by_instance_id`
     pub fn find_node_by_instance_id(
         &self,
         instance_id: &str,
-    ) -> Result<BlockingResponse<Node>, crate::Error> {
-        info!("Finding Nomad Node ID for AWS Instance ID {}", instance_id);
-        let nodes = self.nodes(None, None)?;
-        let result = nodes
-            .data
+        include_non_ready: bool,
+    ) -> Result<NodeMatch, crate::Error> {
+        self.client
+            .find_node_by_instance_id(instance_id, include_non_ready)
+    }
+
+    /// See `Client::wait_for_node_ready`
+    pub fn wait_for_node_ready(
+        &self,
+        instance_id: &str,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<Node, crate::Error> {
+        self.client
+            .wait_for_node_ready(instance_id, timeout, poll_interval)
+    }
+
+    /// See `Client::monitor_node_drain`
+    pub fn monitor_node_drain(
+        &self,
+        node_id: &str,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+    ) -> Result<(), crate::Error> {
+        self.client
+            .monitor_node_drain(node_id, wait_timeout, max_duration)
+    }
+
+    /// See `Client::monitor_node_drain_with_heartbeat`
+    pub fn monitor_node_drain_with_heartbeat<F>(
+        &self,
+        node_id: &str,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+        heartbeat: F,
+    ) -> Result<(), crate::Error>
+    where
+        F: FnMut() -> Result<(), crate::Error>,
+    {
+        self.client.monitor_node_drain_with_heartbeat(
+            node_id,
+            wait_timeout,
+            max_duration,
+            heartbeat,
+        )
+    }
+
+    /// See `Client::monitor_node_drain_with_progress`
+    pub fn monitor_node_drain_with_progress<F>(
+        &self,
+        node_id: &str,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+        allocations: bool,
+        on_event: F,
+    ) -> Result<(), crate::Error>
+    where
+        F: FnMut(&DrainEvent) -> Result<(), crate::Error>,
+    {
+        self.client.monitor_node_drain_with_progress(
+            node_id,
+            wait_timeout,
+            max_duration,
+            allocations,
+            on_event,
+        )
+    }
+
+    /// See `Client::resume_node_drain_monitoring`
+    pub fn resume_node_drain_monitoring<F>(
+        &self,
+        node_id: &str,
+        cursor: &MonitorCursor,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+        allocations: bool,
+        on_event: F,
+    ) -> Result<(), crate::Error>
+    where
+        F: FnMut(&DrainEvent) -> Result<(), crate::Error>,
+    {
+        self.client.resume_node_drain_monitoring(
+            node_id,
+            cursor,
+            wait_timeout,
+            max_duration,
+            allocations,
+            on_event,
+        )
+    }
+
+    /// See `Client::monitor_node_drain_with_allocations`
+    pub fn monitor_node_drain_with_allocations(
+        &self,
+        node_id: &str,
+        wait_timeout: Option<Duration>,
+        max_duration: Option<Duration>,
+    ) -> Result<(), crate::Error> {
+        self.client
+            .monitor_node_drain_with_allocations(node_id, wait_timeout, max_duration)
+    }
+
+    /// See `Client::allocations`
+    pub fn allocations(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<BlockingResponse<Vec<Allocation>>, crate::Error> {
+        self.client.allocations(node_id, wait_index, wait_timeout)
+    }
+
+    /// See `Client::node_allocations`
+    pub fn node_allocations(
+        &self,
+        node_id: &str,
+        wait_index: Option<u64>,
+        wait_timeout: Option<Duration>,
+    ) -> Result<BlockingResponse<Vec<AllocationSummary>>, crate::Error> {
+        self.client
+            .node_allocations(node_id, wait_index, wait_timeout)
+    }
+
+    /// Always fails: setting node eligibility modifies the cluster
+    pub fn set_node_eligibility(
+        &self,
+        node_id: &str,
+        eligibility: NodeEligibility,
+    ) -> Result<NodeEligibilityResponse, crate::Error> {
+        Self::refuse(&format!(
+            "set Node ID {} eligibility to {}",
+            node_id, eligibility
+        ))
+    }
+
+    /// Always fails: draining a node modifies the cluster
+    pub fn set_node_drain(
+        &self,
+        node_id: &str,
+        _monitor: bool,
+        _drain_spec: Option<DrainSpec>,
+    ) -> Result<NodeDrainResponse, crate::Error> {
+        Self::refuse(&format!("drain Node ID {}", node_id))
+    }
+
+    /// Always fails: draining a node modifies the cluster
+    pub fn set_node_drain_ineligible(
+        &self,
+        node_id: &str,
+        _drain_spec: Option<DrainSpec>,
+    ) -> Result<NodeDrainResponse, crate::Error> {
+        Self::refuse(&format!("drain Node ID {}", node_id))
+    }
+
+    /// Always fails: cancelling a drain modifies the cluster
+    pub fn cancel_node_drain(&self, node_id: &str) -> Result<NodeDrainResponse, crate::Error> {
+        Self::refuse(&format!("cancel the drain of Node ID {}", node_id))
+    }
+
+    /// Always fails: purging a node modifies the cluster
+    pub fn purge_node(&self, node_id: &str) -> Result<NodePurgeResponse, crate::Error> {
+        Self::refuse(&format!("purge Node ID {}", node_id))
+    }
+
+    /// Always fails: stopping an allocation modifies the cluster
+    pub fn stop_allocation(&self, alloc_id: &str) -> Result<AllocationStopResponse, crate::Error> {
+        Self::refuse(&format!("stop Allocation ID {}", alloc_id))
+    }
+
+    /// Always fails: signalling an allocation modifies the cluster
+    pub fn signal_allocation(
+        &self,
+        alloc_id: &str,
+        signal: &str,
+        _task: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        Self::refuse(&format!(
+            "signal Allocation ID {} with {}",
+            alloc_id, signal
+        ))
+    }
+
+    fn refuse<T>(operation: &str) -> Result<T, crate::Error> {
+        warn!("Refusing to {} with a read-only Nomad client", operation);
+        Err(crate::Error::ReadOnly {
+            operation: operation.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::NodeFixture;
+    use crate::headers::{NOMAD_AUTH_HEADER, NOMAD_NAMESPACE_HEADER};
+
+    const NOMAD_ADDRESS: &str = "http://127.0.0.1:4646";
+
+    fn node_fixture() -> &'static str {
+        include_str!("../fixtures/nomad_node.json")
+    }
+
+    fn nodes_fixture() -> &'static str {
+        include_str!("../fixtures/nomad_nodes.json")
+    }
+
+    fn allocations_fixture() -> &'static str {
+        include_str!("../fixtures/node_allocations.json")
+    }
+
+    /// Load a scenario into the mock Nomad server of `utils/mock_nomad` and return a client for it
+    ///
+    /// Tests using the mock server are ignored by default. Start it with `docker-compose up` in
+    /// `utils/mock_nomad` and run them with `cargo test -- --ignored`.
+    fn mock_nomad_client(scenario: serde_json::Value) -> Result<Client, crate::Error> {
+        let address =
+            std::env::var("MOCK_NOMAD_ADDR").unwrap_or_else(|_| NOMAD_ADDRESS.to_string());
+        let _ = reqwest::Client::new()
+            .put(&format!("{}/mock/scenario", address))
+            .json(&scenario)
+            .send()?
+            .error_for_status()?;
+        Client::builder(address).build()
+    }
+
+    /// Requests received by the mock Nomad server since the scenario was loaded
+    fn mock_nomad_requests(client: &Client) -> Result<Vec<serde_json::Value>, crate::Error> {
+        Ok(reqwest::Client::new()
+            .get(&format!("{}/mock/requests", client.address()))
+            .send()?
+            .error_for_status()?
+            .json()?)
+    }
+
+    fn nomad_client() -> Client {
+        Client::builder(NOMAD_ADDRESS)
+            .with_token("token")
+            .build()
+            .expect("Not to fail")
+    }
+
+    #[test]
+    fn waiting_for_node_retries_unreachable_nomad_on_the_clock() {
+        let clock = Arc::new(crate::clock::SimulatedClock::new());
+        let client = Client::builder("http://127.0.0.1:1")
+            .build()
+            .expect("Not to fail")
+            .with_retry_policy(RetryPolicy {
+                jitter: false,
+                ..Default::default()
+            })
+            .with_clock(clock.clone());
+
+        let result = client.wait_for_node_ready(
+            "i-1234567890abcdef0",
+            Duration::from_secs(600),
+            Duration::from_secs(5),
+        );
+        assert!(result.is_err());
+        // Both the filtered and the unfiltered node lists are retried
+        assert_eq!(Duration::from_millis(2 * (500 + 1000)), clock.simulated());
+    }
+
+    #[test]
+    fn waiting_for_unreachable_nomad_gives_up_after_the_timeout() {
+        let clock = Arc::new(crate::clock::SimulatedClock::new());
+        let client = Client::builder("http://127.0.0.1:1")
+            .build()
+            .expect("Not to fail")
+            .with_retry_policy(RetryPolicy::none())
+            .with_clock(clock.clone());
+
+        match client.wait_for_reachable(Duration::from_secs(30), Duration::from_secs(5)) {
+            Err(ref e) if e.is_unreachable() => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert_eq!(Duration::from_secs(25), clock.simulated());
+    }
+
+    #[test]
+    fn node_is_deserialized_properly() {
+        let node: Node = serde_json::from_str(node_fixture()).unwrap();
+
+        assert_eq!("02802087-8786-fdf6-4497-98445c891fb7", node.id);
+    }
+
+    #[test]
+    #[cfg(feature = "strict")]
+    fn strict_deserialization_rejects_unknown_fields() {
+        let mut node: serde_json::Value = serde_json::from_str(node_fixture()).unwrap();
+        node["SomeNewField"] = serde_json::Value::Bool(true);
+
+        assert!(serde_json::from_value::<Node>(node).is_err());
+    }
+
+    #[test]
+    fn node_aws_attributes_are_parsed() {
+        let node: Node = serde_json::from_str(node_fixture()).unwrap();
+
+        assert_eq!(Some("i-instance-id"), node.instance_id());
+        assert_eq!(Some("ap-southeast-1c"), node.availability_zone());
+        assert_eq!(Some("c5.large"), node.instance_type());
+        assert_eq!(Some("ami-ami-id"), node.ami_id());
+        assert_eq!(None, node.attribute("platform.gce.zone"));
+    }
+
+    #[test]
+    fn nodes_list_is_deserialized_properly() {
+        let _: Vec<NodesInList> = serde_json::from_str(nodes_fixture()).unwrap();
+    }
+
+    fn blocking_node(id: &str, status: NodeStatus, modify_index: u64) -> BlockingResponse<Node> {
+        NodeFixture::ready()
+            .with_id(id)
+            .with_status(status)
+            .with_modify_index(modify_index)
+            .blocking(0)
+    }
+
+    #[test]
+    fn single_node_match_is_not_ambiguous() {
+        let matches = vec![blocking_node("a", NodeStatus::Ready, 1)];
+        let result = NodeMatch::select("i-instance-id", matches, false).unwrap();
+
+        assert_eq!("a", result.node.data.id);
+        assert!(result.ambiguous.is_none());
+    }
+
+    #[test]
+    fn ambiguous_node_match_prefers_ready_and_most_recent_node() {
+        let matches = vec![
+            blocking_node("down", NodeStatus::Down, 10),
+            blocking_node("old", NodeStatus::Ready, 1),
+            blocking_node("new", NodeStatus::Ready, 5),
+        ];
+        let result = NodeMatch::select("i-instance-id", matches, false).unwrap();
+
+        assert_eq!("new", result.node.data.id);
+        assert_eq!(
+            Some(AmbiguousNodeMatch {
+                instance_id: "i-instance-id".to_string(),
+                selected_node_id: "new".to_string(),
+                other_node_ids: vec!["old".to_string(), "down".to_string()],
+            }),
+            result.ambiguous
+        );
+    }
+
+    #[test]
+    fn non_ready_nodes_are_reported_but_only_selected_if_included() {
+        let matches = || {
+            vec![
+                blocking_node("down", NodeStatus::Down, 10),
+                blocking_node("ready", NodeStatus::Ready, 1),
+            ]
+        };
+        let result = NodeMatch::select("i-instance-id", matches(), false).unwrap();
+        assert_eq!("ready", result.node.data.id);
+        assert_eq!(
+            vec!["down".to_string()],
+            result.ambiguous.unwrap().other_node_ids
+        );
+
+        let down = || vec![blocking_node("down", NodeStatus::Down, 10)];
+        assert!(NodeMatch::select("i-instance-id", down(), false).is_none());
+        let result = NodeMatch::select("i-instance-id", down(), true).unwrap();
+        assert_eq!("down", result.node.data.id);
+        assert!(result.ambiguous.is_none());
+    }
+
+    #[test]
+    fn no_node_match_is_none() {
+        assert!(NodeMatch::select("i-instance-id", vec![], true).is_none());
+    }
+
+    #[test]
+    fn agent_self_is_deserialized_properly() {
+        let agent: AgentSelf =
+            serde_json::from_str(include_str!("../fixtures/agent_self.json")).unwrap();
+
+        assert_eq!("ap-southeast-1", agent.config.region);
+        assert_eq!("ap-southeast-1a", agent.config.datacenter);
+        assert_eq!(None, agent.acl_enabled());
+
+        let agent: AgentSelf =
+            serde_json::from_str(include_str!("../fixtures/agent_self_1.6.json")).unwrap();
+        assert_eq!(Some(true), agent.acl_enabled());
+    }
+
+    #[test]
+    fn clients_are_built_with_request_defaults() -> Result<(), crate::Error> {
+        let client = Client::builder(NOMAD_ADDRESS)
+            .with_token("token")
+            .with_namespace("ops")
+            .with_region("global")
+            .with_user_agent("nomad-drain-test")
+            .with_wait_timeout(Duration::from_secs(60))
+            .build()?;
+        assert_eq!(Duration::from_secs(60), client.wait_timeout());
+
+        let request = client.build_agent_self_request()?;
+        assert_eq!(
+            format!("{}/v1/agent/self?region=global", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
+        assert_eq!("ops", request.headers()[NOMAD_NAMESPACE_HEADER]);
+        Ok(())
+    }
+
+    #[test]
+    fn candidate_tokens_are_used_unverified_when_nomad_cannot_tell() -> Result<(), crate::Error> {
+        let client = Client::builder("http://127.0.0.1:1")
+            .with_candidate_tokens(&["old", "new"])
+            .with_retry_policy(RetryPolicy::none())
+            .build()?;
+        assert_eq!(Some("old"), client.token());
+
+        let transport = Arc::new(
+            crate::fixtures::StubTransport::new()
+                .with_response("GET", "/v1/acl/token/self", 403, "Permission denied")
+                .with_response("GET", "/v1/acl/token/self", 500, "No cluster leader"),
+        );
+        let client = Client::builder(NOMAD_ADDRESS)
+            .with_retry_policy(RetryPolicy::none())
+            .build()?
+            .with_transport(transport.clone())
+            .select_token(vec![
+                crate::Secret("old".to_string()),
+                crate::Secret("new".to_string()),
+            ])?;
+        assert_eq!(Some("new"), client.token());
+        assert_eq!(2, transport.requests().len());
+        Ok(())
+    }
+
+    #[test]
+    fn clients_without_acl_drop_their_token() -> Result<(), crate::Error> {
+        let client = nomad_client().without_acl();
+        assert!(client.acl_disabled());
+
+        let request = client.build_agent_self_request()?;
+        assert!(!request.headers().contains_key(NOMAD_AUTH_HEADER));
+        Ok(())
+    }
+
+    #[test]
+    fn drain_api_is_selected_for_each_supported_version() {
+        for (fixture, version, api) in &[
+            (
+                include_str!("../fixtures/agent_self.json"),
+                NomadVersion::new(0, 8, 6),
+                DrainApi::V0_8,
+            ),
+            (
+                include_str!("../fixtures/agent_self_1.0.json"),
+                NomadVersion::new(1, 0, 18),
+                DrainApi::V0_8,
+            ),
+            (
+                include_str!("../fixtures/agent_self_1.6.json"),
+                NomadVersion::new(1, 6, 3),
+                DrainApi::V1_1,
+            ),
+        ] {
+            let agent: AgentSelf = serde_json::from_str(fixture).unwrap();
+            assert_eq!(Some(*version), agent.version());
+            assert_eq!(*api, DrainApi::for_version(version).unwrap());
+        }
+
+        match DrainApi::for_version(&NomadVersion::new(0, 7, 1)) {
+            Err(crate::Error::UnsupportedNomadVersion { .. }) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deadlines_are_serialized_as_the_api_expects() {
+        for (deadline, nanoseconds) in &[
+            (Deadline::Default, 3_600_000_000_000i64),
+            (
+                Deadline::Duration(Duration::from_millis(1500)),
+                1_500_000_000,
+            ),
+            (Deadline::Force, -1),
+            (Deadline::NoDeadline, 0),
+        ] {
+            assert_eq!(
+                serde_json::json!(nanoseconds),
+                serde_json::to_value(deadline).unwrap()
+            );
+        }
+
+        let deadline: Deadline = serde_json::from_str("-1000000000").unwrap();
+        assert_eq!(Deadline::Force, deadline);
+        let deadline: Deadline = serde_json::from_str("600000000000").unwrap();
+        assert_eq!(Deadline::Duration(Duration::from_secs(600)), deadline);
+    }
+
+    #[test]
+    fn drain_strategy_of_draining_node_is_deserialized_properly() {
+        let node: Node =
+            serde_json::from_str(include_str!("../fixtures/nomad_node_draining.json")).unwrap();
+        let drain_spec = node
+            .drain_strategy
+            .and_then(|strategy| strategy.drain_spec)
+            .expect("Drain spec to be present");
+
+        assert!(node.drain);
+        assert_eq!(NodeEligibility::Ineligible, node.scheduling_eligibility);
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            drain_spec.deadline.duration()
+        );
+        assert!(drain_spec.ignore_system_jobs);
+        assert_eq!(
+            serde_json::json!({ "Deadline": 600_000_000_000u64, "IgnoreSystemJobs": true }),
+            serde_json::to_value(&drain_spec).unwrap()
+        );
+    }
+
+    #[test]
+    fn force_deadlines_of_drain_strategies_are_counted_down() {
+        let strategy: DrainStrategy = serde_json::from_value(serde_json::json!({
+            "Deadline": 600_000_000_000u64,
+            "IgnoreSystemJobs": false,
+            "ForceDeadline": "2019-01-16T10:23:42Z",
+        }))
+        .unwrap();
+        let now = "2019-01-16T10:13:42Z".parse().unwrap();
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            strategy.time_until_deadline(now)
+        );
+        let later = "2019-01-16T10:33:42Z".parse().unwrap();
+        assert_eq!(
+            Some(Duration::from_secs(0)),
+            strategy.time_until_deadline(later)
+        );
+
+        let strategy: DrainStrategy = serde_json::from_value(serde_json::json!({
+            "Deadline": 0,
+            "IgnoreSystemJobs": false,
+            "ForceDeadline": "0001-01-01T00:00:00Z",
+        }))
+        .unwrap();
+        assert_eq!(None, strategy.deadline());
+        assert_eq!(None, strategy.time_until_deadline(now));
+    }
+
+    #[test]
+    fn nomad_versions_are_parsed() {
+        assert_eq!(
+            Some(NomadVersion::new(0, 8, 6)),
+            NomadVersion::parse("v0.8.6")
+        );
+        assert_eq!(
+            Some(NomadVersion::new(1, 6, 0)),
+            NomadVersion::parse("1.6.0-beta.1")
+        );
+        assert_eq!(
+            Some(NomadVersion::new(1, 5, 3)),
+            NomadVersion::parse("1.5.3+ent")
+        );
+        assert_eq!(None, NomadVersion::parse("unknown"));
+    }
+
+    #[test]
+    fn drain_payload_follows_the_drain_api() {
+        let drain_spec = DrainSpec {
+            deadline: Duration::from_secs(600).into(),
+            ignore_system_jobs: true,
+        };
+        let meta: HashMap<String, String> = vec![("source".to_string(), "nomad-drain".to_string())]
             .into_iter()
-            .filter(|node| node.status == NodeStatus::Ready)
-            .map(|node| self.node_details(&node.id, None, None))
-            .find(|details| match details {
-                Ok(details) => match details
-                    .data
-                    .attributes
-                    .get("unique.platform.aws.instance-id")
-                {
-                    Some(id) => id == instance_id,
-                    None => false,
+            .collect();
+
+        let payload = NodeDrainRequest::new(DrainApi::V0_8, "id", &drain_spec, Some(&meta));
+        assert_eq!(
+            serde_json::json!({
+                "NodeID": "id",
+                "DrainSpec": {
+                    "Deadline": 600_000_000_000u64,
+                    "IgnoreSystemJobs": true
                 },
-                Err(_) => false,
-            });
+                "MarkEligible": false
+            }),
+            serde_json::to_value(&payload).unwrap()
+        );
+
+        let payload = NodeDrainRequest::new(DrainApi::V1_1, "id", &drain_spec, Some(&meta));
+        assert_eq!(
+            serde_json::json!({ "source": "nomad-drain" }),
+            serde_json::to_value(&payload).unwrap()["Meta"]
+        );
+
+        assert_eq!(
+            serde_json::json!({
+                "NodeID": "id",
+                "DrainSpec": null,
+                "MarkEligible": true
+            }),
+            serde_json::to_value(NodeDrainRequest::cancel("id")).unwrap()
+        );
+    }
+
+    #[test]
+    fn cluster_identity_is_filled_from_agent() {
+        let agent: AgentSelf =
+            serde_json::from_str(include_str!("../fixtures/agent_self.json")).unwrap();
+        let identity = ClusterIdentity {
+            name: Some("production".to_string()),
+            ..Default::default()
+        };
+        assert!(identity.is_incomplete());
+
+        let identity = identity.with_agent(&agent);
+        assert!(!identity.is_incomplete());
+        assert_eq!(
+            "production/ap-southeast-1/ap-southeast-1a",
+            identity.to_string()
+        );
+
+        let identity = ClusterIdentity::default().with_agent(&agent);
+        assert!(!identity.is_incomplete());
+        assert_eq!(None, identity.name);
+        assert_eq!(
+            "unknown/ap-southeast-1/ap-southeast-1a",
+            identity.to_string()
+        );
+    }
+
+    #[test]
+    fn agent_self_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = nomad_client();
+        let request = client.build_agent_self_request()?;
+
+        assert_eq!(
+            format!("{}/v1/agent/self", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+
+        Ok(())
+    }
+
+    #[test]
+    fn async_node_details_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = AsyncClient::new(NOMAD_ADDRESS, Some("token"), None)?;
+        let request =
+            client.build_node_details_request("id", Some(1), Some(Duration::from_secs(10)))?;
+
+        assert_eq!(
+            format!("{}/v1/node/id?index=1&wait=10s", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn async_drain_api_is_probed_unless_provided() -> Result<(), crate::Error> {
+        let client = AsyncClient::new(NOMAD_ADDRESS, Some("token"), None)?;
+        let request = client.build_agent_self_request()?;
+        assert_eq!(
+            format!("{}/v1/agent/self", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
+
+        // A provided API is used without contacting the server
+        let client = client.with_drain_api(DrainApi::V1_1);
+        assert_eq!(DrainApi::V1_1, client.drain_api().wait()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn async_requests_are_pinned_to_region() -> Result<(), crate::Error> {
+        let client = AsyncClient::new(NOMAD_ADDRESS, Some("token"), None)?.with_region("global");
+        let request = client.build_node_details_request("id", Some(1), None)?;
+
+        assert_eq!(
+            format!("{}/v1/node/id?region=global&index=1", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn async_drain_monitoring_gives_up_after_max_duration() -> Result<(), crate::Error> {
+        let client = AsyncClient::new(NOMAD_ADDRESS, Some("token"), None)?;
+        match client
+            .monitor_node_drain("id", None, Some(Duration::from_secs(0)))
+            .wait()
+        {
+            Err(crate::Error::DrainTimeout { node_id, .. }) => assert_eq!("id", node_id),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn drain_progress_completes_when_drain_strategy_is_cleared() {
+        let mut progress = DrainProgress::default();
+        let now = chrono::Utc::now();
+
+        let mut node = blocking_node("a", NodeStatus::Ready, 1);
+        node.index = 42;
+        node.data.drain_strategy = Some(DrainStrategy {
+            drain_spec: None,
+            force_deadline: now + chrono::Duration::seconds(600),
+        });
+        let event = progress.observe("a", node, now);
+        assert!(!event.completed);
+        assert!(event.strategy_changed);
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            event.time_until_force_deadline
+        );
+        assert_eq!(Some(42), progress.wait_index);
+        assert_eq!(
+            MonitorCursor {
+                phase: MonitorPhase::Node,
+                wait_index: Some(42),
+            },
+            event.cursor
+        );
+
+        let mut node = blocking_node("a", NodeStatus::Down, 2);
+        node.data.drain_strategy = progress.strategy.clone();
+        let event = progress.observe("a", node, now + chrono::Duration::seconds(60));
+        assert!(!event.strategy_changed);
+        assert!(event.node_down);
+        assert_eq!(
+            Some(Duration::from_secs(540)),
+            event.time_until_force_deadline
+        );
+
+        let node = blocking_node("a", NodeStatus::Ready, 3);
+        let event = progress.observe("a", node, now);
+        assert!(event.completed);
+        assert!(event.strategy_changed);
+        assert_eq!(MonitorPhase::Allocations, event.cursor.phase);
+    }
+
+    #[test]
+    fn monitor_cursors_are_persisted_as_json() -> Result<(), crate::Error> {
+        let cursor: MonitorCursor = serde_json::from_str(r#"{"phase":"node","wait_index":42}"#)?;
+        assert_eq!(
+            MonitorCursor {
+                phase: MonitorPhase::Node,
+                wait_index: Some(42),
+            },
+            cursor
+        );
+        assert_eq!(
+            r#"{"phase":"allocations","wait_index":null}"#,
+            serde_json::to_string(&MonitorCursor {
+                phase: MonitorPhase::Allocations,
+                wait_index: None,
+            })?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn remaining_allocations_exclude_terminal_and_system_allocations() {
+        let allocations: Vec<Allocation> = serde_json::from_str(allocations_fixture()).unwrap();
+        let remaining: Vec<_> = remaining_allocations(&allocations)
+            .map(|allocation| allocation.name.as_str())
+            .collect();
+
+        assert_eq!(vec!["example.cache[0]"], remaining);
+    }
+
+    #[test]
+    fn allocation_summaries_are_built_from_allocations() {
+        let allocations: Vec<Allocation> = serde_json::from_str(allocations_fixture()).unwrap();
+        let summary = AllocationSummary::from(&allocations[1]);
+
+        assert_eq!("1b4a5a4b-0a3c-4b8e-8f1d-6f4b7f5d9d0e", summary.id);
+        assert_eq!("example", summary.job_id);
+        assert_eq!("cache", summary.task_group);
+        assert_eq!(AllocationClientStatus::Complete, summary.client_status);
+        assert_eq!("stop", summary.desired_status);
+    }
+
+    #[test]
+    fn allocations_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = nomad_client();
+        let request =
+            client.build_allocations_request("id", Some(1234), Some(Duration::from_secs(300)))?;
+
+        assert_eq!(
+            format!(
+                "{}/v1/node/id/allocations?index=1234&wait=300s",
+                NOMAD_ADDRESS
+            ),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+
+        Ok(())
+    }
+
+    #[test]
+    fn blocking_queries_are_shortened_before_deadline() -> Result<(), crate::Error> {
+        let clock = Arc::new(crate::clock::SimulatedClock::new());
+        let client = nomad_client()
+            .with_clock(clock.clone())
+            .with_deadline(clock.now() + Duration::from_secs(160));
+
+        let request =
+            client.build_node_details_request("id", Some(1234), Some(Duration::from_secs(300)))?;
+        assert_eq!(
+            format!("{}/v1/node/id?index=1234&wait=150s", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+
+        let request = client.build_node_details_request("id", Some(1234), None)?;
+        assert_eq!(
+            format!("{}/v1/node/id?index=1234&wait=150s", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+
+        clock.advance(Duration::from_secs(160));
+        match client.monitor_node_drain("id", None, None) {
+            Err(crate::Error::DeadlineExceeded) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
 
-        let result = result.ok_or_else(|| crate::Error::NomadNodeNotFound {
-            instance_id: instance_id.to_string(),
-        })??;
-        info!(
-            "AWS Instance ID {} is Nomad Node ID {}",
-            instance_id, result.data.id
+        Ok(())
+    }
+
+    #[test]
+    fn drain_monitoring_gives_up_after_max_duration() {
+        match nomad_client().monitor_node_drain("id", None, Some(Duration::from_secs(0))) {
+            Err(crate::Error::DrainTimeout { node_id, .. }) => assert_eq!("id", node_id),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nodes_query_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = nomad_client();
+        let query = NodesQuery {
+            prefix: Some("0280".to_string()),
+            per_page: Some(50),
+            next_token: Some("token".to_string()),
+            ..NodesQuery::instance_id("i-1234567890abcdef0")
+        };
+        let request = client.build_nodes_query_request(&query)?;
+
+        assert_eq!(
+            format!(
+                "{}/v1/nodes?prefix=0280&filter=Attributes%5B%22unique.platform.aws.instance-id%22%5D+%3D%3D+%22i-1234567890abcdef0%22&per_page=50&next_token=token",
+                NOMAD_ADDRESS
+            ),
+            request.url().to_string()
         );
-        Ok(result)
+
+        Ok(())
     }
 
-    /// Set a node eligibility for receiving new allocations
-    ///
-    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
-    /// CA certificate or require client authentication
-    pub fn set_node_eligibility(
-        &self,
-        node_id: &str,
-        eligibility: NodeEligibility,
-    ) -> Result<(), crate::Error> {
-        info!(
-            "Setting Nomad Node ID {} eligibility to {}",
-            node_id, eligibility
+    #[test]
+    fn requests_are_pinned_to_region() -> Result<(), crate::Error> {
+        let client = nomad_client().with_region("global");
+        let request =
+            client.build_node_details_request("id", Some(1234), Some(Duration::from_secs(300)))?;
+
+        assert_eq!(
+            format!(
+                "{}/v1/node/id?region=global&index=1234&wait=300s",
+                NOMAD_ADDRESS
+            ),
+            request.url().to_string()
         );
-        let request = NodeEligibilityRequest {
-            node_id,
-            eligibility,
-        };
 
-        let request = self.build_node_eligibility_request(node_id, &request)?;
-        // Request is successful if the response can be deserialized
-        let _: NodeEligibilityResponse = self.execute_request(request)?;
         Ok(())
     }
 
-    fn build_node_eligibility_request(
-        &self,
-        node_id: &str,
-        payload: &NodeEligibilityRequest,
-    ) -> Result<reqwest::Request, crate::Error> {
-        let address = format!("{}/v1/node/{}/eligibility", self.address, node_id);
-        let request = self.client.post(&address).json(payload);
-        let request = self.add_nomad_token_header(request);
-        Ok(request.build()?)
+    #[test]
+    fn leader_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = nomad_client().with_region("global");
+        let request = client.build_leader_request()?;
+
+        assert_eq!(
+            format!("{}/v1/status/leader?region=global", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+
+        Ok(())
     }
 
-    /// Mark the node for draining
-    ///
-    /// You can optionally specify a `DrainSpec`. If you don't provide one, we will use the default.
-    ///
-    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
-    /// CA certificate or require client authentication
-    pub fn set_node_drain(
-        &self,
-        node_id: &str,
-        monitor: bool,
-        drain_spec: Option<DrainSpec>,
-    ) -> Result<(), crate::Error> {
-        let drain_spec = drain_spec.unwrap_or_default();
-        info!("Draining Node ID {} with {:#?}", node_id, drain_spec);
-        let payload = NodeDrainRequest {
-            node_id,
-            drain_spec: &drain_spec,
-        };
-        let request = self.build_drain_request(node_id, &payload)?;
-        // Request is successful if the response can be deserialized
-        let _: NodeDrainResponse = self.execute_request(request)?;
+    #[test]
+    fn token_self_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = nomad_client();
+        let request = client.build_token_self_request()?;
 
-        if monitor {
-            self.monitor_node_drain(node_id, None)
-        } else {
-            Ok(())
-        }
+        assert_eq!(
+            format!("{}/v1/acl/token/self", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
+
+        Ok(())
     }
 
-    fn build_drain_request(
-        &self,
-        node_id: &str,
-        payload: &NodeDrainRequest,
-    ) -> Result<reqwest::Request, crate::Error> {
-        let address = format!("{}/v1/node/{}/drain", &self.address, node_id);
-        let request = self.client.post(&address).json(payload);
-        let request = self.add_nomad_token_header(request);
-        Ok(request.build()?)
+    #[test]
+    fn requests_are_signed_alongside_the_token() -> Result<(), crate::Error> {
+        let client =
+            nomad_client().with_signer(Arc::new(BearerToken(crate::Secret("oidc".to_string()))));
+        let request = client.sign(client.build_token_self_request()?)?;
+
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
+        assert_eq!(
+            "Bearer oidc",
+            request.headers()[reqwest::header::AUTHORIZATION]
+        );
+
+        Ok(())
     }
 
-    /// Monitor Node Drain
-    ///
-    /// This function will block until the drain is complete, or an error occurs
-    pub fn monitor_node_drain(
-        &self,
-        node_id: &str,
-        wait_timeout: Option<Duration>,
-    ) -> Result<(), crate::Error> {
-        // The procedure is based on https://github.com/hashicorp/nomad/blob/master/api/nodes.go
-        // TODOs:
-        // - Monitor that no allocations are running
-        // - Async everything!
+    #[test]
+    fn purge_node_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = nomad_client();
+        let request = client.build_purge_node_request("id")?;
 
-        let wait_timeout = match wait_timeout {
-            Some(duration) => duration,
-            None => Duration::from_secs(300),
-        };
-        let mut wait_index = None;
-        let mut node;
-        let mut strategy = None;
-        let mut strategy_changed = false;
+        assert_eq!(
+            format!("{}/v1/node/id/purge", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
 
-        info!("Monitoring drain for Node ID {}", node_id);
+        Ok(())
+    }
 
-        loop {
-            info!("Checking if Node ID {} drain is complete", node_id);
-            node = self.node_details(node_id, wait_index, Some(wait_timeout))?;
-            if node.data.drain_strategy.is_none() {
-                if strategy_changed {
-                    info!(
-                        "Node {} has has marked all allocations for migration",
-                        node_id
-                    );
-                } else {
-                    info!("No drain strategy set for node {}", node_id);
-                }
-                break;
-            }
+    #[test]
+    fn allocation_eviction_requests_are_built_properly() -> Result<(), crate::Error> {
+        let client = nomad_client();
+        let request = client.build_stop_allocation_request("alloc")?;
+        assert_eq!(
+            format!("{}/v1/allocation/alloc/stop", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
 
-            if node.data.status == NodeStatus::Down {
-                warn!("Node {} down", node_id);
-            }
+        let payload = AllocationSignalRequest {
+            signal: "SIGTERM",
+            task: None,
+        };
+        let request = client.build_signal_allocation_request("alloc", &payload)?;
+        assert_eq!(
+            format!("{}/v1/client/allocation/alloc/signal", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!(
+            serde_json::json!({ "Signal": "SIGTERM" }),
+            serde_json::to_value(&payload)?
+        );
 
-            if strategy != node.data.drain_strategy {
-                info!(
-                    "Node {} drain updated: {:#?}",
-                    node_id, node.data.drain_strategy
-                );
-            }
+        let response: AllocationStopResponse =
+            serde_json::from_str(r#"{"EvalID": "eval", "Index": 42}"#)?;
+        assert_eq!("eval", response.eval_id);
+        assert_eq!(42, response.index);
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn node_is_found_by_instance_id_with_mock_nomad() -> Result<(), crate::Error> {
+        let node: serde_json::Value = serde_json::from_str(node_fixture())?;
+        let nodes: Vec<serde_json::Value> = serde_json::from_str(nodes_fixture())?;
+        let client = mock_nomad_client(serde_json::json!({
+            "nodes": nodes,
+            "node_details": { node["ID"].as_str().unwrap(): [node] },
+        }))?;
+
+        let found = client.find_node_by_instance_id("i-instance-id", false)?;
+        assert_eq!(node["ID"], found.node.data.id.as_str());
 
-            strategy = node.data.drain_strategy;
-            strategy_changed = true;
-            wait_index = Some(node.index);
+        match client.find_node_by_instance_id("i-unknown", false) {
+            Err(crate::Error::NomadNodeNotFound { .. }) => {}
+            other => panic!("Unexpected result {:?}", other),
         }
-        info!("Done monitoring drain for Node ID {}", node_id);
         Ok(())
     }
 
-    fn add_nomad_token_header(&self, request_builder: RequestBuilder) -> RequestBuilder {
-        match &self.token {
-            Some(token) => request_builder.header(NOMAD_AUTH_HEADER, token.as_str()),
-            None => request_builder,
+    #[test]
+    #[ignore]
+    fn node_drain_is_monitored_until_complete_with_mock_nomad() -> Result<(), crate::Error> {
+        let drained = NodeFixture::ready().value().clone();
+        let draining = NodeFixture::ready().draining().value().clone();
+        let node_id = drained["ID"].as_str().unwrap().to_string();
+        let client = mock_nomad_client(serde_json::json!({
+            "agent": serde_json::from_str::<serde_json::Value>(include_str!(
+                "../fixtures/agent_self.json"
+            ))?,
+            "node_details": { &node_id: [draining.clone(), draining, drained] },
+        }))?;
+
+        client.set_node_drain(&node_id, true, None)?;
+
+        let requests = mock_nomad_requests(&client)?;
+        let node_path = format!("/v1/node/{}", node_id);
+        let drains = requests
+            .iter()
+            .filter(|request| request["path"] == format!("{}/drain", node_path))
+            .count();
+        let checks = requests
+            .iter()
+            .filter(|request| request["path"] == node_path.as_str())
+            .count();
+        assert_eq!(1, drains);
+        assert_eq!(3, checks);
+        Ok(())
+    }
+
+    #[test]
+    fn forbidden_responses_are_permission_denied() {
+        let error = |status| {
+            Client::parse_indexed_response::<AgentSelf>(
+                &Default::default(),
+                "Permission denied",
+                ResponseDiagnostics::new(status, &Default::default()),
+            )
+            .unwrap_err()
+        };
+
+        assert!(Client::is_permission_denied(&error(
+            reqwest::StatusCode::FORBIDDEN
+        )));
+        assert!(!Client::is_permission_denied(&error(
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR
+        )));
+    }
+
+    #[test]
+    fn missing_nodes_are_gone() {
+        let error = Client::parse_indexed_response::<Node>(
+            &Default::default(),
+            "node not found\n",
+            ResponseDiagnostics::new(reqwest::StatusCode::NOT_FOUND, &Default::default()),
+        )
+        .unwrap_err();
+        match node_gone("id", error) {
+            crate::Error::NomadNodeGone { node_id } => assert_eq!("id", node_id),
+            other => panic!("Unexpected error {:?}", other),
         }
+
+        let error = crate::Error::DeadlineExceeded;
+        assert!(matches!(
+            node_gone("id", error),
+            crate::Error::DeadlineExceeded
+        ));
     }
 
-    fn add_blocking_requests(
-        request_builder: RequestBuilder,
-        wait_index: Option<u64>,
-        wait_timeout: Option<Duration>,
-    ) -> RequestBuilder {
-        match wait_index {
-            Some(index) => {
-                let request_builder = request_builder.query(&[("index", index.to_string())]);
-                match wait_timeout {
-                    None => request_builder,
-                    Some(timeout) => {
-                        request_builder.query(&[("wait", format!("{}s", timeout.as_secs()))])
-                    }
-                }
+    #[test]
+    fn error_responses_carry_their_status_and_message() {
+        let result = Client::parse_indexed_response::<Node>(
+            &Default::default(),
+            "node not found\n",
+            ResponseDiagnostics::new(reqwest::StatusCode::NOT_FOUND, &Default::default()),
+        );
+        match result {
+            Err(crate::Error::NomadApi {
+                status, message, ..
+            }) => {
+                assert_eq!(404, status);
+                assert_eq!("node not found", message);
             }
-            None => request_builder,
+            other => panic!("Unexpected result {:?}", other),
         }
     }
 
-    fn make_indexed_response<T>(
-        response: &reqwest::Response,
-        data: T,
-    ) -> Result<BlockingResponse<T>, crate::Error> {
-        let index = match response.headers().get(NOMAD_INDEX_HEADER) {
-            None => 0,
-            Some(index) => index.to_str()?.parse()?,
-        };
+    #[test]
+    fn response_diagnostics_keep_only_diagnostic_headers() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-nomad-index", "1234".parse().unwrap());
+        headers.insert("X-Amzn-Trace-Id", "Root=1-abc".parse().unwrap());
+        headers.insert("Content-Type", "application/json".parse().unwrap());
 
-        Ok(BlockingResponse { data, index })
-    }
-}
+        let diagnostics = ResponseDiagnostics::new(reqwest::StatusCode::BAD_GATEWAY, &headers);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert_eq!(502, diagnostics.status);
+        assert_eq!(2, diagnostics.headers.len());
+        assert_eq!("1234", diagnostics.headers["x-nomad-index"]);
+        assert_eq!("Root=1-abc", diagnostics.headers["x-amzn-trace-id"]);
+        assert_eq!(
+            "HTTP 502, x-amzn-trace-id: Root=1-abc, x-nomad-index: 1234",
+            diagnostics.to_string()
+        );
+    }
 
-    const NOMAD_ADDRESS: &str = "http://127.0.0.1:4646";
+    #[test]
+    fn dry_run_clients_refuse_mutating_calls() {
+        let client = nomad_client().with_context(
+            RequestContext::new()
+                .with_dry_run(true)
+                .with_trace_id("trace"),
+        );
+        assert_eq!(Some("trace"), client.context().trace_id.as_deref());
 
-    fn node_fixture() -> &'static str {
-        include_str!("../fixtures/nomad_node.json")
+        match client.set_node_eligibility("id", NodeEligibility::Ineligible) {
+            Err(crate::Error::ReadOnly { operation }) => {
+                assert_eq!("set Node ID id eligibility to Ineligible", operation)
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match client.set_node_drain("id", true, None) {
+            Err(crate::Error::ReadOnly { operation }) => assert_eq!("drain Node ID id", operation),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match client.purge_node("id") {
+            Err(crate::Error::ReadOnly { operation }) => assert_eq!("purge Node ID id", operation),
+            other => panic!("Unexpected result {:?}", other),
+        }
     }
 
-    fn nodes_fixture() -> &'static str {
-        include_str!("../fixtures/nomad_nodes.json")
-    }
+    #[test]
+    fn read_only_guard_refuses_mutating_calls() {
+        let guard = ReadOnlyGuard::new(nomad_client());
 
-    fn nomad_client() -> Client {
-        Client::new(NOMAD_ADDRESS, Some("token"), None).expect("Not to fail")
+        match guard.set_node_eligibility("id", NodeEligibility::Ineligible) {
+            Err(crate::Error::ReadOnly { .. }) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match guard.set_node_drain("id", true, None) {
+            Err(crate::Error::ReadOnly { operation }) => assert_eq!("drain Node ID id", operation),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match guard.set_node_drain_ineligible("id", None) {
+            Err(crate::Error::ReadOnly { operation }) => assert_eq!("drain Node ID id", operation),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match guard.purge_node("id") {
+            Err(crate::Error::ReadOnly { operation }) => assert_eq!("purge Node ID id", operation),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match guard.client().set_node_drain("id", true, None) {
+            Err(crate::Error::ReadOnly { operation }) => assert_eq!("drain Node ID id", operation),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert!(!guard.into_inner().context().dry_run);
     }
 
     #[test]
-    fn node_is_deserialized_properly() {
-        let node: Node = serde_json::from_str(node_fixture()).unwrap();
+    fn node_lists_are_sharded_by_node_id() {
+        let query = NodesQuery {
+            prefix: Some("02".to_string()),
+            per_page: Some(50),
+            ..Default::default()
+        };
+        let shards = query.shards();
 
-        assert_eq!("02802087-8786-fdf6-4497-98445c891fb7", node.id);
+        assert_eq!(16, shards.len());
+        assert_eq!(Some("020"), shards[0].prefix.as_deref());
+        assert_eq!(Some("02f"), shards[15].prefix.as_deref());
+        assert!(shards.iter().all(|shard| shard.per_page == Some(50)));
     }
 
     #[test]
-    fn nodes_list_is_deserialized_properly() {
-        let _: Vec<NodesInList> = serde_json::from_str(nodes_fixture()).unwrap();
+    fn node_lists_are_capped() {
+        let client = nomad_client().with_max_nodes(2);
+        assert!(client.check_node_count(2).is_ok());
+        match client.check_node_count(3) {
+            Err(crate::Error::TooManyNodes { limit: 2 }) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nodes_list_can_be_filtered() {
+        let nodes: Vec<NodesInList> = serde_json::from_str(nodes_fixture()).unwrap();
+
+        assert!(nodes.iter().all(NodesInList::is_ready));
+        assert_eq!(
+            3,
+            nodes
+                .iter()
+                .filter(|node| node.in_datacenter("ap-southeast-1a"))
+                .count()
+        );
     }
 
     #[test]
@@ -674,6 +4417,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn requests_to_unmodeled_endpoints_are_built_properly() -> Result<(), crate::Error> {
+        let client = nomad_client();
+        let request = client.build_get_request("/jobs", &[("prefix", "web")])?;
+        assert_eq!(
+            format!("{}/v1/jobs?prefix=web", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
+
+        let request = client.build_post_request("job/web/periodic/force", &())?;
+        assert_eq!(
+            format!("{}/v1/job/web/periodic/force", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!("token", request.headers()[NOMAD_AUTH_HEADER]);
+
+        let client = client.with_context(RequestContext::new().with_dry_run(true));
+        match client.post::<serde_json::Value, _>("job/web/periodic/force", &()) {
+            Err(crate::Error::ReadOnly { operation }) => {
+                assert_eq!("post to job/web/periodic/force", operation)
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        Ok(())
+    }
+
     #[test]
     fn node_eligibility_response_is_deserialized_properly() {
         let _: NodeEligibilityResponse =
@@ -682,7 +4454,54 @@ mod tests {
 
     #[test]
     fn node_drain_response_is_deserialized_properly() {
-        let _: NodeDrainResponse =
+        let response: NodeDrainResponse =
             serde_json::from_str(include_str!("../fixtures/node_drain.json")).unwrap();
+        assert_eq!(493383, response.eval_create_index);
+        assert_eq!(5, response.eval_ids().len());
+        assert_eq!(None, response.last_index);
+    }
+
+    #[test]
+    fn evaluation_is_deserialized_properly() {
+        let evaluation: Evaluation =
+            serde_json::from_str(include_str!("../fixtures/evaluation.json")).unwrap();
+
+        assert_eq!("node-drain", evaluation.triggered_by);
+        assert!(evaluation.is_terminal());
+        assert_eq!(vec!["cache"], evaluation.failed_task_groups());
+    }
+
+    #[test]
+    fn evaluation_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = nomad_client();
+        let request = client.build_evaluation_request("id")?;
+
+        assert_eq!(
+            format!("{}/v1/evaluation/id", NOMAD_ADDRESS),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+
+        Ok(())
+    }
+
+    #[test]
+    fn node_drain_response_accepts_string_indices() {
+        let response: NodeDrainResponse = serde_json::from_str(
+            r#"{
+                "EvalCreateIndex": "493383",
+                "EvalIDs": null,
+                "Index": 493382,
+                "NodeModifyIndex": "0",
+                "LastIndex": "493384"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(493383, response.eval_create_index);
+        assert_eq!(493382, response.index);
+        assert_eq!(0, response.node_modify_index);
+        assert_eq!(Some(493384), response.last_index);
+        assert!(response.eval_ids().is_empty());
     }
 }
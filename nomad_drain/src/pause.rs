@@ -0,0 +1,203 @@
+//! Pausing drains during incident response
+//!
+//! While a switch is paused, no new drains are submitted. Drains already in progress carry on and
+//! are still monitored. Operators flip the switch to stop the world and flip it back to resume.
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use log::debug;
+use rusoto_core::credential::AwsCredentials;
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::Region;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::aws::into_reqwest_request;
+
+/// Name of the partition key of the DynamoDB table holding pause flags
+const DYNAMODB_KEY_ATTRIBUTE: &str = "id";
+
+/// Name of the boolean attribute holding the flag in DynamoDB items
+const DYNAMODB_PAUSED_ATTRIBUTE: &str = "paused";
+
+/// A switch to pause drains with
+pub trait PauseSwitch: Debug + Send + Sync {
+    /// Whether drains are paused
+    fn is_paused(&self) -> Result<bool, crate::Error>;
+}
+
+/// Drains are paused while the file exists
+///
+/// Handy to pause a bulk drain from another terminal.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PauseFile(pub PathBuf);
+
+impl PauseSwitch for PauseFile {
+    fn is_paused(&self) -> Result<bool, crate::Error> {
+        Ok(self.0.exists())
+    }
+}
+
+/// Drains are paused while a flag is set in a DynamoDB table
+///
+/// The table has a string partition key named `id`. The flag is the boolean `paused` attribute of
+/// the item with the key, and drains are not paused when the item does not exist.
+///
+/// Credentials are retrieved for every request, so that long running drains outlive temporary
+/// credentials.
+#[derive(Clone, Debug)]
+pub struct DynamoDbPauseFlag {
+    pub region: Region,
+    pub table: String,
+    pub key: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct GetItemResponse {
+    #[serde(default)]
+    item: Option<serde_json::Value>,
+}
+
+impl DynamoDbPauseFlag {
+    /// Use the flag of the item with `key` in `table`
+    ///
+    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
+    /// CA certificate.
+    pub fn new(region: Region, table: &str, key: &str, client: Option<reqwest::Client>) -> Self {
+        Self {
+            region,
+            table: table.to_string(),
+            key: key.to_string(),
+            client: client.unwrap_or_else(reqwest::Client::new),
+        }
+    }
+
+    /// Pause or resume drains
+    pub fn set_paused(&self, paused: bool) -> Result<(), crate::Error> {
+        let payload = json!({
+            "TableName": self.table,
+            "Item": {
+                DYNAMODB_KEY_ATTRIBUTE: { "S": self.key },
+                DYNAMODB_PAUSED_ATTRIBUTE: { "BOOL": paused },
+                "updated_at": { "S": chrono::Utc::now().to_rfc3339() },
+            },
+        });
+        self.execute("PutItem", &payload)?;
+        Ok(())
+    }
+
+    fn execute<T>(&self, action: &str, payload: &T) -> Result<String, crate::Error>
+    where
+        T: Serialize,
+    {
+        let credentials = crate::aws::credentials()?;
+        let request =
+            build_dynamodb_request(&self.client, &credentials, &self.region, action, payload)?;
+        debug!("Executing request: {:#?}", request);
+        let mut response = self.client.execute(request)?;
+        debug!("Response received: {:#?}", response);
+        let body = response.text()?;
+        if !response.status().is_success() {
+            Err(crate::Error::DynamoDbRequestFailed {
+                target: action.to_string(),
+                status: response.status().as_u16(),
+                body: body.clone(),
+            })?;
+        }
+        Ok(body)
+    }
+}
+
+impl PauseSwitch for DynamoDbPauseFlag {
+    fn is_paused(&self) -> Result<bool, crate::Error> {
+        let payload = json!({
+            "TableName": self.table,
+            "Key": { DYNAMODB_KEY_ATTRIBUTE: { "S": self.key } },
+            "ConsistentRead": true,
+        });
+        let body = self.execute("GetItem", &payload)?;
+        Ok(is_paused_item(&serde_json::from_str(&body)?))
+    }
+}
+
+fn is_paused_item(response: &GetItemResponse) -> bool {
+    response
+        .item
+        .as_ref()
+        .and_then(|item| item[DYNAMODB_PAUSED_ATTRIBUTE]["BOOL"].as_bool())
+        .unwrap_or(false)
+}
+
+fn build_dynamodb_request<T>(
+    client: &reqwest::Client,
+    credentials: &AwsCredentials,
+    region: &Region,
+    action: &str,
+    payload: &T,
+) -> Result<reqwest::Request, crate::Error>
+where
+    T: Serialize,
+{
+    let body = serde_json::to_vec(payload)?;
+    let mut request = SignedRequest::new("POST", "dynamodb", region, "/");
+    request.set_content_type("application/x-amz-json-1.0".to_string());
+    request.add_header("x-amz-target", &format!("DynamoDB_20120810.{}", action));
+    request.set_payload(Some(body.clone()));
+    request.sign_with_plus(credentials, true);
+    into_reqwest_request(client, reqwest::Method::POST, &request, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::aws::tests::credentials;
+
+    #[test]
+    fn dynamodb_request_is_signed() -> Result<(), crate::Error> {
+        let request = build_dynamodb_request(
+            &reqwest::Client::new(),
+            &credentials()?,
+            &Region::ApSoutheast1,
+            "GetItem",
+            &json!({ "TableName": "nomad-drain" }),
+        )?;
+
+        assert_eq!(
+            "https://dynamodb.ap-southeast-1.amazonaws.com/",
+            request.url().to_string()
+        );
+        assert_eq!(
+            "DynamoDB_20120810.GetItem",
+            request.headers()["x-amz-target"]
+        );
+        assert!(request.headers().contains_key("authorization"));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_items_are_not_paused() -> Result<(), crate::Error> {
+        let response: GetItemResponse = serde_json::from_str("{}")?;
+        assert!(!is_paused_item(&response));
+
+        let response: GetItemResponse = serde_json::from_str(
+            r#"{"Item": {"id": {"S": "nomad-drain"}, "paused": {"BOOL": true}}}"#,
+        )?;
+        assert!(is_paused_item(&response));
+        Ok(())
+    }
+
+    #[test]
+    fn pause_files_pause_while_they_exist() -> Result<(), crate::Error> {
+        let path = std::env::temp_dir().join(format!("nomad-drain-pause-{}", std::process::id()));
+        let switch = PauseFile(path.clone());
+        assert!(!switch.is_paused()?);
+
+        std::fs::write(&path, "")?;
+        assert!(switch.is_paused()?);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,629 @@
+//! Drain plans for bulk maintenance operations
+//!
+//! A plan is built ahead of time, serialized for review, and then applied.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use log::{info, warn};
+use rusoto_autoscaling::{AutoScalingGroupNamesType, Autoscaling};
+use serde::{Deserialize, Serialize};
+
+use crate::nomad::{self, DrainSpec, NodeEligibility};
+use crate::pause::PauseSwitch;
+
+/// Interval to check whether drains are still paused at, when no drain is in progress
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A reviewable plan to drain the Nomad nodes backing an AWS Auto Scaling Group
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct DrainPlan {
+    /// Name of the Auto Scaling Group
+    pub auto_scaling_group: String,
+    /// Time the plan was created
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Drain specification applied to every node
+    pub drain_spec: DrainSpec,
+    /// Nodes to drain, in order
+    pub drains: Vec<PlannedDrain>,
+    /// Maximum number of nodes draining at the same time in each availability zone
+    ///
+    /// When the limit is reached, applying the plan waits for the oldest drain in the zone to
+    /// complete before starting the next one, preserving the redundancy of services across zones.
+    #[serde(default)]
+    pub max_drains_per_zone: Option<usize>,
+    /// Maximum number of nodes draining at the same time in the cluster
+    ///
+    /// Drains already in progress when the plan is applied count towards the limit. Applying the
+    /// plan is refused if the limit is already reached.
+    #[serde(default)]
+    pub max_concurrent_drains: Option<usize>,
+    /// Minimum number of ready and eligible nodes that have to remain once the plan is applied
+    ///
+    /// Applying the plan is refused if it would leave fewer nodes to schedule allocations on.
+    #[serde(default)]
+    pub min_eligible_nodes: Option<usize>,
+    /// Instances in the group without a matching ready Nomad node
+    #[serde(default)]
+    pub unmatched_instances: Vec<String>,
+}
+
+/// A single node drain in a `DrainPlan`
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct PlannedDrain {
+    /// AWS Instance ID
+    pub instance_id: String,
+    /// AWS Availability Zone of the node, falling back to that of the instance
+    pub availability_zone: String,
+    /// Nomad Node ID
+    pub node_id: String,
+    /// Nomad Node name
+    pub node_name: String,
+    /// Nomad datacenter of the node
+    pub datacenter: String,
+}
+
+/// Outcome of applying a `DrainPlan`, for reporting on the maintenance performed
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct ApplyReport {
+    /// Name of the Auto Scaling Group
+    pub auto_scaling_group: String,
+    /// Time applying the plan started
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Time applying the plan stopped
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    /// Drains started, in order
+    pub drains: Vec<DrainOutcome>,
+    /// Drains not started because an earlier drain failed
+    pub skipped: Vec<PlannedDrain>,
+}
+
+impl ApplyReport {
+    /// The drain that failed, if any
+    pub fn failure(&self) -> Option<&DrainOutcome> {
+        self.drains.iter().find(|outcome| outcome.error.is_some())
+    }
+
+    /// Drains that were started without failing
+    pub fn drained(&self) -> impl Iterator<Item = &DrainOutcome> {
+        self.drains.iter().filter(|outcome| outcome.error.is_none())
+    }
+}
+
+/// Outcome of a single drain in an `ApplyReport`
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+pub struct DrainOutcome {
+    /// The planned drain
+    pub drain: PlannedDrain,
+    /// Time the drain started
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Time the drain was seen to complete. Drains that are not monitored have no completion time.
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Number of allocations running on the node when the drain started, which are migrated
+    pub allocations: usize,
+    /// Error the drain failed with
+    pub error: Option<String>,
+}
+
+impl DrainOutcome {
+    fn new(drain: PlannedDrain) -> Self {
+        Self {
+            drain,
+            started_at: chrono::Utc::now(),
+            completed_at: None,
+            allocations: 0,
+            error: None,
+        }
+    }
+
+    /// Time taken for the drain to complete, if it was seen to complete
+    pub fn duration(&self) -> Option<std::time::Duration> {
+        self.completed_at
+            .and_then(|completed_at| (completed_at - self.started_at).to_std().ok())
+    }
+
+    /// Record the error of a failed step of the drain
+    fn record_error<T>(&mut self, result: Result<T, crate::Error>) -> Result<T, crate::Error> {
+        if let Err(ref e) = result {
+            self.error = Some(crate::display_chain(e));
+        }
+        result
+    }
+}
+
+impl DrainPlan {
+    /// Build a plan to drain every instance in an Auto Scaling Group
+    ///
+    /// Instances are matched to ready Nomad nodes by their AWS instance ID attribute.
+    pub fn for_auto_scaling_group<A>(
+        nomad_client: &nomad::Client,
+        autoscaling_client: &A,
+        auto_scaling_group: &str,
+        drain_spec: DrainSpec,
+    ) -> Result<Self, crate::Error>
+    where
+        A: Autoscaling,
+    {
+        info!(
+            "Building drain plan for Auto Scaling Group {}",
+            auto_scaling_group
+        );
+        let groups = autoscaling_client
+            .describe_auto_scaling_groups(AutoScalingGroupNamesType {
+                auto_scaling_group_names: Some(vec![auto_scaling_group.to_string()]),
+                ..Default::default()
+            })
+            .sync()?;
+        let group = groups
+            .auto_scaling_groups
+            .into_iter()
+            .find(|group| group.auto_scaling_group_name == auto_scaling_group)
+            .ok_or_else(|| crate::Error::AutoScalingGroupNotFound {
+                name: auto_scaling_group.to_string(),
+            })?;
+
+        let mut nodes = Self::nodes_by_instance_id(nomad_client)?;
+        let mut drains = vec![];
+        let mut unmatched_instances = vec![];
+        for instance in group.instances.unwrap_or_default() {
+            match nodes.remove(&instance.instance_id) {
+                Some(node) => drains.push(PlannedDrain {
+                    availability_zone: node
+                        .availability_zone()
+                        .map(ToString::to_string)
+                        .unwrap_or(instance.availability_zone),
+                    instance_id: instance.instance_id,
+                    node_id: node.id,
+                    node_name: node.name,
+                    datacenter: node.datacenter,
+                }),
+                None => {
+                    warn!(
+                        "No Nomad Node found for AWS Instance ID {}",
+                        instance.instance_id
+                    );
+                    unmatched_instances.push(instance.instance_id);
+                }
+            }
+        }
+
+        Ok(Self {
+            auto_scaling_group: auto_scaling_group.to_string(),
+            created_at: chrono::Utc::now(),
+            drain_spec,
+            drains,
+            max_drains_per_zone: None,
+            max_concurrent_drains: None,
+            min_eligible_nodes: None,
+            unmatched_instances,
+        })
+    }
+
+    /// Check the concurrency and capacity guards of the plan against the nodes of the cluster
+    ///
+    /// Returns the number of nodes outside of the plan that are already draining.
+    pub fn check_guards(&self, nodes: &[nomad::NodesInList]) -> Result<usize, crate::Error> {
+        let planned: HashSet<&str> = self
+            .drains
+            .iter()
+            .map(|drain| drain.node_id.as_str())
+            .collect();
+        let already_draining = nodes
+            .iter()
+            .filter(|node| node.drain && !planned.contains(node.id.as_str()))
+            .count();
+        if let Some(limit) = self.max_concurrent_drains {
+            if already_draining >= limit {
+                Err(crate::Error::DrainPlanRefused {
+                    reason: format!(
+                        "{} nodes are already draining, which reaches the limit of {} concurrent \
+                         drains",
+                        already_draining, limit
+                    ),
+                })?;
+            }
+        }
+
+        if let Some(minimum) = self.min_eligible_nodes {
+            let remaining = nodes
+                .iter()
+                .filter(|node| {
+                    node.is_ready()
+                        && !node.drain
+                        && node.scheduling_eligibility == NodeEligibility::Eligible
+                        && !planned.contains(node.id.as_str())
+                })
+                .count();
+            if remaining < minimum {
+                Err(crate::Error::DrainPlanRefused {
+                    reason: format!(
+                        "{} eligible nodes would remain, fewer than the minimum of {}",
+                        remaining, minimum
+                    ),
+                })?;
+            }
+        }
+        Ok(already_draining)
+    }
+
+    /// Apply the plan, draining one node at a time
+    ///
+    /// The guards of the plan are checked before any node is drained. While the optional `pause`
+    /// switch is paused, no new drains are started. Drains already in progress are monitored
+    /// until the switch is resumed.
+    ///
+    /// Returns the IDs of the nodes drained.
+    pub fn apply(
+        &self,
+        nomad_client: &nomad::Client,
+        monitor: bool,
+        pause: Option<&dyn PauseSwitch>,
+    ) -> Result<Vec<String>, crate::Error> {
+        let already_draining = self.check_guards(&nomad_client.nodes(None, None)?.data)?;
+        let mut outcomes = vec![];
+        self.apply_recording(
+            nomad_client,
+            monitor,
+            pause,
+            already_draining,
+            &mut outcomes,
+        )?;
+        Ok(outcomes
+            .into_iter()
+            .map(|outcome| outcome.drain.node_id)
+            .collect())
+    }
+
+    /// Apply the plan like `apply`, recording the outcome of each drain in a report
+    ///
+    /// Errors checking the guards of the plan are returned, since no drain is started. After
+    /// that, applying the plan stops at the first failure, which is recorded in the report rather
+    /// than returned.
+    pub fn apply_with_report(
+        &self,
+        nomad_client: &nomad::Client,
+        monitor: bool,
+        pause: Option<&dyn PauseSwitch>,
+    ) -> Result<ApplyReport, crate::Error> {
+        let already_draining = self.check_guards(&nomad_client.nodes(None, None)?.data)?;
+        let started_at = chrono::Utc::now();
+        let mut outcomes = vec![];
+        if let Err(e) = self.apply_recording(
+            nomad_client,
+            monitor,
+            pause,
+            already_draining,
+            &mut outcomes,
+        ) {
+            warn!("Applying drain plan failed: {}", e);
+        }
+        Ok(ApplyReport {
+            auto_scaling_group: self.auto_scaling_group.clone(),
+            started_at,
+            finished_at: chrono::Utc::now(),
+            skipped: self.drains[outcomes.len()..].to_vec(),
+            drains: outcomes,
+        })
+    }
+
+    fn apply_recording(
+        &self,
+        nomad_client: &nomad::Client,
+        monitor: bool,
+        pause: Option<&dyn PauseSwitch>,
+        already_draining: usize,
+        outcomes: &mut Vec<DrainOutcome>,
+    ) -> Result<(), crate::Error> {
+        info!(
+            "Applying drain plan for Auto Scaling Group {} with {} nodes",
+            self.auto_scaling_group,
+            self.drains.len()
+        );
+        let mut draining: HashMap<&str, VecDeque<usize>> = HashMap::new();
+        for drain in &self.drains {
+            if let Some(pause) = pause {
+                Self::wait_while_paused(nomad_client, pause, &mut draining, outcomes)?;
+            }
+
+            if let Some(limit) = self.max_drains_per_zone {
+                let zone = draining.entry(&drain.availability_zone).or_default();
+                while zone.len() >= std::cmp::max(limit, 1) {
+                    let index = zone.pop_front().expect("zone to have draining nodes");
+                    let outcome = &mut outcomes[index];
+                    info!(
+                        "Waiting for Nomad Node ID {} to drain before draining more nodes in {}",
+                        outcome.drain.node_id, drain.availability_zone
+                    );
+                    Self::monitor_drain(nomad_client, outcome)?;
+                }
+            }
+
+            if let Some(limit) = self.max_concurrent_drains {
+                let limit = std::cmp::max(limit.saturating_sub(already_draining), 1);
+                while draining.values().map(VecDeque::len).sum::<usize>() >= limit {
+                    let index = Self::pop_oldest(&mut draining).expect("nodes to be draining");
+                    let outcome = &mut outcomes[index];
+                    info!(
+                        "Waiting for Nomad Node ID {} to drain before draining more nodes",
+                        outcome.drain.node_id
+                    );
+                    Self::monitor_drain(nomad_client, outcome)?;
+                }
+            }
+
+            info!(
+                "Draining Nomad Node ID {} for AWS Instance ID {}",
+                drain.node_id, drain.instance_id
+            );
+            let mut outcome = DrainOutcome::new(drain.clone());
+            let result = self.drain_node(nomad_client, monitor, &mut outcome);
+            outcomes.push(outcome);
+            result?;
+
+            if !monitor {
+                draining
+                    .entry(&drain.availability_zone)
+                    .or_default()
+                    .push_back(outcomes.len() - 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the drain that was started first from the drains in progress
+    fn pop_oldest(draining: &mut HashMap<&str, VecDeque<usize>>) -> Option<usize> {
+        let zone = draining
+            .values_mut()
+            .filter(|zone| !zone.is_empty())
+            .min_by_key(|zone| zone[0])?;
+        zone.pop_front()
+    }
+
+    /// Wait while drains are paused, monitoring the drains in progress in the meantime
+    fn wait_while_paused(
+        nomad_client: &nomad::Client,
+        pause: &dyn PauseSwitch,
+        draining: &mut HashMap<&str, VecDeque<usize>>,
+        outcomes: &mut [DrainOutcome],
+    ) -> Result<(), crate::Error> {
+        while pause.is_paused()? {
+            match draining.values_mut().find_map(VecDeque::pop_front) {
+                Some(index) => {
+                    let outcome = &mut outcomes[index];
+                    info!(
+                        "Drains are paused. Waiting for Nomad Node ID {} to drain",
+                        outcome.drain.node_id
+                    );
+                    Self::monitor_drain(nomad_client, outcome)?;
+                }
+                None => {
+                    info!(
+                        "Drains are paused. Checking again in {} seconds",
+                        PAUSE_POLL_INTERVAL.as_secs()
+                    );
+                    nomad_client.clock().sleep(PAUSE_POLL_INTERVAL);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn monitor_drain(
+        nomad_client: &nomad::Client,
+        outcome: &mut DrainOutcome,
+    ) -> Result<(), crate::Error> {
+        let result = nomad_client.monitor_node_drain(&outcome.drain.node_id, None, None);
+        outcome.record_error(result)?;
+        outcome.completed_at = Some(chrono::Utc::now());
+        Ok(())
+    }
+
+    fn drain_node(
+        &self,
+        nomad_client: &nomad::Client,
+        monitor: bool,
+        outcome: &mut DrainOutcome,
+    ) -> Result<(), crate::Error> {
+        let node_id = outcome.drain.node_id.clone();
+        let allocations = nomad_client.allocations(&node_id, None, None);
+        outcome.allocations = outcome
+            .record_error(allocations)?
+            .data
+            .iter()
+            .filter(|allocation| !allocation.is_terminal())
+            .count();
+
+        let result = nomad_client.set_node_eligibility(&node_id, NodeEligibility::Ineligible);
+        outcome.record_error(result)?;
+        let result = nomad_client.set_node_drain(&node_id, monitor, Some(self.drain_spec.clone()));
+        outcome.record_error(result)?;
+        if monitor {
+            outcome.completed_at = Some(chrono::Utc::now());
+        }
+        Ok(())
+    }
+
+    /// Map the AWS instance ID of every ready node to the node's details
+    fn nodes_by_instance_id(
+        nomad_client: &nomad::Client,
+    ) -> Result<HashMap<String, nomad::Node>, crate::Error> {
+        let mut nodes = HashMap::new();
+        for node in nomad_client.ready_nodes()? {
+            let details = nomad_client.node_details(&node.id, None, None)?.data;
+            if let Some(instance_id) = details.instance_id() {
+                nodes.insert(instance_id.to_string(), details);
+            }
+        }
+        Ok(nodes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::NodeFixture;
+    use crate::nomad::NodeStatus;
+
+    fn plan_for_nodes(node_ids: &[&str]) -> DrainPlan {
+        DrainPlan {
+            auto_scaling_group: "nomad-clients".to_string(),
+            created_at: chrono::Utc::now(),
+            drain_spec: Default::default(),
+            drains: node_ids
+                .iter()
+                .map(|node_id| PlannedDrain {
+                    instance_id: format!("i-{}", node_id),
+                    availability_zone: "ap-southeast-1c".to_string(),
+                    node_id: node_id.to_string(),
+                    node_name: format!("i-{}", node_id),
+                    datacenter: "ap-southeast-1c".to_string(),
+                })
+                .collect(),
+            max_drains_per_zone: None,
+            max_concurrent_drains: None,
+            min_eligible_nodes: None,
+            unmatched_instances: vec![],
+        }
+    }
+
+    fn node(id: &str) -> NodeFixture {
+        NodeFixture::ready().with_id(id)
+    }
+
+    #[test]
+    fn drain_plan_round_trips_through_json() {
+        let plan = DrainPlan {
+            auto_scaling_group: "nomad-clients".to_string(),
+            created_at: chrono::Utc::now(),
+            drain_spec: Default::default(),
+            drains: vec![PlannedDrain {
+                instance_id: "i-instance-id".to_string(),
+                availability_zone: "ap-southeast-1c".to_string(),
+                node_id: "02802087-8786-fdf6-4497-98445c891fb7".to_string(),
+                node_name: "i-instance-id".to_string(),
+                datacenter: "ap-southeast-1c".to_string(),
+            }],
+            max_drains_per_zone: Some(1),
+            max_concurrent_drains: Some(2),
+            min_eligible_nodes: Some(3),
+            unmatched_instances: vec!["i-other".to_string()],
+        };
+
+        let json = serde_json::to_string_pretty(&plan).unwrap();
+        let deserialized: DrainPlan = serde_json::from_str(&json).unwrap();
+        assert_eq!(plan, deserialized);
+    }
+
+    #[test]
+    fn drain_outcome_duration_is_measured_when_completed() {
+        let mut outcome = DrainOutcome::new(PlannedDrain {
+            instance_id: "i-instance-id".to_string(),
+            availability_zone: "ap-southeast-1c".to_string(),
+            node_id: "02802087-8786-fdf6-4497-98445c891fb7".to_string(),
+            node_name: "i-instance-id".to_string(),
+            datacenter: "ap-southeast-1c".to_string(),
+        });
+        assert_eq!(None, outcome.duration());
+
+        outcome.completed_at = Some(outcome.started_at + chrono::Duration::seconds(90));
+        assert_eq!(Some(std::time::Duration::from_secs(90)), outcome.duration());
+
+        let result: Result<(), _> = Err(crate::Error::NomadNodeNotFound {
+            instance_id: "i-instance-id".to_string(),
+        });
+        assert!(outcome.record_error(result).is_err());
+        assert_eq!(
+            Some("No Nomad Node found for AWS instance ID: i-instance-id"),
+            outcome.error.as_deref()
+        );
+    }
+
+    #[test]
+    fn guards_refuse_plans_exceeding_concurrent_drains() {
+        // Draining nodes in the plan are resumed drains and do not count towards the limit
+        let nodes: Vec<_> = [
+            node("a").draining(),
+            node("b"),
+            node("c").draining(),
+            node("d").draining(),
+        ]
+        .iter()
+        .map(NodeFixture::in_list)
+        .collect();
+        let mut plan = plan_for_nodes(&["a", "b"]);
+        assert_eq!(2, plan.check_guards(&nodes).unwrap());
+
+        plan.max_concurrent_drains = Some(3);
+        assert_eq!(2, plan.check_guards(&nodes).unwrap());
+
+        plan.max_concurrent_drains = Some(2);
+        let error = plan.check_guards(&nodes).unwrap_err();
+        assert!(matches!(error, crate::Error::DrainPlanRefused { .. }));
+        assert_eq!(crate::ErrorKind::Configuration, error.kind());
+    }
+
+    #[test]
+    fn guards_refuse_plans_leaving_too_few_eligible_nodes() {
+        let nodes: Vec<_> = [
+            node("a"),
+            node("b"),
+            node("c"),
+            node("d"),
+            node("e").with_status(NodeStatus::Down),
+            node("f").with_eligibility(NodeEligibility::Ineligible),
+            node("g").draining(),
+        ]
+        .iter()
+        .map(NodeFixture::in_list)
+        .collect();
+        let mut plan = plan_for_nodes(&["a", "b"]);
+        plan.min_eligible_nodes = Some(2);
+        assert!(plan.check_guards(&nodes).is_ok());
+
+        plan.min_eligible_nodes = Some(3);
+        let error = plan.check_guards(&nodes).unwrap_err();
+        assert_eq!(
+            "Refusing to apply the drain plan: 2 eligible nodes would remain, fewer than the \
+             minimum of 3",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn oldest_drain_is_popped_across_zones() {
+        let mut draining: HashMap<&str, VecDeque<usize>> = HashMap::new();
+        draining.insert("ap-southeast-1a", vec![1, 4].into());
+        draining.insert("ap-southeast-1b", vec![0, 3].into());
+        draining.insert("ap-southeast-1c", vec![].into());
+
+        assert_eq!(Some(0), DrainPlan::pop_oldest(&mut draining));
+        assert_eq!(Some(1), DrainPlan::pop_oldest(&mut draining));
+        assert_eq!(Some(3), DrainPlan::pop_oldest(&mut draining));
+        assert_eq!(Some(4), DrainPlan::pop_oldest(&mut draining));
+        assert_eq!(None, DrainPlan::pop_oldest(&mut draining));
+    }
+
+    #[test]
+    fn paused_drains_are_polled_on_the_client_clock() -> Result<(), crate::Error> {
+        /// Paused for the provided number of checks
+        #[derive(Debug)]
+        struct PausedFor(std::sync::Mutex<u32>);
+
+        impl PauseSwitch for PausedFor {
+            fn is_paused(&self) -> Result<bool, crate::Error> {
+                let mut checks = self.0.lock().unwrap();
+                *checks = checks.saturating_sub(1);
+                Ok(*checks > 0)
+            }
+        }
+
+        let clock = std::sync::Arc::new(crate::clock::SimulatedClock::new());
+        let nomad_client = nomad::Client::builder("http://127.0.0.1:4646")
+            .build()?
+            .with_clock(clock.clone());
+        let pause = PausedFor(std::sync::Mutex::new(3));
+        DrainPlan::wait_while_paused(&nomad_client, &pause, &mut HashMap::new(), &mut [])?;
+        assert_eq!(PAUSE_POLL_INTERVAL * 2, clock.simulated());
+        Ok(())
+    }
+}
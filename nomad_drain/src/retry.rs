@@ -0,0 +1,183 @@
+//! Retries of transient failures when talking to Nomad and Vault
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::clock::Clock;
+use crate::Transport;
+
+/// Policy for retrying requests that fail with connection errors or `5xx` responses
+///
+/// Delays grow exponentially from `base_delay`, capped at `max_delay`. With `jitter`, each delay
+/// is randomly shortened by up to half so that concurrent invocations do not retry in lockstep.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub base_delay: Duration,
+    /// Upper bound of the delay between retries
+    pub max_delay: Duration,
+    /// Randomize delays
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Delay to wait after the provided attempt, starting from 1, has failed
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = std::cmp::min(attempt.saturating_sub(1), 31);
+        let delay = self
+            .base_delay
+            .checked_mul(1 << exponent)
+            .map_or(self.max_delay, |delay| std::cmp::min(delay, self.max_delay));
+
+        if self.jitter {
+            let millis = (delay.as_secs() * 1000 + u64::from(delay.subsec_millis())) / 2;
+            if millis > 0 {
+                return delay - Duration::from_millis(random() % (millis + 1));
+            }
+        }
+        delay
+    }
+
+    /// Execute a request, rebuilding and retrying it while it fails transiently
+    ///
+    /// The response of the last attempt is returned, even if it has a `5xx` status.
+    pub(crate) fn execute<F>(
+        &self,
+        transport: &dyn Transport,
+        clock: &dyn Clock,
+        build: F,
+    ) -> Result<reqwest::Response, crate::Error>
+    where
+        F: Fn() -> Result<reqwest::Request, crate::Error>,
+    {
+        let mut attempt = 1;
+        loop {
+            let request = build()?;
+            debug!("Executing request (attempt {}): {:#?}", attempt, request);
+            let failure = match transport.execute(request) {
+                Ok(ref response)
+                    if response.status().is_server_error() && attempt < self.max_attempts =>
+                {
+                    format!("HTTP {}", response.status())
+                }
+                Err(ref e) if is_transient(e) && attempt < self.max_attempts => e.to_string(),
+                result => break Ok(result?),
+            };
+
+            let delay = self.delay(attempt);
+            warn!(
+                "Request failed on attempt {} of {}: {}. Retrying in {:?}",
+                attempt, self.max_attempts, failure, delay
+            );
+            clock.sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether the error is a connection error or timeout that might succeed when retried
+pub(crate) fn is_transient(error: &reqwest::Error) -> bool {
+    match error.get_ref() {
+        Some(inner) if inner.is::<std::io::Error>() => true,
+        _ => error.is_http(),
+    }
+}
+
+/// Whether the error is a request or response body timing out
+///
+/// `reqwest` 0.9 has no `Error::is_timeout` yet and reports timeouts as I/O errors instead.
+pub(crate) fn is_timeout(error: &reqwest::Error) -> bool {
+    match error.get_ref() {
+        Some(inner) => match inner.downcast_ref::<std::io::Error>() {
+            Some(inner) => inner.kind() == std::io::ErrorKind::TimedOut,
+            None => false,
+        },
+        None => false,
+    }
+}
+
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_grow_exponentially_up_to_the_maximum() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..Default::default()
+        };
+
+        assert_eq!(Duration::from_millis(500), policy.delay(1));
+        assert_eq!(Duration::from_millis(1000), policy.delay(2));
+        assert_eq!(Duration::from_millis(2000), policy.delay(3));
+        assert_eq!(Duration::from_secs(10), policy.delay(6));
+        assert_eq!(Duration::from_secs(10), policy.delay(100));
+    }
+
+    #[test]
+    fn jitter_shortens_delays_by_up_to_half() {
+        let policy = RetryPolicy::default();
+
+        for attempt in 1..10 {
+            let delay = policy.delay(attempt);
+            let unjittered = RetryPolicy {
+                jitter: false,
+                ..policy
+            }
+            .delay(attempt);
+            assert!(delay <= unjittered);
+            assert!(delay >= unjittered / 2);
+        }
+    }
+
+    #[test]
+    fn connection_errors_are_retried() {
+        let clock = crate::clock::SimulatedClock::new();
+        let client = reqwest::Client::new();
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(60),
+            jitter: false,
+        };
+
+        // Nothing listens on port 1, so every attempt is refused
+        let result = policy.execute(&client, &clock, || {
+            Ok(client.get("http://127.0.0.1:1/v1/agent/self").build()?)
+        });
+        assert!(result.is_err());
+        assert_eq!(Duration::from_secs(30 + 60 + 60), clock.simulated());
+    }
+
+    #[test]
+    fn no_retry_policy_makes_a_single_attempt() {
+        assert_eq!(1, RetryPolicy::none().max_attempts);
+    }
+}
@@ -0,0 +1,266 @@
+//! Helpers for running AWS Systems Manager commands on instances
+//!
+//! Use these to perform host-level cleanup, such as flushing logs or unmounting volumes, before
+//! an instance is terminated.
+use std::collections::HashMap;
+use std::time::Duration;
+
+use log::{debug, info};
+use rusoto_core::credential::AwsCredentials;
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::Region;
+use serde::{Deserialize, Serialize};
+
+use crate::aws::into_reqwest_request;
+use crate::clock::Clock;
+
+/// Interval between checks on the status of a command
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Systems Manager document to run on an instance
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug, Default)]
+pub struct Document {
+    /// Name or ARN of the document, e.g. `AWS-RunShellScript`
+    pub name: String,
+    /// Parameters of the document
+    #[serde(default)]
+    pub parameters: HashMap<String, Vec<String>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SendCommandRequest<'a> {
+    document_name: &'a str,
+    instance_ids: [&'a str; 1],
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    parameters: &'a HashMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SendCommandResponse {
+    command: SentCommand,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SentCommand {
+    command_id: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct GetCommandInvocationRequest<'a> {
+    command_id: &'a str,
+    instance_id: &'a str,
+}
+
+/// Result of a command on an instance
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct CommandInvocation {
+    pub command_id: String,
+    pub instance_id: String,
+    /// One of `Pending`, `InProgress`, `Delayed`, `Success`, `Cancelled`, `TimedOut`, `Failed` or
+    /// `Cancelling`
+    pub status: String,
+    #[serde(default)]
+    pub status_details: String,
+    /// Exit code of the command, or `-1` if it has not completed
+    #[serde(default)]
+    pub response_code: i64,
+    #[serde(default)]
+    pub standard_output_content: String,
+    #[serde(default)]
+    pub standard_error_content: String,
+}
+
+impl CommandInvocation {
+    /// Whether the command has stopped running
+    pub fn is_terminal(&self) -> bool {
+        !matches!(
+            self.status.as_str(),
+            "Pending" | "InProgress" | "Delayed" | "Cancelling"
+        )
+    }
+
+    /// Whether the command completed successfully
+    pub fn is_success(&self) -> bool {
+        self.status == "Success"
+    }
+}
+
+/// Run a command on an instance and wait for it to complete
+///
+/// Fails if the command does not succeed, or does not complete within `timeout`. The command is
+/// left running on the instance when the timeout elapses.
+///
+/// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
+/// CA certificate or require client authentication
+pub fn run_command(
+    credentials: &AwsCredentials,
+    region: &Region,
+    instance_id: &str,
+    document: &Document,
+    timeout: Duration,
+    clock: &dyn Clock,
+    client: Option<reqwest::Client>,
+) -> Result<CommandInvocation, crate::Error> {
+    let client = match client {
+        Some(client) => client,
+        None => reqwest::Client::new(),
+    };
+    info!(
+        "Running Systems Manager document {} on AWS Instance ID {}",
+        document.name, instance_id
+    );
+    let payload = SendCommandRequest {
+        document_name: &document.name,
+        instance_ids: [instance_id],
+        parameters: &document.parameters,
+    };
+    let started = clock.now();
+    let response: SendCommandResponse = execute(
+        &client,
+        build_ssm_request(&client, credentials, region, "SendCommand", &payload)?,
+    )?;
+    let command_id = response.command.command_id;
+
+    loop {
+        let payload = GetCommandInvocationRequest {
+            command_id: &command_id,
+            instance_id,
+        };
+        let request = build_ssm_request(
+            &client,
+            credentials,
+            region,
+            "GetCommandInvocation",
+            &payload,
+        )?;
+        match execute::<CommandInvocation>(&client, request) {
+            Ok(ref invocation) if invocation.is_success() => {
+                info!(
+                    "Systems Manager command {} succeeded on AWS Instance ID {}",
+                    command_id, instance_id
+                );
+                return Ok(invocation.clone());
+            }
+            Ok(invocation) if invocation.is_terminal() => Err(crate::Error::SsmCommandFailed {
+                instance_id: instance_id.to_string(),
+                command_id: command_id.clone(),
+                status: invocation.status,
+                details: invocation.standard_error_content,
+            })?,
+            Ok(invocation) => debug!("Command status is {}", invocation.status),
+            // The invocation is not visible for a short while after the command is sent
+            Err(crate::Error::SsmRequestFailed { ref body, .. })
+                if body.contains("InvocationDoesNotExist") =>
+            {
+                debug!("Command invocation does not exist yet")
+            }
+            Err(e) => Err(e)?,
+        }
+
+        if clock.elapsed(started) + POLL_INTERVAL > timeout {
+            Err(crate::Error::SsmCommandTimedOut {
+                instance_id: instance_id.to_string(),
+                command_id: command_id.clone(),
+            })?;
+        }
+        clock.sleep(POLL_INTERVAL);
+    }
+}
+
+fn execute<T>(client: &reqwest::Client, request: reqwest::Request) -> Result<T, crate::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let target = request
+        .headers()
+        .get("x-amz-target")
+        .and_then(|target| target.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    debug!("Executing request: {:#?}", request);
+    let mut response = client.execute(request)?;
+    debug!("Response received: {:#?}", response);
+    let body = response.text()?;
+    if !response.status().is_success() {
+        Err(crate::Error::SsmRequestFailed {
+            target,
+            status: response.status().as_u16(),
+            body: body.clone(),
+        })?;
+    }
+    Ok(serde_json::from_str(&body)?)
+}
+
+fn build_ssm_request<T>(
+    client: &reqwest::Client,
+    credentials: &AwsCredentials,
+    region: &Region,
+    action: &str,
+    payload: &T,
+) -> Result<reqwest::Request, crate::Error>
+where
+    T: Serialize,
+{
+    let body = serde_json::to_vec(payload)?;
+    let mut request = SignedRequest::new("POST", "ssm", region, "/");
+    request.set_content_type("application/x-amz-json-1.1".to_string());
+    request.add_header("x-amz-target", &format!("AmazonSSM.{}", action));
+    request.set_payload(Some(body.clone()));
+    request.sign_with_plus(credentials, true);
+    into_reqwest_request(client, reqwest::Method::POST, &request, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::aws::tests::credentials;
+
+    #[test]
+    fn ssm_request_is_signed() -> Result<(), crate::Error> {
+        let client = reqwest::Client::new();
+        let parameters = Default::default();
+        let payload = SendCommandRequest {
+            document_name: "AWS-RunShellScript",
+            instance_ids: ["i-1234567890abcdef0"],
+            parameters: &parameters,
+        };
+        let request = build_ssm_request(
+            &client,
+            &credentials()?,
+            &Region::ApSoutheast1,
+            "SendCommand",
+            &payload,
+        )?;
+
+        assert_eq!(
+            "https://ssm.ap-southeast-1.amazonaws.com/",
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!("AmazonSSM.SendCommand", request.headers()["x-amz-target"]);
+        assert!(request.headers().contains_key("authorization"));
+        Ok(())
+    }
+
+    #[test]
+    fn command_invocation_status_is_interpreted() {
+        let invocation: CommandInvocation = serde_json::from_str(
+            r#"{
+                "CommandId": "b8eac879-0541-439d-94ec-47a80d554f44",
+                "InstanceId": "i-1234567890abcdef0",
+                "Status": "InProgress",
+                "ResponseCode": -1
+            }"#,
+        )
+        .unwrap();
+
+        assert!(!invocation.is_terminal());
+        assert!(!invocation.is_success());
+    }
+}
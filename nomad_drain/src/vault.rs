@@ -2,11 +2,43 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+use futures::future::{self, Future};
 use log::{debug, info, warn};
-use reqwest::{Client as HttpClient, ClientBuilder};
+use reqwest::header::{HeaderMap, HeaderValue, LOCATION};
+use reqwest::r#async::{Client as AsyncHttpClient, ClientBuilder as AsyncClientBuilder};
+use reqwest::{
+    Client as HttpClient, ClientBuilder as HttpClientBuilder, RedirectPolicy, StatusCode,
+};
 use serde::{Deserialize, Serialize};
 
+use crate::clock::{Clock, SystemClock};
+use crate::context::RequestContext;
+use crate::headers::{RequestBuilderExt, VAULT_MFA_HEADER, VAULT_NAMESPACE_HEADER};
+use crate::retry::RetryPolicy;
+use crate::tls::TlsConfig;
+
+pub mod auth;
+
+pub use self::auth::{AppRole, AuthMethod, AwsEc2, AwsIam, Kubernetes, Token};
+
+/// Maximum number of redirects followed for a request
+const MAX_REDIRECTS: usize = 10;
+
+/// Timeout of HTTP requests made by clients built with `ClientBuilder`
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Fragments of Vault AWS authentication errors caused by IAM eventual consistency, where a newly
+/// created IAM principal cannot be resolved yet
+const IAM_PRINCIPAL_NOT_FOUND_ERRORS: &[&str] = &[
+    "principal not found",
+    "unable to resolve arn",
+    "nosuchentity",
+];
+
 /// Vault API Client
 #[derive(Clone, Debug)]
 pub struct Client {
@@ -14,10 +46,113 @@ pub struct Client {
     address: String,
     client: HttpClient,
     revoke_self_on_drop: bool,
+    mfa: Vec<MfaCredentials>,
+    namespace: Option<String>,
+    retry_policy: RetryPolicy,
+    clock: Arc<dyn Clock>,
+    context: RequestContext,
+}
+
+/// Builder of Vault `Client`s
+///
+/// The client either uses an existing token, or logs in with an authentication method when it is
+/// built.
+///
+/// ```rust,no_run
+/// # use nomad_drain::vault::{AppRole, Client};
+/// # use nomad_drain::tls::TlsConfig;
+/// let client = Client::builder("https://vault.service.consul:8200")
+///     .with_auth(AppRole {
+///         path: "approle".to_string(),
+///         role_id: "role".to_string(),
+///         secret_id: None,
+///     })
+///     .with_tls(&TlsConfig::from_vault_env())
+///     .with_namespace("ops")
+///     .build()?;
+/// # Ok::<(), nomad_drain::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    address: String,
+    token: Option<crate::Secret>,
+    auth: Option<Arc<dyn AuthMethod>>,
+    login_retry_window: Option<Duration>,
+    revoke_self_on_drop: Option<bool>,
+    http_client: Option<HttpClient>,
+    tls: Option<TlsConfig>,
+    timeout: Duration,
+    namespace: Option<String>,
+    mfa: Vec<MfaCredentials>,
+    retry_policy: RetryPolicy,
+    clock: Arc<dyn Clock>,
+    context: RequestContext,
+    warm_up: Option<Arc<Mutex<Option<JoinHandle<()>>>>>,
+}
+
+/// Credentials for a Vault MFA method, sent in the `X-Vault-MFA` header
+///
+/// Parsed from strings in the form `method_name:passcode`. The passcode can be omitted for push
+/// based methods like Duo push.
+#[derive(Clone, Eq, PartialEq)]
+pub struct MfaCredentials {
+    /// Name or ID of the MFA method
+    pub method: String,
+    /// Passcode for the method, if any
+    pub passcode: Option<crate::Secret>,
+}
+
+impl MfaCredentials {
+    fn header_value(&self) -> String {
+        match self.passcode {
+            Some(ref passcode) => format!("{}:{}", self.method, passcode.expose_secret()),
+            None => self.method.clone(),
+        }
+    }
+}
+
+impl Debug for MfaCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MfaCredentials")
+            .field("method", &self.method)
+            .field("passcode", &self.passcode)
+            .finish()
+    }
+}
+
+impl std::str::FromStr for MfaCredentials {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let method = parts.next().unwrap_or_default().trim();
+        if method.is_empty() {
+            Err(crate::Error::InvalidMfaCredentials)?;
+        }
+        Ok(Self {
+            method: method.to_string(),
+            passcode: parts
+                .next()
+                .map(|passcode| From::from(passcode.to_string())),
+        })
+    }
+}
+
+/// Asynchronous Vault API Client
+///
+/// Methods return futures that can be driven by a tokio runtime instead of blocking a thread.
+/// Unlike `Client`, tokens are not revoked when the client is dropped; call `revoke_self`
+/// explicitly.
+#[derive(Clone, Debug)]
+pub struct AsyncClient {
+    token: crate::Secret,
+    address: String,
+    client: AsyncHttpClient,
 }
 
 /// Generic Vault Response
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize-secrets", derive(Serialize))]
 #[serde(untagged)]
 #[allow(clippy::large_enum_variant)]
 pub enum Response {
@@ -31,7 +166,8 @@ pub enum Response {
 }
 
 /// Vault General Response Data
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize-secrets", derive(Serialize))]
 pub struct ResponseData {
     /// Request UUID
     request_id: String,
@@ -52,12 +188,33 @@ pub struct ResponseData {
     /// Data for secrets requests
     #[serde(default)]
     data: Option<HashMap<String, String>>,
-    // Missing and ignored fields:
-    // - wrap_info
+
+    /// Response wrapping information, in place of the data for wrapped responses
+    #[serde(default)]
+    wrap_info: Option<WrapInfo>,
+}
+
+/// Response wrapping information from Vault
+///
+/// See [Vault Documentation](https://www.vaultproject.io/docs/concepts/response-wrapping.html)
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "serialize-secrets", derive(Serialize))]
+pub struct WrapInfo {
+    /// Single use token to unwrap the response with
+    pub token: crate::Secret,
+    /// Accessor of the wrapping token
+    pub accessor: String,
+    /// Seconds until the wrapping token expires
+    pub ttl: u64,
+    /// Time the response was wrapped
+    pub creation_time: String,
+    /// Path of the request whose response was wrapped
+    pub creation_path: String,
 }
 
 /// Authentication data from Vault
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize-secrets", derive(Serialize))]
 pub struct Authentication {
     /// The actual token
     pub client_token: crate::Secret,
@@ -90,6 +247,97 @@ pub enum TokenType {
     Batch,
 }
 
+/// A Nomad token leased from the Nomad secrets engine
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NomadToken {
+    /// The Nomad token
+    pub secret_id: crate::Secret,
+    /// Accessor ID of the Nomad token
+    pub accessor_id: String,
+    /// ID of the Vault lease for the token
+    pub lease_id: String,
+    /// Duration of the lease in seconds
+    pub lease_duration: u64,
+    /// Whether the lease can be renewed
+    pub renewable: bool,
+}
+
+/// Health of a Vault server, from the `sys/health` endpoint
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct Health {
+    /// Whether Vault has been initialized
+    pub initialized: bool,
+    /// Whether Vault is sealed
+    pub sealed: bool,
+    /// Whether the server is a standby node
+    pub standby: bool,
+    /// Version of Vault
+    #[serde(default)]
+    pub version: String,
+}
+
+/// Details of a secrets engine or authentication method mount
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct MountInfo {
+    /// Path the engine is mounted at, with a trailing slash
+    pub path: String,
+    /// Type of the engine, e.g. `nomad` or `kv`
+    #[serde(rename = "type")]
+    pub mount_type: String,
+    /// Description of the mount
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Remaining lifetime of a Vault token, from the `auth/token/lookup-self` endpoint
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct TokenTtl {
+    /// Seconds until the token expires, or 0 for tokens that never expire
+    pub ttl: u64,
+    /// Whether the token can be renewed
+    #[serde(default)]
+    pub renewable: bool,
+}
+
+impl TokenTtl {
+    /// Time until the token expires, or `None` if it never expires
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.ttl == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(self.ttl))
+        }
+    }
+
+    /// Whether the token is still valid after `duration`
+    pub fn outlives(&self, duration: Duration) -> bool {
+        self.remaining()
+            .map(|remaining| remaining > duration)
+            .unwrap_or(true)
+    }
+}
+
+/// Response from the `auth/token/lookup-self` endpoint
+#[derive(Deserialize, Debug)]
+struct TokenLookupResponse {
+    data: TokenTtl,
+}
+
+/// Response from the `sys/internal/ui/mounts/:path` endpoint
+#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[serde(untagged)]
+enum MountResponse {
+    Error { errors: Vec<String> },
+    Mount { data: MountInfo },
+}
+
+/// Body of Vault error responses
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
 /// Payload to send to Vault for logging in via AWS IAM
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
 pub struct AwsIamLoginPayload<'a, 'b> {
@@ -98,38 +346,335 @@ pub struct AwsIamLoginPayload<'a, 'b> {
     pub aws_payload: Cow<'b, crate::aws::VaultAwsAuthIamPayload>,
 }
 
-impl Client {
-    /// Create a new API client from an existing Token
+impl ClientBuilder {
+    /// Build a client of the Vault server at `address`
     ///
-    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
-    /// CA certificate or require client authentication
-    #[allow(clippy::new_ret_no_self)]
-    pub fn new<S1, S2>(
-        vault_address: S1,
-        vault_token: S2,
-        revoke_self_on_drop: bool,
-        client: Option<HttpClient>,
-    ) -> Result<Self, crate::Error>
-    where
-        S1: AsRef<str>,
-        S2: AsRef<str>,
-    {
-        let client = match client {
-            Some(client) => client,
-            None => ClientBuilder::new().build()?,
+    /// Provide a token with `with_token` or a method to login with using `with_auth`.
+    pub fn new<S: AsRef<str>>(address: S) -> Self {
+        Self {
+            address: address.as_ref().to_string(),
+            token: None,
+            auth: None,
+            login_retry_window: None,
+            revoke_self_on_drop: None,
+            http_client: None,
+            tls: None,
+            timeout: DEFAULT_HTTP_TIMEOUT,
+            namespace: None,
+            mfa: vec![],
+            retry_policy: Default::default(),
+            clock: Arc::new(SystemClock),
+            context: Default::default(),
+            warm_up: None,
+        }
+    }
+
+    /// Use an existing token
+    ///
+    /// The token is not verified, and is not revoked when the client is dropped unless
+    /// `with_revoke_self_on_drop` says otherwise. Use `with_auth(Token(..))` to verify it instead.
+    pub fn with_token<S: AsRef<str>>(mut self, token: S) -> Self {
+        self.token = Some(crate::Secret(token.as_ref().to_string()));
+        self
+    }
+
+    /// Login with the authentication method when the client is built, instead of using a token
+    pub fn with_auth<M: AuthMethod + 'static>(mut self, method: M) -> Self {
+        self.auth = Some(Arc::new(method));
+        self
+    }
+
+    /// Retry the login while Vault cannot find the IAM principal, until `retry_window` has elapsed
+    ///
+    /// Freshly created IAM roles can take a while to be visible to Vault due to IAM eventual
+    /// consistency. Such logins are retried with exponential backoff. Other errors are returned
+    /// immediately.
+    pub fn with_login_retry_window(mut self, retry_window: Duration) -> Self {
+        self.login_retry_window = Some(retry_window);
+        self
+    }
+
+    /// Set whether the token is revoked when the client is dropped
+    ///
+    /// Defaults to revoking tokens created by the login, and keeping tokens provided with
+    /// `with_token`.
+    pub fn with_revoke_self_on_drop(mut self, revoke_self_on_drop: bool) -> Self {
+        self.revoke_self_on_drop = Some(revoke_self_on_drop);
+        self
+    }
+
+    /// Use the provided `reqwest::Client`, for specific needs the builder does not cover
+    ///
+    /// The HTTP client is used as is, ignoring the timeout and TLS settings of the builder. The
+    /// namespace is still sent with requests made with the token, but not with the login. Disable
+    /// its redirects so that requests to standby servers are redirected with their token.
+    pub fn with_http_client(mut self, http_client: HttpClient) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Talk to Vault with the TLS settings, for servers with a private CA or mutual TLS
+    pub fn with_tls(mut self, tls: &TlsConfig) -> Self {
+        self.tls = Some(tls.clone());
+        self
+    }
+
+    /// Timeout of HTTP requests. Defaults to 30 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Make every request, including the login, in the
+    /// [namespace](https://www.vaultproject.io/docs/enterprise/namespaces)
+    pub fn with_namespace<S: AsRef<str>>(mut self, namespace: S) -> Self {
+        self.namespace = Some(namespace.as_ref().to_string());
+        self
+    }
+
+    /// Send the MFA credentials with the login and every request made with the token
+    pub fn with_mfa(mut self, mfa: Vec<MfaCredentials>) -> Self {
+        self.mfa = mfa;
+        self
+    }
+
+    /// Retry requests that fail transiently according to the policy
+    ///
+    /// Logins always use the default policy.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Use the provided clock to retry the login and requests with, instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Login and make every request in the provided context. See `Client::with_context`.
+    pub fn with_context(mut self, context: RequestContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Connect to Vault in the background, ahead of the login
+    ///
+    /// The HTTP client is built now and checks the health of Vault on another thread, which
+    /// establishes a TLS connection that the login reuses from the connection pool. Do the rest of
+    /// the work needed to login, like signing AWS requests, before building the client to shave
+    /// the handshake off the login. Failures to warm up are only logged.
+    ///
+    /// Call this after the TLS, timeout and namespace settings, which the HTTP client is built
+    /// with.
+    pub fn warm_up(mut self) -> Result<Self, crate::Error> {
+        let client = self.http_client()?;
+        let address = self.address.clone();
+        self.http_client = Some(client.clone());
+        let handle = thread::spawn(move || {
+            let started = Instant::now();
+            let response = Client::build_health_request(&address, &client)
+                .and_then(|request| Ok(client.execute(request)?))
+                .and_then(|mut response| {
+                    // The connection only returns to the pool once the body has been read
+                    response.copy_to(&mut std::io::sink())?;
+                    Ok(response.status())
+                });
+            match response {
+                Ok(status) => debug!(
+                    "Connection to Vault warmed up in {:?} with status {}",
+                    started.elapsed(),
+                    status
+                ),
+                Err(e) => warn!("Failed to warm up the connection to Vault: {}", e),
+            }
+        });
+        self.warm_up = Some(Arc::new(Mutex::new(Some(handle))));
+        Ok(self)
+    }
+
+    /// The HTTP client to talk to Vault with
+    fn http_client(&self) -> Result<HttpClient, crate::Error> {
+        if let Some(ref client) = self.http_client {
+            return Ok(client.clone());
+        }
+        let mut builder = Client::http_client_builder().timeout(Some(self.timeout));
+        if let Some(ref namespace) = self.namespace {
+            let mut headers = HeaderMap::new();
+            headers.insert(VAULT_NAMESPACE_HEADER, HeaderValue::from_str(namespace)?);
+            builder = builder.default_headers(headers);
+        }
+        Ok(match self.tls {
+            Some(ref tls) => tls.apply(builder)?,
+            None => builder,
+        }
+        .build()?)
+    }
+
+    /// Build the client, logging in with the authentication method if there is one
+    ///
+    /// Fails with `Error::MissingVaultCredentials` if there is neither a token nor an
+    /// authentication method.
+    pub fn build(self) -> Result<Client, crate::Error> {
+        let client = self.http_client()?;
+        // Wait for the warm up to complete, so that the login reuses its connection
+        let warm_up = self
+            .warm_up
+            .and_then(|warm_up| warm_up.lock().ok().and_then(|mut handle| handle.take()));
+        if let Some(handle) = warm_up {
+            let _ = handle.join();
+        }
+
+        let (token, owns_token) = match (self.auth, self.token) {
+            (Some(method), _) => {
+                info!("Logging in to Vault at {}", self.address);
+                let authentication = login(
+                    &*method,
+                    &client,
+                    &self.address,
+                    &self.mfa,
+                    self.login_retry_window,
+                    &self.context,
+                    &*self.clock,
+                )?;
+                info!("Vault authentication successful. Received Vault Token");
+                (authentication.client_token, method.owns_token())
+            }
+            (None, Some(token)) => (token, false),
+            (None, None) => Err(crate::Error::MissingVaultCredentials)?,
         };
 
-        Ok(Self {
-            address: vault_address.as_ref().to_string(),
-            token: crate::Secret(vault_token.as_ref().to_string()),
-            revoke_self_on_drop,
+        Ok(Client {
+            address: self.address,
+            token,
+            revoke_self_on_drop: self.revoke_self_on_drop.unwrap_or(owns_token),
             client,
+            mfa: self.mfa,
+            namespace: self.namespace,
+            retry_policy: self.retry_policy,
+            clock: self.clock,
+            context: self.context,
         })
     }
+}
+
+/// Login with the method, retrying while the IAM principal is not found for up to `retry_window`
+fn login(
+    method: &dyn AuthMethod,
+    client: &HttpClient,
+    vault_address: &str,
+    mfa: &[MfaCredentials],
+    retry_window: Option<Duration>,
+    context: &RequestContext,
+    clock: &dyn Clock,
+) -> Result<Authentication, crate::Error> {
+    let retry_window = retry_window.unwrap_or_default();
+    let start = clock.now();
+    let mut delay = Duration::from_secs(1);
+    loop {
+        match method.login(client, vault_address, mfa, context, clock) {
+            Err(ref e)
+                if is_iam_principal_not_found(e) && clock.elapsed(start) + delay < retry_window =>
+            {
+                warn!(
+                    "IAM principal not found by Vault: {}. Retrying in {:?}",
+                    e, delay
+                );
+                clock.sleep(delay);
+                delay = std::cmp::min(delay * 2, Duration::from_secs(16));
+            }
+            result => break result,
+        }
+    }
+}
+
+impl Client {
+    /// Start building a client of the Vault server at `address`
+    pub fn builder<S: AsRef<str>>(address: S) -> ClientBuilder {
+        ClientBuilder::new(address)
+    }
+
+    /// Build an HTTP client configured with the TLS settings
+    ///
+    /// Provide this to `Client::health` to talk to Vault servers with a private CA or mutual TLS.
+    pub fn build_http_client(tls: &TlsConfig) -> Result<HttpClient, crate::Error> {
+        Ok(tls.apply(Self::http_client_builder())?.build()?)
+    }
+
+    /// Builder for HTTP clients that leave redirects to `execute_request`
+    fn http_client_builder() -> HttpClientBuilder {
+        HttpClientBuilder::new().redirect(RedirectPolicy::none())
+    }
+
+    /// Replace the HTTP client with a client configured with the TLS settings
+    pub fn with_tls(mut self, tls: &TlsConfig) -> Result<Self, crate::Error> {
+        self.client = Self::build_http_client(tls)?;
+        Ok(self)
+    }
+
+    /// Send the MFA credentials with every request made with the token
+    ///
+    /// Required when Vault enforces MFA on the paths the client uses, such as the Nomad secrets
+    /// engine.
+    pub fn with_mfa(mut self, mfa: Vec<MfaCredentials>) -> Self {
+        self.mfa = mfa;
+        self
+    }
+
+    /// Returns the MFA credentials sent with requests
+    pub fn mfa(&self) -> &[MfaCredentials] {
+        &self.mfa
+    }
+
+    /// Make every request made with the token in the namespace
+    ///
+    /// Use `ClientBuilder::with_namespace` to login in the namespace as well.
+    pub fn with_namespace<S: AsRef<str>>(mut self, namespace: S) -> Self {
+        self.namespace = Some(namespace.as_ref().to_string());
+        self
+    }
+
+    /// Returns the namespace requests are made in, if any
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Retry requests that fail transiently according to the provided policy
+    ///
+    /// Logins always use the default policy.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Returns the policy used to retry requests
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Use the provided clock for retries instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Make every request in the provided context
+    ///
+    /// Requests fail with `Error::DeadlineExceeded` once the deadline has passed and carry the
+    /// trace ID. Dry runs only concern Nomad, so Vault requests are made regardless.
+    pub fn with_context(mut self, context: RequestContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Returns the context requests are made in
+    pub fn context(&self) -> &RequestContext {
+        &self.context
+    }
 
     /// Returns the Vault Token
     pub fn token(&self) -> &str {
-        &self.token
+        self.token.expose_secret()
     }
 
     /// Returns the Vault address
@@ -142,83 +687,97 @@ impl Client {
         &self.client
     }
 
-    fn execute_request<T>(client: &HttpClient, request: reqwest::Request) -> Result<T, crate::Error>
+    /// Add the MFA credentials and namespace to a request made with the token
+    fn add_request_defaults(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let request = add_mfa_headers(request, &self.mfa);
+        match self.namespace {
+            Some(ref namespace) => request.vault_namespace(namespace),
+            None => request,
+        }
+    }
+
+    /// Execute the request built by `build`, rebuilding it for every retry
+    fn execute_request<T, F>(
+        client: &HttpClient,
+        retry_policy: &RetryPolicy,
+        clock: &dyn Clock,
+        context: &RequestContext,
+        build: F,
+    ) -> Result<T, crate::Error>
     where
         T: serde::de::DeserializeOwned + Debug,
+        F: Fn() -> Result<reqwest::Request, crate::Error>,
     {
-        debug!("Executing request: {:#?}", request);
-        let mut response = client.execute(request)?;
+        let mut response =
+            Self::execute_following_redirects(client, retry_policy, clock, context, build)?;
         debug!("Response received: {:#?}", response);
         let body = response.text()?;
         debug!("Response body: {}", body);
-        let result = serde_json::from_str(&body)?;
+        check_status(response.status(), &body)?;
+        // Responses without a body, like `204 No Content`, deserialize like `null`
+        let body = if body.trim().is_empty() {
+            "null"
+        } else {
+            &body
+        };
+        let result = crate::from_json_str(body)?;
         debug!("Deserialized body: {:#?}", result);
         Ok(result)
     }
 
-    fn execute_request_no_body(
+    fn execute_request_no_body<F>(
         client: &HttpClient,
-        request: reqwest::Request,
-    ) -> Result<(), crate::Error> {
-        debug!("Executing request: {:#?}", request);
-        let response = client.execute(request)?;
+        retry_policy: &RetryPolicy,
+        clock: &dyn Clock,
+        context: &RequestContext,
+        build: F,
+    ) -> Result<(), crate::Error>
+    where
+        F: Fn() -> Result<reqwest::Request, crate::Error>,
+    {
+        let mut response =
+            Self::execute_following_redirects(client, retry_policy, clock, context, build)?;
         debug!("Response received: {:#?}", response);
+        if !response.status().is_success() {
+            check_status(response.status(), &response.text()?)?;
+        }
         Ok(())
     }
 
-    /// Login with AWS IAM authentication method. Returns a Vault token on success
+    /// Execute the request built by `build`, following redirects to the active server
     ///
-    /// - `address`: Address of Vault Server. Include the scheme (e.g. `https`) and the host with an
-    ///    optional port
-    /// - `path`: Path to the AWS authentication engine. Usually just `aws`.
-    /// - `role`: Name fo the AWS authentication role
-    /// - `payload`: Authentication payload from calling `aws::VaultAwsAuthIamPayload::new`
-    ///
-    /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
-    /// CA certificate or require client authentication
-    pub fn login_aws_iam(
-        vault_address: &str,
-        aws_auth_path: &str,
-        aws_auth_role: &str,
-        aws_payload: &crate::aws::VaultAwsAuthIamPayload,
-        client: Option<HttpClient>,
-    ) -> Result<Self, crate::Error> {
-        info!(
-            "Logging in to Vault with AWS Credentials at path `{}` and role `{}",
-            aws_auth_path, aws_auth_role
-        );
-        let client = match client {
-            Some(client) => client,
-            None => ClientBuilder::new().build()?,
-        };
-
-        let request = Self::build_login_aws_iam_request(
-            vault_address,
-            aws_auth_path,
-            aws_auth_role,
-            aws_payload,
-            &client,
-        )?;
-        let response: Response = Self::execute_request(&client, request)?;
-        let token = match response {
-            Response::Error { errors } => {
-                Err(crate::Error::InvalidVaultResponse(errors.join("; ")))?
+    /// Standby servers redirect requests to the active server with `307 Temporary Redirect`. The
+    /// request is rebuilt for the new location so that the token, MFA credentials and body are
+    /// sent again, which the HTTP client does not guarantee when it follows redirects itself.
+    fn execute_following_redirects<F>(
+        client: &HttpClient,
+        retry_policy: &RetryPolicy,
+        clock: &dyn Clock,
+        context: &RequestContext,
+        build: F,
+    ) -> Result<reqwest::Response, crate::Error>
+    where
+        F: Fn() -> Result<reqwest::Request, crate::Error>,
+    {
+        let build = || context.apply(build()?);
+        context.check_deadline(clock)?;
+        let mut response = retry_policy.execute(client, clock, build)?;
+        let mut redirects = 0;
+        while let Some(location) = redirect_location(&response)? {
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                Err(crate::Error::TooManyRedirects {
+                    location: location.to_string(),
+                })?;
             }
-            Response::Response(ResponseData {
-                auth: Some(auth), ..
-            }) => Ok(auth.client_token),
-            _ => Err(crate::Error::InvalidVaultResponse(
-                "Missing authentication data".to_string(),
-            )),
-        }?;
-
-        info!("Vault authentication successful. Received Vault Token");
-        Ok(Self {
-            address: vault_address.to_string(),
-            token,
-            revoke_self_on_drop: true,
-            client,
-        })
+            info!(
+                "Following redirect to the active Vault server at {}",
+                location
+            );
+            response =
+                retry_policy.execute(client, clock, || Ok(redirected(build()?, &location)))?;
+        }
+        Ok(response)
     }
 
     fn build_login_aws_iam_request(
@@ -226,6 +785,7 @@ impl Client {
         aws_auth_path: &str,
         aws_auth_role: &str,
         aws_payload: &crate::aws::VaultAwsAuthIamPayload,
+        mfa: &[MfaCredentials],
         client: &HttpClient,
     ) -> Result<reqwest::Request, crate::Error> {
         let vault_address = url::Url::parse(vault_address)?;
@@ -234,80 +794,764 @@ impl Client {
             role: aws_auth_role,
             aws_payload: Cow::Borrowed(aws_payload),
         };
-        Ok(client.post(vault_address).json(&payload).build()?)
+        let request = client.post(vault_address).json(&payload);
+        Ok(add_mfa_headers(request, mfa).build()?)
     }
 
-    /// Get a token from Nomad Secrets Engine
+    /// Retrieve the health of a Vault server. This does not require a token.
     ///
     /// You can optionally provide a `reqwest::Client` if you have specific needs like custom root
     /// CA certificate or require client authentication
+    pub fn health(vault_address: &str, client: Option<HttpClient>) -> Result<Health, crate::Error> {
+        info!("Checking health of Vault at {}", vault_address);
+        let client = match client {
+            Some(client) => client,
+            None => Self::http_client_builder().build()?,
+        };
+        Self::execute_request(
+            &client,
+            &RetryPolicy::default(),
+            &SystemClock,
+            &RequestContext::new(),
+            || Self::build_health_request(vault_address, &client),
+        )
+    }
+
+    fn build_health_request(
+        vault_address: &str,
+        client: &HttpClient,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let vault_address = url::Url::parse(vault_address)?;
+        let vault_address = vault_address.join("/v1/sys/health")?;
+        // Respond with 200 regardless of the state of the server so that it can be reported
+        let request = client.get(vault_address).query(&[
+            ("standbyok", "true"),
+            ("perfstandbyok", "true"),
+            ("sealedcode", "200"),
+            ("uninitcode", "200"),
+        ]);
+        Ok(request.build()?)
+    }
+
+    /// Extract the authentication data from a login or renewal response
+    fn authentication(response: Response) -> Result<Authentication, crate::Error> {
+        match response {
+            Response::Error { errors } => {
+                Err(crate::Error::InvalidVaultResponse(errors.join("; ")))
+            }
+            Response::Response(ResponseData {
+                auth: Some(auth), ..
+            }) => Ok(auth),
+            _ => Err(crate::Error::InvalidVaultResponse(
+                "Missing authentication data".to_string(),
+            )),
+        }
+    }
+
+    /// Get a token from Nomad Secrets Engine
+    ///
+    /// With `wrap_ttl`, Vault wraps the response and the single use wrapping token is returned
+    /// instead, valid for `wrap_ttl`. The Nomad token can then be retrieved with `unwrap` by
+    /// whoever the wrapping token is handed to, without it passing through anyone else.
     pub fn get_nomad_token(
         &self,
         nomad_path: &str,
         nomad_role: &str,
+        wrap_ttl: Option<Duration>,
     ) -> Result<crate::Secret, crate::Error> {
         info!(
             "Retrieving Nomad Token from Secrets engine mounted at `{}` with role `{}`",
             nomad_path, nomad_role
         );
-        let request = self.build_nomad_token_request(nomad_path, nomad_role)?;
-        let response: Response = Self::execute_request(&self.client, request)?;
-        Ok(From::from(match response {
-            Response::Error { errors } => {
-                Err(crate::Error::InvalidVaultResponse(errors.join("; ")))?
-            }
-            Response::Response(ResponseData {
-                data: Some(mut data),
-                ..
-            }) => data.remove("secret_id").ok_or_else(|| {
-                crate::Error::InvalidVaultResponse("Missing Nomad token from response".to_string())
-            })?,
-            _ => Err(crate::Error::InvalidVaultResponse(
-                "Missing secrets data".to_string(),
-            ))?,
-        }))
+        self.ensure_mount_type(nomad_path, "nomad")?;
+        let response: Response = Self::execute_request(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_nomad_token_request(nomad_path, nomad_role, wrap_ttl),
+        )?;
+        match wrap_ttl {
+            Some(_) => Ok(Self::wrap_info(response)?.token),
+            None => Ok(Self::nomad_token(response)?.secret_id),
+        }
     }
 
-    /// Revoke the Vault token itself
+    /// Get a token from Nomad Secrets Engine, along with its lease
     ///
-    /// If successful, the Vault Token can no longer be used
-    pub fn revoke_self(&self) -> Result<(), crate::Error> {
-        info!("Revoking self Vault Token");
-
-        let request = self.build_revoke_self_request()?;
-        // HTTP 204 is returned
-        Self::execute_request_no_body(&self.client, request)?;
-        Ok(())
-    }
-
-    fn build_revoke_self_request(&self) -> Result<reqwest::Request, crate::Error> {
+    /// The lease can be revoked with `revoke_lease` once the token is no longer needed.
+    pub fn get_nomad_token_with_lease(
+        &self,
+        nomad_path: &str,
+        nomad_role: &str,
+    ) -> Result<NomadToken, crate::Error> {
+        info!(
+            "Retrieving Nomad Token from Secrets engine mounted at `{}` with role `{}`",
+            nomad_path, nomad_role
+        );
+        self.ensure_mount_type(nomad_path, "nomad")?;
+        let response: Response = Self::execute_request(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_nomad_token_request(nomad_path, nomad_role, None),
+        )?;
+        Self::nomad_token(response)
+    }
+
+    /// Unwrap a response wrapped by Vault, such as a Nomad token from `get_nomad_token`
+    ///
+    /// The wrapping token is sent in place of the token of the client, and can only be used once.
+    /// A client for a recipient without a Vault token of its own can be created with the wrapping
+    /// token.
+    pub fn unwrap(&self, wrapping_token: &str) -> Result<Response, crate::Error> {
+        info!("Unwrapping Vault response");
+        let response: Response = Self::execute_request(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_unwrap_request(wrapping_token),
+        )?;
+        match response {
+            Response::Error { errors } => {
+                Err(crate::Error::InvalidVaultResponse(errors.join("; ")))
+            }
+            response => Ok(response),
+        }
+    }
+
+    /// Unwrap a Nomad token wrapped by `get_nomad_token`, along with its lease
+    pub fn unwrap_nomad_token(&self, wrapping_token: &str) -> Result<NomadToken, crate::Error> {
+        Self::nomad_token(self.unwrap(wrapping_token)?)
+    }
+
+    fn build_unwrap_request(&self, wrapping_token: &str) -> Result<reqwest::Request, crate::Error> {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address = vault_address.join("/v1/sys/wrapping/unwrap")?;
+
+        let request = self.client.post(vault_address).vault_token(wrapping_token);
+        Ok(self.add_request_defaults(request).build()?)
+    }
+
+    /// Extract the response wrapping information from a wrapped response
+    fn wrap_info(response: Response) -> Result<WrapInfo, crate::Error> {
+        match response {
+            Response::Error { errors } => {
+                Err(crate::Error::InvalidVaultResponse(errors.join("; ")))
+            }
+            Response::Response(ResponseData {
+                wrap_info: Some(wrap_info),
+                ..
+            }) => Ok(wrap_info),
+            _ => Err(crate::Error::InvalidVaultResponse(
+                "Missing response wrapping information".to_string(),
+            )),
+        }
+    }
+
+    /// Extract the Nomad token from a Nomad Secrets engine response
+    fn nomad_token(response: Response) -> Result<NomadToken, crate::Error> {
+        match response {
+            Response::Error { errors } => {
+                Err(crate::Error::InvalidVaultResponse(errors.join("; ")))
+            }
+            Response::Response(ResponseData {
+                data: Some(mut data),
+                lease_id,
+                lease_duration,
+                renewable,
+                ..
+            }) => {
+                let secret_id = data.remove("secret_id").ok_or_else(|| {
+                    crate::Error::InvalidVaultResponse(
+                        "Missing Nomad token from response".to_string(),
+                    )
+                })?;
+                Ok(NomadToken {
+                    secret_id: From::from(secret_id),
+                    accessor_id: data.remove("accessor_id").unwrap_or_default(),
+                    lease_id,
+                    lease_duration,
+                    renewable,
+                })
+            }
+            _ => Err(crate::Error::InvalidVaultResponse(
+                "Missing secrets data".to_string(),
+            )),
+        }
+    }
+
+    /// Revoke a secret lease
+    ///
+    /// If successful, the secret can no longer be used
+    pub fn revoke_lease(&self, lease_id: &str) -> Result<(), crate::Error> {
+        info!("Revoking Vault lease {}", lease_id);
+
+        // HTTP 204 is returned
+        Self::execute_request_no_body(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_revoke_lease_request(lease_id),
+        )?;
+        Ok(())
+    }
+
+    fn build_revoke_lease_request(&self, lease_id: &str) -> Result<reqwest::Request, crate::Error> {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address = vault_address.join("/v1/sys/leases/revoke")?;
+
+        let mut payload = HashMap::new();
+        payload.insert("lease_id", lease_id);
+
+        let request = self
+            .client
+            .put(vault_address)
+            .vault_token(self.token.expose_secret())
+            .json(&payload);
+        Ok(self.add_request_defaults(request).build()?)
+    }
+
+    /// Resolve the mount that a path belongs to
+    ///
+    /// Uses the `sys/internal/ui/mounts/:path` endpoint, which is available to any token with
+    /// access to the path.
+    pub fn mount_info(&self, path: &str) -> Result<MountInfo, crate::Error> {
+        debug!("Resolving Vault mount for path `{}`", path);
+        let response = Self::execute_request(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_mount_info_request(path),
+        );
+        let response = match response {
+            // Vault responds with `400 Bad Request` for paths outside of any mount
+            Err(crate::Error::VaultApi { errors, .. }) if is_no_matching_mount(&errors) => {
+                MountResponse::Error { errors }
+            }
+            response => response?,
+        };
+        Self::mount(path, response)
+    }
+
+    /// Extract the mount details from a mount response
+    fn mount(path: &str, response: MountResponse) -> Result<MountInfo, crate::Error> {
+        match response {
+            MountResponse::Error { ref errors } if is_no_matching_mount(errors) => {
+                Err(crate::Error::VaultMountNotFound {
+                    path: path.to_string(),
+                })
+            }
+            MountResponse::Error { errors } => {
+                Err(crate::Error::InvalidVaultResponse(errors.join("; ")))
+            }
+            MountResponse::Mount { data } => Ok(data),
+        }
+    }
+
+    /// Check that the mount at `path` is of the expected type
+    ///
+    /// Failing to resolve the mount for other reasons, such as the token lacking permissions, is
+    /// only logged. The subsequent request to the mount will surface those errors.
+    fn ensure_mount_type(&self, path: &str, expected: &str) -> Result<(), crate::Error> {
+        Self::check_resolved_mount_type(path, self.mount_info(path), expected)
+    }
+
+    fn check_resolved_mount_type(
+        path: &str,
+        mount: Result<MountInfo, crate::Error>,
+        expected: &str,
+    ) -> Result<(), crate::Error> {
+        let mount = match mount {
+            Ok(mount) => mount,
+            Err(e @ crate::Error::VaultMountNotFound { .. }) => Err(e)?,
+            Err(e) => {
+                warn!("Unable to resolve Vault mount for path `{}`: {}", path, e);
+                return Ok(());
+            }
+        };
+        Self::check_mount_type(&mount, expected)
+    }
+
+    fn check_mount_type(mount: &MountInfo, expected: &str) -> Result<(), crate::Error> {
+        if mount.mount_type == expected {
+            Ok(())
+        } else {
+            Err(crate::Error::UnexpectedVaultMountType {
+                path: mount.path.clone(),
+                actual: mount.mount_type.clone(),
+                expected: expected.to_string(),
+            })
+        }
+    }
+
+    fn build_mount_info_request(&self, path: &str) -> Result<reqwest::Request, crate::Error> {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address = vault_address.join(&format!("/v1/sys/internal/ui/mounts/{}", path))?;
+
+        let request = self
+            .client
+            .get(vault_address)
+            .vault_token(self.token.expose_secret());
+        Ok(self.add_request_defaults(request).build()?)
+    }
+
+    /// Renew the Vault token itself
+    ///
+    /// `increment` requests a new TTL for the token, which Vault may cap. Without it, the token is
+    /// renewed with its default TTL.
+    pub fn renew_self(&self, increment: Option<Duration>) -> Result<Authentication, crate::Error> {
+        info!("Renewing self Vault Token");
+
+        let response: Response = Self::execute_request(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_renew_self_request(increment),
+        )?;
+        Self::authentication(response)
+    }
+
+    fn build_renew_self_request(
+        &self,
+        increment: Option<Duration>,
+    ) -> Result<reqwest::Request, crate::Error> {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address = vault_address.join("/v1/auth/token/renew-self")?;
+
+        let mut payload = HashMap::new();
+        if let Some(increment) = increment {
+            payload.insert("increment", format!("{}s", increment.as_secs()));
+        }
+
+        let request = self
+            .client
+            .post(vault_address)
+            .vault_token(self.token.expose_secret())
+            .json(&payload);
+        Ok(self.add_request_defaults(request).build()?)
+    }
+
+    /// Look up the remaining lifetime of the Vault token itself
+    ///
+    /// Use this to decide whether the token has to be renewed, or a new one obtained, before a
+    /// long running operation.
+    pub fn token_ttl(&self) -> Result<TokenTtl, crate::Error> {
+        debug!("Looking up self Vault Token");
+
+        let response: TokenLookupResponse = Self::execute_request(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_lookup_self_request(),
+        )?;
+        Ok(response.data)
+    }
+
+    fn build_lookup_self_request(&self) -> Result<reqwest::Request, crate::Error> {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address = vault_address.join("/v1/auth/token/lookup-self")?;
+
+        let request = self
+            .client
+            .get(vault_address)
+            .vault_token(self.token.expose_secret());
+        Ok(self.add_request_defaults(request).build()?)
+    }
+
+    /// Revoke the Vault token itself
+    ///
+    /// If successful, the Vault Token can no longer be used
+    pub fn revoke_self(&self) -> Result<(), crate::Error> {
+        info!("Revoking self Vault Token");
+
+        // HTTP 204 is returned
+        Self::execute_request_no_body(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_revoke_self_request(),
+        )?;
+        Ok(())
+    }
+
+    fn build_revoke_self_request(&self) -> Result<reqwest::Request, crate::Error> {
         let vault_address = url::Url::parse(self.address())?;
         let vault_address = vault_address.join("/v1/auth/token/revoke-self")?;
 
-        Ok(self
+        let request = self
             .client
             .post(vault_address)
-            .header("X-Vault-Token", self.token.as_str())
-            .build()?)
+            .vault_token(self.token.expose_secret());
+        Ok(self.add_request_defaults(request).build()?)
+    }
+
+    /// Get an endpoint of the Vault API that the client does not model
+    ///
+    /// `path` is relative to `/v1/`, e.g. `secret/data/nomad`, and `query` is serialized as its
+    /// query string. The request is made with the token, MFA credentials and namespace, and is
+    /// retried and redirected to the active server like every other request of the client.
+    /// Responses without a body deserialize like `null`, so use `()` or an `Option` for them.
+    ///
+    /// ```rust,no_run
+    /// # fn main() -> Result<(), nomad_drain::Error> {
+    /// let client = nomad_drain::vault::Client::builder("https://vault.service.consul:8200")
+    ///     .with_token("token")
+    ///     .build()?;
+    /// let secret: serde_json::Value = client.get("secret/data/nomad", &[("version", "2")])?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get<T, Q>(&self, path: &str, query: &Q) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Debug,
+        Q: Serialize + ?Sized,
+    {
+        info!("Requesting Vault {}", path);
+        Self::execute_request(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_get_request(path, query),
+        )
+    }
+
+    fn build_get_request<Q>(&self, path: &str, query: &Q) -> Result<reqwest::Request, crate::Error>
+    where
+        Q: Serialize + ?Sized,
+    {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address = vault_address.join(&format!("/v1/{}", path.trim_start_matches('/')))?;
+
+        let request = self
+            .client
+            .get(vault_address)
+            .query(query)
+            .vault_token(self.token.expose_secret());
+        Ok(self.add_request_defaults(request).build()?)
+    }
+
+    /// Post `body` as JSON to an endpoint of the Vault API that the client does not model
+    ///
+    /// `path` is relative to `/v1/`, like with `get`.
+    pub fn post<T, B>(&self, path: &str, body: &B) -> Result<T, crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Debug,
+        B: Serialize + ?Sized,
+    {
+        info!("Posting to Vault {}", path);
+        Self::execute_request(
+            &self.client,
+            &self.retry_policy,
+            &*self.clock,
+            &self.context,
+            || self.build_post_request(path, body),
+        )
+    }
+
+    fn build_post_request<B>(&self, path: &str, body: &B) -> Result<reqwest::Request, crate::Error>
+    where
+        B: Serialize + ?Sized,
+    {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address = vault_address.join(&format!("/v1/{}", path.trim_start_matches('/')))?;
+
+        let request = self
+            .client
+            .post(vault_address)
+            .vault_token(self.token.expose_secret())
+            .json(body);
+        Ok(self.add_request_defaults(request).build()?)
     }
 
     fn build_nomad_token_request(
         &self,
         nomad_path: &str,
         nomad_role: &str,
+        wrap_ttl: Option<Duration>,
     ) -> Result<reqwest::Request, crate::Error> {
         let vault_address = url::Url::parse(self.address())?;
         let vault_address =
             vault_address.join(&format!("/v1/{}/creds/{}", nomad_path, nomad_role))?;
 
+        let request = self
+            .client
+            .get(vault_address)
+            .vault_token(self.token.expose_secret());
+        let request = match wrap_ttl {
+            Some(ttl) => request.vault_wrap_ttl(ttl),
+            None => request,
+        };
+        Ok(self.add_request_defaults(request).build()?)
+    }
+}
+
+impl AsyncClient {
+    /// Create a new asynchronous API client from an existing Token
+    ///
+    /// You can optionally provide a `reqwest::async::Client` if you have specific needs like
+    /// custom root CA certificate or require client authentication
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<S1, S2>(
+        vault_address: S1,
+        vault_token: S2,
+        client: Option<AsyncHttpClient>,
+    ) -> Result<Self, crate::Error>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let client = match client {
+            Some(client) => client,
+            None => AsyncClientBuilder::new().build()?,
+        };
+
+        Ok(Self {
+            address: vault_address.as_ref().to_string(),
+            token: crate::Secret(vault_token.as_ref().to_string()),
+            client,
+        })
+    }
+
+    /// Returns the Vault Token
+    pub fn token(&self) -> &str {
+        self.token.expose_secret()
+    }
+
+    /// Returns the Vault address
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the HTTP Client
+    pub fn http_client(&self) -> &AsyncHttpClient {
+        &self.client
+    }
+
+    fn execute_request<T>(
+        client: &AsyncHttpClient,
+        request: Result<reqwest::r#async::Request, crate::Error>,
+    ) -> impl Future<Item = T, Error = crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Debug,
+    {
+        let client = client.clone();
+        future::result(request)
+            .and_then(move |request| crate::execute_async(&client, request))
+            .and_then(|(status, _, body)| {
+                check_status(status, &body)?;
+                let result = crate::from_json_str(&body)?;
+                debug!("Deserialized body: {:#?}", result);
+                Ok(result)
+            })
+    }
+
+    /// Login with AWS IAM authentication method. Resolves to a client with the Vault token on
+    /// success
+    ///
+    /// See `AwsIam`
+    pub fn login_aws_iam(
+        vault_address: &str,
+        aws_auth_path: &str,
+        aws_auth_role: &str,
+        aws_payload: &crate::aws::VaultAwsAuthIamPayload,
+        client: Option<AsyncHttpClient>,
+    ) -> impl Future<Item = Self, Error = crate::Error> {
+        info!(
+            "Logging in to Vault with AWS Credentials at path `{}` and role `{}",
+            aws_auth_path, aws_auth_role
+        );
+        let vault_address = vault_address.to_string();
+        let prepared = match client {
+            Some(client) => Ok(client),
+            None => AsyncClientBuilder::new().build().map_err(From::from),
+        }
+        .and_then(|client| {
+            let request = Self::build_login_aws_iam_request(
+                &vault_address,
+                aws_auth_path,
+                aws_auth_role,
+                aws_payload,
+                &client,
+            )?;
+            Ok((client, request))
+        });
+
+        future::result(prepared).and_then(move |(client, request)| {
+            Self::execute_request(&client, Ok(request))
+                .and_then(Client::authentication)
+                .map(move |authentication| {
+                    info!("Vault authentication successful. Received Vault Token");
+                    Self {
+                        address: vault_address,
+                        token: authentication.client_token,
+                        client,
+                    }
+                })
+        })
+    }
+
+    fn build_login_aws_iam_request(
+        vault_address: &str,
+        aws_auth_path: &str,
+        aws_auth_role: &str,
+        aws_payload: &crate::aws::VaultAwsAuthIamPayload,
+        client: &AsyncHttpClient,
+    ) -> Result<reqwest::r#async::Request, crate::Error> {
+        let vault_address = url::Url::parse(vault_address)?;
+        let vault_address = vault_address.join(&format!("/v1/auth/{}/login", aws_auth_path))?;
+        let payload = AwsIamLoginPayload {
+            role: aws_auth_role,
+            aws_payload: Cow::Borrowed(aws_payload),
+        };
+        Ok(client.post(vault_address).json(&payload).build()?)
+    }
+
+    /// Get a token from Nomad Secrets engine
+    ///
+    /// See `Client::get_nomad_token`
+    pub fn get_nomad_token(
+        &self,
+        nomad_path: &str,
+        nomad_role: &str,
+    ) -> impl Future<Item = crate::Secret, Error = crate::Error> {
+        info!(
+            "Retrieving Nomad Token from Secrets engine mounted at `{}` with role `{}`",
+            nomad_path, nomad_role
+        );
+        let path = nomad_path.to_string();
+        let request = self.build_nomad_token_request(nomad_path, nomad_role);
+        let client = self.client.clone();
+
+        self.mount_info(nomad_path)
+            .then(move |mount| Client::check_resolved_mount_type(&path, mount, "nomad"))
+            .and_then(move |()| Self::execute_request(&client, request))
+            .and_then(Client::nomad_token)
+            .map(|token| token.secret_id)
+    }
+
+    fn build_nomad_token_request(
+        &self,
+        nomad_path: &str,
+        nomad_role: &str,
+    ) -> Result<reqwest::r#async::Request, crate::Error> {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address =
+            vault_address.join(&format!("/v1/{}/creds/{}", nomad_path, nomad_role))?;
+
+        Ok(self
+            .client
+            .get(vault_address)
+            .vault_token(self.token.expose_secret())
+            .build()?)
+    }
+
+    /// Resolve the mount that a path belongs to
+    ///
+    /// See `Client::mount_info`
+    pub fn mount_info(&self, path: &str) -> impl Future<Item = MountInfo, Error = crate::Error> {
+        debug!("Resolving Vault mount for path `{}`", path);
+        let request = self.build_mount_info_request(path);
+        let path = path.to_string();
+        Self::execute_request(&self.client, request)
+            .and_then(move |response| Client::mount(&path, response))
+    }
+
+    fn build_mount_info_request(
+        &self,
+        path: &str,
+    ) -> Result<reqwest::r#async::Request, crate::Error> {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address = vault_address.join(&format!("/v1/sys/internal/ui/mounts/{}", path))?;
+
         Ok(self
             .client
             .get(vault_address)
-            .header("X-Vault-Token", self.token.as_str())
+            .vault_token(self.token.expose_secret())
+            .build()?)
+    }
+
+    /// Revoke the Vault token itself
+    ///
+    /// If successful, the Vault Token can no longer be used
+    pub fn revoke_self(&self) -> impl Future<Item = (), Error = crate::Error> {
+        info!("Revoking self Vault Token");
+        let request = self.build_revoke_self_request();
+        let client = self.client.clone();
+        // HTTP 204 is returned
+        future::result(request)
+            .and_then(move |request| crate::execute_async(&client, request))
+            .and_then(|(status, _, body)| check_status(status, &body))
+    }
+
+    fn build_revoke_self_request(&self) -> Result<reqwest::r#async::Request, crate::Error> {
+        let vault_address = url::Url::parse(self.address())?;
+        let vault_address = vault_address.join("/v1/auth/token/revoke-self")?;
+
+        Ok(self
+            .client
+            .post(vault_address)
+            .vault_token(self.token.expose_secret())
             .build()?)
     }
 }
 
+/// Add a `X-Vault-MFA` header for each set of MFA credentials
+fn add_mfa_headers(
+    mut request_builder: reqwest::RequestBuilder,
+    mfa: &[MfaCredentials],
+) -> reqwest::RequestBuilder {
+    for credentials in mfa {
+        request_builder = request_builder.header(VAULT_MFA_HEADER, credentials.header_value());
+    }
+    request_builder
+}
+
+/// Fail with `Error::VaultApi` if Vault responded with an error status
+///
+/// Vault returns a JSON body with a list of errors. Bodies in any other form, such as those of
+/// proxies in front of Vault, are returned as the only error.
+fn check_status(status: StatusCode, body: &str) -> Result<(), crate::Error> {
+    if status.is_success() {
+        return Ok(());
+    }
+    warn!("Vault responded with HTTP {}: {}", status, body);
+    let errors = match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(response) => response.errors,
+        Err(_) if body.trim().is_empty() => vec![],
+        Err(_) => vec![body.trim().to_string()],
+    };
+    Err(crate::Error::VaultApi {
+        status: status.as_u16(),
+        errors,
+    })
+}
+
+/// Whether Vault could not find a mount for a path
+fn is_no_matching_mount(errors: &[String]) -> bool {
+    errors.iter().any(|e| e.contains("no matching mount"))
+}
+
+/// Whether the error is Vault failing to find an IAM principal that might not have propagated yet
+fn is_iam_principal_not_found(error: &crate::Error) -> bool {
+    let message = match error {
+        crate::Error::InvalidVaultResponse(message) => message.to_lowercase(),
+        crate::Error::VaultApi { errors, .. } => errors.join("; ").to_lowercase(),
+        _ => return false,
+    };
+    IAM_PRINCIPAL_NOT_FOUND_ERRORS
+        .iter()
+        .any(|fragment| message.contains(fragment))
+}
+
 impl Drop for Client {
     fn drop(&mut self) {
         if self.revoke_self_on_drop {
@@ -320,12 +1564,33 @@ impl Drop for Client {
     }
 }
 
+/// The request sent to the location it was redirected to instead
+fn redirected(mut request: reqwest::Request, location: &url::Url) -> reqwest::Request {
+    *request.url_mut() = location.clone();
+    request
+}
+
+/// Location to follow a `307` or `308` redirect to, if the response is one
+fn redirect_location(response: &reqwest::Response) -> Result<Option<url::Url>, crate::Error> {
+    match response.status() {
+        StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT => {
+            match response.headers().get(LOCATION) {
+                Some(location) => Ok(Some(response.url().join(location.to_str()?)?)),
+                None => Ok(None),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
 
     use std::env;
 
+    use crate::headers::{VAULT_TOKEN_HEADER, VAULT_WRAP_TTL_HEADER};
+
     pub(crate) fn vault_address() -> String {
         env::var("VAULT_ADDR").unwrap_or_else(|_| "http://127.0.0.1:8200".to_string())
     }
@@ -339,7 +1604,8 @@ pub(crate) mod tests {
             "aws",
             "default",
             &aws_payload,
-            &ClientBuilder::new().build()?,
+            &[],
+            &HttpClient::new(),
         )?;
 
         assert_eq!(
@@ -353,6 +1619,70 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    #[test]
+    fn redirects_to_the_active_server_are_followed() -> Result<(), crate::Error> {
+        let redirect = |status, location: &str| -> reqwest::Response {
+            http::Response::builder()
+                .status(status)
+                .header("Location", location)
+                .body("")
+                .unwrap()
+                .into()
+        };
+
+        let location = redirect(307, "https://active.vault:8200/v1/auth/token/renew-self");
+        assert_eq!(
+            Some("https://active.vault:8200/v1/auth/token/renew-self"),
+            redirect_location(&location)?.as_ref().map(url::Url::as_str)
+        );
+        let location = redirect(308, "/v1/sys/health");
+        assert_eq!(
+            Some("/v1/sys/health"),
+            redirect_location(&location)?.as_ref().map(url::Url::path)
+        );
+        assert!(redirect_location(&redirect(302, "/v1/sys/health"))?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn redirected_requests_keep_the_token_and_body() -> Result<(), crate::Error> {
+        let client = Client::http_client_builder().build()?;
+        let request = client
+            .post("https://standby.vault:8200/v1/auth/token/renew-self")
+            .vault_token("token")
+            .json(&serde_json::json!({ "increment": "1h" }))
+            .build()?;
+        let location = url::Url::parse("https://active.vault:8200/v1/auth/token/renew-self")?;
+        let request = redirected(request, &location);
+
+        assert_eq!(location, *request.url());
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!("token", request.headers()[VAULT_TOKEN_HEADER]);
+        assert!(request.body().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn health_request_tolerates_sealed_servers() -> Result<(), crate::Error> {
+        let address = vault_address();
+        let request = Client::build_health_request(&address, &HttpClient::new())?;
+
+        assert_eq!(
+            format!(
+                "{}/v1/sys/health?standbyok=true&perfstandbyok=true&sealedcode=200&uninitcode=200",
+                address
+            ),
+            request.url().to_string()
+        );
+
+        let health: Health = serde_json::from_str(
+            r#"{"initialized":true,"sealed":true,"standby":true,"server_time_utc":1516639589}"#,
+        )?;
+        assert!(health.sealed);
+        assert!(health.version.is_empty());
+        Ok(())
+    }
+
     /// Requires Mock AWS API and Vault server
     /// This test does not verify if the signature from rusoto is correct.
     #[test]
@@ -361,11 +1691,123 @@ pub(crate) mod tests {
         let aws_payload =
             crate::aws::tests::vault_aws_iam_payload(Some("vault.example.com"), None)?;
 
-        let client = Client::login_aws_iam(&address, "aws", "default", &aws_payload, None)?;
+        let client = Client::builder(&address)
+            .with_auth(AwsIam {
+                path: "aws".to_string(),
+                role: "default".to_string(),
+                payload: aws_payload,
+            })
+            .build()?;
         assert!(!client.token().is_empty());
         Ok(())
     }
 
+    #[test]
+    fn clients_are_built_with_a_token_or_login() -> Result<(), crate::Error> {
+        match Client::builder(vault_address()).build() {
+            Err(crate::Error::MissingVaultCredentials) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let client = Client::builder(vault_address())
+            .with_token("vault_token")
+            .with_mfa(vec!["my_duo".parse()?])
+            .with_namespace("ops")
+            .build()?;
+        assert_eq!("vault_token", client.token());
+        assert_eq!(vault_address(), client.address());
+        assert_eq!(1, client.mfa().len());
+        assert_eq!(Some("ops"), client.namespace());
+        assert!(!client.revoke_self_on_drop);
+
+        let request = client.build_lookup_self_request()?;
+        assert_eq!("ops", request.headers()[VAULT_NAMESPACE_HEADER]);
+        Ok(())
+    }
+
+    #[test]
+    fn failures_to_warm_up_do_not_fail_clients() -> Result<(), crate::Error> {
+        let client = Client::builder("http://127.0.0.1:1")
+            .with_timeout(Duration::from_secs(1))
+            .warm_up()?
+            .with_token("vault_token")
+            .build()?;
+        assert_eq!("vault_token", client.token());
+        Ok(())
+    }
+
+    #[test]
+    fn logins_are_retried_on_the_clock_while_the_iam_principal_is_not_found() {
+        #[derive(Debug)]
+        struct NotFound(Arc<Mutex<u32>>);
+
+        impl AuthMethod for NotFound {
+            fn login(
+                &self,
+                _: &HttpClient,
+                _: &str,
+                _: &[MfaCredentials],
+                _: &RequestContext,
+                _: &dyn Clock,
+            ) -> Result<Authentication, crate::Error> {
+                *self.0.lock().unwrap() += 1;
+                Err(crate::Error::InvalidVaultResponse(
+                    "NoSuchEntity: The role with name test cannot be found".to_string(),
+                ))
+            }
+        }
+
+        let clock = Arc::new(crate::clock::SimulatedClock::new());
+        let attempts = Arc::new(Mutex::new(0));
+        let result = Client::builder(vault_address())
+            .with_auth(NotFound(attempts.clone()))
+            .with_login_retry_window(Duration::from_secs(60))
+            .with_clock(clock.clone())
+            .build();
+        assert!(result.is_err());
+        // Retried after 1, 2, 4, 8, 16 and 16 seconds, until the next retry would exceed the window
+        assert_eq!(7, *attempts.lock().unwrap());
+        assert_eq!(Duration::from_secs(47), clock.simulated());
+    }
+
+    #[test]
+    fn iam_principal_not_found_errors_are_detected() {
+        let error = crate::Error::InvalidVaultResponse(
+            "error making upstream request: NoSuchEntity: The role with name test cannot be found"
+                .to_string(),
+        );
+        assert!(is_iam_principal_not_found(&error));
+
+        let error = crate::Error::InvalidVaultResponse("permission denied".to_string());
+        assert!(!is_iam_principal_not_found(&error));
+
+        let error = crate::Error::VaultApi {
+            status: 400,
+            errors: vec!["NoSuchEntity: The user with name test cannot be found".to_string()],
+        };
+        assert!(is_iam_principal_not_found(&error));
+    }
+
+    #[test]
+    fn error_statuses_carry_vault_errors() {
+        assert!(check_status(StatusCode::NO_CONTENT, "").is_ok());
+
+        match check_status(StatusCode::FORBIDDEN, r#"{"errors":["permission denied"]}"#) {
+            Err(crate::Error::VaultApi { status, errors }) => {
+                assert_eq!(403, status);
+                assert_eq!(vec!["permission denied".to_string()], errors);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match check_status(StatusCode::BAD_GATEWAY, "upstream unavailable\n") {
+            Err(crate::Error::VaultApi { status, errors }) => {
+                assert_eq!(502, status);
+                assert_eq!(vec!["upstream unavailable".to_string()], errors);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
     #[test]
     fn nomad_token_secrets_engine_payload_can_be_deserialized() {
         // Example payload from Nomad Secrets Engine
@@ -389,20 +1831,289 @@ pub(crate) mod tests {
         };
         let nomad = data.unwrap();
         assert_eq!(nomad["secret_id"], "secret");
+
+        let token = Client::nomad_token(serde_json::from_str(json).unwrap()).unwrap();
+        assert_eq!("secret", token.secret_id.expose_secret());
+        assert_eq!("accessor", token.accessor_id);
+        assert_eq!("nomad/creds/default/xxx", token.lease_id);
+        assert_eq!(2_764_800, token.lease_duration);
+        assert!(token.renewable);
     }
 
     #[test]
-    fn nomad_token_request_is_built_properly() -> Result<(), crate::Error> {
-        let client = Client::new(vault_address(), "vault_token", false, None)?;
+    fn mount_info_payload_can_be_deserialized() {
+        let json = r#"
+{
+  "request_id": "xxx",
+  "lease_id": "",
+  "renewable": false,
+  "lease_duration": 0,
+  "data": {
+    "accessor": "kv_1234",
+    "config": {
+      "default_lease_ttl": 0,
+      "force_no_cache": false,
+      "max_lease_ttl": 0
+    },
+    "description": "key/value secret storage",
+    "local": false,
+    "options": null,
+    "path": "nomad/",
+    "seal_wrap": false,
+    "type": "kv"
+  },
+  "wrap_info": null,
+  "warnings": null,
+  "auth": null
+}
+"#;
+        let mount = match serde_json::from_str::<MountResponse>(json).unwrap() {
+            MountResponse::Mount { data } => data,
+            _ => panic!("Invalid deserialization"),
+        };
+        assert_eq!("nomad/", mount.path);
+
+        match Client::check_mount_type(&mount, "nomad") {
+            Err(crate::Error::UnexpectedVaultMountType {
+                actual, expected, ..
+            }) => {
+                assert_eq!("kv", actual);
+                assert_eq!("nomad", expected);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mount_info_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = Client::builder(vault_address())
+            .with_token("vault_token")
+            .build()?;
+        let request = client.build_mount_info_request("nomad")?;
+
+        assert_eq!(
+            format!("{}/v1/sys/internal/ui/mounts/nomad", vault_address()),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+
+        Ok(())
+    }
+
+    #[test]
+    fn revoke_lease_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = Client::builder(vault_address())
+            .with_token("vault_token")
+            .build()?;
+        let request = client.build_revoke_lease_request("nomad/creds/default/xxx")?;
+
+        assert_eq!(
+            format!("{}/v1/sys/leases/revoke", vault_address()),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::PUT, request.method());
+        assert_eq!("vault_token", request.headers()[VAULT_TOKEN_HEADER]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrapped_nomad_token_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = Client::builder(vault_address())
+            .with_token("vault_token")
+            .build()?;
+        let request = client.build_nomad_token_request("nomad", "default", None)?;
+        assert!(!request.headers().contains_key(VAULT_WRAP_TTL_HEADER));
+
+        let request =
+            client.build_nomad_token_request("nomad", "default", Some(Duration::from_secs(300)))?;
+        assert_eq!(
+            format!("{}/v1/nomad/creds/default", vault_address()),
+            request.url().to_string()
+        );
+        assert_eq!("300s", request.headers()[VAULT_WRAP_TTL_HEADER]);
+
+        let request = client.build_unwrap_request("wrapping_token")?;
+        assert_eq!(
+            format!("{}/v1/sys/wrapping/unwrap", vault_address()),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!("wrapping_token", request.headers()[VAULT_TOKEN_HEADER]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrapped_response_can_be_deserialized() -> Result<(), crate::Error> {
+        let json = r#"
+{
+  "request_id": "",
+  "lease_id": "",
+  "renewable": false,
+  "lease_duration": 0,
+  "data": null,
+  "wrap_info": {
+    "token": "wrapping_token",
+    "accessor": "wrapping_accessor",
+    "ttl": 300,
+    "creation_time": "2019-01-01T00:00:00.000000000Z",
+    "creation_path": "nomad/creds/default"
+  },
+  "warnings": null,
+  "auth": null
+}
+"#;
+        let wrap_info = Client::wrap_info(serde_json::from_str(json)?)?;
+        assert_eq!("wrapping_token", wrap_info.token.expose_secret());
+        assert_eq!(300, wrap_info.ttl);
+        assert_eq!("nomad/creds/default", wrap_info.creation_path);
+        Ok(())
+    }
+
+    #[test]
+    fn renew_self_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = Client::builder(vault_address())
+            .with_token("vault_token")
+            .build()?;
+        let request = client.build_renew_self_request(Some(Duration::from_secs(3600)))?;
+
+        assert_eq!(
+            format!("{}/v1/auth/token/renew-self", vault_address()),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!("vault_token", request.headers()[VAULT_TOKEN_HEADER]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn requests_to_unmodeled_endpoints_are_built_properly() -> Result<(), crate::Error> {
+        let client = Client::builder(vault_address())
+            .with_token("vault_token")
+            .build()?;
+        let request = client.build_get_request("/secret/data/nomad", &[("version", "2")])?;
+        assert_eq!(
+            format!("{}/v1/secret/data/nomad?version=2", vault_address()),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+        assert_eq!("vault_token", request.headers()[VAULT_TOKEN_HEADER]);
+
+        let request =
+            client.build_post_request("sys/policy/nomad", &serde_json::json!({ "policy": "" }))?;
+        assert_eq!(
+            format!("{}/v1/sys/policy/nomad", vault_address()),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!("vault_token", request.headers()[VAULT_TOKEN_HEADER]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_ttl_is_looked_up() -> Result<(), crate::Error> {
+        let client = Client::builder(vault_address())
+            .with_token("vault_token")
+            .build()?;
+        let request = client.build_lookup_self_request()?;
+        assert_eq!(
+            format!("{}/v1/auth/token/lookup-self", vault_address()),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+        assert_eq!("vault_token", request.headers()[VAULT_TOKEN_HEADER]);
+
+        let response: TokenLookupResponse = serde_json::from_str(
+            r#"{
+  "data": {
+    "accessor": "8609694a-cdbc-db9b-d345-e782dbb562ed",
+    "creation_ttl": 2764800,
+    "display_name": "aws-iam",
+    "explicit_max_ttl": 0,
+    "renewable": true,
+    "ttl": 600
+  }
+}"#,
+        )?;
+        let ttl = response.data;
+        assert!(ttl.renewable);
+        assert_eq!(Some(Duration::from_secs(600)), ttl.remaining());
+        assert!(ttl.outlives(Duration::from_secs(300)));
+        assert!(!ttl.outlives(Duration::from_secs(900)));
+
+        let root = TokenTtl {
+            ttl: 0,
+            renewable: false,
+        };
+        assert!(root.outlives(Duration::from_secs(86400)));
+        Ok(())
+    }
+
+    #[test]
+    fn mfa_credentials_are_parsed() -> Result<(), crate::Error> {
+        let totp: MfaCredentials = "my_totp:123456".parse()?;
+        assert_eq!("my_totp", totp.method);
+        assert_eq!("my_totp:123456", totp.header_value());
+
+        let push: MfaCredentials = "my_duo".parse()?;
+        assert_eq!(None, push.passcode);
+        assert_eq!("my_duo", push.header_value());
+
+        assert!(":123456".parse::<MfaCredentials>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mfa_headers_are_added_to_requests() -> Result<(), crate::Error> {
+        let client = Client::builder(vault_address())
+            .with_token("vault_token")
+            .build()?
+            .with_mfa(vec!["my_totp:123456".parse()?, "my_duo".parse()?]);
+        let request = client.build_nomad_token_request("nomad", "default", None)?;
+
+        let headers: Vec<_> = request
+            .headers()
+            .get_all(VAULT_MFA_HEADER)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        assert_eq!(vec!["my_totp:123456", "my_duo"], headers);
+
+        Ok(())
+    }
+
+    #[test]
+    fn async_nomad_token_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = AsyncClient::new(vault_address(), "vault_token", None)?;
         let request = client.build_nomad_token_request("nomad", "default")?;
 
+        assert_eq!(
+            format!("{}/v1/nomad/creds/default", vault_address()),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::GET, request.method());
+        assert_eq!("vault_token", request.headers()[VAULT_TOKEN_HEADER]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn nomad_token_request_is_built_properly() -> Result<(), crate::Error> {
+        let client = Client::builder(vault_address())
+            .with_token("vault_token")
+            .build()?;
+        let request = client.build_nomad_token_request("nomad", "default", None)?;
+
         assert_eq!(
             format!("{}/v1/nomad/creds/default", vault_address()),
             request.url().to_string()
         );
         assert_eq!(&reqwest::Method::GET, request.method());
 
-        let actual_token = request.headers().get("X-Vault-Token");
+        let actual_token = request.headers().get(VAULT_TOKEN_HEADER);
         assert!(actual_token.is_some());
         assert_eq!("vault_token", actual_token.unwrap());
 
@@ -0,0 +1,455 @@
+//! Methods to login to Vault with
+//!
+//! Every method implements `AuthMethod`, so that `ClientBuilder::with_auth` can build a client
+//! with any of them.
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+
+use super::{add_mfa_headers, Authentication, Client, MfaCredentials, TokenType};
+use crate::clock::Clock;
+use crate::context::RequestContext;
+use crate::headers::RequestBuilderExt;
+use crate::retry::RetryPolicy;
+use crate::SerializableSecret;
+
+/// Path to the service account token mounted into Kubernetes pods
+const KUBERNETES_SERVICE_ACCOUNT_TOKEN: &str =
+    "/var/run/secrets/kubernetes.io/serviceaccount/token";
+
+/// A method to login to Vault with
+pub trait AuthMethod: Debug {
+    /// Login to the Vault server at `vault_address`, sending the MFA credentials, if any
+    ///
+    /// Requests are made in `context`, whose deadline is checked against `clock`.
+    fn login(
+        &self,
+        client: &HttpClient,
+        vault_address: &str,
+        mfa: &[MfaCredentials],
+        context: &RequestContext,
+        clock: &dyn Clock,
+    ) -> Result<Authentication, crate::Error>;
+
+    /// Whether the token is created by the login, and should be revoked when the client is dropped
+    fn owns_token(&self) -> bool {
+        true
+    }
+}
+
+/// AWS IAM authentication
+#[derive(Clone, Debug)]
+pub struct AwsIam {
+    /// Path the AWS authentication method is mounted at, usually `aws`
+    pub path: String,
+    pub role: String,
+    /// Payload from calling `aws::VaultAwsAuthIamPayload::new`
+    pub payload: crate::aws::VaultAwsAuthIamPayload,
+}
+
+impl AuthMethod for AwsIam {
+    fn login(
+        &self,
+        client: &HttpClient,
+        vault_address: &str,
+        mfa: &[MfaCredentials],
+        context: &RequestContext,
+        clock: &dyn Clock,
+    ) -> Result<Authentication, crate::Error> {
+        let response =
+            Client::execute_request(client, &RetryPolicy::default(), clock, context, || {
+                Client::build_login_aws_iam_request(
+                    vault_address,
+                    &self.path,
+                    &self.role,
+                    &self.payload,
+                    mfa,
+                    client,
+                )
+            })?;
+        Client::authentication(response)
+    }
+}
+
+/// AWS EC2 authentication, with the signed identity document of the instance
+#[derive(Clone, Debug)]
+pub struct AwsEc2 {
+    /// Path the AWS authentication method is mounted at, usually `aws`
+    pub path: String,
+    pub role: String,
+    /// PKCS #7 signature of the instance identity document, without newlines
+    pub pkcs7: String,
+    /// Nonce to prevent the identity document from being reused by another client
+    pub nonce: Option<crate::Secret>,
+}
+
+/// Login payload of `AwsEc2`
+#[derive(Serialize)]
+struct AwsEc2Payload<'a> {
+    role: &'a str,
+    pkcs7: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<SerializableSecret<'a>>,
+}
+
+impl AwsEc2 {
+    /// Login with the identity document of the instance this is running on
+    pub fn from_instance_identity(
+        path: &str,
+        role: &str,
+        nonce: Option<crate::Secret>,
+    ) -> Result<Self, crate::Error> {
+        Ok(Self {
+            path: path.to_string(),
+            role: role.to_string(),
+            pkcs7: crate::aws::imds::get_instance_identity_pkcs7(None)?,
+            nonce,
+        })
+    }
+}
+
+impl AuthMethod for AwsEc2 {
+    fn login(
+        &self,
+        client: &HttpClient,
+        vault_address: &str,
+        mfa: &[MfaCredentials],
+        context: &RequestContext,
+        clock: &dyn Clock,
+    ) -> Result<Authentication, crate::Error> {
+        login_with_payload(
+            client,
+            vault_address,
+            &self.path,
+            &self.payload(),
+            mfa,
+            context,
+            clock,
+        )
+    }
+}
+
+impl AwsEc2 {
+    fn payload(&self) -> AwsEc2Payload<'_> {
+        AwsEc2Payload {
+            role: &self.role,
+            pkcs7: &self.pkcs7,
+            nonce: self.nonce.as_ref().map(crate::Secret::serializable),
+        }
+    }
+}
+
+/// AppRole authentication
+#[derive(Clone, Debug)]
+pub struct AppRole {
+    /// Path the AppRole authentication method is mounted at, usually `approle`
+    pub path: String,
+    pub role_id: String,
+    /// Secret ID, unless the role does not require one
+    pub secret_id: Option<crate::Secret>,
+}
+
+/// Login payload of `AppRole`
+#[derive(Serialize)]
+struct AppRolePayload<'a> {
+    role_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret_id: Option<SerializableSecret<'a>>,
+}
+
+impl AppRole {
+    fn payload(&self) -> AppRolePayload<'_> {
+        AppRolePayload {
+            role_id: &self.role_id,
+            secret_id: self.secret_id.as_ref().map(crate::Secret::serializable),
+        }
+    }
+}
+
+impl AuthMethod for AppRole {
+    fn login(
+        &self,
+        client: &HttpClient,
+        vault_address: &str,
+        mfa: &[MfaCredentials],
+        context: &RequestContext,
+        clock: &dyn Clock,
+    ) -> Result<Authentication, crate::Error> {
+        login_with_payload(
+            client,
+            vault_address,
+            &self.path,
+            &self.payload(),
+            mfa,
+            context,
+            clock,
+        )
+    }
+}
+
+/// Kubernetes authentication, with a service account token
+#[derive(Clone, Debug)]
+pub struct Kubernetes {
+    /// Path the Kubernetes authentication method is mounted at, usually `kubernetes`
+    pub path: String,
+    pub role: String,
+    pub jwt: crate::Secret,
+}
+
+/// Login payload of `Kubernetes`
+#[derive(Serialize)]
+struct KubernetesPayload<'a> {
+    role: &'a str,
+    jwt: SerializableSecret<'a>,
+}
+
+impl Kubernetes {
+    /// Login with the service account token mounted into the pod this is running in
+    pub fn from_service_account(path: &str, role: &str) -> Result<Self, crate::Error> {
+        let jwt = fs::read_to_string(KUBERNETES_SERVICE_ACCOUNT_TOKEN)?;
+        Ok(Self {
+            path: path.to_string(),
+            role: role.to_string(),
+            jwt: crate::Secret(jwt.trim().to_string()),
+        })
+    }
+
+    fn payload(&self) -> KubernetesPayload<'_> {
+        KubernetesPayload {
+            role: &self.role,
+            jwt: self.jwt.serializable(),
+        }
+    }
+}
+
+impl AuthMethod for Kubernetes {
+    fn login(
+        &self,
+        client: &HttpClient,
+        vault_address: &str,
+        mfa: &[MfaCredentials],
+        context: &RequestContext,
+        clock: &dyn Clock,
+    ) -> Result<Authentication, crate::Error> {
+        login_with_payload(
+            client,
+            vault_address,
+            &self.path,
+            &self.payload(),
+            mfa,
+            context,
+            clock,
+        )
+    }
+}
+
+/// An existing token
+///
+/// The token is looked up to verify that it is valid. It is not revoked when the client is
+/// dropped, since it was not created by the client.
+#[derive(Clone, Debug)]
+pub struct Token(pub crate::Secret);
+
+impl AuthMethod for Token {
+    fn login(
+        &self,
+        client: &HttpClient,
+        vault_address: &str,
+        mfa: &[MfaCredentials],
+        context: &RequestContext,
+        clock: &dyn Clock,
+    ) -> Result<Authentication, crate::Error> {
+        let response: LookupResponse =
+            Client::execute_request(client, &RetryPolicy::default(), clock, context, || {
+                let url = url::Url::parse(vault_address)?.join("/v1/auth/token/lookup-self")?;
+                let request = client.get(url).vault_token(self.0.expose_secret());
+                Ok(add_mfa_headers(request, mfa).build()?)
+            })?;
+        match response {
+            LookupResponse::Error { errors } => {
+                Err(crate::Error::InvalidVaultResponse(errors.join("; ")))
+            }
+            LookupResponse::Lookup { data } => Ok(data.into_authentication(self.0.clone())),
+        }
+    }
+
+    fn owns_token(&self) -> bool {
+        false
+    }
+}
+
+/// Response from the `auth/token/lookup-self` endpoint
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum LookupResponse {
+    Error { errors: Vec<String> },
+    Lookup { data: TokenLookup },
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenLookup {
+    accessor: String,
+    policies: Vec<String>,
+    #[serde(default)]
+    meta: Option<HashMap<String, String>>,
+    ttl: u64,
+    renewable: bool,
+    #[serde(default)]
+    entity_id: String,
+    #[serde(rename = "type")]
+    token_type: TokenType,
+}
+
+impl TokenLookup {
+    fn into_authentication(self, client_token: crate::Secret) -> Authentication {
+        Authentication {
+            client_token,
+            accessor: self.accessor,
+            token_policies: self.policies.clone(),
+            policies: self.policies,
+            metadata: self.meta.unwrap_or_default(),
+            lease_duration: self.ttl,
+            renewable: self.renewable,
+            entity_id: self.entity_id,
+            token_type: self.token_type,
+        }
+    }
+}
+
+/// Login by posting the payload to the `login` endpoint of the method mounted at `path`
+fn login_with_payload<T>(
+    client: &HttpClient,
+    vault_address: &str,
+    path: &str,
+    payload: &T,
+    mfa: &[MfaCredentials],
+    context: &RequestContext,
+    clock: &dyn Clock,
+) -> Result<Authentication, crate::Error>
+where
+    T: Serialize,
+{
+    let response =
+        Client::execute_request(client, &RetryPolicy::default(), clock, context, || {
+            build_login_request(client, vault_address, path, payload, mfa)
+        })?;
+    Client::authentication(response)
+}
+
+fn build_login_request<T>(
+    client: &HttpClient,
+    vault_address: &str,
+    path: &str,
+    payload: &T,
+    mfa: &[MfaCredentials],
+) -> Result<reqwest::Request, crate::Error>
+where
+    T: Serialize,
+{
+    let vault_address = url::Url::parse(vault_address)?;
+    let vault_address = vault_address.join(&format!("/v1/auth/{}/login", path))?;
+    let request = client.post(vault_address).json(payload);
+    Ok(add_mfa_headers(request, mfa).build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::vault::tests::vault_address;
+
+    #[test]
+    fn login_request_is_built_for_the_mount() -> Result<(), crate::Error> {
+        let address = vault_address();
+        let method = AppRole {
+            path: "approle".to_string(),
+            role_id: "role".to_string(),
+            secret_id: None,
+        };
+        let request = build_login_request(
+            &HttpClient::new(),
+            &address,
+            "approle",
+            &method.payload(),
+            &[],
+        )?;
+
+        assert_eq!(
+            format!("{}/v1/auth/approle/login", address),
+            request.url().to_string()
+        );
+        assert_eq!(&reqwest::Method::POST, request.method());
+        assert_eq!(
+            serde_json::json!({ "role_id": "role" }),
+            serde_json::to_value(method.payload())?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn logins_are_made_in_the_context() {
+        let clock = crate::clock::SimulatedClock::new();
+        let context = RequestContext::new().with_deadline(clock.now());
+        let method = AppRole {
+            path: "approle".to_string(),
+            role_id: "role".to_string(),
+            secret_id: None,
+        };
+        match method.login(&HttpClient::new(), &vault_address(), &[], &context, &clock) {
+            Err(crate::Error::DeadlineExceeded) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn token_lookup_is_converted_to_authentication() {
+        let json = r#"
+{
+  "data": {
+    "accessor": "8609694a-cdbc-db9b-d345-e782dbb562ed",
+    "creation_ttl": 2764800,
+    "entity_id": "",
+    "id": "cf64a70f-3a12-3f6c-791d-6cef6d390eed",
+    "meta": null,
+    "policies": ["default", "nomad"],
+    "renewable": true,
+    "ttl": 2764790,
+    "type": "service"
+  }
+}
+"#;
+        let authentication = match serde_json::from_str::<LookupResponse>(json).unwrap() {
+            LookupResponse::Lookup { data } => {
+                data.into_authentication(crate::Secret("token".to_string()))
+            }
+            other => panic!("Unexpected response {:?}", other),
+        };
+        assert_eq!("token", authentication.client_token.expose_secret());
+        assert_eq!(vec!["default", "nomad"], authentication.policies);
+        assert_eq!(2_764_790, authentication.lease_duration);
+        assert!(authentication.metadata.is_empty());
+        assert!(!Token(authentication.client_token).owns_token());
+    }
+
+    #[test]
+    fn aws_ec2_payload_omits_the_mount_path() -> Result<(), crate::Error> {
+        let method = AwsEc2 {
+            path: "aws".to_string(),
+            role: "nomad-drain".to_string(),
+            pkcs7: "MIAGCSqGSIb3DQEHAqCAMIACAQEx".to_string(),
+            nonce: Some(crate::Secret("nonce".to_string())),
+        };
+        assert_eq!(
+            serde_json::json!({
+                "role": "nomad-drain",
+                "pkcs7": "MIAGCSqGSIb3DQEHAqCAMIACAQEx",
+                "nonce": "nonce",
+            }),
+            serde_json::to_value(method.payload())?
+        );
+        Ok(())
+    }
+}
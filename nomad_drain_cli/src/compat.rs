@@ -0,0 +1,167 @@
+//! Check that the Nomad and Vault servers are supported before rolling out
+//!
+//! Versions are checked against the matrix of the library, and the endpoints used to drain nodes
+//! are probed with a node that does not exist, so that nothing is changed.
+use nomad_drain::compat::{self, Compatibility, SupportedVersions};
+use nomad_drain::nomad::{NodeEligibility, NomadVersion};
+use nomad_drain::vault::Client as VaultClient;
+
+use crate::doctor::Report;
+use crate::Opt;
+
+/// ID of the node the endpoints are probed with. No node has this ID.
+const PROBE_NODE_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// Check the versions and endpoints of Nomad and Vault
+pub fn check(opt: &Opt) -> Report {
+    let mut report = Report::default();
+
+    match opt.vault.vault_address {
+        Some(ref vault_address) => check_vault(opt, vault_address, &mut report),
+        None => report.skip("Vault version", "`vault-addr` is not set"),
+    }
+
+    let nomad_client = match opt.nomad_client() {
+        Ok(client) => client,
+        Err(e) => {
+            report.check(
+                "Nomad client",
+                "Check `nomad-addr` and `nomad-token`, or run `doctor`",
+                Err(e),
+            );
+            return report;
+        }
+    };
+    let supported = report.check(
+        "Nomad version",
+        "Upgrade Nomad to a supported version",
+        nomad_client
+            .agent_self()
+            .map_err(|e| nomad_drain::display_chain(&e))
+            .and_then(|agent| {
+                let version = agent
+                    .version()
+                    .ok_or_else(|| "the agent does not report its version".to_string())?;
+                let detail = check_version("Nomad", &version, &compat::NOMAD)?;
+                let api = nomad_client
+                    .drain_api()
+                    .map_err(|e| nomad_drain::display_chain(&e))?;
+                Ok(format!("{}, drain API {:?}", detail, api))
+            }),
+    );
+    if opt.dry_run {
+        report.skip("Nomad drain endpoint", "dry run");
+        report.skip("Nomad eligibility endpoint", "dry run");
+        return report;
+    }
+    if !supported {
+        report.skip(
+            "Nomad drain endpoint",
+            "Nomad is unsupported or unavailable",
+        );
+        report.skip(
+            "Nomad eligibility endpoint",
+            "Nomad is unsupported or unavailable",
+        );
+        return report;
+    }
+    report.check(
+        "Nomad drain endpoint",
+        "Grant the token `node { policy = \"write\" }`",
+        probe(nomad_client.set_node_drain(PROBE_NODE_ID, false, None)),
+    );
+    report.check(
+        "Nomad eligibility endpoint",
+        "Grant the token `node { policy = \"write\" }`",
+        probe(nomad_client.set_node_eligibility(PROBE_NODE_ID, NodeEligibility::Ineligible)),
+    );
+    report
+}
+
+/// Check the version of Vault and the mount of the Nomad secrets engine
+fn check_vault(opt: &Opt, vault_address: &str, report: &mut Report) {
+    let supported = report.check(
+        "Vault version",
+        "Upgrade Vault to a supported version",
+        VaultClient::build_http_client(&opt.vault.tls())
+            .and_then(|client| VaultClient::health(vault_address, Some(client)))
+            .map_err(|e| nomad_drain::display_chain(&e))
+            .and_then(|health| {
+                let version = NomadVersion::parse(&health.version)
+                    .ok_or_else(|| format!("unable to parse the version `{}`", health.version))?;
+                check_version("Vault", &version, &compat::VAULT)
+            }),
+    );
+
+    let nomad_path = match opt.vault.nomad_path {
+        Some(ref nomad_path) if supported => nomad_path,
+        Some(_) => {
+            report.skip(
+                "Vault Nomad secrets engine",
+                "Vault is unsupported or unavailable",
+            );
+            return;
+        }
+        None => {
+            report.skip(
+                "Vault Nomad secrets engine",
+                "`vault-nomad-path` is not set",
+            );
+            return;
+        }
+    };
+    report.check(
+        "Vault Nomad secrets engine",
+        "Check `vault-nomad-path`, and that the token can read `sys/internal/ui/mounts`",
+        opt.vault_client()
+            .map_err(|e| nomad_drain::display_chain(&e))
+            .and_then(|client| {
+                client
+                    .mount_info(nomad_path)
+                    .map_err(|e| nomad_drain::display_chain(&e))
+            })
+            .and_then(|mount| {
+                if mount.mount_type == "nomad" {
+                    Ok(format!("`{}creds` is available", mount.path))
+                } else {
+                    Err(format!(
+                        "`{}` is mounted with {} instead of nomad",
+                        mount.path, mount.mount_type
+                    ))
+                }
+            }),
+    );
+}
+
+/// Describe a version that is supported, or fail with why it is not
+fn check_version(
+    server: &str,
+    version: &NomadVersion,
+    supported: &SupportedVersions,
+) -> Result<String, String> {
+    match supported.check(version) {
+        Compatibility::Supported => Ok(format!("{} {} is supported", server, version)),
+        Compatibility::Untested => Ok(format!(
+            "{} {} is newer than the tested versions {}",
+            server, version, supported
+        )),
+        Compatibility::Unsupported => Err(format!(
+            "{} {} is older than the supported versions {}",
+            server, version, supported
+        )),
+    }
+}
+
+/// Interpret the response to a request made with `PROBE_NODE_ID`
+///
+/// Nomad only looks the node up after checking that the token can write nodes, so a node that is
+/// not found means that the endpoint exists and is allowed.
+fn probe<T>(result: Result<T, nomad_drain::Error>) -> Result<String, nomad_drain::Error> {
+    match result {
+        Ok(_) => {}
+        Err(nomad_drain::Error::NomadApi { ref message, .. })
+            if message.to_lowercase().contains("not found") => {}
+        Err(e) => Err(e)?,
+    }
+    Ok("available and allowed for the token".to_string())
+}
@@ -0,0 +1,265 @@
+//! Diagnose the configuration end to end
+//!
+//! Each link of the chain from AWS credentials to Vault to Nomad is checked in turn, and checks
+//! that depend on an earlier failure are skipped.
+use std::fmt;
+
+use rusoto_core::Region;
+
+use nomad_drain::vault::{Client as VaultClient, Health};
+
+use crate::Opt;
+
+/// Outcome of a check
+enum Outcome {
+    Pass(String),
+    Fail(String),
+    Skip(String),
+}
+
+/// A check and its outcome
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+    hint: &'static str,
+}
+
+/// Outcomes of all the checks
+#[derive(Default)]
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    /// Record the outcome of a check. Returns whether the check passed.
+    pub fn check<E>(
+        &mut self,
+        name: &'static str,
+        hint: &'static str,
+        result: Result<String, E>,
+    ) -> bool
+    where
+        E: Into<Box<dyn std::error::Error>>,
+    {
+        let outcome = match result {
+            Ok(detail) => Outcome::Pass(detail),
+            Err(e) => Outcome::Fail(nomad_drain::display_chain(&*e.into())),
+        };
+        let passed = matches!(outcome, Outcome::Pass(_));
+        self.checks.push(Check {
+            name,
+            outcome,
+            hint,
+        });
+        passed
+    }
+
+    /// Record a check that passed
+    pub fn pass(&mut self, name: &'static str, detail: &str) {
+        self.checks.push(Check {
+            name,
+            outcome: Outcome::Pass(detail.to_string()),
+            hint: "",
+        });
+    }
+
+    /// Record a check that was not performed
+    pub fn skip(&mut self, name: &'static str, reason: &str) {
+        self.checks.push(Check {
+            name,
+            outcome: Outcome::Skip(reason.to_string()),
+            hint: "",
+        });
+    }
+
+    /// Number of checks that failed
+    pub fn failures(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, Outcome::Fail(_)))
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in &self.checks {
+            match check.outcome {
+                Outcome::Pass(ref detail) => writeln!(f, "[PASS] {}: {}", check.name, detail)?,
+                Outcome::Skip(ref reason) => writeln!(f, "[SKIP] {}: {}", check.name, reason)?,
+                Outcome::Fail(ref error) => {
+                    writeln!(f, "[FAIL] {}: {}", check.name, error)?;
+                    writeln!(f, "       Hint: {}", check.hint)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check the configuration of AWS, Vault and Nomad
+pub fn diagnose(opt: &Opt) -> Report {
+    let mut report = Report::default();
+
+    let has_credentials = report.check(
+        "AWS credentials",
+        "Provide credentials with environment variables, the shared credentials file or an \
+         instance profile",
+        nomad_drain::get_aws_credentials()
+            .map(|credentials| format!("access key {}", credentials.aws_access_key_id())),
+    );
+    if has_credentials {
+        report.check(
+            "AWS STS signing",
+            "Check that the system clock is accurate and that the credentials have not expired",
+            nomad_drain::aws::caller_identity(Region::default())
+                .map(|arn| format!("signed in as {}", arn)),
+        );
+    } else {
+        report.skip("AWS STS signing", "no AWS credentials");
+    }
+
+    let vault_ok = match opt.vault.vault_address {
+        Some(ref vault_address) => diagnose_vault(opt, vault_address, &mut report),
+        None => {
+            report.skip("Vault", "`vault-addr` is not set");
+            true
+        }
+    };
+
+    if !vault_ok && opt.nomad.nomad_token.is_none() {
+        report.skip("Nomad", "the Nomad token is retrieved from Vault");
+        return report;
+    }
+    let nomad_client = match opt.nomad_client() {
+        Ok(client) => client,
+        Err(e) => {
+            report.check(
+                "Nomad client",
+                "Check `nomad-addr` and `nomad-token`",
+                Err(e),
+            );
+            return report;
+        }
+    };
+    let has_leader = report.check(
+        "Nomad leader",
+        "Check `nomad-addr`, that Nomad is reachable and that the servers have elected a leader",
+        nomad_client
+            .leader()
+            .map(|leader| format!("leader at {}", leader)),
+    );
+    if !has_leader {
+        report.skip("Nomad ACL token", "Nomad is unavailable");
+        report.skip("Nomad node read", "Nomad is unavailable");
+        return report;
+    }
+    report.check(
+        "Nomad ACL token",
+        "Check that the token is valid in the region and has not expired. The token needs \
+         `node { policy = \"write\" }` to drain nodes",
+        nomad_client.token_self().map(|token| {
+            format!(
+                "{} token `{}` with policies [{}]",
+                token.token_type,
+                token.name,
+                token.policies.unwrap_or_default().join(", ")
+            )
+        }),
+    );
+    report.check(
+        "Nomad node read",
+        "Grant the token `node { policy = \"write\" }`",
+        nomad_client
+            .nodes(None, None)
+            .map(|nodes| format!("{} nodes visible", nodes.data.len())),
+    );
+    report
+}
+
+/// Check the Vault server, login and the Nomad secrets engine. Returns whether all checks passed.
+fn diagnose_vault(opt: &Opt, vault_address: &str, report: &mut Report) -> bool {
+    let healthy = report.check(
+        "Vault health",
+        "Check `vault-addr` and that Vault is initialized and unsealed",
+        VaultClient::build_http_client(&opt.vault.tls())
+            .and_then(|client| VaultClient::health(vault_address, Some(client)))
+            .and_then(describe_health),
+    );
+    if !healthy {
+        report.skip("Vault login", "Vault is unavailable");
+        return false;
+    }
+
+    let vault_client = match opt.vault_client() {
+        Ok(client) => client,
+        Err(e) => {
+            report.check(
+                "Vault login",
+                "Check `vault-auth-path`, `vault-auth-role` and that the role is bound to the IAM \
+                 principal reported by STS",
+                Err(e),
+            );
+            return false;
+        }
+    };
+    report.pass("Vault login", "logged in");
+
+    let (nomad_path, nomad_role) = match (&opt.vault.nomad_path, &opt.vault.nomad_role) {
+        (Some(nomad_path), Some(nomad_role)) => (nomad_path, nomad_role),
+        _ => {
+            report.skip(
+                "Vault Nomad secrets engine",
+                "`vault-nomad-path` is not set",
+            );
+            return true;
+        }
+    };
+    let mounted = report.check(
+        "Vault Nomad secrets engine",
+        "Check `vault-nomad-path` and that the token can read `sys/internal/ui/mounts`",
+        vault_client.mount_info(nomad_path).and_then(|mount| {
+            if mount.mount_type != "nomad" {
+                Err(nomad_drain::Error::UnexpectedVaultMountType {
+                    path: mount.path.clone(),
+                    actual: mount.mount_type,
+                    expected: "nomad".to_string(),
+                })?;
+            }
+            Ok(format!("mounted at `{}`", mount.path))
+        }),
+    );
+    if !mounted {
+        report.skip("Vault Nomad role", "the secrets engine is unavailable");
+        return false;
+    }
+    report.check(
+        "Vault Nomad role",
+        "Check `vault-nomad-role` and that the Vault policies allow reading `creds/<role>` of the \
+         Nomad secrets engine",
+        vault_client
+            .get_nomad_token_with_lease(nomad_path, nomad_role)
+            .and_then(|token| {
+                vault_client.revoke_lease(&token.lease_id)?;
+                Ok(format!("issued token {}", token.accessor_id))
+            }),
+    )
+}
+
+fn describe_health(health: Health) -> Result<String, nomad_drain::Error> {
+    if !health.initialized {
+        Err(nomad_drain::Error::InvalidVaultResponse(
+            "Vault is not initialized".to_string(),
+        ))?;
+    }
+    if health.sealed {
+        Err(nomad_drain::Error::InvalidVaultResponse(
+            "Vault is sealed".to_string(),
+        ))?;
+    }
+    Ok(format!(
+        "version {}, {}",
+        health.version,
+        if health.standby { "standby" } else { "active" }
+    ))
+}
@@ -0,0 +1,108 @@
+use nomad_drain::ErrorKind;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Lib(#[from] nomad_drain::Error),
+    #[error("Error reading or writing file")]
+    Io(#[from] std::io::Error),
+    #[error("Error (de)serializing JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("Option `{0}` was expected but is missing")]
+    MissingConfiguration(String),
+    #[error("{0} configuration checks failed")]
+    ChecksFailed(usize),
+    #[error("Draining Nomad Node ID {node_id} failed: {error}")]
+    DrainFailed { node_id: String, error: String },
+}
+
+impl Error {
+    /// Process exit code to report the error with, documented in `--help`
+    pub fn exit_code(&self) -> i32 {
+        let kind = match self {
+            Error::Lib(e) => e.kind(),
+            Error::MissingConfiguration(_) | Error::ChecksFailed(_) => ErrorKind::Configuration,
+            Error::Io(_) | Error::Json(_) | Error::DrainFailed { .. } => ErrorKind::Other,
+        };
+        kind.exit_code()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Description of the exit code in `--help`
+    fn documented(code: i32) -> Option<&'static str> {
+        crate::EXIT_CODES.lines().skip(1).find_map(|line| {
+            let mut parts = line.trim().splitn(2, ' ');
+            match parts.next()?.parse::<i32>() {
+                Ok(documented) if documented == code => Some(parts.next()?.trim()),
+                _ => None,
+            }
+        })
+    }
+
+    #[test]
+    fn errors_exit_with_their_documented_code() {
+        let cases = vec![
+            (
+                Error::Lib(nomad_drain::Error::MissingVaultCredentials),
+                "Credentials or tokens were missing or rejected",
+            ),
+            (
+                Error::Lib(nomad_drain::Error::AutoScalingGroupNotFound {
+                    name: "nomad-clients".to_string(),
+                }),
+                "Node, mount or Auto Scaling Group not found",
+            ),
+            (
+                Error::Lib(nomad_drain::Error::DeadlineExceeded),
+                "Timed out",
+            ),
+            (
+                Error::Lib(nomad_drain::Error::ReqwestError(
+                    nomad_drain::fixtures::timed_out(),
+                )),
+                "Timed out",
+            ),
+            (
+                Error::Lib(nomad_drain::Error::IncompleteClientCertificate),
+                "Invalid configuration",
+            ),
+            (
+                Error::Lib(nomad_drain::Error::InvalidVaultResponse(String::new())),
+                "Any other error",
+            ),
+            (
+                Error::Io(std::io::ErrorKind::PermissionDenied.into()),
+                "Any other error",
+            ),
+            (
+                Error::Json(serde_json::from_str::<()>("{").unwrap_err()),
+                "Any other error",
+            ),
+            (
+                Error::MissingConfiguration("vault-auth-path".to_string()),
+                "Invalid configuration",
+            ),
+            (Error::ChecksFailed(2), "Invalid configuration"),
+            (
+                Error::DrainFailed {
+                    node_id: "id".to_string(),
+                    error: "Deadline exceeded".to_string(),
+                },
+                "Any other error",
+            ),
+        ];
+        for (error, description) in cases {
+            assert_eq!(
+                Some(description),
+                documented(error.exit_code()),
+                "{:?}",
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,740 @@
+mod compat;
+mod doctor;
+mod error;
+mod report;
+
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use rusoto_autoscaling::AutoscalingClient;
+use rusoto_core::Region;
+use structopt::StructOpt;
+
+use nomad_drain::aws::VaultAwsAuthIamPayload;
+use nomad_drain::context::RequestContext;
+use nomad_drain::nomad::{
+    Client as NomadClient, ClientBuilder as NomadClientBuilder, Deadline, DrainSpec,
+    NodeEligibility,
+};
+use nomad_drain::pause::{DynamoDbPauseFlag, PauseFile, PauseSwitch};
+use nomad_drain::plan::DrainPlan;
+use nomad_drain::tls::{Pem, TlsConfig};
+use nomad_drain::vault::{
+    AwsIam, Client as VaultClient, ClientBuilder as VaultClientBuilder, MfaCredentials,
+};
+use nomad_drain::NomadDrain;
+
+use crate::error::Error;
+
+/// Exit codes documented in `--help`, which `Error::exit_code` follows
+const EXIT_CODES: &str = "EXIT CODES:
+    1    Any other error
+    2    Invalid command line usage
+    3    Credentials or tokens were missing or rejected
+    4    Node, mount or Auto Scaling Group not found
+    5    Timed out
+    6    Nomad or Vault unreachable
+    7    Invalid configuration";
+
+/// Drain Nomad nodes using the same logic as the Lambda
+#[derive(StructOpt, Debug)]
+#[structopt(name = "nomad-drain", raw(after_help = "EXIT_CODES"))]
+struct Opt {
+    #[structopt(flatten)]
+    nomad: NomadOpt,
+
+    #[structopt(flatten)]
+    vault: VaultOpt,
+
+    /// Refuse to make changes to the Nomad cluster, failing at the first change that would have
+    /// been made
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    /// ID sent with every Nomad and Vault request, to correlate them in audit logs
+    #[structopt(long = "trace-id", env = "NOMAD_DRAIN_TRACE_ID")]
+    trace_id: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+struct NomadOpt {
+    /// Address of Nomad server
+    #[structopt(
+        long = "nomad-addr",
+        env = "NOMAD_ADDR",
+        default_value = "http://127.0.0.1:4646"
+    )]
+    nomad_address: String,
+
+    /// Nomad token. During token rotation, this can be a comma separated list of candidate
+    /// tokens. If absent, a token is retrieved from Vault when the Nomad secrets engine is
+    /// configured.
+    #[structopt(long = "nomad-token", env = "NOMAD_TOKEN", hide_env_values = true)]
+    nomad_token: Option<String>,
+
+    /// Region to send Nomad requests to
+    #[structopt(long = "nomad-region", env = "NOMAD_REGION")]
+    nomad_region: Option<String>,
+
+    /// Path to, or PEM contents of, the CA certificates to verify Nomad with
+    #[structopt(long = "nomad-cacert", env = "NOMAD_CACERT")]
+    ca_cert: Option<String>,
+
+    /// Path to, or PEM contents of, the client certificate to present to Nomad
+    #[structopt(long = "nomad-client-cert", env = "NOMAD_CLIENT_CERT")]
+    client_cert: Option<String>,
+
+    /// Path to, or PEM contents of, the private key of the client certificate
+    #[structopt(
+        long = "nomad-client-key",
+        env = "NOMAD_CLIENT_KEY",
+        hide_env_values = true
+    )]
+    client_key: Option<String>,
+
+    /// Name the Nomad server certificate is issued for
+    #[structopt(long = "nomad-tls-server-name", env = "NOMAD_TLS_SERVER_NAME")]
+    tls_server_name: Option<String>,
+}
+
+impl NomadOpt {
+    fn tls(&self) -> TlsConfig {
+        let pem = |value: &Option<String>| value.as_ref().map(|value| Pem::from_value(value));
+        TlsConfig {
+            ca_cert: pem(&self.ca_cert),
+            client_cert: pem(&self.client_cert),
+            client_key: pem(&self.client_key),
+            server_name: self.tls_server_name.clone(),
+            skip_verify: false,
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct VaultOpt {
+    /// Address of Vault server
+    #[structopt(long = "vault-addr", env = "VAULT_ADDR")]
+    vault_address: Option<String>,
+
+    /// Vault token. If absent, AWS credentials are used to login to Vault.
+    #[structopt(long = "vault-token", env = "VAULT_TOKEN", hide_env_values = true)]
+    vault_token: Option<String>,
+
+    /// Vault Enterprise namespace to login and make requests in
+    #[structopt(long = "vault-namespace", env = "VAULT_NAMESPACE")]
+    namespace: Option<String>,
+
+    /// Path to the Vault AWS authentication engine
+    #[structopt(long = "vault-auth-path", env = "VAULT_AUTH_PATH")]
+    auth_path: Option<String>,
+
+    /// Vault AWS authentication role
+    #[structopt(long = "vault-auth-role", env = "VAULT_AUTH_ROLE")]
+    auth_role: Option<String>,
+
+    /// Value of the `iam_server_id_header_value` of the Vault AWS authentication engine
+    #[structopt(long = "vault-auth-header-value", env = "VAULT_AUTH_HEADER_VALUE")]
+    auth_header_value: Option<String>,
+
+    /// Seconds to retry Vault AWS logins while the IAM principal is not found
+    #[structopt(
+        long = "vault-auth-retry-window",
+        env = "VAULT_AUTH_RETRY_WINDOW",
+        default_value = "60"
+    )]
+    auth_retry_window: u64,
+
+    /// Vault MFA credentials in the form `method_name[:passcode]`, sent with the login and every
+    /// subsequent Vault request. Can be repeated for multiple methods.
+    #[structopt(long = "vault-mfa", env = "VAULT_MFA", hide_env_values = true)]
+    mfa: Vec<MfaCredentials>,
+
+    /// Path to the Vault Nomad secrets engine
+    #[structopt(long = "vault-nomad-path", env = "VAULT_NOMAD_PATH")]
+    nomad_path: Option<String>,
+
+    /// Vault Nomad secrets engine role
+    #[structopt(long = "vault-nomad-role", env = "VAULT_NOMAD_ROLE")]
+    nomad_role: Option<String>,
+
+    /// Response-wrapped Nomad token from the Vault Nomad secrets engine, handed over by someone
+    /// else. Unwrapped with Vault instead of retrieving a new token.
+    #[structopt(
+        long = "vault-nomad-wrapped-token",
+        env = "VAULT_NOMAD_WRAPPED_TOKEN",
+        hide_env_values = true
+    )]
+    nomad_wrapped_token: Option<String>,
+
+    /// Path to, or PEM contents of, the CA certificates to verify Vault with
+    #[structopt(long = "vault-cacert", env = "VAULT_CACERT")]
+    vault_ca_cert: Option<String>,
+
+    /// Path to, or PEM contents of, the client certificate to present to Vault
+    #[structopt(long = "vault-client-cert", env = "VAULT_CLIENT_CERT")]
+    vault_client_cert: Option<String>,
+
+    /// Path to, or PEM contents of, the private key of the client certificate
+    #[structopt(
+        long = "vault-client-key",
+        env = "VAULT_CLIENT_KEY",
+        hide_env_values = true
+    )]
+    vault_client_key: Option<String>,
+
+    /// Name the Vault server certificate is issued for
+    #[structopt(long = "vault-tls-server-name", env = "VAULT_TLS_SERVER_NAME")]
+    vault_tls_server_name: Option<String>,
+
+    /// Do not verify the Vault server certificate. Also enabled by `VAULT_SKIP_VERIFY`. Insecure.
+    #[structopt(long = "vault-skip-verify")]
+    skip_verify: bool,
+}
+
+impl VaultOpt {
+    /// Builder of Vault clients with the TLS settings and namespace
+    fn client_builder(&self, vault_address: &str) -> VaultClientBuilder {
+        let builder = VaultClient::builder(vault_address).with_tls(&self.tls());
+        match self.namespace {
+            Some(ref namespace) => builder.with_namespace(namespace),
+            None => builder,
+        }
+    }
+
+    fn tls(&self) -> TlsConfig {
+        let pem = |value: &Option<String>| value.as_ref().map(|value| Pem::from_value(value));
+        TlsConfig {
+            ca_cert: pem(&self.vault_ca_cert),
+            client_cert: pem(&self.vault_client_cert),
+            client_key: pem(&self.vault_client_key),
+            server_name: self.vault_tls_server_name.clone(),
+            skip_verify: self.skip_verify || TlsConfig::from_vault_env().skip_verify,
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Drain a node
+    #[structopt(name = "drain")]
+    Drain {
+        #[structopt(flatten)]
+        target: Target,
+
+        /// Drain deadline in seconds
+        #[structopt(long = "deadline", default_value = "3600")]
+        deadline: u64,
+
+        /// Stop all allocations on the node immediately, ignoring `deadline`
+        #[structopt(long = "force", conflicts_with = "no_deadline")]
+        force: bool,
+
+        /// Wait for allocations to migrate for as long as it takes, ignoring `deadline`
+        #[structopt(long = "no-deadline")]
+        no_deadline: bool,
+
+        /// Do not drain system jobs
+        #[structopt(long = "ignore-system-jobs")]
+        ignore_system_jobs: bool,
+
+        /// Wait for the drain to complete
+        #[structopt(long = "monitor")]
+        monitor: bool,
+
+        /// Do not verify that the node is ineligible after draining it, or mark it ineligible,
+        /// for tokens that are only allowed to drain nodes
+        #[structopt(long = "skip-eligibility")]
+        skip_eligibility: bool,
+    },
+    /// Set the scheduling eligibility of a node
+    #[structopt(name = "eligibility")]
+    Eligibility {
+        #[structopt(flatten)]
+        target: Target,
+
+        /// Scheduling eligibility
+        #[structopt(raw(possible_values = r#"&["eligible", "ineligible"]"#))]
+        eligibility: String,
+    },
+    /// Wait for the drain of a node to complete
+    #[structopt(name = "monitor")]
+    Monitor {
+        #[structopt(flatten)]
+        target: Target,
+
+        /// Also wait for allocations on the node to stop
+        #[structopt(long = "allocations")]
+        allocations: bool,
+
+        /// Give up after monitoring the drain for this many seconds
+        #[structopt(long = "max-duration")]
+        max_duration: Option<u64>,
+    },
+    /// Stop an allocation that is holding up a drain, so that it is rescheduled elsewhere
+    #[structopt(name = "stop-allocation")]
+    StopAllocation {
+        /// Nomad Allocation ID
+        alloc_id: String,
+    },
+    /// Send a signal to the tasks of an allocation that is holding up a drain
+    #[structopt(name = "signal-allocation")]
+    SignalAllocation {
+        /// Nomad Allocation ID
+        alloc_id: String,
+
+        /// Signal to send
+        #[structopt(default_value = "SIGTERM")]
+        signal: String,
+
+        /// Only signal this task of the allocation
+        #[structopt(long = "task")]
+        task: Option<String>,
+    },
+    /// Plan the drain of every node in an AWS Auto Scaling Group
+    #[structopt(name = "plan")]
+    Plan {
+        /// Name of the Auto Scaling Group
+        auto_scaling_group: String,
+
+        /// Drain deadline in seconds
+        #[structopt(long = "deadline", default_value = "3600")]
+        deadline: u64,
+
+        /// Do not drain system jobs
+        #[structopt(long = "ignore-system-jobs")]
+        ignore_system_jobs: bool,
+
+        /// Maximum number of nodes draining at the same time in each availability zone
+        #[structopt(long = "max-drains-per-zone")]
+        max_drains_per_zone: Option<usize>,
+
+        /// Maximum number of nodes draining at the same time in the cluster
+        #[structopt(long = "max-concurrent-drains")]
+        max_concurrent_drains: Option<usize>,
+
+        /// Minimum number of eligible nodes that have to remain after the plan is applied
+        #[structopt(long = "min-eligible-nodes")]
+        min_eligible_nodes: Option<usize>,
+
+        /// Write the plan to a file instead of standard output
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Apply a plan from the `plan` subcommand
+    #[structopt(name = "apply")]
+    Apply {
+        /// Path to the plan
+        #[structopt(parse(from_os_str))]
+        plan: PathBuf,
+
+        /// Wait for each drain to complete before starting the next
+        #[structopt(long = "monitor")]
+        monitor: bool,
+
+        /// Write a report of the drains to this path, suitable for attaching to change tickets
+        #[structopt(long = "report", parse(from_os_str))]
+        report: Option<PathBuf>,
+
+        /// Format of the report
+        #[structopt(
+            long = "report-format",
+            default_value = "markdown",
+            raw(possible_values = r#"&["markdown", "html", "json"]"#)
+        )]
+        report_format: String,
+
+        #[structopt(flatten)]
+        pause: PauseFlag,
+
+        /// Pause drains while this file exists, instead of with the DynamoDB pause flag
+        #[structopt(long = "pause-file", conflicts_with = "table", parse(from_os_str))]
+        pause_file: Option<PathBuf>,
+    },
+    /// Pause drains applied with the DynamoDB pause flag. Drains in progress carry on.
+    #[structopt(name = "pause")]
+    Pause {
+        #[structopt(flatten)]
+        pause: PauseFlag,
+    },
+    /// Resume drains paused with the `pause` subcommand
+    #[structopt(name = "resume")]
+    Resume {
+        #[structopt(flatten)]
+        pause: PauseFlag,
+    },
+    /// Check the configuration of AWS, Vault and Nomad and report problems
+    #[structopt(name = "doctor")]
+    Doctor,
+    /// Check that the versions of Nomad and Vault are supported, and that the endpoints used to
+    /// drain nodes are available
+    #[structopt(name = "compat")]
+    Compat,
+    /// Print version and build details
+    #[structopt(name = "version")]
+    Version,
+}
+
+#[derive(StructOpt, Debug)]
+struct Target {
+    /// AWS Instance ID (starting with `i-`) or Nomad Node ID
+    target: String,
+
+    /// Search Nomad nodes that are down or initializing when looking up an AWS Instance ID
+    #[structopt(long = "include-down-nodes")]
+    include_down_nodes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+struct PauseFlag {
+    /// DynamoDB table holding the flag to pause drains with, shared with the Lambda
+    #[structopt(long = "pause-table", env = "NOMAD_DRAIN_PAUSE_TABLE")]
+    table: Option<String>,
+
+    /// Key of the item holding the pause flag in the DynamoDB table
+    #[structopt(long = "pause-key", default_value = "nomad-drain")]
+    key: String,
+}
+
+impl PauseFlag {
+    fn flag(&self) -> Option<DynamoDbPauseFlag> {
+        self.table
+            .as_ref()
+            .map(|table| DynamoDbPauseFlag::new(Region::default(), table, &self.key, None))
+    }
+
+    fn set_paused(&self, paused: bool) -> Result<(), Error> {
+        let flag = self
+            .flag()
+            .ok_or_else(|| Error::MissingConfiguration("pause-table".to_string()))?;
+        flag.set_paused(paused)?;
+        info!(
+            "Drains {} with flag {} in DynamoDB table {}",
+            if paused { "paused" } else { "resumed" },
+            flag.key,
+            flag.table
+        );
+        Ok(())
+    }
+}
+
+impl Target {
+    /// Resolve the target to a Nomad Node ID
+    fn node_id(&self, nomad_client: &NomadClient) -> Result<String, Error> {
+        if !self.target.starts_with("i-") {
+            return Ok(self.target.clone());
+        }
+
+        let node = nomad_client.find_node_by_instance_id(&self.target, self.include_down_nodes)?;
+        if let Some(ref ambiguous) = node.ambiguous {
+            warn!("{}", ambiguous);
+        }
+        info!(
+            "AWS Instance ID {} is Nomad Node ID {}",
+            self.target, node.node.data.id
+        );
+        Ok(node.node.data.id)
+    }
+}
+
+impl Opt {
+    /// Context every Nomad and Vault request is made in
+    fn request_context(&self) -> RequestContext {
+        let context = RequestContext::new().with_dry_run(self.dry_run);
+        match self.trace_id {
+            Some(ref trace_id) => context.with_trace_id(trace_id),
+            None => context,
+        }
+    }
+
+    fn nomad_client(&self) -> Result<NomadClient, Error> {
+        let mut builder = NomadClient::builder(&self.nomad.nomad_address)
+            .with_tls(&self.nomad.tls())
+            .with_user_agent(format!("nomad-drain/{}", env!("CARGO_PKG_VERSION")));
+        if let Some(ref region) = self.nomad.nomad_region {
+            builder = builder.with_region(region);
+        }
+        Ok(self
+            .authenticate_nomad_client(builder)?
+            .with_context(self.request_context()))
+    }
+
+    fn authenticate_nomad_client(&self, builder: NomadClientBuilder) -> Result<NomadClient, Error> {
+        if let Some(ref tokens) = self.nomad.nomad_token {
+            let tokens: Vec<&str> = tokens
+                .split(',')
+                .map(str::trim)
+                .filter(|token| !token.is_empty())
+                .collect();
+            if tokens.is_empty() {
+                Err(Error::MissingConfiguration("nomad-token".to_string()))?;
+            }
+            return Ok(builder.with_candidate_tokens(&tokens).build()?);
+        }
+
+        if let Some(ref wrapping_token) = self.vault.nomad_wrapped_token {
+            info!("Unwrapping Nomad Token with Vault");
+            let vault_address = self
+                .vault
+                .vault_address
+                .as_ref()
+                .ok_or_else(|| Error::MissingConfiguration("vault-addr".to_string()))?;
+            let vault_client = self
+                .vault
+                .client_builder(vault_address)
+                .with_token(wrapping_token)
+                .build()?
+                .with_context(self.request_context());
+            let token = vault_client.unwrap_nomad_token(wrapping_token)?;
+            return Ok(builder
+                .with_token(token.secret_id.expose_secret())
+                .build()?);
+        }
+
+        match (&self.vault.nomad_path, &self.vault.nomad_role) {
+            (Some(nomad_path), Some(nomad_role)) => {
+                info!("No Nomad Token configured. Retrieving from Vault");
+                let vault_client = self.vault_client()?;
+                let token = vault_client.get_nomad_token(nomad_path, nomad_role, None)?;
+                Ok(builder.with_token(token.expose_secret()).build()?)
+            }
+            _ => {
+                info!("No Nomad token in use");
+                Ok(builder.build()?)
+            }
+        }
+    }
+
+    fn vault_client(&self) -> Result<VaultClient, Error> {
+        let vault_address = self
+            .vault
+            .vault_address
+            .as_ref()
+            .ok_or_else(|| Error::MissingConfiguration("vault-addr".to_string()))?;
+
+        let builder = self
+            .vault
+            .client_builder(vault_address)
+            .with_mfa(self.vault.mfa.clone());
+        let client = match self.vault.vault_token {
+            Some(ref token) => builder.with_token(token).build()?,
+            None => {
+                info!("No Vault Token configured. Using AWS Credentials to retrieve from Vault");
+                let auth_path =
+                    self.vault.auth_path.as_ref().ok_or_else(|| {
+                        Error::MissingConfiguration("vault-auth-path".to_string())
+                    })?;
+                let auth_role =
+                    self.vault.auth_role.as_ref().ok_or_else(|| {
+                        Error::MissingConfiguration("vault-auth-role".to_string())
+                    })?;
+
+                let aws_credentials = nomad_drain::get_aws_credentials()?;
+                let aws_payload = VaultAwsAuthIamPayload::new(
+                    &aws_credentials,
+                    self.vault.auth_header_value.as_deref(),
+                    None::<Region>,
+                );
+                builder
+                    .with_auth(AwsIam {
+                        path: auth_path.to_string(),
+                        role: auth_role.to_string(),
+                        payload: aws_payload,
+                    })
+                    .with_login_retry_window(Duration::from_secs(self.vault.auth_retry_window))
+                    .build()?
+            }
+        };
+
+        Ok(client.with_context(self.request_context()))
+    }
+}
+
+fn main() {
+    env_logger::init();
+    info!("nomad-drain {}", nomad_drain::build_info());
+    let opt = Opt::from_args();
+
+    if let Err(e) = run(&opt) {
+        error!("{}", e);
+        let mut source = std::error::Error::source(&e);
+        while let Some(cause) = source {
+            error!("Caused by: {}", cause);
+            source = cause.source();
+        }
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn run(opt: &Opt) -> Result<(), Error> {
+    let nomad_client = match opt.command {
+        Command::Version => {
+            println!("nomad-drain {}", nomad_drain::build_info());
+            return Ok(());
+        }
+        Command::Doctor => {
+            let report = doctor::diagnose(opt);
+            print!("{}", report);
+            return match report.failures() {
+                0 => Ok(()),
+                failures => Err(Error::ChecksFailed(failures)),
+            };
+        }
+        Command::Compat => {
+            let report = compat::check(opt);
+            print!("{}", report);
+            return match report.failures() {
+                0 => Ok(()),
+                failures => Err(Error::ChecksFailed(failures)),
+            };
+        }
+        Command::Pause { ref pause } => return pause.set_paused(true),
+        Command::Resume { ref pause } => return pause.set_paused(false),
+        _ => opt.nomad_client()?,
+    };
+
+    match opt.command {
+        Command::Drain {
+            ref target,
+            deadline,
+            force,
+            no_deadline,
+            ignore_system_jobs,
+            monitor,
+            skip_eligibility,
+        } => {
+            let deadline = if force {
+                Deadline::Force
+            } else if no_deadline {
+                Deadline::NoDeadline
+            } else {
+                Duration::from_secs(deadline).into()
+            };
+            let node_id = target.node_id(&nomad_client)?;
+            NomadDrain::new(nomad_client)
+                .with_drain_spec(DrainSpec {
+                    deadline,
+                    ignore_system_jobs,
+                })
+                .with_skip_eligibility(skip_eligibility)
+                .drain_node(&node_id, monitor)?;
+        }
+        Command::Eligibility {
+            ref target,
+            ref eligibility,
+        } => {
+            let node_id = target.node_id(&nomad_client)?;
+            let eligibility = match eligibility.as_str() {
+                "eligible" => NodeEligibility::Eligible,
+                _ => NodeEligibility::Ineligible,
+            };
+            nomad_client.set_node_eligibility(&node_id, eligibility)?;
+        }
+        Command::Monitor {
+            ref target,
+            allocations,
+            max_duration,
+        } => {
+            let node_id = target.node_id(&nomad_client)?;
+            let max_duration = max_duration.map(Duration::from_secs);
+            if allocations {
+                nomad_client.monitor_node_drain_with_allocations(&node_id, None, max_duration)?;
+            } else {
+                nomad_client.monitor_node_drain(&node_id, None, max_duration)?;
+            }
+        }
+        Command::StopAllocation { ref alloc_id } => {
+            let response = nomad_client.stop_allocation(alloc_id)?;
+            info!(
+                "Allocation ID {} stopped with Evaluation ID {}",
+                alloc_id, response.eval_id
+            );
+        }
+        Command::SignalAllocation {
+            ref alloc_id,
+            ref signal,
+            ref task,
+        } => {
+            nomad_client.signal_allocation(alloc_id, signal, task.as_deref())?;
+        }
+        Command::Plan {
+            ref auto_scaling_group,
+            deadline,
+            ignore_system_jobs,
+            max_drains_per_zone,
+            max_concurrent_drains,
+            min_eligible_nodes,
+            ref output,
+        } => {
+            let autoscaling_client = AutoscalingClient::new(Region::default());
+            let mut plan = DrainPlan::for_auto_scaling_group(
+                &nomad_client,
+                &autoscaling_client,
+                auto_scaling_group,
+                DrainSpec {
+                    deadline: Duration::from_secs(deadline).into(),
+                    ignore_system_jobs,
+                },
+            )?;
+            plan.max_drains_per_zone = max_drains_per_zone;
+            plan.max_concurrent_drains = max_concurrent_drains;
+            plan.min_eligible_nodes = min_eligible_nodes;
+            match output {
+                Some(path) => serde_json::to_writer_pretty(File::create(path)?, &plan)?,
+                None => println!("{}", serde_json::to_string_pretty(&plan)?),
+            }
+        }
+        Command::Apply {
+            ref plan,
+            monitor,
+            ref report,
+            ref report_format,
+            ref pause,
+            ref pause_file,
+        } => {
+            let plan: DrainPlan = serde_json::from_reader(File::open(plan)?)?;
+            let pause: Option<Box<dyn PauseSwitch>> = match (pause.flag(), pause_file) {
+                (Some(flag), _) => Some(Box::new(flag)),
+                (None, Some(path)) => Some(Box::new(PauseFile(path.clone()))),
+                (None, None) => None,
+            };
+            let pause = pause.as_deref();
+            match report {
+                None => {
+                    let drained = plan.apply(&nomad_client, monitor, pause)?;
+                    info!("Drained {} Nomad nodes", drained.len());
+                }
+                Some(path) => {
+                    let apply_report = plan.apply_with_report(&nomad_client, monitor, pause)?;
+                    let rendered = match report_format.as_str() {
+                        "html" => report::html(&apply_report),
+                        "json" => report::json(&apply_report)?,
+                        _ => report::markdown(&apply_report),
+                    };
+                    std::fs::write(path, rendered)?;
+                    info!("Drain report written to {}", path.display());
+                    if let Some(failure) = apply_report.failure() {
+                        Err(Error::DrainFailed {
+                            node_id: failure.drain.node_id.clone(),
+                            error: failure.error.clone().unwrap_or_default(),
+                        })?;
+                    }
+                    info!("Drained {} Nomad nodes", apply_report.drained().count());
+                }
+            }
+        }
+        Command::Version
+        | Command::Doctor
+        | Command::Compat
+        | Command::Pause { .. }
+        | Command::Resume { .. } => {
+            unreachable!("{:?} is handled without building clients", opt.command)
+        }
+    }
+
+    Ok(())
+}
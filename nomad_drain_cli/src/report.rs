@@ -0,0 +1,368 @@
+//! Render reports of applied drain plans
+//!
+//! Reports summarise the nodes drained, how long each drain took, the allocations migrated and
+//! any failure, so that they can be attached to change tickets. JSON reports are meant for tools
+//! instead.
+use std::fmt::Write;
+use std::time::Duration;
+
+use nomad_drain::plan::{ApplyReport, DrainOutcome};
+
+/// Render the report as Markdown
+pub fn markdown(report: &ApplyReport) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Drain of Auto Scaling Group `{}`\n",
+        report.auto_scaling_group
+    )
+    .unwrap();
+    for (name, value) in summary(report) {
+        writeln!(out, "- **{}**: {}", name, value).unwrap();
+    }
+
+    writeln!(out, "\n## Drains\n").unwrap();
+    writeln!(
+        out,
+        "| Node | Instance | Availability Zone | Started | Duration | Allocations | Result |"
+    )
+    .unwrap();
+    writeln!(out, "|---|---|---|---|---|---|---|").unwrap();
+    for outcome in &report.drains {
+        let row: Vec<_> = row(outcome)
+            .iter()
+            .map(|cell| cell.replace('|', "\\|"))
+            .collect();
+        writeln!(out, "| {} |", row.join(" | ")).unwrap();
+    }
+
+    if !report.skipped.is_empty() {
+        writeln!(out, "\n## Skipped\n").unwrap();
+        for drain in &report.skipped {
+            writeln!(
+                out,
+                "- {} (`{}`, {})",
+                drain.node_name, drain.instance_id, drain.availability_zone
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+/// Render the report as a standalone HTML document
+pub fn html(report: &ApplyReport) -> String {
+    let title = format!(
+        "Drain of Auto Scaling Group {}",
+        escape(&report.auto_scaling_group)
+    );
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n\
+         </head>\n<body>\n<h1>{0}</h1>",
+        title
+    )
+    .unwrap();
+    writeln!(out, "<ul>").unwrap();
+    for (name, value) in summary(report) {
+        writeln!(
+            out,
+            "<li><strong>{}</strong>: {}</li>",
+            name,
+            escape(&value)
+        )
+        .unwrap();
+    }
+    writeln!(out, "</ul>").unwrap();
+
+    writeln!(out, "<h2>Drains</h2>\n<table>").unwrap();
+    writeln!(
+        out,
+        "<tr><th>Node</th><th>Instance</th><th>Availability Zone</th><th>Started</th>\
+         <th>Duration</th><th>Allocations</th><th>Result</th></tr>"
+    )
+    .unwrap();
+    for outcome in &report.drains {
+        let cells: String = row(outcome)
+            .iter()
+            .map(|cell| format!("<td>{}</td>", escape(cell)))
+            .collect();
+        writeln!(out, "<tr>{}</tr>", cells).unwrap();
+    }
+    writeln!(out, "</table>").unwrap();
+
+    if !report.skipped.is_empty() {
+        writeln!(out, "<h2>Skipped</h2>\n<ul>").unwrap();
+        for drain in &report.skipped {
+            writeln!(
+                out,
+                "<li>{} ({}, {})</li>",
+                escape(&drain.node_name),
+                escape(&drain.instance_id),
+                escape(&drain.availability_zone)
+            )
+            .unwrap();
+        }
+        writeln!(out, "</ul>").unwrap();
+    }
+    writeln!(out, "</body>\n</html>").unwrap();
+    out
+}
+
+/// Render the report as JSON
+pub fn json(report: &ApplyReport) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(report)
+}
+
+/// Name and value of each line of the summary
+fn summary(report: &ApplyReport) -> Vec<(&'static str, String)> {
+    let planned = report.drains.len() + report.skipped.len();
+    let allocations: usize = report.drained().map(|outcome| outcome.allocations).sum();
+    vec![
+        ("Started", report.started_at.to_string()),
+        ("Finished", report.finished_at.to_string()),
+        (
+            "Nodes drained",
+            format!("{} of {}", report.drained().count(), planned),
+        ),
+        ("Allocations migrated", allocations.to_string()),
+        (
+            "Result",
+            match report.failure() {
+                Some(failure) => format!("Failed draining {}", failure.drain.node_name),
+                None if report.skipped.is_empty() => "Succeeded".to_string(),
+                None => "Incomplete".to_string(),
+            },
+        ),
+    ]
+}
+
+/// Cells of the row for a drain, unescaped
+fn row(outcome: &DrainOutcome) -> Vec<String> {
+    vec![
+        outcome.drain.node_name.clone(),
+        outcome.drain.instance_id.clone(),
+        outcome.drain.availability_zone.clone(),
+        outcome.started_at.to_string(),
+        outcome
+            .duration()
+            .map(format_duration)
+            .unwrap_or_else(|| "not monitored".to_string()),
+        outcome.allocations.to_string(),
+        match outcome.error {
+            Some(ref error) => format!("Failed: {}", error),
+            None => "Drained".to_string(),
+        },
+    ]
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, seconds) => format!("{}s", seconds),
+        (0, minutes, seconds) => format!("{}m {}s", minutes, seconds),
+        (hours, minutes, seconds) => format!("{}h {}m {}s", hours, minutes, seconds),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use nomad_drain::plan::PlannedDrain;
+
+    fn planned(name: &str, availability_zone: &str) -> PlannedDrain {
+        PlannedDrain {
+            instance_id: format!("i-{}", name),
+            availability_zone: availability_zone.to_string(),
+            node_id: format!("{}-id", name),
+            node_name: name.to_string(),
+            datacenter: "dc1".to_string(),
+        }
+    }
+
+    fn empty_report() -> ApplyReport {
+        ApplyReport {
+            auto_scaling_group: "nomad-clients".to_string(),
+            started_at: chrono::Utc.ymd(2021, 3, 4).and_hms(5, 6, 7),
+            finished_at: chrono::Utc.ymd(2021, 3, 4).and_hms(5, 6, 8),
+            drains: vec![],
+            skipped: vec![],
+        }
+    }
+
+    /// One node drained, one failed and one skipped
+    fn partial_report() -> ApplyReport {
+        let started_at = chrono::Utc.ymd(2021, 3, 4).and_hms(5, 6, 7);
+        ApplyReport {
+            finished_at: chrono::Utc.ymd(2021, 3, 4).and_hms(6, 10, 0),
+            drains: vec![
+                DrainOutcome {
+                    drain: planned("web-1", "ap-southeast-1a"),
+                    started_at,
+                    completed_at: Some(started_at + chrono::Duration::seconds(3725)),
+                    allocations: 3,
+                    error: None,
+                },
+                DrainOutcome {
+                    drain: planned("web|2", "ap-southeast-1b"),
+                    started_at: started_at + chrono::Duration::seconds(3725),
+                    completed_at: None,
+                    allocations: 0,
+                    error: Some("Nomad responded with <500>".to_string()),
+                },
+            ],
+            skipped: vec![planned("web-3", "ap-southeast-1c")],
+            ..empty_report()
+        }
+    }
+
+    #[test]
+    fn empty_reports_are_rendered_as_markdown() {
+        let expected = "\
+# Drain of Auto Scaling Group `nomad-clients`
+
+- **Started**: 2021-03-04 05:06:07 UTC
+- **Finished**: 2021-03-04 05:06:08 UTC
+- **Nodes drained**: 0 of 0
+- **Allocations migrated**: 0
+- **Result**: Succeeded
+
+## Drains
+
+| Node | Instance | Availability Zone | Started | Duration | Allocations | Result |
+|---|---|---|---|---|---|---|
+";
+        assert_eq!(expected, markdown(&empty_report()));
+    }
+
+    #[test]
+    fn partial_reports_are_rendered_as_markdown() {
+        let expected = "\
+# Drain of Auto Scaling Group `nomad-clients`
+
+- **Started**: 2021-03-04 05:06:07 UTC
+- **Finished**: 2021-03-04 06:10:00 UTC
+- **Nodes drained**: 1 of 3
+- **Allocations migrated**: 3
+- **Result**: Failed draining web|2
+
+## Drains
+
+| Node | Instance | Availability Zone | Started | Duration | Allocations | Result |
+|---|---|---|---|---|---|---|
+| web-1 | i-web-1 | ap-southeast-1a | 2021-03-04 05:06:07 UTC | 1h 2m 5s | 3 | Drained |
+| web\\|2 | i-web\\|2 | ap-southeast-1b | 2021-03-04 06:08:12 UTC | not monitored | 0 | Failed: Nomad responded with <500> |
+
+## Skipped
+
+- web-3 (`i-web-3`, ap-southeast-1c)
+";
+        assert_eq!(expected, markdown(&partial_report()));
+    }
+
+    #[test]
+    fn partial_reports_are_rendered_as_html() {
+        let expected = "\
+<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Drain of Auto Scaling Group nomad-clients</title>
+</head>
+<body>
+<h1>Drain of Auto Scaling Group nomad-clients</h1>
+<ul>
+<li><strong>Started</strong>: 2021-03-04 05:06:07 UTC</li>
+<li><strong>Finished</strong>: 2021-03-04 06:10:00 UTC</li>
+<li><strong>Nodes drained</strong>: 1 of 3</li>
+<li><strong>Allocations migrated</strong>: 3</li>
+<li><strong>Result</strong>: Failed draining web|2</li>
+</ul>
+<h2>Drains</h2>
+<table>
+<tr><th>Node</th><th>Instance</th><th>Availability Zone</th><th>Started</th><th>Duration</th>\
+<th>Allocations</th><th>Result</th></tr>
+<tr><td>web-1</td><td>i-web-1</td><td>ap-southeast-1a</td><td>2021-03-04 05:06:07 UTC</td>\
+<td>1h 2m 5s</td><td>3</td><td>Drained</td></tr>
+<tr><td>web|2</td><td>i-web|2</td><td>ap-southeast-1b</td><td>2021-03-04 06:08:12 UTC</td>\
+<td>not monitored</td><td>0</td><td>Failed: Nomad responded with &lt;500&gt;</td></tr>
+</table>
+<h2>Skipped</h2>
+<ul>
+<li>web-3 (i-web-3, ap-southeast-1c)</li>
+</ul>
+</body>
+</html>
+";
+        assert_eq!(expected, html(&partial_report()));
+    }
+
+    #[test]
+    fn empty_reports_are_rendered_as_json() {
+        let expected = r#"{
+  "auto_scaling_group": "nomad-clients",
+  "started_at": "2021-03-04T05:06:07Z",
+  "finished_at": "2021-03-04T05:06:08Z",
+  "drains": [],
+  "skipped": []
+}"#;
+        assert_eq!(expected, json(&empty_report()).unwrap());
+    }
+
+    #[test]
+    fn partial_reports_are_rendered_as_json() {
+        let expected = r#"{
+  "auto_scaling_group": "nomad-clients",
+  "started_at": "2021-03-04T05:06:07Z",
+  "finished_at": "2021-03-04T06:10:00Z",
+  "drains": [
+    {
+      "drain": {
+        "instance_id": "i-web-1",
+        "availability_zone": "ap-southeast-1a",
+        "node_id": "web-1-id",
+        "node_name": "web-1",
+        "datacenter": "dc1"
+      },
+      "started_at": "2021-03-04T05:06:07Z",
+      "completed_at": "2021-03-04T06:08:12Z",
+      "allocations": 3,
+      "error": null
+    },
+    {
+      "drain": {
+        "instance_id": "i-web|2",
+        "availability_zone": "ap-southeast-1b",
+        "node_id": "web|2-id",
+        "node_name": "web|2",
+        "datacenter": "dc1"
+      },
+      "started_at": "2021-03-04T06:08:12Z",
+      "completed_at": null,
+      "allocations": 0,
+      "error": "Nomad responded with <500>"
+    }
+  ],
+  "skipped": [
+    {
+      "instance_id": "i-web-3",
+      "availability_zone": "ap-southeast-1c",
+      "node_id": "web-3-id",
+      "node_name": "web-3",
+      "datacenter": "dc1"
+    }
+  ]
+}"#;
+        assert_eq!(expected, json(&partial_report()).unwrap());
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use lambda_runtime::Context;
+use log::{info, warn};
+use nomad_drain::clock::{Clock, SystemClock};
+
+/// Minimum amount of time worth spending on monitoring a drain
+const MINIMUM_MONITOR_TIME: Duration = Duration::from_secs(10);
+
+/// Stages of the handler, in the order they are executed
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Stage {
+    /// Retrieving Vault and Nomad tokens
+    Auth,
+    /// Finding the Nomad node for the instance
+    Lookup,
+    /// Waiting while drains are paused
+    Pause,
+    /// Marking the node ineligible and submitting the drain
+    Drain,
+    /// Monitoring the drain until it is complete
+    Monitor,
+    /// Running a Systems Manager command on the instance
+    Command,
+    /// Completing the lifecycle action
+    Complete,
+}
+
+impl Stage {
+    const ALL: [Stage; 7] = [
+        Stage::Auth,
+        Stage::Lookup,
+        Stage::Pause,
+        Stage::Drain,
+        Stage::Monitor,
+        Stage::Command,
+        Stage::Complete,
+    ];
+
+    /// Time reserved for the stage by default. Waiting while paused and monitoring take whatever
+    /// is left over, and commands are only reserved time when they are configured.
+    fn reserved(self) -> Duration {
+        match self {
+            Stage::Auth => Duration::from_secs(30),
+            Stage::Lookup => Duration::from_secs(60),
+            Stage::Pause => Duration::from_secs(0),
+            Stage::Drain => Duration::from_secs(30),
+            Stage::Monitor => Duration::from_secs(0),
+            Stage::Command => Duration::from_secs(0),
+            Stage::Complete => Duration::from_secs(30),
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stage::Auth => write!(f, "Auth"),
+            Stage::Lookup => write!(f, "Lookup"),
+            Stage::Pause => write!(f, "Pause"),
+            Stage::Drain => write!(f, "Drain"),
+            Stage::Monitor => write!(f, "Monitor"),
+            Stage::Command => write!(f, "Command"),
+            Stage::Complete => write!(f, "Complete"),
+        }
+    }
+}
+
+/// Execution budget for the handler, allocated per stage from the remaining invocation time
+///
+/// Each stage is time boxed to its allowance, which ends at `stage_deadline`.
+#[derive(Clone, Debug)]
+pub struct Budget {
+    clock: Arc<dyn Clock>,
+    deadline: Instant,
+    command_time: Duration,
+    stage_started: Option<(Stage, Instant, Duration)>,
+}
+
+impl Budget {
+    /// Create a budget with the provided amount of time remaining
+    pub fn new(remaining: Duration) -> Self {
+        Self::with_clock(remaining, Arc::new(SystemClock))
+    }
+
+    /// Create a budget with the provided amount of time remaining on the clock
+    pub fn with_clock(remaining: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            deadline: clock.now() + remaining,
+            clock,
+            command_time: Duration::from_secs(0),
+            stage_started: None,
+        }
+    }
+
+    /// Reserve time for running a command on the instance after monitoring the drain
+    pub fn with_command_time(mut self, command_time: Duration) -> Self {
+        self.command_time = command_time;
+        self
+    }
+
+    /// Time reserved for a stage
+    fn reserved(&self, stage: Stage) -> Duration {
+        match stage {
+            Stage::Command => self.command_time,
+            _ => stage.reserved(),
+        }
+    }
+
+    /// Create a budget from the time remaining in the Lambda invocation
+    pub fn from_context(context: &Context) -> Self {
+        let remaining = context.get_time_remaining_millis() as u64;
+        Self::new(Duration::from_millis(remaining))
+    }
+
+    /// Instant the invocation ends at
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Time remaining in the invocation
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(self.clock.now())
+    }
+
+    /// Instant the current stage has to end by, or the end of the invocation outside of stages
+    pub fn stage_deadline(&self) -> Instant {
+        match self.stage_started {
+            Some((_, started, allowance)) => std::cmp::min(started + allowance, self.deadline),
+            None => self.deadline,
+        }
+    }
+
+    /// Time available to the stage after reserving time for all the stages after it
+    pub fn allowance(&self, stage: Stage) -> Duration {
+        let reserved_after = Stage::ALL
+            .iter()
+            .filter(|later| **later > stage)
+            .map(|later| self.reserved(*later))
+            .sum();
+        self.remaining().saturating_sub(reserved_after)
+    }
+
+    /// Whether there is enough time left to monitor a drain
+    pub fn can_monitor(&self) -> bool {
+        self.allowance(Stage::Monitor) >= MINIMUM_MONITOR_TIME
+    }
+
+    /// Mark the start of a stage, ending the previous stage if any. Returns the stage allowance.
+    pub fn start(&mut self, stage: Stage) -> Duration {
+        self.end();
+        let allowance = self.allowance(stage);
+        if allowance < self.reserved(stage) {
+            warn!(
+                "Stage {} has {:?} left, less than the {:?} reserved for it",
+                stage,
+                allowance,
+                self.reserved(stage)
+            );
+        } else {
+            info!("Stage {} has {:?} allocated", stage, allowance);
+        }
+        self.stage_started = Some((stage, self.clock.now(), allowance));
+        allowance
+    }
+
+    /// Mark the end of the current stage, if any
+    pub fn end(&mut self) {
+        if let Some((stage, started, allowance)) = self.stage_started.take() {
+            let elapsed = self.clock.elapsed(started);
+            if elapsed > allowance {
+                warn!(
+                    "Stage {} overran its allocation of {:?} by {:?}",
+                    stage,
+                    allowance,
+                    elapsed - allowance
+                );
+            } else {
+                info!("Stage {} completed in {:?}", stage, elapsed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nomad_drain::clock::SimulatedClock;
+
+    fn budget(remaining: u64) -> (Budget, Arc<SimulatedClock>) {
+        let clock = Arc::new(SimulatedClock::new());
+        let budget = Budget::with_clock(Duration::from_secs(remaining), clock.clone());
+        (budget, clock)
+    }
+
+    #[test]
+    fn allowances_reserve_time_for_later_stages() {
+        let (budget, _) = budget(900);
+        assert_eq!(Duration::from_secs(780), budget.allowance(Stage::Auth));
+        assert_eq!(Duration::from_secs(840), budget.allowance(Stage::Lookup));
+        assert_eq!(Duration::from_secs(870), budget.allowance(Stage::Monitor));
+        assert_eq!(Duration::from_secs(900), budget.allowance(Stage::Complete));
+
+        let budget = budget.with_command_time(Duration::from_secs(120));
+        assert_eq!(Duration::from_secs(750), budget.allowance(Stage::Monitor));
+        assert_eq!(Duration::from_secs(870), budget.allowance(Stage::Command));
+    }
+
+    #[test]
+    fn monitoring_needs_a_minimum_amount_of_time() {
+        let (budget, clock) = budget(60);
+        assert!(budget.can_monitor());
+
+        clock.advance(Duration::from_secs(25));
+        assert_eq!(Duration::from_secs(5), budget.allowance(Stage::Monitor));
+        assert!(!budget.can_monitor());
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(Duration::from_secs(0), budget.remaining());
+        assert_eq!(Duration::from_secs(0), budget.allowance(Stage::Monitor));
+    }
+
+    #[test]
+    fn stages_are_time_boxed_to_their_allowance() {
+        let (mut budget, clock) = budget(300);
+        assert_eq!(budget.deadline(), budget.stage_deadline());
+
+        let allowance = budget.start(Stage::Auth);
+        assert_eq!(Duration::from_secs(180), allowance);
+        assert_eq!(clock.now() + allowance, budget.stage_deadline());
+
+        // Overrunning a stage eats into the allowance of the later ones
+        clock.advance(Duration::from_secs(200));
+        let allowance = budget.start(Stage::Monitor);
+        assert_eq!(Duration::from_secs(70), allowance);
+        assert_eq!(clock.now() + allowance, budget.stage_deadline());
+        assert!(budget.stage_deadline() <= budget.deadline());
+
+        budget.end();
+        assert_eq!(budget.deadline(), budget.stage_deadline());
+    }
+}
@@ -1,22 +1,56 @@
+mod budget;
 mod error;
+mod pipeline;
 
-use std::borrow::Cow;
+use std::fmt;
+use std::time::{Duration, Instant};
 
 use aws_lambda_events::event::autoscaling::AutoScalingEvent as Event;
-use failure::Fail;
+use aws_lambda_events::event::sqs::SqsEvent;
 use lambda_runtime::{error::HandlerError, lambda, Context};
-use log::{error, info};
-use rusoto_autoscaling::{Autoscaling, AutoscalingClient, CompleteLifecycleActionType};
+use log::{error, info, warn};
+use rusoto_autoscaling::{Autoscaling, AutoscalingClient};
+use rusoto_core::Region;
 use serde::{Deserialize, Serialize};
 
-use nomad_drain::nomad::Client as NomadClient;
-use nomad_drain::vault::Client as VaultClient;
-use nomad_drain::Secret;
+use nomad_drain::asg::LifecycleActionResult;
+use nomad_drain::aws::VaultAwsAuthIamPayload;
+use nomad_drain::batch::BatchPolicy;
+use nomad_drain::checkpoint::{Checkpoint, STEP_FUNCTIONS_STATE_LIMIT};
+use nomad_drain::clock::SystemClock;
+use nomad_drain::context::RequestContext;
+use nomad_drain::degraded::DegradedOperations;
+use nomad_drain::events::{AsgEventDetails, AsgLifecycleTransition, LifecycleNotification};
+use nomad_drain::metrics::{EmbeddedMetrics, MetricsSink};
+use nomad_drain::nomad::{
+    Client as NomadClient, ClusterIdentity, DrainSpec, MonitorCursor, ReadOnlyGuard,
+};
+use nomad_drain::pause::{DynamoDbPauseFlag, PauseSwitch};
+use nomad_drain::retry::RetryPolicy;
+use nomad_drain::ssm::Document;
+use nomad_drain::tls::TlsConfig;
+use nomad_drain::vault::{AwsIam, Client as VaultClient};
+use nomad_drain::{BuildInfo, Secret};
 
+use crate::budget::{Budget, Stage};
 use crate::error::Error;
+use crate::pipeline::{
+    complete_lifecycle_action, CompleteLifecycle, DrainNode, Invocation, LookupNode, Pipeline,
+    ReachNomad, RegisterNode, RunCommand, TargetCluster, ValidateEvent, WaitWhilePaused,
+};
+
+/// Maximum size of the continuation returned to check the status of a drain with, leaving room for
+/// the rest of the result in the state of a Step Functions state machine
+const CONTINUATION_LIMIT: usize = STEP_FUNCTIONS_STATE_LIMIT / 2;
 
 #[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 struct Config {
+    /// Entry point of the function, unless the event selects another in the `NomadDrainHandler`
+    /// key of its detail
+    #[serde(default)]
+    handler_mode: HandlerMode,
+
     /// Address of Nomad server
     /// Deserialized from `NOMAD_ADDR`
     #[serde(rename = "nomad_addr")]
@@ -27,8 +61,138 @@ struct Config {
     use_nomad_token: bool,
 
     /// Nomad token, if any
+    ///
+    /// During token rotation, this can be a comma separated list of candidate tokens. The first
+    /// token that Nomad accepts is used.
     nomad_token: Option<Secret>,
 
+    /// Region to send Nomad requests to. Set to the authoritative region in federated clusters
+    /// so that tokens retrieved from Vault are usable before they are replicated.
+    nomad_region: Option<String>,
+
+    /// Seconds to wait for a Nomad token retrieved from Vault to be accepted
+    #[serde(default = "Config::default_nomad_token_replication_window")]
+    nomad_token_replication_window: u64,
+
+    /// Seconds to wait in each Nomad blocking query while monitoring drains. Shortened
+    /// automatically when the invocation is running out of time.
+    #[serde(default = "Config::default_nomad_wait_timeout")]
+    nomad_wait_timeout: u64,
+    /// Same as `nomad_wait_timeout`, which it takes precedence over
+    nomad_blocking_wait: Option<u64>,
+    /// Seconds before Nomad requests time out. Defaults to 6 minutes. Blocking queries are
+    /// shortened to fit.
+    nomad_timeout: Option<u64>,
+
+    /// Seconds to keep trying to reach Nomad before completing the lifecycle action of terminating
+    /// instances without draining them. If unset, invocations fail when Nomad is unreachable and
+    /// lifecycle actions are left to time out.
+    nomad_unreachable_timeout: Option<u64>,
+    /// Result to complete lifecycle actions with when Nomad is unreachable
+    #[serde(default = "Config::default_nomad_unreachable_result")]
+    nomad_unreachable_result: LifecycleActionResult,
+    /// Result to complete lifecycle actions with when the invocation fails. If unset, lifecycle
+    /// actions of failed invocations are left to time out with the default result of the hook.
+    lifecycle_failure_result: Option<LifecycleActionResult>,
+
+    /// Search Nomad nodes that are down or initializing when looking up the instance
+    #[serde(default)]
+    nomad_include_down_nodes: bool,
+    /// Fail instead of listing more Nomad nodes than this, on very large clusters
+    nomad_max_nodes: Option<usize>,
+    /// List Nomad nodes in shards by node ID when the instance has to be searched for in every
+    /// node, keeping each listing within `nomad_max_nodes`
+    #[serde(default)]
+    nomad_shard_node_lists: bool,
+
+    /// Purge the Nomad node once its drain is complete, instead of waiting for Nomad to garbage
+    /// collect it after the instance terminates
+    #[serde(default)]
+    nomad_purge_after_drain: bool,
+
+    /// Drain deadline in seconds, unless overridden by the event
+    #[serde(default = "Config::default_nomad_drain_deadline")]
+    nomad_drain_deadline: u64,
+    /// Whether system jobs are ignored when draining, unless overridden by the event
+    #[serde(default)]
+    nomad_drain_ignore_system_jobs: bool,
+    /// Monitor drains for as long as the invocation allows. Otherwise, the lifecycle action is
+    /// completed as soon as the drain is submitted.
+    #[serde(default = "Config::default_nomad_drain_monitor")]
+    nomad_drain_monitor: bool,
+    /// Seconds to monitor a drain for before giving up and completing the lifecycle action
+    /// anyway, even if the invocation has time left
+    nomad_drain_max_duration: Option<u64>,
+    /// JSON list of rules deciding what happens to allocations of batch jobs on drained nodes,
+    /// e.g. `[{"job": "reports-*", "action": "wait"}]`. Actions are `wait` for them to complete,
+    /// `migrate` them immediately, or leave them to the drain `deadline`, which is the default.
+    nomad_batch_policy: Option<String>,
+    /// Leave the lifecycle action pending when the invocation runs out of time monitoring a drain,
+    /// and return the cursor to resume monitoring from. The caller, e.g. a Step Functions state
+    /// machine, re-invokes the function with the cursor in the `NomadDrainMonitorCursor` key of
+    /// the event detail, or with the result and its `continuation`. Without
+    /// `nomad_drain_monitor`, drains are only monitored by later invocations.
+    #[serde(default)]
+    nomad_drain_time_sliced: bool,
+
+    /// Do not verify that drained nodes are ineligible, or mark them ineligible, for tokens that
+    /// are only allowed to drain nodes. Draining a node makes it ineligible in any case.
+    #[serde(default)]
+    nomad_skip_eligibility: bool,
+
+    /// Handle `InstanceLaunching` events by waiting for the Nomad node of the instance to be ready
+    /// and marking it eligible
+    #[serde(default)]
+    handle_launching_events: bool,
+    /// Seconds to wait for the Nomad node of a launching instance to be ready
+    #[serde(default = "Config::default_node_registration_timeout")]
+    node_registration_timeout: u64,
+
+    /// Seconds between lifecycle action heartbeats while monitoring a drain. Keep this below the
+    /// heartbeat timeout of the lifecycle hook.
+    #[serde(default = "Config::default_lifecycle_heartbeat_interval")]
+    lifecycle_heartbeat_interval: u64,
+
+    /// Systems Manager document to run on terminating instances after they are drained, e.g. to
+    /// flush logs or unmount volumes
+    ssm_document_name: Option<String>,
+    /// Parameters of the Systems Manager document as a JSON object of string arrays
+    ssm_document_parameters: Option<String>,
+    /// Seconds to wait for the Systems Manager command to complete
+    #[serde(default = "Config::default_ssm_command_timeout")]
+    ssm_command_timeout: u64,
+
+    /// Maximum attempts for Nomad and Vault requests failing with connection errors or `5xx`
+    #[serde(default = "Config::default_retry_max_attempts")]
+    retry_max_attempts: u32,
+    /// Milliseconds to wait before the first retry, doubling for every subsequent retry
+    #[serde(default = "Config::default_retry_base_delay")]
+    retry_base_delay: u64,
+
+    /// Name of the Nomad cluster, used to attribute drain events
+    nomad_cluster_name: Option<String>,
+    /// Nomad region. Retrieved from the Nomad agent if not set
+    nomad_cluster_region: Option<String>,
+    /// Nomad datacenter. Retrieved from the Nomad agent if not set
+    nomad_cluster_datacenter: Option<String>,
+
+    /// DynamoDB table holding a flag to pause drains with. New drains wait while the flag is set.
+    drain_pause_table: Option<String>,
+    /// Key of the item holding the pause flag in the DynamoDB table
+    #[serde(default = "Config::default_drain_pause_key")]
+    drain_pause_key: String,
+
+    /// S3 bucket to write a summary of every handled event to, for auditing
+    audit_bucket: Option<String>,
+    /// Prefix of the S3 keys audit summaries are written to
+    #[serde(default)]
+    audit_prefix: String,
+
+    /// SNS topic to publish the outcome of every handled event to, including failures
+    sns_topic_arn: Option<String>,
+    /// CloudWatch namespace to emit drain metrics to, as Embedded Metric Format log lines
+    metrics_namespace: Option<String>,
+
     #[serde(flatten)]
     vault_config: VaultConfig,
     // Implicitly: RUST_LOG via `env_logger.
@@ -36,44 +200,271 @@ struct Config {
 }
 
 #[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 struct VaultConfig {
     vault_token: Option<Secret>,
 
     #[serde(rename = "vault_addr")]
     vault_address: Option<String>,
+    /// Vault Enterprise namespace to login and make requests in
+    vault_namespace: Option<String>,
+    /// Seconds before Vault requests time out. Defaults to 30 seconds.
+    vault_timeout: Option<u64>,
+    /// Connect to Vault ahead of the login, to shave the TLS handshake off the time it takes to
+    /// start draining
+    #[serde(default)]
+    vault_warm_up: bool,
 
     auth_path: Option<String>,
     auth_role: Option<String>,
     auth_header_value: Option<String>,
+    /// Seconds to keep retrying Vault logins that fail because the IAM principal is not found
+    #[serde(default = "VaultConfig::default_auth_retry_window")]
+    auth_retry_window: u64,
 
     nomad_path: Option<String>,
     nomad_role: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
-#[serde(rename_all = "PascalCase")]
-struct AsgEventDetails {
-    pub lifecycle_action_token: String,
-    pub auto_scaling_group_name: String,
-    #[serde(rename = "EC2InstanceId")]
-    pub instance_id: String,
-    pub lifecycle_transition: AsgLifecycleTransition,
-    pub lifecycle_hook_name: String,
+/// Entry points of the function
+///
+/// Drains that outlast a single invocation can be orchestrated by a Step Functions state machine
+/// looping over `check_drain_status`, with a `Wait` state, until the `state` of the result is
+/// `done`.
+#[derive(Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+enum HandlerMode {
+    /// Drain the node, monitor the drain and complete the lifecycle action in one invocation
+    #[default]
+    Full,
+    /// Submit the drain and return a `continuation` to check its status with, leaving the
+    /// lifecycle action pending
+    InitiateDrain,
+    /// Monitor the drain of the `continuation` the function is invoked with for as long as the
+    /// invocation allows, and complete the lifecycle action once the drain is complete
+    CheckDrainStatus,
+}
+
+/// Payloads the function is invoked with
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
+enum Payload {
+    /// Lifecycle hook notifications delivered to an SQS queue
+    Sqs(SqsEvent),
+    /// The result of an earlier invocation, to check the status of its drain
+    Continuation(Continuation),
+    /// A lifecycle hook event delivered through CloudWatch Events or EventBridge
+    Event(Event),
+}
+
+/// Output of the function, depending on its payload
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
+enum Output {
+    Result(HandlerResult),
+    Batch(BatchResponse),
+}
+
+/// Records of an SQS batch that failed and should be delivered again
+///
+/// Only records listed here are retried when the event source mapping reports batch item
+/// failures. Otherwise, the whole batch is retried if any record failed.
+#[derive(Serialize, Debug, Clone, Default, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct BatchResponse {
+    batch_item_failures: Vec<BatchItemFailure>,
+}
+
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct BatchItemFailure {
+    /// Message ID of the record
+    item_identifier: String,
+}
+
+/// Result of an earlier invocation with a drain in progress. Other keys of the result are ignored.
+#[derive(Deserialize, Debug, Clone)]
+struct Continuation {
+    continuation: Checkpoint,
+}
+
+/// State of a drain in progress, carried between invocations in a `Checkpoint`
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+struct DrainContinuation {
+    event: AsgEventDetails,
+    monitor_cursor: MonitorCursor,
+    eval_ids: Vec<String>,
+    allocations: usize,
 }
 
+/// Detail of the events the function is invoked with
 #[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
-enum AsgLifecycleTransition {
-    #[serde(rename = "autoscaling:EC2_INSTANCE_LAUNCHING")]
-    InstanceLaunching,
-    #[serde(rename = "autoscaling:EC2_INSTANCE_TERMINATING")]
-    InstanceTerminating,
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct InvocationDetail {
+    #[serde(flatten)]
+    event: AsgEventDetails,
+    /// Cursor to resume monitoring a drain from, when an invocation is continuing the work of an
+    /// earlier one
+    #[serde(rename = "NomadDrainMonitorCursor", default)]
+    monitor_cursor: Option<MonitorCursor>,
+    /// Entry point to handle the event with, instead of the configured one
+    #[serde(rename = "NomadDrainHandler", default)]
+    handler_mode: Option<HandlerMode>,
 }
 
- #[derive(Serialize, Debug, Clone, Eq, PartialEq)] 
+/// Per-event overrides supplied as JSON in the lifecycle hook `NotificationMetadata`
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct EventOverrides {
+    /// Drain deadline in seconds
+    drain_deadline: Option<u64>,
+    /// Whether system jobs are ignored when draining
+    ignore_system_jobs: Option<bool>,
+    /// Do not make any changes to Nomad. The lifecycle action is still completed.
+    #[serde(default)]
+    dry_run: bool,
+    /// Only handle the event if the configured Nomad cluster name matches
+    target_cluster: Option<String>,
+}
+
+impl EventOverrides {
+    /// Parse overrides from the notification metadata of the event, if any
+    pub fn from_event(event: &AsgEventDetails) -> Result<Self, serde_json::Error> {
+        event.metadata()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 struct HandlerResult {
     pub instance_id: String,
-    pub node_id: String,
+    pub node_id: Option<String>,
+    pub cluster: ClusterIdentity,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Whether the drain is still in progress, and its status has to be checked again
+    pub state: HandlerState,
+    /// State to invoke the function with, with the rest of the result, to check the status of the
+    /// drain while it is in progress
+    pub continuation: Option<Checkpoint>,
+    pub drained: bool,
+    /// IDs of the evaluations created by the drain, to track placement of migrated allocations
+    pub eval_ids: Vec<String>,
+    /// Nomad could not be reached, and the lifecycle action was completed without draining
+    pub nomad_unreachable: bool,
+    /// Time at which allocations that had not migrated when monitoring stopped are stopped, if
+    /// the drain has a deadline
+    pub force_deadline: Option<chrono::DateTime<chrono::Utc>>,
+    /// Seconds left until `force_deadline` at `timestamp`
+    pub seconds_until_force_deadline: Option<u64>,
+    /// Number of allocations on the node when it was drained, which are migrated
+    pub allocations: usize,
+    /// Seconds the drain took to complete, if it was monitored until it completed
+    pub drain_seconds: Option<u64>,
+    /// Where to resume monitoring the drain from in another invocation, if monitoring is time
+    /// sliced and this invocation ran out of time
+    pub monitor_cursor: Option<MonitorCursor>,
+    pub dry_run: bool,
+    pub warnings: Vec<String>,
+    /// Optional operations that failed without failing the invocation
+    pub degraded: DegradedOperations,
+    pub build: BuildInfo,
+}
+
+/// Whether an invocation is done with the event
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+enum HandlerState {
+    /// The event has been handled, and its lifecycle action completed unless it failed
+    Done,
+    /// The drain is in progress, and the lifecycle action pending
+    InProgress,
+}
+
+/// Message published to SNS once an event has been handled, successfully or not
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct DrainNotification {
+    pub instance_id: String,
+    pub node_id: Option<String>,
+    pub cluster: ClusterIdentity,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub outcome: DrainOutcome,
+    /// Number of allocations on the node when it was drained, which are migrated
+    pub allocations: usize,
+    /// Seconds the drain took to complete, if it was monitored until it completed
+    pub drain_seconds: Option<u64>,
+    /// Error the invocation failed with
+    pub error: Option<String>,
+}
+
+/// Outcome of an event in a `DrainNotification`
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+enum DrainOutcome {
+    /// The node was drained
+    Drained,
+    /// The event was handled without draining, e.g. on dry runs or for nodes that are down
+    NotDrained,
+    /// The invocation failed
+    Failed,
+}
+
+impl fmt::Display for DrainOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DrainOutcome::Drained => write!(f, "Drained"),
+            DrainOutcome::NotDrained => write!(f, "Not drained"),
+            DrainOutcome::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+impl DrainNotification {
+    /// Subject of the SNS message
+    fn subject(&self) -> String {
+        format!("nomad-drain: {} {}", self.outcome, self.instance_id)
+    }
+}
+
+/// A destination for the notifications of handled events
+trait Notifier: fmt::Debug {
+    /// Publish the notification
+    fn publish(&self, notification: &DrainNotification) -> Result<(), Error>;
+}
+
+/// SNS topic that notifications are published to, in the region of the topic
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct SnsTopic {
+    arn: String,
+}
+
+impl Notifier for SnsTopic {
+    fn publish(&self, notification: &DrainNotification) -> Result<(), Error> {
+        let message = serde_json::to_string(notification)?;
+        let credentials = nomad_drain::get_aws_credentials()?;
+        let region = nomad_drain::aws::arn_region(&self.arn).unwrap_or_default();
+        nomad_drain::aws::publish_sns_message(
+            &credentials,
+            &region,
+            &self.arn,
+            Some(&notification.subject()),
+            &message,
+            None,
+        )?;
+        info!("Notification published to SNS topic {}", self.arn);
+        Ok(())
+    }
+}
+
+impl VaultConfig {
+    const fn default_auth_retry_window() -> u64 {
+        60
+    }
 }
 
 impl Config {
@@ -82,72 +473,430 @@ impl Config {
         Ok(envy::from_env()?)
     }
 
+    /// Wait time of Nomad blocking queries, shortened to complete within the request timeout
+    ///
+    /// Nomad adds a random jitter of up to a sixteenth of the wait time to blocking queries.
+    fn nomad_wait_timeout(&self) -> Duration {
+        let wait_timeout =
+            Duration::from_secs(self.nomad_blocking_wait.unwrap_or(self.nomad_wait_timeout));
+        match self.nomad_timeout {
+            Some(timeout) if wait_timeout * 17 / 16 >= Duration::from_secs(timeout) => {
+                let shortened = Duration::from_secs(timeout) * 15 / 16;
+                warn!(
+                    "Nomad blocking queries of {:?} would time out after {}s. Waiting {:?} instead",
+                    wait_timeout, timeout, shortened
+                );
+                shortened
+            }
+            _ => wait_timeout,
+        }
+    }
+
+    /// Split the configured Nomad token into its candidate tokens
+    fn nomad_tokens(tokens: &Secret) -> Result<Vec<&str>, Error> {
+        let tokens: Vec<&str> = tokens
+            .expose_secret()
+            .split(',')
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .collect();
+        if tokens.is_empty() {
+            Err(Error::MissingConfiguration("nomad_token".to_string()))?;
+        }
+        Ok(tokens)
+    }
+
     const fn default_use_nomad_token() -> bool {
         true
     }
+
+    const fn default_nomad_token_replication_window() -> u64 {
+        30
+    }
+
+    const fn default_nomad_wait_timeout() -> u64 {
+        300
+    }
+
+    const fn default_nomad_unreachable_result() -> LifecycleActionResult {
+        LifecycleActionResult::Continue
+    }
+
+    const fn default_nomad_drain_deadline() -> u64 {
+        // Lambda has a max runtime of 900s
+        600
+    }
+
+    const fn default_nomad_drain_monitor() -> bool {
+        true
+    }
+
+    const fn default_node_registration_timeout() -> u64 {
+        300
+    }
+
+    const fn default_lifecycle_heartbeat_interval() -> u64 {
+        60
+    }
+
+    const fn default_ssm_command_timeout() -> u64 {
+        120
+    }
+
+    fn default_drain_pause_key() -> String {
+        "nomad-drain".to_string()
+    }
+
+    const fn default_retry_max_attempts() -> u32 {
+        3
+    }
+
+    const fn default_retry_base_delay() -> u64 {
+        500
+    }
+
+    /// Policy to retry Nomad and Vault requests with
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.retry_max_attempts,
+            base_delay: Duration::from_millis(self.retry_base_delay),
+            ..Default::default()
+        }
+    }
+
+    /// Systems Manager document to run on terminating instances, if any
+    pub fn ssm_document(&self) -> Result<Option<Document>, Error> {
+        let name = match self.ssm_document_name {
+            Some(ref name) => name.clone(),
+            None => return Ok(None),
+        };
+        let parameters = match self.ssm_document_parameters {
+            Some(ref parameters) => serde_json::from_str(parameters)?,
+            None => Default::default(),
+        };
+        Ok(Some(Document { name, parameters }))
+    }
+
+    /// What to do with allocations of batch jobs
+    pub fn batch_policy(&self) -> Result<BatchPolicy, Error> {
+        match self.nomad_batch_policy {
+            Some(ref policy) => Ok(serde_json::from_str(policy)?),
+            None => Ok(Default::default()),
+        }
+    }
+
+    /// Flag to pause drains with, if any
+    pub fn pause_flag(&self) -> Option<DynamoDbPauseFlag> {
+        self.drain_pause_table.as_ref().map(|table| {
+            DynamoDbPauseFlag::new(Default::default(), table, &self.drain_pause_key, None)
+        })
+    }
+
+    /// S3 key of the audit summary for a result
+    fn audit_key(&self, result: &HandlerResult) -> String {
+        format!(
+            "{}{}/{}.json",
+            self.audit_prefix,
+            result.instance_id,
+            result.timestamp.format("%Y%m%dT%H%M%SZ")
+        )
+    }
+
+    /// Identity of the Nomad cluster as configured
+    pub fn cluster_identity(&self) -> ClusterIdentity {
+        ClusterIdentity {
+            name: self.nomad_cluster_name.clone(),
+            region: self.nomad_cluster_region.clone(),
+            datacenter: self.nomad_cluster_datacenter.clone(),
+        }
+    }
+}
+
+/// How the outcome of invocations is reported, which is needed even when the rest of the
+/// configuration cannot be loaded
+#[derive(Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+struct Reporting {
+    lifecycle_failure_result: Option<LifecycleActionResult>,
+    sns_topic_arn: Option<String>,
+    metrics_namespace: Option<String>,
+}
+
+impl Reporting {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            lifecycle_failure_result: config.lifecycle_failure_result,
+            sns_topic_arn: config.sns_topic_arn.clone(),
+            metrics_namespace: config.metrics_namespace.clone(),
+        }
+    }
+
+    /// Read the environment variables of the reporting settings directly, for when the
+    /// configuration cannot be loaded
+    pub fn from_environment() -> Self {
+        envy::from_env().unwrap_or_else(|e| {
+            error!("Unable to read how failures are reported: {}", e);
+            Default::default()
+        })
+    }
+
+    /// SNS topic to publish notifications to, if any
+    pub fn sns_topic(&self) -> Option<SnsTopic> {
+        self.sns_topic_arn
+            .as_ref()
+            .map(|arn| SnsTopic { arn: arn.clone() })
+    }
+
+    /// Sink to emit drain metrics to, if any
+    pub fn metrics_sink(&self) -> Option<EmbeddedMetrics> {
+        self.metrics_namespace
+            .as_ref()
+            .map(|namespace| EmbeddedMetrics::new(namespace))
+    }
+
+    /// Report the outcome of the invocation, which failed with `error` if any, publishing the
+    /// notification with `notifier`
+    ///
+    /// The lifecycle action of a failed invocation is completed with `lifecycle_failure_result`,
+    /// if set. Failures to notify and emit metrics are recorded as degraded operations.
+    pub fn report(
+        &self,
+        autoscaling: &dyn Autoscaling,
+        notifier: Option<&dyn Notifier>,
+        invocation: &mut Invocation,
+        error: Option<&Error>,
+    ) {
+        if let (Some(e), Some(failure_result)) = (error, self.lifecycle_failure_result) {
+            error!(
+                "Invocation failed: completing the lifecycle action with {}: {}",
+                failure_result, e
+            );
+            if let Err(e) =
+                complete_lifecycle_action(autoscaling, &invocation.event, failure_result)
+            {
+                error!("Failed to complete the lifecycle action: {}", e);
+            }
+        }
+        if let Some(notifier) = notifier {
+            let publish = notifier.publish(&invocation.notification(error));
+            invocation.degraded.check("SNS notification", publish);
+        }
+        if let Some(sink) = self.metrics_sink() {
+            let metrics = invocation.metrics(error.is_some());
+            let emit = sink.emit(&invocation.cluster, &metrics);
+            invocation.degraded.check("metrics", emit);
+        }
+    }
 }
 
 #[derive(Debug)]
 struct Clients {
     pub nomad_client: NomadClient,
+    /// Vault client used to retrieve the Nomad token, if any. Held until the end of the invocation
+    /// so that a token obtained by logging in is revoked when the clients are dropped.
     pub vault_client: Option<VaultClient>,
+    /// Vault lease of the Nomad token, if it was retrieved from Vault
+    pub nomad_token_lease_id: Option<String>,
 }
 
 impl Clients {
-    pub fn new(config: &Config) -> Result<Self, Error> {
+    /// Build the clients, making every request in `context`
+    ///
+    /// Logging in, retrieving the Nomad token and selecting candidate tokens have to be done by
+    /// `auth_deadline`. Failures to extend the lifetime of the Vault token are recorded in
+    /// `degraded`.
+    pub fn new(
+        config: &Config,
+        context: &RequestContext,
+        auth_deadline: Instant,
+        degraded: &mut DegradedOperations,
+    ) -> Result<Self, Error> {
         let mut vault_client = None;
+        let mut nomad_token_lease_id = None;
+        let auth_context = context.clone().with_deadline(auth_deadline);
 
         info!("Building Nomad Client");
-        let nomad_token =
-            if config.use_nomad_token {
-                info!("Using Nomad token");
-
-                Some(match config.nomad_token {
-                    Some(ref token) => Cow::Borrowed(token.as_str()),
-                    None => {
-                        info!("No Nomad Token configured. Retrieving from Vault");
-                        vault_client = Some(Self::get_vault_client(config)?);
-
-                        let nomad_path =
-                            config.vault_config.nomad_path.as_ref().ok_or_else(|| {
-                                Error::MissingConfiguration("nomad_path".to_string())
-                            })?;
-                        let nomad_role =
-                            config.vault_config.nomad_role.as_ref().ok_or_else(|| {
-                                Error::MissingConfiguration("nomad_role".to_string())
-                            })?;
-
-                        Cow::Owned(
-                            vault_client
-                                .as_ref()
-                                .unwrap_or_else(|| unreachable!("Should not be reachable!"))
-                                .get_nomad_token(nomad_path, nomad_role)?
-                                .0,
-                        )
+        // Read from `NOMAD_CACERT`, `NOMAD_CLIENT_CERT`, `NOMAD_CLIENT_KEY` and
+        // `NOMAD_TLS_SERVER_NAME`
+        let tls = TlsConfig::from_nomad_env();
+        let mut builder = NomadClient::builder(&config.nomad_address)
+            .with_context(auth_context.clone())
+            .with_retry_policy(config.retry_policy())
+            .with_wait_timeout(config.nomad_wait_timeout());
+        if let Some(timeout) = config.nomad_timeout {
+            builder = builder.with_timeout(Duration::from_secs(timeout));
+        }
+        if !tls.is_empty() {
+            builder = builder.with_tls(&tls);
+        }
+        if let Some(ref region) = config.nomad_region {
+            info!("Pinning Nomad requests to region {}", region);
+            builder = builder.with_region(region);
+        }
+        let mut nomad_client = if !config.use_nomad_token {
+            info!("No Nomad token in use");
+            builder.build()?.without_acl()
+        } else {
+            info!("Using Nomad token");
+            match config.nomad_token {
+                Some(ref tokens) => {
+                    let tokens = Config::nomad_tokens(tokens)?;
+                    if tokens.len() > 1 {
+                        info!("{} candidate Nomad tokens configured", tokens.len());
                     }
-                })
-            } else {
-                info!("No Nomad token in use");
-                None
-            };
+                    builder.with_candidate_tokens(&tokens).build()?
+                }
+                None => {
+                    info!("No Nomad Token configured. Retrieving from Vault");
+                    let client = Self::get_vault_client(config, &auth_context)?
+                        .with_retry_policy(config.retry_policy());
+
+                    let nomad_path = config
+                        .vault_config
+                        .nomad_path
+                        .as_ref()
+                        .ok_or_else(|| Error::MissingConfiguration("nomad_path".to_string()))?;
+                    let nomad_role = config
+                        .vault_config
+                        .nomad_role
+                        .as_ref()
+                        .ok_or_else(|| Error::MissingConfiguration("nomad_role".to_string()))?;
+
+                    if let Some(remaining) = context.remaining(&SystemClock) {
+                        Self::ensure_vault_token_lifetime(&client, remaining, degraded);
+                    }
+                    let nomad_token = client.get_nomad_token_with_lease(nomad_path, nomad_role)?;
+                    info!(
+                        "Retrieved Nomad token with accessor ID {} and a lease of {}s",
+                        nomad_token.accessor_id, nomad_token.lease_duration
+                    );
+                    // The lease is revoked at the end of the invocation, after the auth stage
+                    vault_client = Some(client.with_context(context.clone()));
+                    nomad_token_lease_id = Some(nomad_token.lease_id);
+                    builder
+                        .with_token(nomad_token.secret_id.expose_secret())
+                        .build()?
+                }
+            }
+        };
+
+        if nomad_token_lease_id.is_some() {
+            // Freshly minted tokens might not have been replicated to this region yet
+            nomad_client
+                .wait_for_token(Duration::from_secs(config.nomad_token_replication_window))?;
+        }
+        if config.use_nomad_token {
+            nomad_client.check_acl_mode()?;
+        }
 
-        let nomad_client = NomadClient::new(&config.nomad_address, nomad_token.as_ref(), None)?;
+        nomad_client = nomad_client
+            .with_context(context.clone())
+            .with_sharded_node_lists(config.nomad_shard_node_lists);
+        if let Some(max_nodes) = config.nomad_max_nodes {
+            nomad_client = nomad_client.with_max_nodes(max_nodes);
+        }
 
         Ok(Self {
             nomad_client,
             vault_client,
+            nomad_token_lease_id,
         })
     }
 
-    fn get_vault_client(config: &Config) -> Result<VaultClient, Error> {
+    /// Make sure that the Vault token lives for at least `needed`
+    ///
+    /// The Nomad token lease is revoked along with the Vault token, so a token expiring before
+    /// the end of the invocation is renewed if possible. Otherwise, the drain can only be
+    /// monitored until the token expires.
+    fn ensure_vault_token_lifetime(
+        client: &VaultClient,
+        needed: Duration,
+        degraded: &mut DegradedOperations,
+    ) {
+        match client.token_ttl() {
+            Ok(ref ttl) if ttl.outlives(needed) => {}
+            Ok(ref ttl) if ttl.renewable => {
+                info!(
+                    "Vault token expires in {}s, before the end of the invocation. Renewing",
+                    ttl.ttl
+                );
+                degraded.check("Vault token renewal", client.renew_self(Some(needed)));
+            }
+            Ok(ttl) => warn!(
+                "Vault token expires in {}s and cannot be renewed. Monitoring the drain will fail \
+                 after it expires",
+                ttl.ttl
+            ),
+            Err(e) => degraded.record("Vault token lookup", e),
+        }
+    }
+
+    /// Release credentials obtained for the invocation
+    ///
+    /// Failures are only recorded in `degraded`, since the credentials expire on their own
+    /// eventually.
+    pub fn teardown(&self, degraded: &mut DegradedOperations) {
+        if let (Some(vault_client), Some(lease_id)) = (
+            self.vault_client.as_ref(),
+            self.nomad_token_lease_id.as_ref(),
+        ) {
+            degraded.check(
+                "Nomad token lease revocation",
+                vault_client.revoke_lease(lease_id),
+            );
+        }
+    }
+
+    /// Identity of the Nomad cluster, filling in missing identifiers from the Nomad agent
+    pub fn cluster_identity(
+        &self,
+        config: &Config,
+        degraded: &mut DegradedOperations,
+    ) -> ClusterIdentity {
+        let identity = config.cluster_identity();
+        if !identity.is_incomplete() {
+            return identity;
+        }
+
+        match degraded.check(
+            "Nomad cluster identity lookup",
+            self.nomad_client.agent_self(),
+        ) {
+            Some(agent) => identity.with_agent(&agent),
+            None => identity,
+        }
+    }
+
+    fn get_vault_client(config: &Config, context: &RequestContext) -> Result<VaultClient, Error> {
         let vault_address = config
             .vault_config
             .vault_address
             .as_ref()
             .ok_or_else(|| Error::MissingConfiguration("vault_address".to_string()))?;
 
+        let tls = TlsConfig::from_vault_env();
+        let builder = VaultClient::builder(vault_address).with_context(context.clone());
+        let builder = if tls.is_empty() {
+            builder
+        } else {
+            builder.with_tls(&tls)
+        };
+        let builder = match config.vault_config.vault_namespace {
+            Some(ref namespace) => builder.with_namespace(namespace),
+            None => builder,
+        };
+        let builder = match config.vault_config.vault_timeout {
+            Some(timeout) => builder.with_timeout(Duration::from_secs(timeout)),
+            None => builder,
+        };
+        // Connects while AWS credentials are retrieved and the login request is signed
+        let builder = if config.vault_config.vault_warm_up {
+            builder.warm_up()?
+        } else {
+            builder
+        };
+
         match config.vault_config.vault_token {
-            Some(ref token) => Ok(VaultClient::new(vault_address, token, false, None)?),
+            Some(ref token) => Ok(builder.with_token(token.expose_secret()).build()?),
             None => {
                 info!("No Vault Token configured. Using AWS Credentials to retrieve from Vault");
                 let vault_auth_path = config
@@ -162,19 +911,21 @@ impl Clients {
                     .ok_or_else(|| Error::MissingConfiguration("auth_role".to_string()))?;
 
                 let aws_credentials = nomad_drain::get_aws_credentials()?;
-
-                Ok(nomad_drain::login_to_vault(
-                    vault_address,
-                    vault_auth_path,
-                    vault_auth_role,
+                let payload = VaultAwsAuthIamPayload::new(
                     &aws_credentials,
-                    config
-                        .vault_config
-                        .auth_header_value
-                        .as_ref()
-                        .map(|s| s.as_str()),
-                    None,
-                )?)
+                    config.vault_config.auth_header_value.as_deref(),
+                    None::<Region>,
+                );
+                Ok(builder
+                    .with_auth(AwsIam {
+                        path: vault_auth_path.to_string(),
+                        role: vault_auth_role.to_string(),
+                        payload,
+                    })
+                    .with_login_retry_window(Duration::from_secs(
+                        config.vault_config.auth_retry_window,
+                    ))
+                    .build()?)
             }
         }
     }
@@ -182,84 +933,547 @@ impl Clients {
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
+    #[cfg(feature = "schema")]
+    {
+        if std::env::args().nth(1).as_deref() == Some("schema") {
+            println!("{}", serde_json::to_string_pretty(&schemas())?);
+            return Ok(());
+        }
+    }
+    info!("nomad_drain {}", nomad_drain::build_info());
     lambda!(lambda_wrapper);
     Ok(())
 }
 
+/// JSON Schemas of the configuration from the environment, the event detail and override metadata
+/// the function is invoked with, and the result and notification it produces
+#[cfg(feature = "schema")]
+fn schemas() -> serde_json::Value {
+    use schemars::schema_for;
+
+    serde_json::json!({
+        "config": schema_for!(Config),
+        "event_detail": schema_for!(InvocationDetail),
+        "event_overrides": schema_for!(EventOverrides),
+        "result": schema_for!(HandlerResult),
+        "notification": schema_for!(DrainNotification),
+    })
+}
+
 #[allow(clippy::needless_pass_by_value)]
-fn lambda_wrapper(event: Event, context: Context) -> Result<HandlerResult, HandlerError> {
-    match lambda_handler(&event, &context) {
-        Ok(result) => Ok(result),
-        Err(e) => {
-            let mut error_output = vec![format!("{}", e)];
-            if let Some(backtrace) = e.backtrace() {
-                error_output.push(format!("Backtrace: {}", backtrace));
+fn lambda_wrapper(payload: Payload, context: Context) -> Result<Output, HandlerError> {
+    match payload {
+        Payload::Event(event) => match lambda_handler(&event, &context) {
+            Ok(result) => Ok(Output::Result(result)),
+            Err(e) => {
+                let error_output = error_output(&e);
+                error!("{}", error_output);
+                Err(context.new_error(&error_output))
+            }
+        },
+        Payload::Sqs(event) => Ok(Output::Batch(sqs_handler(&event, &context))),
+        Payload::Continuation(continuation) => {
+            match continuation_handler(&continuation, &context) {
+                Ok(result) => Ok(Output::Result(result)),
+                Err(e) => {
+                    let error_output = error_output(&e);
+                    error!("{}", error_output);
+                    Err(context.new_error(&error_output))
+                }
             }
-            let error_output = error_output.join("\n");
-            error!("{}", error_output);
-            Err(context.new_error(&error_output))
         }
     }
 }
 
-fn lambda_handler(event: &Event, _context: &Context) -> Result<HandlerResult, Error> {
-    let config = Config::from_environment()?;
+/// The error and its causes, one per line
+fn error_output(e: &Error) -> String {
+    let mut error_output = vec![format!("{}", e)];
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        error_output.push(format!("Caused by: {}", cause));
+        source = cause.source();
+    }
+    error_output.join("\n")
+}
 
-    info!("Configuration loaded: {:#?}", config);
-    let clients = Clients::new(&config)?;
+/// Handle every lifecycle hook notification in the batch, reporting the records that failed
+fn sqs_handler(event: &SqsEvent, context: &Context) -> BatchResponse {
+    let mut response = BatchResponse::default();
+    for record in &event.records {
+        let message_id = record.message_id.clone().unwrap_or_default();
+        info!("Handling SQS message ID {}", message_id);
+        let notification =
+            LifecycleNotification::from_message(record.body.as_deref().unwrap_or_default());
+        let result =
+            notification
+                .map_err(Error::from)
+                .and_then(|notification| match notification {
+                    LifecycleNotification::Test => {
+                        info!("Ignoring test notification");
+                        Ok(())
+                    }
+                    LifecycleNotification::Action(event) => {
+                        let detail = InvocationDetail {
+                            event,
+                            monitor_cursor: None,
+                            handler_mode: None,
+                        };
+                        detail_handler(detail, None, context).map(|_| ())
+                    }
+                });
+        if let Err(e) = result {
+            error!(
+                "Failed to handle SQS message ID {}: {}",
+                message_id,
+                error_output(&e)
+            );
+            response.batch_item_failures.push(BatchItemFailure {
+                item_identifier: message_id,
+            });
+        }
+    }
+    response
+}
 
-    let asg_event: AsgEventDetails = serde_json::from_value(serde_json::to_value(&event.detail)?)?;
-    info!("Event Details: {:#?}", asg_event);
+fn lambda_handler(event: &Event, context: &Context) -> Result<HandlerResult, Error> {
+    if event.version.as_deref() != Some(nomad_drain::events::EVENT_VERSION) {
+        warn!(
+            "Unexpected event schema version {:?}. Parsing its details anyway",
+            event.version
+        );
+    }
+    let detail = serde_json::to_value(&event.detail)?;
+    // Some event routes only identify the instance in the resources of the event
+    let detail = nomad_drain::events::detail_with_instance_id(detail, &event.resources);
+    detail_handler(serde_json::from_value(detail)?, None, context)
+}
+
+/// Check the status of the drain of an earlier invocation
+fn continuation_handler(
+    continuation: &Continuation,
+    context: &Context,
+) -> Result<HandlerResult, Error> {
+    let state: DrainContinuation = continuation.continuation.unpack(None, None)?;
+    let detail = InvocationDetail {
+        event: state.event.clone(),
+        monitor_cursor: Some(state.monitor_cursor),
+        handler_mode: Some(HandlerMode::CheckDrainStatus),
+    };
+    detail_handler(detail, Some(state), context)
+}
 
-    if asg_event.lifecycle_transition != AsgLifecycleTransition::InstanceTerminating {
-        Err(Error::UnexpectedLifecycleTransition)?;
+/// Nomad client for the pipeline handling `event`
+///
+/// Dry runs get a client built through `ReadOnlyGuard`, so that changes to the cluster are refused
+/// even where a middleware does not check for dry runs itself. Invalid overrides are left for
+/// `ValidateEvent` to warn about.
+fn pipeline_nomad_client(nomad_client: &NomadClient, event: &AsgEventDetails) -> NomadClient {
+    match EventOverrides::from_event(event) {
+        Ok(ref overrides) if overrides.dry_run => {
+            info!("Dry run: refusing changes to Nomad");
+            ReadOnlyGuard::new(nomad_client.clone()).client().clone()
+        }
+        _ => nomad_client.clone(),
     }
+}
 
-    info!("Instance ID {} is being terminated", asg_event.instance_id);
+/// Handle the lifecycle hook event in the detail, continuing the drain of an earlier invocation if
+/// there is one
+///
+/// Failures are reported as configured however the invocation fails, even if the configuration
+/// cannot be loaded.
+fn detail_handler(
+    detail: InvocationDetail,
+    continuation: Option<DrainContinuation>,
+    context: &Context,
+) -> Result<HandlerResult, Error> {
+    let config = Config::from_environment();
+    let reporting = match config {
+        Ok(ref config) => Reporting::from_config(config),
+        Err(_) => Reporting::from_environment(),
+    };
+    let autoscaling = AutoscalingClient::new(Default::default());
+    let sns_topic = reporting.sns_topic();
+    let mut invocation = Invocation::new(
+        detail.event.clone(),
+        Default::default(),
+        Budget::from_context(context),
+    );
+    invocation.resume_from = detail.monitor_cursor;
+    if let Some(continuation) = continuation {
+        invocation.eval_ids = continuation.eval_ids;
+        invocation.allocations = continuation.allocations;
+    }
+    handle_and_report(
+        config,
+        detail,
+        invocation,
+        &context.aws_request_id,
+        &reporting,
+        &autoscaling,
+        sns_topic.as_ref().map(|topic| topic as &dyn Notifier),
+    )
+}
 
-    let node = clients
-        .nomad_client
-        .find_node_by_instance_id(&asg_event.instance_id)?;
+/// Handle the event of `invocation` with the configuration, if it could be loaded, and report the
+/// outcome with `reporting`
+fn handle_and_report(
+    config: Result<Config, Error>,
+    detail: InvocationDetail,
+    mut invocation: Invocation,
+    trace_id: &str,
+    reporting: &Reporting,
+    autoscaling: &dyn Autoscaling,
+    notifier: Option<&dyn Notifier>,
+) -> Result<HandlerResult, Error> {
+    let (config, result) = match config {
+        Ok(config) => {
+            invocation.cluster = config.cluster_identity();
+            let result = handle_detail(&config, detail, trace_id, autoscaling, &mut invocation);
+            (Some(config), result)
+        }
+        Err(e) => (None, Err(e)),
+    };
+    reporting.report(
+        autoscaling,
+        notifier,
+        &mut invocation,
+        result.as_ref().err(),
+    );
+    let continuation = result?;
 
-    info!("Setting Node ID {} to be ineligible", node.data.id);
-    clients.nomad_client.set_node_eligibility(
-        &node.data.id,
-        nomad_drain::nomad::NodeEligibility::Ineligible,
-    )?;
+    let mut result = invocation.into_result();
+    result.continuation = continuation;
+    // Invocations only succeed with the configuration loaded
+    if let Some(config) = config {
+        if let Some(ref bucket) = config.audit_bucket {
+            // A failed upload can only be reported in the returned result
+            let key = config.audit_key(&result);
+            let upload = write_audit_summary(bucket, &key, &result);
+            result.degraded.check("audit summary", upload);
+        }
+    }
+    Ok(result)
+}
 
-    info!("Draining Nomad Node ID {}", node.data.id);
-    // Lambda has a max runtime of 900s. Let's set a deadline for 600s
-    clients.nomad_client.set_node_drain(
-        &node.data.id,
-        true,
-        Some(nomad_drain::nomad::DrainSpec {
-            deadline: 600,
-            ignore_system_jobs: false,
-        }),
+/// Handle the event of `invocation` with the configuration, returning the state to continue a
+/// drain still in progress with, if any
+///
+/// Requests are correlated with the invocation with `trace_id`. Failures are left to the caller to
+/// report.
+fn handle_detail(
+    config: &Config,
+    detail: InvocationDetail,
+    trace_id: &str,
+    autoscaling: &dyn Autoscaling,
+    invocation: &mut Invocation,
+) -> Result<Option<Checkpoint>, Error> {
+    let handler_mode = detail.handler_mode.unwrap_or(config.handler_mode);
+    if handler_mode == HandlerMode::CheckDrainStatus && detail.monitor_cursor.is_none() {
+        Err(Error::MissingContinuation)?;
+    }
+    info!("Handling the event with {:?}", handler_mode);
+    let ssm_document = config.ssm_document()?;
+    let batch_policy = config.batch_policy()?;
+    let pause_flag = config.pause_flag();
+    let command_timeout = Duration::from_secs(config.ssm_command_timeout);
+    if ssm_document.is_some() {
+        invocation.budget = invocation.budget.clone().with_command_time(command_timeout);
+    }
+
+    info!("Configuration loaded: {:#?}", config);
+    invocation.budget.start(Stage::Auth);
+    // Requests are correlated with the invocation in Nomad and Vault audit logs
+    let request_context = RequestContext::new()
+        .with_deadline(invocation.budget.deadline())
+        .with_trace_id(trace_id);
+    let clients = Clients::new(
+        config,
+        &request_context,
+        invocation.budget.stage_deadline(),
+        &mut invocation.degraded,
     )?;
+    invocation.cluster = clients.cluster_identity(config, &mut invocation.degraded);
+    info!("Nomad cluster: {}", invocation.cluster);
 
-    info!("Node ID {} Drained", node.data.id);
-
-    info!("Marking lifecycle action complete");
-    // Complete the lifecycle action
-    let asg_client = AutoscalingClient::new(Default::default());
-    let _ = asg_client
-        .complete_lifecycle_action(CompleteLifecycleActionType {
-            auto_scaling_group_name: asg_event.auto_scaling_group_name.to_string(),
-            instance_id: Some(asg_event.instance_id.to_string()),
-            lifecycle_action_result: "CONTINUE".to_string(),
-            lifecycle_action_token: Some(asg_event.lifecycle_action_token.to_string()),
-            lifecycle_hook_name: asg_event.lifecycle_hook_name.to_string(),
+    info!("Event Details: {:#?}", detail);
+    let nomad_client = pipeline_nomad_client(&clients.nomad_client, &invocation.event);
+    let lookup_node = LookupNode {
+        nomad_client: &nomad_client,
+        include_down_nodes: config.nomad_include_down_nodes,
+    };
+    let drain_node = DrainNode {
+        nomad_client: &nomad_client,
+        autoscaling,
+        heartbeat_interval: Duration::from_secs(config.lifecycle_heartbeat_interval),
+        purge_after_drain: config.nomad_purge_after_drain,
+        skip_eligibility: config.nomad_skip_eligibility,
+        drain_spec: DrainSpec {
+            deadline: Duration::from_secs(config.nomad_drain_deadline).into(),
+            ignore_system_jobs: config.nomad_drain_ignore_system_jobs,
+        },
+        batch_policy,
+        monitor: match handler_mode {
+            HandlerMode::Full => config.nomad_drain_monitor,
+            HandlerMode::InitiateDrain => false,
+            HandlerMode::CheckDrainStatus => true,
+        },
+        max_duration: config.nomad_drain_max_duration.map(Duration::from_secs),
+        time_sliced: config.nomad_drain_time_sliced || handler_mode != HandlerMode::Full,
+    };
+    let run_command = RunCommand {
+        document: ssm_document,
+        timeout: command_timeout,
+    };
+
+    let pipeline = Pipeline::new()
+        .with(ValidateEvent {
+            handle_launching: config.handle_launching_events,
         })
-        .sync()?;
+        .with(TargetCluster);
+    let pipeline = match invocation.event.lifecycle_transition {
+        AsgLifecycleTransition::InstanceLaunching => pipeline.with(RegisterNode {
+            nomad_client: &nomad_client,
+            registration_timeout: Duration::from_secs(config.node_registration_timeout),
+        }),
+        // Drains being checked on have already been submitted
+        _ if handler_mode == HandlerMode::CheckDrainStatus => pipeline
+            .with(lookup_node)
+            .with(drain_node)
+            .with(run_command),
+        _ => pipeline
+            .with(ReachNomad {
+                nomad_client: &nomad_client,
+                autoscaling,
+                timeout: config.nomad_unreachable_timeout.map(Duration::from_secs),
+                result: config.nomad_unreachable_result,
+            })
+            .with(lookup_node)
+            .with(WaitWhilePaused {
+                autoscaling,
+                pause: pause_flag.as_ref().map(|flag| flag as &dyn PauseSwitch),
+                heartbeat_interval: Duration::from_secs(config.lifecycle_heartbeat_interval),
+            })
+            .with(drain_node)
+            .with(run_command),
+    };
 
-    info!("Lifecycle action complete");
+    let result = pipeline
+        .with(CompleteLifecycle { autoscaling })
+        .run(invocation);
+    clients.teardown(&mut invocation.degraded);
+    result?;
 
-    // Revoke self
+    let continuation = match invocation.monitor_cursor {
+        Some(monitor_cursor) => {
+            let state = DrainContinuation {
+                event: invocation.event.clone(),
+                monitor_cursor,
+                eval_ids: invocation.eval_ids.clone(),
+                allocations: invocation.allocations,
+            };
+            Some(Checkpoint::pack(&state, CONTINUATION_LIMIT, None, None)?)
+        }
+        None => None,
+    };
+    Ok(continuation)
+}
 
-    Ok(HandlerResult {
-        instance_id: asg_event.instance_id.to_string(),
-        node_id: node.data.id.to_string(),
-        timestamp: chrono::Utc::now(),
-    })
+/// Write the result to S3. Failures should not fail the invocation, since the event has been
+/// handled by now.
+fn write_audit_summary(bucket: &str, key: &str, result: &HandlerResult) -> Result<(), Error> {
+    serde_json::to_vec_pretty(result)
+        .map_err(Error::from)
+        .and_then(|summary| {
+            let credentials = nomad_drain::get_aws_credentials()?;
+            Ok(nomad_drain::aws::put_s3_object(
+                &credentials,
+                &Default::default(),
+                bucket,
+                key,
+                summary,
+                "application/json",
+                None,
+            )?)
+        })?;
+    info!("Audit summary written to s3://{}/{}", bucket, key);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use rusoto_core::signature::SignedRequestPayload;
+    use rusoto_mock::{MockCredentialsProvider, MockRequestDispatcher};
+
+    /// Notifier recording the published notifications
+    #[derive(Debug, Default)]
+    struct RecordedNotifications(Mutex<Vec<DrainNotification>>);
+
+    impl Notifier for RecordedNotifications {
+        fn publish(&self, notification: &DrainNotification) -> Result<(), Error> {
+            self.0.lock().unwrap().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    fn event() -> AsgEventDetails {
+        AsgEventDetails {
+            lifecycle_action_token: "87654321-4321-4321-4321-210987654321".to_string(),
+            auto_scaling_group_name: "nomad-clients".to_string(),
+            instance_id: "i-1234567890abcdef0".to_string(),
+            lifecycle_transition: AsgLifecycleTransition::InstanceTerminating,
+            lifecycle_hook_name: "nomad-drain".to_string(),
+            notification_metadata: None,
+            origin: None,
+            destination: None,
+        }
+    }
+
+    #[test]
+    fn error_output_shows_each_cause_once() {
+        let error = Error::from(nomad_drain::Error::from(
+            "not a number".parse::<u64>().unwrap_err(),
+        ));
+        let output = error_output(&error);
+        assert_eq!(
+            "Error parsing integer\nCaused by: invalid digit found in string",
+            output
+        );
+        assert_eq!(1, output.matches("invalid digit found in string").count());
+    }
+
+    #[test]
+    fn dry_runs_get_a_read_only_nomad_client() {
+        let nomad_client = NomadClient::builder("http://127.0.0.1:4646")
+            .build()
+            .unwrap();
+        let mut event = event();
+        assert!(
+            !pipeline_nomad_client(&nomad_client, &event)
+                .context()
+                .dry_run
+        );
+
+        event.notification_metadata = Some(r#"{"dry_run": true}"#.to_string());
+        let read_only = pipeline_nomad_client(&nomad_client, &event);
+        match read_only.set_node_drain("id", false, None) {
+            Err(nomad_drain::Error::ReadOnly { operation }) => {
+                assert_eq!("drain Node ID id", operation)
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failures_to_build_the_clients_are_notified() {
+        // Without a Nomad token or a Vault address, the Nomad token cannot be retrieved
+        let config: Config = envy::from_iter(vec![(
+            "NOMAD_ADDR".to_string(),
+            "http://127.0.0.1:4646".to_string(),
+        )])
+        .unwrap();
+        let reporting = Reporting {
+            sns_topic_arn: Some("arn:aws:sns:us-east-1:123456789012:nomad-drain".to_string()),
+            ..Default::default()
+        };
+        let autoscaling = AutoscalingClient::new_with(
+            MockRequestDispatcher::with_status(200),
+            MockCredentialsProvider,
+            Region::UsEast1,
+        );
+        let notifications = RecordedNotifications::default();
+        let detail = InvocationDetail {
+            event: event(),
+            monitor_cursor: None,
+            handler_mode: None,
+        };
+        let invocation = Invocation::new(
+            detail.event.clone(),
+            Default::default(),
+            Budget::new(Duration::from_secs(900)),
+        );
+
+        let result = handle_and_report(
+            Ok(config),
+            detail,
+            invocation,
+            "trace",
+            &reporting,
+            &autoscaling,
+            Some(&notifications),
+        );
+        match result {
+            Err(Error::MissingConfiguration(ref field)) => assert_eq!("vault_address", field),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        let notifications = notifications.0.lock().unwrap();
+        assert_eq!(1, notifications.len());
+        assert_eq!(DrainOutcome::Failed, notifications[0].outcome);
+        assert_eq!("i-1234567890abcdef0", notifications[0].instance_id);
+        assert!(notifications[0]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("vault_address"));
+    }
+
+    #[test]
+    fn configuration_failures_complete_the_lifecycle_action_with_the_failure_result() {
+        let actions = Arc::new(Mutex::new(vec![]));
+        let recorded = actions.clone();
+        let dispatcher =
+            MockRequestDispatcher::with_status(200).with_request_checker(move |request| {
+                let payload = match request.payload {
+                    Some(SignedRequestPayload::Buffer(ref payload)) => {
+                        String::from_utf8_lossy(payload).into_owned()
+                    }
+                    _ => String::new(),
+                };
+                recorded.lock().unwrap().push(payload);
+            });
+        let autoscaling =
+            AutoscalingClient::new_with(dispatcher, MockCredentialsProvider, Region::UsEast1);
+        let reporting = Reporting {
+            lifecycle_failure_result: Some(LifecycleActionResult::Abandon),
+            sns_topic_arn: Some("arn:aws:sns:us-east-1:123456789012:nomad-drain".to_string()),
+            ..Default::default()
+        };
+        let notifications = RecordedNotifications::default();
+        let detail = InvocationDetail {
+            event: event(),
+            monitor_cursor: None,
+            handler_mode: None,
+        };
+        let invocation = Invocation::new(
+            detail.event.clone(),
+            Default::default(),
+            Budget::new(Duration::from_secs(900)),
+        );
+        let config = envy::from_iter::<_, Config>(vec![]).map_err(Error::from);
+
+        let result = handle_and_report(
+            config,
+            detail,
+            invocation,
+            "trace",
+            &reporting,
+            &autoscaling,
+            Some(&notifications),
+        );
+        match result {
+            Err(Error::ConfigurationDecodingError(_)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        let actions = actions.lock().unwrap();
+        assert_eq!(1, actions.len());
+        assert!(actions[0].contains("Action=CompleteLifecycleAction"));
+        assert!(actions[0].contains("LifecycleActionResult=ABANDON"));
+        let notifications = notifications.0.lock().unwrap();
+        assert_eq!(1, notifications.len());
+        assert_eq!(DrainOutcome::Failed, notifications[0].outcome);
+    }
 }
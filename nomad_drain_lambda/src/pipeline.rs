@@ -0,0 +1,1134 @@
+//! The handler as a pipeline of middleware
+//!
+//! Each middleware handles one concern of the handler and can stop the pipeline early. Stages
+//! only depend on the `Invocation` and whatever they are constructed with, so that they can be
+//! composed and exercised in isolation.
+use std::time::Duration;
+
+use log::{error, info, warn};
+use rusoto_autoscaling::{Autoscaling, CompleteLifecycleActionType};
+
+use nomad_drain::asg::{record_lifecycle_action_heartbeat, LifecycleActionResult};
+use nomad_drain::batch::BatchPolicy;
+use nomad_drain::clock::{Clock, SystemClock};
+use nomad_drain::degraded::DegradedOperations;
+use nomad_drain::events::{AsgEventDetails, AsgLifecycleTransition};
+use nomad_drain::metrics::{self, Metric};
+use nomad_drain::nomad::{
+    Client as NomadClient, ClusterIdentity, DrainSpec, MonitorCursor, Node, NodeEligibility,
+    NodeStatus,
+};
+use nomad_drain::pause::PauseSwitch;
+use nomad_drain::ssm::{self, Document};
+
+use crate::budget::{Budget, Stage};
+use crate::error::Error;
+use crate::{DrainNotification, DrainOutcome, EventOverrides, HandlerResult, HandlerState};
+
+/// Interval to check whether drains are still paused at
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Interval to check whether Nomad is reachable at
+const REACHABILITY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whether the pipeline should continue after a middleware
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Flow {
+    /// Run the next middleware
+    Continue,
+    /// Skip the rest of the pipeline
+    Stop,
+}
+
+/// A single stage of the handler
+pub trait Middleware {
+    /// Name of the middleware for logging
+    fn name(&self) -> &'static str;
+
+    /// Handle the invocation
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error>;
+}
+
+/// State of a handler invocation, passed through the pipeline
+#[derive(Debug)]
+pub struct Invocation {
+    pub event: AsgEventDetails,
+    pub cluster: ClusterIdentity,
+    pub budget: Budget,
+    pub overrides: EventOverrides,
+    pub node: Option<Node>,
+    pub drained: bool,
+    pub eval_ids: Vec<String>,
+    pub nomad_unreachable: bool,
+    /// Force deadline of the drain, as of the last time it was monitored
+    pub force_deadline: Option<chrono::DateTime<chrono::Utc>>,
+    /// Number of allocations on the node when it was drained, which are migrated
+    pub allocations: usize,
+    /// Time the drain took to complete, if it was monitored until it completed
+    pub drain_duration: Option<Duration>,
+    /// Where monitoring of a drain submitted by an earlier invocation is resumed from
+    pub resume_from: Option<MonitorCursor>,
+    /// Where a later invocation should resume monitoring from, if this one ran out of time
+    pub monitor_cursor: Option<MonitorCursor>,
+    pub warnings: Vec<String>,
+    /// Optional operations that failed
+    pub degraded: DegradedOperations,
+}
+
+impl Invocation {
+    pub fn new(event: AsgEventDetails, cluster: ClusterIdentity, budget: Budget) -> Self {
+        Self {
+            event,
+            cluster,
+            budget,
+            overrides: Default::default(),
+            node: None,
+            drained: false,
+            eval_ids: vec![],
+            nomad_unreachable: false,
+            force_deadline: None,
+            allocations: 0,
+            drain_duration: None,
+            resume_from: None,
+            monitor_cursor: None,
+            warnings: vec![],
+            degraded: Default::default(),
+        }
+    }
+
+    /// Record a warning to be returned in the result
+    pub fn warn(&mut self, warning: String) {
+        warn!("{}", warning);
+        self.warnings.push(warning);
+    }
+
+    /// Notification of the outcome of the invocation, which failed with `error` if any
+    pub fn notification(&self, error: Option<&Error>) -> DrainNotification {
+        let outcome = match error {
+            Some(_) => DrainOutcome::Failed,
+            None if self.drained => DrainOutcome::Drained,
+            None => DrainOutcome::NotDrained,
+        };
+        DrainNotification {
+            instance_id: self.event.instance_id.clone(),
+            node_id: self.node.as_ref().map(|node| node.id.clone()),
+            cluster: self.cluster.clone(),
+            timestamp: chrono::Utc::now(),
+            outcome,
+            allocations: self.allocations,
+            drain_seconds: self.drain_duration.map(|duration| duration.as_secs()),
+            error: error.map(ToString::to_string),
+        }
+    }
+
+    /// Metrics of the invocation, which failed if `failed` is set
+    pub fn metrics(&self, failed: bool) -> Vec<Metric> {
+        let mut metrics = vec![Metric::count(metrics::DRAIN_FAILURES, failed as usize)];
+        if self.drained {
+            metrics.push(Metric::count(
+                metrics::ALLOCATIONS_MIGRATED,
+                self.allocations,
+            ));
+        }
+        if let Some(duration) = self.drain_duration {
+            metrics.push(Metric::seconds(
+                metrics::DRAIN_DURATION_SECONDS,
+                duration.as_secs(),
+            ));
+        }
+        metrics
+    }
+
+    pub fn into_result(self) -> HandlerResult {
+        let timestamp = chrono::Utc::now();
+        HandlerResult {
+            instance_id: self.event.instance_id,
+            node_id: self.node.map(|node| node.id),
+            cluster: self.cluster,
+            timestamp,
+            drained: self.drained,
+            eval_ids: self.eval_ids,
+            nomad_unreachable: self.nomad_unreachable,
+            force_deadline: self.force_deadline,
+            seconds_until_force_deadline: self
+                .force_deadline
+                .map(|deadline| std::cmp::max((deadline - timestamp).num_seconds(), 0) as u64),
+            allocations: self.allocations,
+            drain_seconds: self.drain_duration.map(|duration| duration.as_secs()),
+            state: match self.monitor_cursor {
+                Some(_) => HandlerState::InProgress,
+                None => HandlerState::Done,
+            },
+            continuation: None,
+            monitor_cursor: self.monitor_cursor,
+            dry_run: self.overrides.dry_run,
+            warnings: self.warnings,
+            degraded: self.degraded,
+            build: nomad_drain::build_info(),
+        }
+    }
+}
+
+/// An ordered list of middleware
+#[derive(Default)]
+pub struct Pipeline<'a> {
+    middleware: Vec<Box<dyn Middleware + 'a>>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Append a middleware to the pipeline
+    pub fn with<M>(mut self, middleware: M) -> Self
+    where
+        M: Middleware + 'a,
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Run the middleware in order until one stops the pipeline or fails
+    pub fn run(&self, invocation: &mut Invocation) -> Result<(), Error> {
+        for middleware in &self.middleware {
+            info!("Running {}", middleware.name());
+            if middleware.handle(invocation)? == Flow::Stop {
+                info!("{} stopped the pipeline", middleware.name());
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the event is for a terminating instance, or a launching instance if enabled, and
+/// parses its overrides
+pub struct ValidateEvent {
+    pub handle_launching: bool,
+}
+
+impl Middleware for ValidateEvent {
+    fn name(&self) -> &'static str {
+        "event validation"
+    }
+
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error> {
+        match invocation.event.lifecycle_transition {
+            AsgLifecycleTransition::InstanceTerminating => info!(
+                "Instance ID {} in cluster {} is being terminated",
+                invocation.event.instance_id, invocation.cluster
+            ),
+            AsgLifecycleTransition::InstanceLaunching if self.handle_launching => info!(
+                "Instance ID {} in cluster {} is being launched",
+                invocation.event.instance_id, invocation.cluster
+            ),
+            _ => Err(Error::UnexpectedLifecycleTransition)?,
+        }
+
+        invocation.overrides = match EventOverrides::from_event(&invocation.event) {
+            Ok(overrides) => overrides,
+            Err(e) => {
+                invocation.warn(format!(
+                    "Ignoring invalid lifecycle hook NotificationMetadata: {}",
+                    e
+                ));
+                Default::default()
+            }
+        };
+        info!("Event overrides: {:#?}", invocation.overrides);
+        Ok(Flow::Continue)
+    }
+}
+
+/// Skips events that target another Nomad cluster
+pub struct TargetCluster;
+
+impl Middleware for TargetCluster {
+    fn name(&self) -> &'static str {
+        "target cluster check"
+    }
+
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error> {
+        match invocation.overrides.target_cluster {
+            Some(ref target_cluster)
+                if invocation.cluster.name.as_ref() != Some(target_cluster) =>
+            {
+                info!(
+                    "Event targets cluster {} but this is cluster {}. Skipping event",
+                    target_cluster, invocation.cluster
+                );
+                Ok(Flow::Stop)
+            }
+            _ => Ok(Flow::Continue),
+        }
+    }
+}
+
+/// Completes the lifecycle action without draining when Nomad cannot be reached at all
+///
+/// Nomad is checked for up to `timeout`, if set. When it cannot be reached, the lifecycle action
+/// is completed with `result` and the rest of the pipeline is skipped, so that an outage of Nomad
+/// does not also hold up every scale-in. This is logged as an error to be alerted on, and
+/// recorded in the result.
+pub struct ReachNomad<'a> {
+    pub nomad_client: &'a NomadClient,
+    pub autoscaling: &'a dyn Autoscaling,
+    pub timeout: Option<Duration>,
+    pub result: LifecycleActionResult,
+}
+
+impl<'a> Middleware for ReachNomad<'a> {
+    fn name(&self) -> &'static str {
+        "Nomad reachability check"
+    }
+
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Ok(Flow::Continue),
+        };
+        let allowance = invocation.budget.start(Stage::Lookup);
+        let error = match self.nomad_client.wait_for_reachable(
+            std::cmp::min(allowance, timeout),
+            REACHABILITY_POLL_INTERVAL,
+        ) {
+            Ok(()) => return Ok(Flow::Continue),
+            Err(e) => e,
+        };
+
+        error!(
+            "Nomad at {} is unreachable: {}. Completing the lifecycle action of AWS Instance ID {} \
+             with {} WITHOUT DRAINING",
+            self.nomad_client.address(),
+            error,
+            invocation.event.instance_id,
+            self.result
+        );
+        invocation.nomad_unreachable = true;
+        invocation.warn(format!(
+            "Nomad was unreachable. Lifecycle action completed with {} without draining",
+            self.result
+        ));
+        invocation.budget.start(Stage::Complete);
+        complete_lifecycle_action(self.autoscaling, &invocation.event, self.result)?;
+        invocation.budget.end();
+        Ok(Flow::Stop)
+    }
+}
+
+/// Finds the Nomad node of the instance
+pub struct LookupNode<'a> {
+    pub nomad_client: &'a NomadClient,
+    pub include_down_nodes: bool,
+}
+
+impl<'a> Middleware for LookupNode<'a> {
+    fn name(&self) -> &'static str {
+        "node lookup"
+    }
+
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error> {
+        invocation.budget.start(Stage::Lookup);
+        let nomad_client = self
+            .nomad_client
+            .clone()
+            .with_deadline(invocation.budget.stage_deadline());
+        let node_match = nomad_client
+            .find_node_by_instance_id(&invocation.event.instance_id, self.include_down_nodes)?;
+        if let Some(ambiguous) = node_match.ambiguous {
+            invocation.warn(ambiguous.to_string());
+        }
+        invocation.node = Some(node_match.node.data);
+        Ok(Flow::Continue)
+    }
+}
+
+/// Waits for the node of a launching instance to be ready and marks it eligible
+pub struct RegisterNode<'a> {
+    pub nomad_client: &'a NomadClient,
+    pub registration_timeout: Duration,
+}
+
+impl<'a> Middleware for RegisterNode<'a> {
+    fn name(&self) -> &'static str {
+        "node registration"
+    }
+
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error> {
+        let allowance = invocation.budget.start(Stage::Lookup);
+        let node = self.nomad_client.wait_for_node_ready(
+            &invocation.event.instance_id,
+            std::cmp::min(allowance, self.registration_timeout),
+            Duration::from_secs(5),
+        )?;
+        info!(
+            "AWS Instance ID {} registered as Nomad Node ID {}",
+            invocation.event.instance_id, node.id
+        );
+
+        if invocation.overrides.dry_run {
+            info!(
+                "Dry run: not marking Nomad Node ID {} in cluster {} eligible",
+                node.id, invocation.cluster
+            );
+        } else {
+            self.nomad_client
+                .set_node_eligibility(&node.id, NodeEligibility::Eligible)?;
+        }
+        invocation.node = Some(node);
+        Ok(Flow::Continue)
+    }
+}
+
+/// Waits before draining the node while drains are paused with `pause`, if configured
+///
+/// Lifecycle action heartbeats are recorded every `heartbeat_interval` while waiting. If drains
+/// are still paused when the stage runs out of time, the invocation fails without completing the
+/// lifecycle action. Drains proceed when the pause switch cannot be read, since terminating
+/// instances cannot be held back indefinitely.
+pub struct WaitWhilePaused<'a> {
+    pub autoscaling: &'a dyn Autoscaling,
+    pub pause: Option<&'a dyn PauseSwitch>,
+    pub heartbeat_interval: Duration,
+}
+
+impl<'a> Middleware for WaitWhilePaused<'a> {
+    fn name(&self) -> &'static str {
+        "drain pause check"
+    }
+
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error> {
+        let pause = match self.pause {
+            Some(pause) => pause,
+            None => return Ok(Flow::Continue),
+        };
+        if invocation.node.is_none() || invocation.overrides.dry_run {
+            return Ok(Flow::Continue);
+        }
+
+        let allowance = invocation.budget.start(Stage::Pause);
+        let clock = SystemClock;
+        let started = clock.now();
+        let mut last_heartbeat = started;
+        loop {
+            match pause.is_paused() {
+                Ok(true) => {}
+                Ok(false) => return Ok(Flow::Continue),
+                Err(e) => {
+                    invocation.degraded.record("pause check", e);
+                    info!("Unable to check whether drains are paused. Draining anyway");
+                    return Ok(Flow::Continue);
+                }
+            }
+            if clock.elapsed(started) + PAUSE_POLL_INTERVAL > allowance {
+                Err(Error::DrainsPaused)?;
+            }
+            info!(
+                "Drains are paused. Checking again in {:?}",
+                PAUSE_POLL_INTERVAL
+            );
+            clock.sleep(PAUSE_POLL_INTERVAL);
+            if clock.elapsed(last_heartbeat) >= self.heartbeat_interval {
+                invocation.degraded.check(
+                    "lifecycle action heartbeat",
+                    record_lifecycle_action_heartbeat(self.autoscaling, &invocation.event),
+                );
+                last_heartbeat = clock.now();
+            }
+        }
+    }
+}
+
+/// Drains the node and monitors the drain for as long as the budget allows
+///
+/// Lifecycle action heartbeats are recorded every `heartbeat_interval` while monitoring so that
+/// the drain can outlast the heartbeat timeout of the lifecycle hook. If `purge_after_drain` is
+/// set, the node is purged from Nomad once the drain is complete.
+///
+/// The drain makes the node ineligible, which is verified afterwards. If `skip_eligibility` is
+/// set, eligibility is neither verified nor updated, for tokens that may only drain nodes.
+///
+/// `drain_spec` applies unless overridden by the event. Without `monitor`, the drain is only
+/// submitted. With `max_duration`, monitoring gives up after that long even if the budget allows
+/// for more.
+pub struct DrainNode<'a> {
+    pub nomad_client: &'a NomadClient,
+    pub autoscaling: &'a dyn Autoscaling,
+    pub heartbeat_interval: Duration,
+    pub purge_after_drain: bool,
+    pub skip_eligibility: bool,
+    pub drain_spec: DrainSpec,
+    /// What to do with allocations of batch jobs, instead of leaving them to the drain deadline
+    pub batch_policy: BatchPolicy,
+    pub monitor: bool,
+    pub max_duration: Option<Duration>,
+    /// Leave the lifecycle action pending when the invocation runs out of time while monitoring,
+    /// for a later invocation to resume monitoring from `HandlerResult::monitor_cursor`. Without
+    /// `monitor`, monitoring is left to later invocations altogether.
+    pub time_sliced: bool,
+}
+
+impl<'a> Middleware for DrainNode<'a> {
+    fn name(&self) -> &'static str {
+        "drain"
+    }
+
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error> {
+        let node_id = match invocation.node {
+            Some(ref node) if node.status == NodeStatus::Down => {
+                info!("Node ID {} is already down. Skipping drain", node.id);
+                return Ok(Flow::Continue);
+            }
+            Some(ref node) if invocation.overrides.dry_run => {
+                info!(
+                    "Dry run: not draining Nomad Node ID {} in cluster {}",
+                    node.id, invocation.cluster
+                );
+                return Ok(Flow::Continue);
+            }
+            Some(ref node) => node.id.clone(),
+            None => return Ok(Flow::Continue),
+        };
+
+        let drain_started = self.nomad_client.clock().now();
+        match invocation.resume_from {
+            Some(cursor) => {
+                info!(
+                    "Resuming monitoring of the drain of Nomad Node ID {} from {:?}",
+                    node_id, cursor
+                );
+                invocation.drained = true;
+            }
+            None => self.submit(&node_id, invocation)?,
+        }
+        if !self.monitor {
+            info!("Not monitoring the drain of Node ID {}", node_id);
+            self.hand_off(invocation.resume_from.unwrap_or_default(), invocation);
+        } else if invocation.budget.can_monitor() {
+            self.monitor(&node_id, drain_started, invocation)?;
+        } else {
+            let warning = format!(
+                "Skipped monitoring drain of Node ID {}: {:?} left in the invocation",
+                node_id,
+                invocation.budget.remaining()
+            );
+            invocation.warn(warning);
+            self.hand_off(invocation.resume_from.unwrap_or_default(), invocation);
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+impl<'a> DrainNode<'a> {
+    /// Submit the drain of the node
+    fn submit(&self, node_id: &str, invocation: &mut Invocation) -> Result<(), Error> {
+        invocation.budget.start(Stage::Drain);
+        info!(
+            "Draining Nomad Node ID {} in cluster {}",
+            node_id, invocation.cluster
+        );
+        // Recorded on the node by Nomad versions that support drain metadata
+        let meta = vec![
+            ("drained_by", "nomad-drain"),
+            (
+                "auto_scaling_group",
+                invocation.event.auto_scaling_group_name.as_str(),
+            ),
+            ("ec2_instance_id", invocation.event.instance_id.as_str()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        let nomad_client = self.nomad_client.clone().with_drain_meta(meta);
+        // Allocations waited for by the batch policy decide the deadline of the drain
+        let allocations = if self.batch_policy.is_empty() {
+            None
+        } else {
+            Some(nomad_client.allocations(node_id, None, None)?.data)
+        };
+        let deadline = invocation
+            .overrides
+            .drain_deadline
+            .map(|deadline| Duration::from_secs(deadline).into())
+            .unwrap_or(self.drain_spec.deadline);
+        let drain_spec = Some(DrainSpec {
+            deadline: match allocations {
+                Some(ref allocations) => self.batch_policy.deadline(deadline, allocations),
+                None => deadline,
+            },
+            ignore_system_jobs: invocation
+                .overrides
+                .ignore_system_jobs
+                .unwrap_or(self.drain_spec.ignore_system_jobs),
+        });
+        // The drain is submitted first since every second counts during spot interruptions
+        let response = if self.skip_eligibility {
+            // Monitoring inline would block without regard for the budget, so it is left to
+            // the time boxed monitor stage instead
+            nomad_client.set_node_drain(node_id, false, drain_spec)?
+        } else {
+            nomad_client.set_node_drain_ineligible(node_id, drain_spec)?
+        };
+        info!("Drain created evaluations {:?}", response.eval_ids());
+        invocation.drained = true;
+        invocation.eval_ids = response.eval_ids().to_vec();
+        // Allocations only stop once they have been migrated, so they are still counted
+        let allocations = match allocations {
+            Some(allocations) => Some(allocations),
+            None => {
+                let allocations = nomad_client.allocations(node_id, None, None);
+                invocation
+                    .degraded
+                    .check("allocation count", allocations)
+                    .map(|allocations| allocations.data)
+            }
+        };
+        if let Some(allocations) = allocations {
+            invocation.allocations = allocations
+                .iter()
+                .filter(|allocation| !allocation.is_terminal())
+                .count();
+            for allocation in self.batch_policy.to_migrate(&allocations) {
+                let stop = nomad_client.stop_allocation(&allocation.id);
+                invocation
+                    .degraded
+                    .check("batch allocation migration", stop);
+            }
+        }
+        Ok(())
+    }
+
+    /// Monitor the drain of the node for as long as the invocation allows
+    fn monitor(
+        &self,
+        node_id: &str,
+        drain_started: std::time::Instant,
+        invocation: &mut Invocation,
+    ) -> Result<(), Error> {
+        invocation.budget.start(Stage::Monitor);
+        let clock = self.nomad_client.clock();
+        // Blocking queries are shortened so that monitoring ends within the stage's time box
+        let nomad_client = self
+            .nomad_client
+            .clone()
+            .with_deadline(invocation.budget.stage_deadline());
+        let wait_timeout = std::cmp::min(nomad_client.wait_timeout(), self.heartbeat_interval);
+
+        let event = &invocation.event;
+        let degraded = &mut invocation.degraded;
+        let mut last_heartbeat = clock.now();
+        let mut node_down = false;
+        let mut node_gone = false;
+        let mut force_deadline = None;
+        let resume_from = invocation.resume_from.unwrap_or_default();
+        let mut cursor = resume_from;
+        let result = nomad_client.resume_node_drain_monitoring(
+            node_id,
+            &resume_from,
+            Some(wait_timeout),
+            self.max_duration,
+            false,
+            |progress| {
+                node_down |= progress.node_down;
+                node_gone |= progress.node_gone;
+                force_deadline = progress.force_deadline;
+                cursor = progress.cursor;
+                if !progress.completed && clock.elapsed(last_heartbeat) >= self.heartbeat_interval {
+                    degraded.check(
+                        "lifecycle action heartbeat",
+                        record_lifecycle_action_heartbeat(self.autoscaling, event),
+                    );
+                    last_heartbeat = clock.now();
+                }
+                Ok(())
+            },
+        );
+        invocation.force_deadline = force_deadline;
+        if node_down {
+            invocation.warn(format!(
+                "Node ID {} went down while it was draining",
+                node_id
+            ));
+        }
+        match result {
+            Ok(()) if node_gone => invocation.warn(format!(
+                "Node ID {} was removed from Nomad while it was draining",
+                node_id
+            )),
+            Ok(()) => {
+                info!(
+                    "Node ID {} in cluster {} Drained",
+                    node_id, invocation.cluster
+                );
+                // A resumed drain started in an earlier invocation, at an unknown time
+                if invocation.resume_from.is_none() {
+                    invocation.drain_duration = Some(clock.elapsed(drain_started));
+                }
+                if self.purge_after_drain {
+                    let purge = self.nomad_client.purge_node(node_id);
+                    invocation.degraded.check("node purge", purge);
+                }
+            }
+            Err(nomad_drain::Error::DeadlineExceeded) => {
+                invocation.warn(format!(
+                    "Stopped monitoring drain of Node ID {}: out of time in the invocation",
+                    node_id
+                ));
+                self.hand_off(cursor, invocation);
+            }
+            Err(e @ nomad_drain::Error::DrainTimeout { .. }) => {
+                invocation.warn(format!("Stopped monitoring drain: {}", e))
+            }
+            Err(e) => Err(e)?,
+        }
+        Ok(())
+    }
+
+    /// Leave monitoring from `cursor` to a later invocation, if drains are monitored time sliced
+    fn hand_off(&self, cursor: MonitorCursor, invocation: &mut Invocation) {
+        if self.time_sliced {
+            info!(
+                "Monitoring continues in a later invocation from {:?}",
+                cursor
+            );
+            invocation.monitor_cursor = Some(cursor);
+        }
+    }
+}
+
+/// Runs a Systems Manager document on the instance, if configured, before it terminates
+///
+/// Failures are only recorded as warnings since the instance is terminating regardless. Drains
+/// still monitored by a later invocation run the document in that invocation instead.
+pub struct RunCommand {
+    pub document: Option<Document>,
+    pub timeout: Duration,
+}
+
+impl Middleware for RunCommand {
+    fn name(&self) -> &'static str {
+        "instance command"
+    }
+
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error> {
+        let document = match self.document {
+            Some(ref document) => document,
+            None => return Ok(Flow::Continue),
+        };
+        if invocation.monitor_cursor.is_some() {
+            return Ok(Flow::Continue);
+        }
+        let instance_id = invocation.event.instance_id.clone();
+        if invocation.overrides.dry_run {
+            info!(
+                "Dry run: not running Systems Manager document {} on AWS Instance ID {}",
+                document.name, instance_id
+            );
+            return Ok(Flow::Continue);
+        }
+
+        let allowance = invocation.budget.start(Stage::Command);
+        let result = nomad_drain::get_aws_credentials().and_then(|credentials| {
+            ssm::run_command(
+                &credentials,
+                &Default::default(),
+                &instance_id,
+                document,
+                std::cmp::min(allowance, self.timeout),
+                &SystemClock,
+                None,
+            )
+        });
+        if let Err(e) = result {
+            invocation.warn(format!(
+                "Error running Systems Manager document {} on AWS Instance ID {}: {}",
+                document.name, instance_id, e
+            ));
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// Completes the lifecycle action so that the instance can terminate
+pub struct CompleteLifecycle<'a> {
+    pub autoscaling: &'a dyn Autoscaling,
+}
+
+impl<'a> Middleware for CompleteLifecycle<'a> {
+    fn name(&self) -> &'static str {
+        "lifecycle completion"
+    }
+
+    fn handle(&self, invocation: &mut Invocation) -> Result<Flow, Error> {
+        if invocation.monitor_cursor.is_some() {
+            info!("Leaving the lifecycle action pending until the drain has been monitored");
+            return Ok(Flow::Continue);
+        }
+        invocation.budget.start(Stage::Complete);
+        complete_lifecycle_action(
+            self.autoscaling,
+            &invocation.event,
+            LifecycleActionResult::Continue,
+        )?;
+        invocation.budget.end();
+        Ok(Flow::Continue)
+    }
+}
+
+/// Complete the lifecycle action of the event with the result
+pub fn complete_lifecycle_action(
+    autoscaling: &dyn Autoscaling,
+    event: &AsgEventDetails,
+    result: LifecycleActionResult,
+) -> Result<(), Error> {
+    info!("Marking lifecycle action complete with {}", result);
+    let _ = autoscaling
+        .complete_lifecycle_action(CompleteLifecycleActionType {
+            auto_scaling_group_name: event.auto_scaling_group_name.to_string(),
+            instance_id: Some(event.instance_id.to_string()),
+            lifecycle_action_result: result.to_string(),
+            lifecycle_action_token: Some(event.lifecycle_action_token.to_string()),
+            lifecycle_hook_name: event.lifecycle_hook_name.to_string(),
+        })
+        .sync()?;
+
+    info!("Lifecycle action complete");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::sync::{Arc, Mutex};
+
+    use nomad_drain::clock::SimulatedClock;
+    use nomad_drain::fixtures::{allocations_json, AllocationFixture, NodeFixture, StubTransport};
+    use nomad_drain::nomad::DrainApi;
+    use nomad_drain::retry::RetryPolicy;
+    use rusoto_autoscaling::AutoscalingClient;
+    use rusoto_core::signature::SignedRequestPayload;
+    use rusoto_core::Region;
+    use rusoto_mock::{MockCredentialsProvider, MockRequestDispatcher};
+
+    const NODE_ID: &str = "02802087-8786-fdf6-4497-98445c891fb7";
+
+    fn event(lifecycle_transition: AsgLifecycleTransition) -> AsgEventDetails {
+        AsgEventDetails {
+            lifecycle_action_token: "87654321-4321-4321-4321-210987654321".to_string(),
+            auto_scaling_group_name: "nomad-clients".to_string(),
+            instance_id: "i-1234567890abcdef0".to_string(),
+            lifecycle_transition,
+            lifecycle_hook_name: "nomad-drain".to_string(),
+            notification_metadata: None,
+            origin: None,
+            destination: None,
+        }
+    }
+
+    fn invocation(remaining: u64) -> (Invocation, Arc<SimulatedClock>) {
+        let clock = Arc::new(SimulatedClock::new());
+        let budget = Budget::with_clock(Duration::from_secs(remaining), clock.clone());
+        let cluster = ClusterIdentity {
+            name: Some("production".to_string()),
+            ..Default::default()
+        };
+        let invocation = Invocation::new(
+            event(AsgLifecycleTransition::InstanceTerminating),
+            cluster,
+            budget,
+        );
+        (invocation, clock)
+    }
+
+    fn nomad_client(transport: &Arc<StubTransport>, clock: &Arc<SimulatedClock>) -> NomadClient {
+        NomadClient::builder("http://127.0.0.1:4646")
+            .with_retry_policy(RetryPolicy::none())
+            .build()
+            .unwrap()
+            .with_drain_api(DrainApi::V1_1)
+            .with_transport(transport.clone())
+            .with_clock(clock.clone())
+    }
+
+    /// Auto Scaling client recording the action and result of every request
+    fn autoscaling() -> (AutoscalingClient, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        let dispatcher =
+            MockRequestDispatcher::with_status(200).with_request_checker(move |request| {
+                // Parameters of the query protocol are form encoded in the payload
+                let payload = match request.payload {
+                    Some(SignedRequestPayload::Buffer(ref payload)) => {
+                        String::from_utf8_lossy(payload).into_owned()
+                    }
+                    _ => String::new(),
+                };
+                let param = |name: &str| {
+                    payload
+                        .split('&')
+                        .filter_map(|pair| pair.split_once('='))
+                        .find(|(key, _)| *key == name)
+                        .map(|(_, value)| value.to_string())
+                };
+                let request = match param("LifecycleActionResult") {
+                    Some(result) => format!("{} {}", param("Action").unwrap_or_default(), result),
+                    None => param("Action").unwrap_or_default(),
+                };
+                recorded.lock().unwrap().push(request);
+            });
+        let client =
+            AutoscalingClient::new_with(dispatcher, MockCredentialsProvider, Region::UsEast1);
+        (client, requests)
+    }
+
+    fn drain_node<'a>(
+        nomad_client: &'a NomadClient,
+        autoscaling: &'a dyn Autoscaling,
+        monitor: bool,
+    ) -> DrainNode<'a> {
+        DrainNode {
+            nomad_client,
+            autoscaling,
+            heartbeat_interval: Duration::from_secs(600),
+            purge_after_drain: false,
+            skip_eligibility: false,
+            drain_spec: Default::default(),
+            batch_policy: Default::default(),
+            monitor,
+            max_duration: None,
+            time_sliced: true,
+        }
+    }
+
+    /// Responses of a node that is drained after it has been checked `polls` times
+    fn draining_node(polls: usize) -> StubTransport {
+        let node_path = format!("/v1/node/{}", NODE_ID);
+        let draining = NodeFixture::ready().with_id(NODE_ID).draining().json();
+        let transport = (0..polls).fold(StubTransport::new(), |transport, _| {
+            transport.with_response("GET", &node_path, 200, draining.as_str())
+        });
+        transport
+            .with_response(
+                "GET",
+                &node_path,
+                200,
+                NodeFixture::ready()
+                    .with_id(NODE_ID)
+                    .with_eligibility(NodeEligibility::Ineligible)
+                    .json(),
+            )
+            .with_response(
+                "POST",
+                &format!("/v1/node/{}/drain", NODE_ID),
+                200,
+                include_str!("../../nomad_drain/fixtures/node_drain.json"),
+            )
+            .with_response(
+                "GET",
+                &format!("/v1/node/{}/allocations", NODE_ID),
+                200,
+                allocations_json(&[AllocationFixture::running().on_node(NODE_ID)]),
+            )
+    }
+
+    /// Middleware recording that it ran
+    struct Step<'a> {
+        name: &'static str,
+        result: fn() -> Result<Flow, Error>,
+        ran: &'a RefCell<Vec<&'static str>>,
+    }
+
+    impl<'a> Middleware for Step<'a> {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn handle(&self, _: &mut Invocation) -> Result<Flow, Error> {
+            self.ran.borrow_mut().push(self.name);
+            (self.result)()
+        }
+    }
+
+    #[test]
+    fn pipelines_stop_at_the_first_middleware_that_stops_or_fails() {
+        let ran = RefCell::new(vec![]);
+        let step = |name, result| Step {
+            name,
+            result,
+            ran: &ran,
+        };
+
+        let (mut invocation, _) = invocation(900);
+        let pipeline = Pipeline::new()
+            .with(step("first", || Ok(Flow::Continue)))
+            .with(step("second", || Ok(Flow::Stop)))
+            .with(step("third", || Ok(Flow::Continue)));
+        pipeline.run(&mut invocation).unwrap();
+        assert_eq!(vec!["first", "second"], *ran.borrow());
+
+        ran.borrow_mut().clear();
+        let pipeline = Pipeline::new()
+            .with(step("first", || Err(Error::MissingContinuation)))
+            .with(step("second", || Ok(Flow::Continue)));
+        assert!(pipeline.run(&mut invocation).is_err());
+        assert_eq!(vec!["first"], *ran.borrow());
+    }
+
+    #[test]
+    fn events_are_validated_and_their_overrides_parsed() {
+        let validate = ValidateEvent {
+            handle_launching: false,
+        };
+        let (mut invocation, _) = invocation(900);
+        invocation.event.notification_metadata =
+            Some(r#"{"dry_run": true, "drain_deadline": 60}"#.to_string());
+        assert_eq!(Flow::Continue, validate.handle(&mut invocation).unwrap());
+        assert!(invocation.overrides.dry_run);
+        assert_eq!(Some(60), invocation.overrides.drain_deadline);
+
+        invocation.event.notification_metadata = Some("not JSON".to_string());
+        assert_eq!(Flow::Continue, validate.handle(&mut invocation).unwrap());
+        assert_eq!(EventOverrides::default(), invocation.overrides);
+        assert_eq!(1, invocation.warnings.len());
+
+        invocation.event.lifecycle_transition = AsgLifecycleTransition::InstanceLaunching;
+        match validate.handle(&mut invocation) {
+            Err(Error::UnexpectedLifecycleTransition) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        let validate = ValidateEvent {
+            handle_launching: true,
+        };
+        assert_eq!(Flow::Continue, validate.handle(&mut invocation).unwrap());
+    }
+
+    #[test]
+    fn events_for_other_clusters_are_skipped() {
+        let (mut invocation, _) = invocation(900);
+        assert_eq!(
+            Flow::Continue,
+            TargetCluster.handle(&mut invocation).unwrap()
+        );
+
+        invocation.overrides.target_cluster = Some("production".to_string());
+        assert_eq!(
+            Flow::Continue,
+            TargetCluster.handle(&mut invocation).unwrap()
+        );
+
+        invocation.overrides.target_cluster = Some("staging".to_string());
+        assert_eq!(Flow::Stop, TargetCluster.handle(&mut invocation).unwrap());
+
+        // Clusters without a configured name are never targeted
+        invocation.cluster.name = None;
+        assert_eq!(Flow::Stop, TargetCluster.handle(&mut invocation).unwrap());
+    }
+
+    #[test]
+    fn node_lookups_are_time_boxed_to_their_stage() {
+        // Everything left is reserved for draining and completing the lifecycle action
+        let (mut invocation, clock) = invocation(60);
+        let transport = Arc::new(StubTransport::new());
+        let nomad_client = nomad_client(&transport, &clock);
+        let lookup = LookupNode {
+            nomad_client: &nomad_client,
+            include_down_nodes: false,
+        };
+
+        match lookup.handle(&mut invocation) {
+            Err(Error::LibError(nomad_drain::Error::DeadlineExceeded)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert!(transport.requests().is_empty());
+        assert_eq!(None, nomad_client.context().deadline);
+    }
+
+    #[test]
+    fn drains_are_handed_off_without_monitoring() {
+        let (mut invocation, clock) = invocation(900);
+        let transport = Arc::new(draining_node(1));
+        let nomad_client = nomad_client(&transport, &clock);
+        let (autoscaling, _) = autoscaling();
+        invocation.node = Some(NodeFixture::ready().with_id(NODE_ID).node());
+
+        let drain = drain_node(&nomad_client, &autoscaling, false);
+        assert_eq!(Flow::Continue, drain.handle(&mut invocation).unwrap());
+        assert!(invocation.drained);
+        assert_eq!(5, invocation.eval_ids.len());
+        assert_eq!(1, invocation.allocations);
+        assert_eq!(Some(MonitorCursor::default()), invocation.monitor_cursor);
+        assert_eq!(None, invocation.drain_duration);
+        assert_eq!(
+            vec![
+                format!("POST /v1/node/{}/drain", NODE_ID),
+                format!("GET /v1/node/{}", NODE_ID),
+                format!("GET /v1/node/{}/allocations", NODE_ID),
+            ],
+            transport.requests()
+        );
+        assert_eq!(HandlerState::InProgress, invocation.into_result().state);
+    }
+
+    #[test]
+    fn drains_are_handed_off_when_there_is_no_time_to_monitor() {
+        let (mut invocation, clock) = invocation(35);
+        let transport = Arc::new(draining_node(1));
+        let nomad_client = nomad_client(&transport, &clock);
+        let (autoscaling, _) = autoscaling();
+        invocation.node = Some(NodeFixture::ready().with_id(NODE_ID).node());
+
+        let drain = drain_node(&nomad_client, &autoscaling, true);
+        assert_eq!(Flow::Continue, drain.handle(&mut invocation).unwrap());
+        assert!(invocation.drained);
+        assert_eq!(Some(MonitorCursor::default()), invocation.monitor_cursor);
+        assert_eq!(1, invocation.warnings.len());
+        assert_eq!(3, transport.requests().len());
+    }
+
+    #[test]
+    fn drains_are_monitored_to_completion() {
+        let (mut invocation, clock) = invocation(900);
+        let transport = Arc::new(draining_node(2));
+        let nomad_client = nomad_client(&transport, &clock);
+        let (autoscaling, autoscaling_requests) = autoscaling();
+        invocation.node = Some(NodeFixture::ready().with_id(NODE_ID).node());
+
+        let drain = drain_node(&nomad_client, &autoscaling, true);
+        assert_eq!(Flow::Continue, drain.handle(&mut invocation).unwrap());
+        assert!(invocation.drained);
+        assert_eq!(None, invocation.monitor_cursor);
+        assert!(invocation.drain_duration.is_some());
+        assert!(invocation.warnings.is_empty());
+        // The drain is checked once more after it was submitted, until the node is drained
+        let node_path = format!("GET /v1/node/{}", NODE_ID);
+        assert_eq!(
+            vec![node_path.clone(), node_path],
+            transport.requests()[3..].to_vec()
+        );
+        assert!(autoscaling_requests.lock().unwrap().is_empty());
+        assert_eq!(HandlerState::Done, invocation.into_result().state);
+    }
+
+    #[test]
+    fn lifecycle_actions_are_completed_with_continue() {
+        let (autoscaling, requests) = autoscaling();
+        let complete = CompleteLifecycle {
+            autoscaling: &autoscaling,
+        };
+        let (mut invocation, _) = invocation(900);
+        assert_eq!(Flow::Continue, complete.handle(&mut invocation).unwrap());
+        assert_eq!(
+            vec!["CompleteLifecycleAction CONTINUE"],
+            *requests.lock().unwrap()
+        );
+
+        // Drains still monitored by a later invocation leave the lifecycle action pending
+        requests.lock().unwrap().clear();
+        invocation.monitor_cursor = Some(MonitorCursor::default());
+        assert_eq!(Flow::Continue, complete.handle(&mut invocation).unwrap());
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
@@ -1,8 +1,21 @@
 # Reference: https://docs.aws.amazon.com/autoscaling/ec2/userguide/lifecycle-hooks.html
 locals {
   lambda_payload_path = var.lambda_payload
+
+  # Optional features are only configured when they are set
+  optional_environment = {
+    for name, value in {
+      SSM_DOCUMENT_NAME = var.ssm_document_name
+      SNS_TOPIC_ARN     = var.sns_topic_arn
+      DRAIN_PAUSE_TABLE = var.drain_pause_table
+    } : name => value if value != ""
+  }
 }
 
+data "aws_region" "current" {}
+
+data "aws_caller_identity" "current" {}
+
 data "aws_autoscaling_group" "asg" {
   name = var.asg_name
 }
@@ -73,7 +86,7 @@ resource "aws_lambda_function" "drain" {
   }
 
   environment {
-    variables = {
+    variables = merge(local.optional_environment, {
       NOMAD_ADDR        = var.nomad_address
       USE_NOMAD_TOKEN   = "true"
       VAULT_ADDR        = var.vault_address
@@ -84,7 +97,7 @@ resource "aws_lambda_function" "drain" {
       NOMAD_ROLE        = var.nomad_role
       RUST_LOG          = var.log_level
       RUST_BACKTRACE    = var.enable_backtrace ? "1" : "0"
-    }
+    })
   }
 
   tags = var.tags
@@ -124,12 +137,71 @@ data "aws_iam_policy_document" "asg_lifecycle" {
   statement {
     actions = [
       "autoscaling:CompleteLifecycleAction",
+      "autoscaling:RecordLifecycleActionHeartbeat",
     ]
 
     resources = [
       data.aws_autoscaling_group.asg.arn,
     ]
   }
+
+  # Run the Systems Manager document on instances of the Auto Scaling Group only
+  dynamic "statement" {
+    for_each = var.ssm_document_name != "" ? [var.ssm_document_name] : []
+
+    content {
+      actions = ["ssm:SendCommand"]
+
+      resources = [
+        "arn:aws:ssm:${data.aws_region.current.name}:${data.aws_caller_identity.current.account_id}:document/${statement.value}",
+        "arn:aws:ssm:${data.aws_region.current.name}::document/${statement.value}",
+      ]
+    }
+  }
+
+  dynamic "statement" {
+    for_each = var.ssm_document_name != "" ? [var.ssm_document_name] : []
+
+    content {
+      actions   = ["ssm:SendCommand"]
+      resources = ["arn:aws:ec2:${data.aws_region.current.name}:${data.aws_caller_identity.current.account_id}:instance/*"]
+
+      condition {
+        test     = "StringEquals"
+        variable = "ssm:resourceTag/aws:autoscaling:groupName"
+        values   = [var.asg_name]
+      }
+    }
+  }
+
+  # `GetCommandInvocation` does not support resource level permissions
+  dynamic "statement" {
+    for_each = var.ssm_document_name != "" ? [var.ssm_document_name] : []
+
+    content {
+      actions   = ["ssm:GetCommandInvocation"]
+      resources = ["*"]
+    }
+  }
+
+  dynamic "statement" {
+    for_each = var.sns_topic_arn != "" ? [var.sns_topic_arn] : []
+
+    content {
+      actions   = ["sns:Publish"]
+      resources = [statement.value]
+    }
+  }
+
+  # The Lambda only reads the pause flag. Operators pausing drains need `dynamodb:PutItem`.
+  dynamic "statement" {
+    for_each = var.drain_pause_table != "" ? [var.drain_pause_table] : []
+
+    content {
+      actions   = ["dynamodb:GetItem"]
+      resources = ["arn:aws:dynamodb:${data.aws_region.current.name}:${data.aws_caller_identity.current.account_id}:table/${statement.value}"]
+    }
+  }
 }
 
 resource "aws_autoscaling_lifecycle_hook" "terminate" {
@@ -99,6 +99,21 @@ variable "notification_metadata" {
   default     = ""
 }
 
+variable "ssm_document_name" {
+  description = "Systems Manager document to run on terminating instances after they are drained, if set"
+  default     = ""
+}
+
+variable "sns_topic_arn" {
+  description = "ARN of the SNS topic to publish the outcome of every handled event to, if set"
+  default     = ""
+}
+
+variable "drain_pause_table" {
+  description = "DynamoDB table holding the flag to pause drains with, if set"
+  default     = ""
+}
+
 variable "tags" {
   description = "Map of tags for resources"
 
@@ -0,0 +1,178 @@
+#!/usr/bin/env python3
+"""Mock Nomad server for integration tests
+
+Serves the subset of the Nomad HTTP API used to find, drain and monitor nodes from a scripted
+scenario, so that drains can be tested without a live cluster.
+
+A scenario is a JSON object with the following keys, all optional:
+
+- `agent`: Response of `/v1/agent/self`
+- `nodes`: Response of `/v1/nodes`
+- `node_details`: Responses of `/v1/node/:id`, keyed by node ID. Each is a list of responses that
+  are returned in order, one per request, with the last one repeated once the list runs out.
+- `allocations`: Responses of `/v1/node/:id/allocations`, keyed by node ID and sequenced like
+  `node_details`.
+
+The scenario is loaded from the file passed as the only argument on startup, and can be replaced
+at any time with `PUT /mock/scenario`. Requests received since the scenario was loaded are
+returned by `GET /mock/requests`.
+"""
+import json
+import re
+import sys
+import threading
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+from urllib.parse import parse_qs, urlparse
+
+PORT = 4646
+
+FILTER = re.compile(r'^Attributes\["(?P<key>[^"]+)"\] == "(?P<value>[^"]*)"$')
+
+LOCK = threading.Lock()
+STATE = {}
+
+
+def load(scenario):
+    with LOCK:
+        STATE.clear()
+        STATE.update(
+            {
+                "scenario": scenario,
+                "positions": {},
+                "requests": [],
+                "index": 1,
+            }
+        )
+
+
+def next_in_sequence(kind, node_id):
+    """Next response of the sequence of `kind` for the node, or `None` if there is none"""
+    sequence = STATE["scenario"].get(kind, {}).get(node_id)
+    if not sequence:
+        return None
+    position = STATE["positions"].get((kind, node_id), 0)
+    STATE["positions"][(kind, node_id)] = position + 1
+    if position > 0 and position < len(sequence):
+        STATE["index"] += 1
+    return sequence[min(position, len(sequence) - 1)]
+
+
+def current_details(node_id):
+    sequence = STATE["scenario"].get("node_details", {}).get(node_id)
+    if not sequence:
+        return None
+    position = STATE["positions"].get(("node_details", node_id), 0)
+    return sequence[min(position, len(sequence) - 1)]
+
+
+def matches_filter(node, expression):
+    match = FILTER.match(expression)
+    if match is None:
+        return True
+    details = current_details(node["ID"]) or {}
+    attributes = details.get("Attributes") or {}
+    return attributes.get(match.group("key")) == match.group("value")
+
+
+def update_response():
+    STATE["index"] += 1
+    return {
+        "EvalCreateIndex": STATE["index"],
+        "EvalIDs": ["eval-{}".format(STATE["index"])],
+        "Index": STATE["index"],
+        "NodeModifyIndex": STATE["index"],
+    }
+
+
+class Handler(BaseHTTPRequestHandler):
+    def do_GET(self):
+        self.handle_request("GET")
+
+    def do_POST(self):
+        self.handle_request("POST")
+
+    def do_PUT(self):
+        self.handle_request("PUT")
+
+    def handle_request(self, method):
+        url = urlparse(self.path)
+        query = parse_qs(url.query)
+        length = int(self.headers.get("Content-Length") or 0)
+        body = self.rfile.read(length).decode("utf-8") if length else ""
+
+        if url.path == "/mock/scenario" and method == "PUT":
+            load(json.loads(body))
+            return self.respond(200, {})
+        if url.path == "/mock/requests":
+            with LOCK:
+                return self.respond(200, STATE["requests"])
+
+        with LOCK:
+            STATE["requests"].append(
+                {
+                    "method": method,
+                    "path": url.path,
+                    "query": query,
+                    "body": json.loads(body) if body else None,
+                }
+            )
+            status, response = self.route(method, url.path.rstrip("/").split("/")[1:], query)
+        self.respond(status, response)
+
+    def route(self, method, segments, query):
+        scenario = STATE["scenario"]
+        if method == "GET" and segments == ["v1", "agent", "self"]:
+            agent = scenario.get("agent")
+            return (200, agent) if agent is not None else (404, "agent not scripted")
+        if method == "GET" and segments == ["v1", "status", "leader"]:
+            return 200, "127.0.0.1:4647"
+        if method == "GET" and segments == ["v1", "nodes"]:
+            nodes = scenario.get("nodes", [])
+            expression = query.get("filter", [None])[0]
+            if expression is not None:
+                nodes = [node for node in nodes if matches_filter(node, expression)]
+            return 200, nodes
+        if len(segments) >= 3 and segments[:2] == ["v1", "node"]:
+            node_id = segments[2]
+            action = segments[3:]
+            if method == "GET" and action == []:
+                node = next_in_sequence("node_details", node_id)
+                return (200, node) if node is not None else (404, "node not found")
+            if method == "GET" and action == ["allocations"]:
+                allocations = next_in_sequence("allocations", node_id)
+                return 200, allocations if allocations is not None else []
+            if method == "POST" and action in (["drain"], ["eligibility"]):
+                if current_details(node_id) is None:
+                    return 404, "node not found"
+                return 200, update_response()
+        return 404, "Invalid URL path"
+
+    def respond(self, status, response):
+        if isinstance(response, str) and status >= 400:
+            payload = response.encode("utf-8")
+            content_type = "text/plain"
+        else:
+            payload = json.dumps(response).encode("utf-8")
+            content_type = "application/json"
+        self.send_response(status)
+        self.send_header("Content-Type", content_type)
+        self.send_header("Content-Length", str(len(payload)))
+        self.send_header("X-Nomad-Index", str(STATE.get("index", 1)))
+        self.send_header("X-Nomad-KnownLeader", "true")
+        self.end_headers()
+        self.wfile.write(payload)
+
+
+def main():
+    scenario = {}
+    if len(sys.argv) > 1:
+        with open(sys.argv[1]) as f:
+            scenario = json.load(f)
+    load(scenario)
+    server = ThreadingHTTPServer(("0.0.0.0", PORT), Handler)
+    print("Mock Nomad listening on port {}".format(PORT), flush=True)
+    server.serve_forever()
+
+
+if __name__ == "__main__":
+    main()